@@ -0,0 +1,120 @@
+use std::sync::Arc;
+
+use nostr::types::TryIntoUrl;
+use nostr_relay_pool::{RelayPool, RelayPoolNotification};
+use portal::router::{PortalId, channel::Channel};
+
+#[cfg(feature = "testing")]
+use portal::test_framework::{SimulatedChannel, SimulatedChannelError};
+
+/// The channel used by the app router
+///
+/// In production this is always a relay pool, with the `testing` feature the app can also run on
+/// top of a simulated network.
+pub enum AppChannel {
+    RelayPool(Arc<RelayPool>),
+    #[cfg(feature = "testing")]
+    Simulated(SimulatedChannel),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AppChannelError {
+    #[error("Relay pool error: {0}")]
+    RelayPool(#[from] nostr_relay_pool::pool::Error),
+
+    #[cfg(feature = "testing")]
+    #[error("Simulated channel error: {0}")]
+    Simulated(#[from] SimulatedChannelError),
+
+    #[error("Url error: {0}")]
+    Url(#[from] nostr::types::url::Error),
+}
+
+fn to_strings<I, U>(urls: I) -> Result<Vec<String>, U::Err>
+where
+    I: IntoIterator<Item = U>,
+    U: TryIntoUrl,
+{
+    urls.into_iter()
+        .map(|url| url.try_into_url().map(|url| url.to_string()))
+        .collect()
+}
+
+impl Channel for AppChannel {
+    type Error = AppChannelError;
+
+    async fn subscribe(&self, id: PortalId, filter: nostr::Filter) -> Result<usize, Self::Error> {
+        match self {
+            AppChannel::RelayPool(pool) => Ok(Channel::subscribe(pool, id, filter).await?),
+            #[cfg(feature = "testing")]
+            AppChannel::Simulated(channel) => Ok(channel.subscribe(id, filter).await?),
+        }
+    }
+
+    async fn subscribe_to<I, U>(
+        &self,
+        urls: I,
+        id: PortalId,
+        filter: nostr::Filter,
+    ) -> Result<(), Self::Error>
+    where
+        <I as IntoIterator>::IntoIter: Send,
+        I: IntoIterator<Item = U> + Send,
+        U: TryIntoUrl,
+        Self::Error: From<<U as TryIntoUrl>::Err>,
+    {
+        let urls = to_strings(urls)?;
+        match self {
+            AppChannel::RelayPool(pool) => Ok(Channel::subscribe_to(pool, urls, id, filter).await?),
+            #[cfg(feature = "testing")]
+            AppChannel::Simulated(channel) => Ok(channel.subscribe_to(urls, id, filter).await?),
+        }
+    }
+
+    async fn unsubscribe(&self, id: PortalId) -> Result<(), Self::Error> {
+        match self {
+            AppChannel::RelayPool(pool) => Ok(Channel::unsubscribe(pool, id).await?),
+            #[cfg(feature = "testing")]
+            AppChannel::Simulated(channel) => Ok(channel.unsubscribe(id).await?),
+        }
+    }
+
+    async fn broadcast(&self, event: nostr::Event) -> Result<(), Self::Error> {
+        match self {
+            AppChannel::RelayPool(pool) => Ok(Channel::broadcast(pool, event).await?),
+            #[cfg(feature = "testing")]
+            AppChannel::Simulated(channel) => Ok(channel.broadcast(event).await?),
+        }
+    }
+
+    async fn broadcast_to<I, U>(&self, urls: I, event: nostr::Event) -> Result<(), Self::Error>
+    where
+        <I as IntoIterator>::IntoIter: Send,
+        I: IntoIterator<Item = U> + Send,
+        U: TryIntoUrl,
+        Self::Error: From<<U as TryIntoUrl>::Err>,
+    {
+        let urls = to_strings(urls)?;
+        match self {
+            AppChannel::RelayPool(pool) => Ok(Channel::broadcast_to(pool, urls, event).await?),
+            #[cfg(feature = "testing")]
+            AppChannel::Simulated(channel) => Ok(channel.broadcast_to(urls, event).await?),
+        }
+    }
+
+    async fn receive(&self) -> Result<RelayPoolNotification, Self::Error> {
+        match self {
+            AppChannel::RelayPool(pool) => Ok(Channel::receive(pool).await?),
+            #[cfg(feature = "testing")]
+            AppChannel::Simulated(channel) => Ok(channel.receive().await?),
+        }
+    }
+
+    async fn shutdown(&self) -> Result<(), Self::Error> {
+        match self {
+            AppChannel::RelayPool(pool) => Ok(Channel::shutdown(pool).await?),
+            #[cfg(feature = "testing")]
+            AppChannel::Simulated(channel) => Ok(channel.shutdown().await?),
+        }
+    }
+}
@@ -89,3 +89,22 @@ impl PortalDB {
         Ok(counter)
     }
 }
+
+impl PortalDB {
+    /// Read `key`, `None` if it was never written
+    ///
+    /// The database reports keys that were never written as errors, so a failed read is only
+    /// treated as a missing key when the key has no history.
+    pub(crate) async fn read_if_exists(&self, key: String) -> Result<Option<String>, AppError> {
+        match self.read(key.clone()).await {
+            Ok(value) => Ok(Some(value)),
+            Err(e) => {
+                if self.read_history(key).await?.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(e)
+                }
+            }
+        }
+    }
+}
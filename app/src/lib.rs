@@ -1,10 +1,20 @@
+pub mod channel;
 pub mod db;
 pub mod logger;
 pub mod nwc;
 pub mod runtime;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod vault;
 pub mod wallet;
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+};
 
 use bitcoin::{Network, bip32};
 use lightning_invoice::{Bolt11Invoice, ParseOrSemanticError};
@@ -16,14 +26,24 @@ use nostr::event::EventBuilder;
 use nostr_relay_pool::monitor::{Monitor, MonitorNotification};
 use portal::{
     app::{
+        access::SharedServiceAccessList,
         auth::{
             AuthChallengeEvent, AuthChallengeListenerConversation, AuthResponseConversation,
             KeyHandshakeConversation,
         },
+        certificates::{
+            CertificateIssueListenerConversation, CertificateIssuedEvent, CertificateRequestEvent,
+            CertificateRequestListenerConversation, CertificateResponseSenderConversation,
+            CertificateRevocationListenerConversation, CertificateRevokedEvent,
+        },
         payments::{
             PaymentRequestContent, PaymentRequestEvent, PaymentRequestListenerConversation,
             PaymentStatusSenderConversation, RecurringPaymentStatusSenderConversation,
         },
+        tickets::{
+            TicketPresentationSenderConversation, TicketScanRequestEvent,
+            TicketScanRequestListenerConversation,
+        },
     },
     cashu::{
         CashuDirectReceiverConversation, CashuRequestReceiverConversation,
@@ -41,8 +61,13 @@ use portal::{
         key_handshake::KeyHandshakeUrl,
         model::{
             Timestamp,
-            auth::{AuthResponseStatus, SubkeyProof},
+            auth::{AuthResponseStatus, Capability, SubkeyProof},
             bindings::PublicKey,
+            event_kinds::{
+                AUTH_CHALLENGE, CASHU_DIRECT, CASHU_REQUEST, INVOICE_REQUEST, PAYMENT_REQUEST,
+                RECURRING_PAYMENT_REQUEST,
+            },
+            identity::CertificateResponseContent,
             payment::{
                 CashuDirectContentWithKey, CashuRequestContentWithKey, CashuResponseContent,
                 CashuResponseStatus, CloseRecurringPaymentContent, CloseRecurringPaymentResponse,
@@ -54,7 +79,7 @@ use portal::{
     },
     router::{
         MessageRouter, MultiKeyListenerAdapter, MultiKeySenderAdapter, NotificationStream,
-        adapters::one_shot::OneShotSenderAdapter,
+        RateLimit, RateLimitPolicy, adapters::one_shot::OneShotSenderAdapter,
     },
     utils::verify_nip05,
 };
@@ -63,8 +88,12 @@ pub use portal::app::*;
 pub use rates;
 
 use crate::{
+    channel::AppChannel,
     logger::{CallbackLogger, LogCallback, LogLevel},
     runtime::BindingsRuntime,
+    vault::{
+        CertificateDisclosure, CertificateRequestListener, CertificateVault, TicketScanListener,
+    },
 };
 
 uniffi::setup_scaffolding!();
@@ -230,9 +259,16 @@ pub enum KeypairError {
 
 #[derive(uniffi::Object)]
 pub struct PortalApp {
-    router: Arc<MessageRouter<Arc<RelayPool>>>,
+    router: Arc<MessageRouter<AppChannel>>,
     relay_pool: Arc<RelayPool>,
     runtime: Arc<BindingsRuntime>,
+    require_signed_key_handshake: AtomicBool,
+    /// Set with [`PortalApp::set_capabilities`] to advertise only some of the registered listeners
+    allowed_capabilities: RwLock<Option<Vec<Capability>>>,
+    /// The number of registered listeners that handle each capability
+    listening: RwLock<HashMap<Capability, usize>>,
+    /// The services allowed to send requests, checked before the listeners are called
+    service_access: SharedServiceAccessList,
 }
 #[derive(uniffi::Record, Debug)]
 pub struct Bolt11InvoiceData {
@@ -277,6 +313,11 @@ pub fn parse_calendar(s: &str) -> Result<portal::protocol::calendar::Calendar, P
     Ok(portal::protocol::calendar::Calendar::from_str(s)?)
 }
 
+#[uniffi::export]
+pub fn parse_rrule(s: &str) -> Result<portal::protocol::calendar::Calendar, ParseError> {
+    Ok(portal::protocol::calendar::Calendar::from_rrule(s)?)
+}
+
 #[derive(Debug, PartialEq, thiserror::Error, uniffi::Error)]
 pub enum ParseError {
     #[error("Parse error: {0}")]
@@ -320,7 +361,7 @@ pub trait PaymentStatusNotifier: Send + Sync {
 }
 
 struct LocalStatusNotifier {
-    router: Arc<MessageRouter<Arc<RelayPool>>>,
+    router: Arc<MessageRouter<AppChannel>>,
     request: PaymentRequestEvent,
 }
 
@@ -408,6 +449,69 @@ pub trait CashuDirectListener: Send + Sync {
     async fn on_cashu_direct(&self, event: CashuDirectContentWithKey) -> Result<(), CallbackError>;
 }
 
+impl PortalApp {
+    async fn new_with_channel(
+        keypair: portal::protocol::LocalKeypair,
+        relays: Vec<String>,
+        channel: AppChannel,
+        relay_pool: Arc<RelayPool>,
+        runtime: Arc<BindingsRuntime>,
+    ) -> Result<Arc<Self>, AppError> {
+        // Create router with keypair
+        let router = async_utility::task::spawn(async move {
+            let router = MessageRouter::new(channel, keypair);
+            Arc::new(router)
+        })
+        .join()
+        .await
+        .map_err(|_| AppError::ConversationError("Failed to start router actor".to_string()))?;
+
+        // Ensure the actor is ready
+        log::debug!("Pinging router actor to ensure it's ready...");
+        router.ping().await?;
+        log::debug!("Router actor is ready");
+
+        for relay in &relays {
+            // Make sure the relay nodes are created
+            router.add_relay(relay.clone(), false).await?;
+        }
+
+        Ok(Arc::new(Self {
+            router,
+            relay_pool,
+            runtime,
+            require_signed_key_handshake: AtomicBool::new(false),
+            allowed_capabilities: RwLock::new(None),
+            listening: RwLock::new(HashMap::new()),
+            service_access: SharedServiceAccessList::default(),
+        }))
+    }
+
+    /// Create an app instance connected to a simulated network instead of real relays
+    #[cfg(feature = "testing")]
+    pub async fn new_simulated(
+        keypair: Arc<Keypair>,
+        channel: portal::test_framework::SimulatedChannel,
+    ) -> Result<Arc<Self>, AppError> {
+        use portal::test_framework::SIMULATED_RELAY_URL;
+
+        // The pool is never connected, it only keeps track of the relays we "listen" from
+        let relay_pool = Arc::new(RelayPool::new());
+        relay_pool
+            .add_relay(SIMULATED_RELAY_URL, RelayOptions::default())
+            .await?;
+
+        Self::new_with_channel(
+            keypair.inner.clone(),
+            vec![SIMULATED_RELAY_URL.to_string()],
+            AppChannel::Simulated(channel),
+            relay_pool,
+            Arc::new(BindingsRuntime::new()),
+        )
+        .await
+    }
+}
+
 #[uniffi::export]
 impl PortalApp {
     #[uniffi::constructor]
@@ -439,42 +543,22 @@ impl PortalApp {
             relay_status_listener,
         );
 
-        // Create router with keypair
-        let keypair = keypair.inner.clone();
-        let relay_pool_clone = Arc::clone(&relay_pool);
-        let router = async_utility::task::spawn(async move {
-            let router = MessageRouter::new(relay_pool_clone, keypair);
-            Arc::new(router)
-        })
-        .join()
-        .await
-        .map_err(|_| AppError::ConversationError("Failed to start router actor".to_string()))?;
-
-        // Ensure the actor is ready
-        log::debug!("Pinging router actor to ensure it's ready...");
-        router.ping().await?;
-        log::debug!("Router actor is ready");
-
-        for relay in &relays {
-            // Make sure the relay nodes are created
-            router.add_relay(relay.clone(), false).await?;
-        }
-
-        Ok(Arc::new(Self {
-            router,
+        Self::new_with_channel(
+            keypair.inner.clone(),
+            relays,
+            AppChannel::RelayPool(Arc::clone(&relay_pool)),
             relay_pool,
             runtime,
-        }))
+        )
+        .await
     }
 
     /// Reconnect to all relays
     ///
     /// This method disconnects all relays and then connects them again.
     pub async fn reconnect(&self) -> Result<(), AppError> {
-        let router = self.router.channel();
-
         // 1. Disconnect all relays (sets them to Terminated)
-        router.disconnect().await;
+        self.relay_pool.disconnect().await;
 
         // 2. Reset all relay connection stats
         // let relays = router.relays().await;
@@ -483,7 +567,7 @@ impl PortalApp {
         // }
 
         // 3. Connect all relays (spawns fresh tasks)
-        router.connect().await;
+        self.relay_pool.connect().await;
 
         Ok(())
     }
@@ -501,7 +585,87 @@ impl PortalApp {
         Ok(())
     }
 
+    /// Refuse key handshake urls that are not signed by the service
+    ///
+    /// Expired urls and urls with an invalid signature are always refused.
+    pub fn set_require_signed_key_handshake(&self, require: bool) {
+        self.require_signed_key_handshake
+            .store(require, Ordering::Relaxed);
+    }
+
+    /// Restrict the requests advertised to services during the key handshake
+    ///
+    /// Services won't send requests outside of this set. Only the requests handled by a registered
+    /// listener are advertised, so by default that is everything the app is listening for.
+    pub fn set_capabilities(&self, capabilities: Vec<Capability>) {
+        *self.allowed_capabilities.write().unwrap() = Some(capabilities);
+    }
+
+    /// The requests advertised to services during the key handshake
+    pub fn capabilities(&self) -> Vec<Capability> {
+        let listening = self.listening.read().unwrap();
+        let allowed = self.allowed_capabilities.read().unwrap();
+        Capability::all()
+            .into_iter()
+            .filter(|capability| listening.contains_key(capability))
+            .filter(|capability| {
+                allowed
+                    .as_ref()
+                    .is_none_or(|allowed| allowed.contains(capability))
+            })
+            .collect()
+    }
+
+    /// Only accept requests from `services`, or from every service that is not denied if `None`
+    pub fn set_allowed_services(&self, services: Option<Vec<PublicKey>>) {
+        self.service_access.write().unwrap().set_allowed(services);
+    }
+
+    /// Drop the requests of `services` before they reach the listeners
+    pub fn set_denied_services(&self, services: Vec<PublicKey>) {
+        self.service_access.write().unwrap().set_denied(services);
+    }
+
+    /// Limit the events received from each service, and the requests of each kind from all the
+    /// services combined
+    ///
+    /// Events over the limits are dropped before they reach the listeners. Nothing is limited by
+    /// default.
+    pub async fn set_rate_limits(
+        &self,
+        per_service: Option<RateLimit>,
+        per_request_kind: Option<RateLimit>,
+    ) -> Result<(), AppError> {
+        let mut policy = RateLimitPolicy::new();
+        if let Some(limit) = per_service {
+            policy = policy.per_sender(limit);
+        }
+        if let Some(limit) = per_request_kind {
+            for kind in [
+                AUTH_CHALLENGE,
+                PAYMENT_REQUEST,
+                RECURRING_PAYMENT_REQUEST,
+                INVOICE_REQUEST,
+                CASHU_REQUEST,
+                CASHU_DIRECT,
+            ] {
+                policy = policy.kind(nostr::event::Kind::Custom(kind), limit);
+            }
+        }
+
+        self.router.set_rate_limit_policy(policy).await?;
+        Ok(())
+    }
+
     pub async fn send_key_handshake(&self, url: KeyHandshakeUrl) -> Result<(), AppError> {
+        url.verify()
+            .map_err(|e| AppError::InvalidKeyHandshakeUrl(e.to_string()))?;
+        if !url.is_signed() && self.require_signed_key_handshake.load(Ordering::Relaxed) {
+            return Err(AppError::InvalidKeyHandshakeUrl(
+                "Url is not signed".to_string(),
+            ));
+        }
+
         let our_relays = self
             .relay_pool
             .relays()
@@ -543,7 +707,8 @@ impl PortalApp {
                 Box::new(OneShotSenderAdapter::new_with_user(
                     url.send_to(),
                     url.subkey.map(|s| vec![s.into()]).unwrap_or_default(),
-                    KeyHandshakeConversation::new(url.clone(), our_relays),
+                    KeyHandshakeConversation::new(url.clone(), our_relays)
+                        .with_capabilities(self.capabilities()),
                 )),
                 url.relays,
             )
@@ -568,7 +733,9 @@ impl PortalApp {
         &self,
         evt: Arc<dyn AuthChallengeListener>,
     ) -> Result<(), AppError> {
-        let inner = AuthChallengeListenerConversation::new(self.router.keypair().public_key());
+        let _registration = self.register_listener(&[Capability::Auth]);
+        let inner = AuthChallengeListenerConversation::new(self.router.keypair().public_key())
+            .with_access_list(Arc::clone(&self.service_access));
         let mut rx: NotificationStream<portal::app::auth::AuthChallengeEvent> = self
             .router
             .add_and_subscribe(Box::new(MultiKeyListenerAdapter::new(
@@ -607,11 +774,182 @@ impl PortalApp {
         Ok(())
     }
 
+    /// Store the certificates issued to us in `vault`, and mark them as revoked when their
+    /// issuer revokes them
+    pub async fn listen_for_certificates(
+        &self,
+        vault: Arc<CertificateVault>,
+    ) -> Result<(), AppError> {
+        let _registration = self.register_listener(&[Capability::Certificates]);
+        let local_key = self.router.keypair().public_key();
+        let subkey_proof = self.router.keypair().subkey_proof().cloned();
+
+        let inner = CertificateIssueListenerConversation::new(local_key)
+            .with_access_list(Arc::clone(&self.service_access));
+        let mut issued: NotificationStream<CertificateIssuedEvent> = self
+            .router
+            .add_and_subscribe(Box::new(MultiKeyListenerAdapter::new(
+                inner,
+                subkey_proof.clone(),
+            )))
+            .await?;
+
+        let inner = CertificateRevocationListenerConversation::new(local_key);
+        let mut revoked: NotificationStream<CertificateRevokedEvent> = self
+            .router
+            .add_and_subscribe(Box::new(MultiKeyListenerAdapter::new(inner, subkey_proof)))
+            .await?;
+
+        loop {
+            tokio::select! {
+                event = issued.next() => {
+                    let Ok(event) = event.ok_or(AppError::ListenerDisconnected)? else {
+                        break;
+                    };
+                    log::debug!("Received certificate {}", event.content.certificate.id());
+
+                    if let Err(e) = vault
+                        .add(event.content.certificate, event.content.replaces)
+                        .await
+                    {
+                        log::error!("Failed to store certificate: {}", e);
+                    }
+                }
+                event = revoked.next() => {
+                    let Ok(event) = event.ok_or(AppError::ListenerDisconnected)? else {
+                        break;
+                    };
+                    log::debug!("Received revocation of {}", event.content.certificate_id);
+
+                    if let Err(e) = vault.revoke(event.issuer_key, event.content).await {
+                        log::error!("Failed to revoke certificate: {}", e);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Answer the certificate requests of services with the fields the user agrees to reveal
+    ///
+    /// Declined requests are answered with no certificates.
+    pub async fn listen_for_certificate_requests(
+        &self,
+        vault: Arc<CertificateVault>,
+        evt: Arc<dyn CertificateRequestListener>,
+    ) -> Result<(), AppError> {
+        let _registration = self.register_listener(&[Capability::Certificates]);
+        let inner = CertificateRequestListenerConversation::new(self.router.keypair().public_key())
+            .with_access_list(Arc::clone(&self.service_access));
+        let mut rx: NotificationStream<CertificateRequestEvent> = self
+            .router
+            .add_and_subscribe(Box::new(MultiKeyListenerAdapter::new(
+                inner,
+                self.router.keypair().subkey_proof().cloned(),
+            )))
+            .await?;
+
+        while let Ok(request) = rx.next().await.ok_or(AppError::ListenerDisconnected)? {
+            let evt = Arc::clone(&evt);
+            let vault = Arc::clone(&vault);
+            let router = Arc::clone(&self.router);
+
+            let _ = self.runtime.add_task(async move {
+                log::debug!("Received certificate request: {:?}", request);
+
+                let certificates = vault.matching(&request.content).await?;
+                let disclosure = evt
+                    .on_certificate_request(request.clone(), certificates)
+                    .await?;
+
+                let certificates = match disclosure {
+                    CertificateDisclosure::Approved { selections } => {
+                        vault.disclose(&request.content, &selections).await?
+                    }
+                    CertificateDisclosure::Declined => HashMap::new(),
+                };
+                let content = CertificateResponseContent {
+                    request_id: request.content.request_id.clone(),
+                    certificates,
+                    status_proofs: None,
+                };
+
+                let recipient = request.recipient;
+                let conv = CertificateResponseSenderConversation::new(request, content);
+                router
+                    .add_conversation(Box::new(OneShotSenderAdapter::new_with_user(
+                        recipient.into(),
+                        vec![],
+                        conv,
+                    )))
+                    .await?;
+
+                Ok::<(), AppError>(())
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Present our tickets at the door, with the proof that we own them
+    ///
+    /// Scans for which the user picks no ticket are not answered.
+    pub async fn listen_for_ticket_scans(
+        &self,
+        vault: Arc<CertificateVault>,
+        evt: Arc<dyn TicketScanListener>,
+    ) -> Result<(), AppError> {
+        let _registration = self.register_listener(&[Capability::Certificates]);
+        let inner = TicketScanRequestListenerConversation::new(self.router.keypair().public_key())
+            .with_access_list(Arc::clone(&self.service_access));
+        let mut rx: NotificationStream<TicketScanRequestEvent> = self
+            .router
+            .add_and_subscribe(Box::new(MultiKeyListenerAdapter::new(
+                inner,
+                self.router.keypair().subkey_proof().cloned(),
+            )))
+            .await?;
+
+        while let Ok(request) = rx.next().await.ok_or(AppError::ListenerDisconnected)? {
+            let evt = Arc::clone(&evt);
+            let vault = Arc::clone(&vault);
+            let router = Arc::clone(&self.router);
+
+            let _ = self.runtime.add_task(async move {
+                log::debug!("Received ticket scan: {:?}", request);
+
+                let tickets = vault.tickets_for(&request.content.event_id).await?;
+                let Some(ticket_id) = evt.on_ticket_scan(request.clone(), tickets).await? else {
+                    return Ok(());
+                };
+                let content = vault.present_ticket(&ticket_id, &request.content).await?;
+
+                let recipient = request.recipient;
+                let conv = TicketPresentationSenderConversation::new(request, content);
+                router
+                    .add_conversation(Box::new(OneShotSenderAdapter::new_with_user(
+                        recipient.into(),
+                        vec![],
+                        conv,
+                    )))
+                    .await?;
+
+                Ok::<(), AppError>(())
+            });
+        }
+
+        Ok(())
+    }
+
     pub async fn listen_for_payment_request(
         &self,
         evt: Arc<dyn PaymentRequestListener>,
     ) -> Result<(), AppError> {
-        let inner = PaymentRequestListenerConversation::new(self.router.keypair().public_key());
+        let _registration =
+            self.register_listener(&[Capability::SinglePayment, Capability::RecurringPayment]);
+        let inner = PaymentRequestListenerConversation::new(self.router.keypair().public_key())
+            .with_access_list(Arc::clone(&self.service_access));
         let mut rx: NotificationStream<portal::app::payments::PaymentRequestEvent> = self
             .router
             .add_and_subscribe(Box::new(MultiKeyListenerAdapter::new(
@@ -722,7 +1060,7 @@ impl PortalApp {
     }
 
     pub async fn connection_status(&self) -> HashMap<RelayUrl, RelayStatus> {
-        let relays = self.router.channel().relays().await;
+        let relays = self.relay_pool.relays().await;
         relays
             .into_iter()
             .map(|(u, r)| (RelayUrl(u), RelayStatus::from(r.status())))
@@ -756,7 +1094,8 @@ impl PortalApp {
         evt: Arc<dyn ClosedRecurringPaymentListener>,
     ) -> Result<(), AppError> {
         let inner =
-            CloseRecurringPaymentReceiverConversation::new(self.router.keypair().public_key());
+            CloseRecurringPaymentReceiverConversation::new(self.router.keypair().public_key())
+                .with_access_list(Arc::clone(&self.service_access));
         let mut rx: NotificationStream<
             portal::protocol::model::payment::CloseRecurringPaymentResponse,
         > = self
@@ -814,7 +1153,9 @@ impl PortalApp {
         &self,
         evt: Arc<dyn InvoiceRequestListener>,
     ) -> Result<(), AppError> {
-        let inner = InvoiceReceiverConversation::new(self.router.keypair().public_key());
+        let _registration = self.register_listener(&[Capability::Invoice]);
+        let inner = InvoiceReceiverConversation::new(self.router.keypair().public_key())
+            .with_access_list(Arc::clone(&self.service_access));
         let mut rx: NotificationStream<
             portal::protocol::model::payment::InvoiceRequestContentWithKey,
         > = self
@@ -898,7 +1239,9 @@ impl PortalApp {
         &self,
         evt: Arc<dyn CashuRequestListener>,
     ) -> Result<(), AppError> {
-        let inner = CashuRequestReceiverConversation::new(self.router.keypair().public_key());
+        let _registration = self.register_listener(&[Capability::Cashu]);
+        let inner = CashuRequestReceiverConversation::new(self.router.keypair().public_key())
+            .with_access_list(Arc::clone(&self.service_access));
         let mut rx: NotificationStream<CashuRequestContentWithKey> = self
             .router
             .add_and_subscribe(Box::new(MultiKeyListenerAdapter::new(
@@ -936,7 +1279,9 @@ impl PortalApp {
         &self,
         evt: Arc<dyn CashuDirectListener>,
     ) -> Result<(), AppError> {
-        let inner = CashuDirectReceiverConversation::new(self.router.keypair().public_key());
+        let _registration = self.register_listener(&[Capability::Cashu]);
+        let inner = CashuDirectReceiverConversation::new(self.router.keypair().public_key())
+            .with_access_list(Arc::clone(&self.service_access));
         let mut rx: NotificationStream<CashuDirectContentWithKey> = self
             .router
             .add_and_subscribe(Box::new(MultiKeyListenerAdapter::new(
@@ -956,7 +1301,43 @@ impl PortalApp {
     }
 }
 
+/// Keeps the capabilities of a listener advertised until it stops
+struct ListenerRegistration<'a> {
+    app: &'a PortalApp,
+    capabilities: &'static [Capability],
+}
+
+impl Drop for ListenerRegistration<'_> {
+    fn drop(&mut self) {
+        let mut listening = self.app.listening.write().unwrap();
+        for capability in self.capabilities {
+            if let Some(count) = listening.get_mut(capability) {
+                *count -= 1;
+                if *count == 0 {
+                    listening.remove(capability);
+                }
+            }
+        }
+    }
+}
+
 impl PortalApp {
+    /// Advertise `capabilities` for as long as the returned registration is alive
+    ///
+    /// Listeners register before subscribing, so a service that sees the capability in a key
+    /// handshake never sends a request that nobody is listening for.
+    fn register_listener(&self, capabilities: &'static [Capability]) -> ListenerRegistration<'_> {
+        let mut listening = self.listening.write().unwrap();
+        for capability in capabilities {
+            *listening.entry(*capability).or_default() += 1;
+        }
+
+        ListenerRegistration {
+            app: self,
+            capabilities,
+        }
+    }
+
     /// Set up relay status monitoring in a separate task
     fn setup_relay_status_monitoring(
         runtime: Arc<BindingsRuntime>,
@@ -1120,6 +1501,12 @@ pub enum AppError {
 
     #[error("Profile fetching error: {0}")]
     ProfileFetchingError(String),
+
+    #[error("Invalid key handshake url: {0}")]
+    InvalidKeyHandshakeUrl(String),
+
+    #[error("Certificate error: {0}")]
+    CertificateError(String),
 }
 
 impl From<portal::router::ConversationError> for AppError {
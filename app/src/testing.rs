@@ -0,0 +1,931 @@
+//! Helpers to run a [`PortalSDK`] and a [`PortalApp`] against each other without real relays
+//!
+//! Both sides are connected to the same [`SimulatedChannel`], so end-to-end flows can be tested
+//! in-process, optionally with faults injected in the network.
+
+use std::sync::Arc;
+
+use portal::{
+    nostr::Keys,
+    protocol::LocalKeypair,
+    router::MessageRouter,
+    test_framework::{FaultConfig, SIMULATED_RELAY_URL, SimulatedChannel},
+};
+use sdk::{PortalSDK, PortalSDKError};
+use tokio::task::JoinHandle;
+
+use crate::{AppError, Keypair, PortalApp};
+
+/// A service (SDK) and a user (app) connected to the same simulated network
+pub struct PairedInstances {
+    pub network: SimulatedChannel,
+    pub sdk: PortalSDK<SimulatedChannel>,
+    pub sdk_keypair: LocalKeypair,
+    pub app: Arc<PortalApp>,
+    pub app_keypair: LocalKeypair,
+    _app_listener: JoinHandle<Result<(), AppError>>,
+}
+
+impl PairedInstances {
+    /// Create a paired SDK and app on a network without faults
+    pub async fn new() -> Result<Self, PairedInstancesError> {
+        Self::new_with_faults(FaultConfig::default()).await
+    }
+
+    /// Create a paired SDK and app on a network that applies `faults` to every event
+    pub async fn new_with_faults(faults: FaultConfig) -> Result<Self, PairedInstancesError> {
+        Self::new_with_app_keypair(faults, LocalKeypair::new(Keys::generate(), None)).await
+    }
+
+    /// Create a paired SDK and an app that uses `app_keypair`, which can be a subkey
+    pub async fn new_with_app_keypair(
+        faults: FaultConfig,
+        app_keypair: LocalKeypair,
+    ) -> Result<Self, PairedInstancesError> {
+        let network = SimulatedChannel::new_with_faults(faults);
+
+        let sdk_keypair = LocalKeypair::new(Keys::generate(), None);
+        let router = Arc::new(MessageRouter::new(
+            network.connect().await,
+            sdk_keypair.clone(),
+        ));
+        let sdk = PortalSDK::new_with_router(router, vec![SIMULATED_RELAY_URL.to_string()]).await?;
+
+        let app = PortalApp::new_simulated(
+            Arc::new(Keypair {
+                inner: app_keypair.clone(),
+            }),
+            network.connect().await,
+        )
+        .await?;
+
+        let _app = Arc::clone(&app);
+        let _app_listener = tokio::spawn(async move { _app.listen().await });
+
+        Ok(Self {
+            network,
+            sdk,
+            sdk_keypair,
+            app,
+            app_keypair,
+            _app_listener,
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PairedInstancesError {
+    #[error("SDK error: {0}")]
+    Sdk(#[from] PortalSDKError),
+
+    #[error("App error: {0}")]
+    App(#[from] AppError),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use bitcoin::{
+        hashes::{Hash, sha256},
+        hex::DisplayHex,
+        secp256k1::{Secp256k1, SecretKey},
+    };
+    use lightning_invoice::{InvoiceBuilder, PaymentSecret};
+    use portal::{
+        issuer::{IssueRequest, Issuer, MemoryIssuanceLog},
+        protocol::{
+            identity::{
+                CertificateData, PartialCertificate, VerificationLevel, VerificationMethod,
+            },
+            model::{
+                Nonce, Timestamp,
+                auth::{AuthResponseStatus, Capability},
+                identity::{CertificateIssueContent, CertificateRequestContent},
+                payment::{
+                    Currency, PaymentResponseContent, PaymentStatus,
+                    RecurringPaymentResponseContent, SinglePaymentRequestContent,
+                },
+                ticket::TicketScanRequestContent,
+            },
+            subkey::{PrivateSubkeyManager, SubkeyMetadata},
+            ticket::{MemoryRedemptionList, TicketData, TicketError, TicketVerifier},
+            trust::{TrustDecision, TrustedIssuer},
+        },
+    };
+
+    use super::*;
+    use crate::{
+        AuthChallengeEvent, AuthChallengeListener, CallbackError, CertificateRequestEvent,
+        PaymentRequestListener, PaymentStatusNotifier, RecurringPaymentRequest,
+        SinglePaymentRequest, TicketScanRequestEvent,
+        vault::{
+            CertificateDisclosure, CertificateRequestListener, CertificateStorage,
+            CertificateSummary, CertificateVault, FieldSelection, TicketScanListener,
+        },
+    };
+
+    async fn key_handshake(instances: &PairedInstances) {
+        let (url, mut stream) = instances
+            .sdk
+            .new_key_handshake_url(None, None)
+            .await
+            .unwrap();
+        instances.app.send_key_handshake(url).await.unwrap();
+
+        let event = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .expect("Timed out waiting for the key handshake")
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.main_key, instances.app_keypair.public_key());
+    }
+
+    /// Wait until the app advertises `capabilities`, the listeners register in their own tasks
+    async fn wait_for_capabilities(instances: &PairedInstances, capabilities: &[Capability]) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while capabilities
+                .iter()
+                .any(|capability| !instances.app.capabilities().contains(capability))
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Timed out waiting for the listeners");
+    }
+
+    #[tokio::test]
+    async fn test_paired_key_handshake() {
+        let instances = PairedInstances::new().await.unwrap();
+        key_handshake(&instances).await;
+    }
+
+    #[tokio::test]
+    async fn test_refuse_unsigned_key_handshake() {
+        let instances = PairedInstances::new().await.unwrap();
+        instances.app.set_require_signed_key_handshake(true);
+
+        let (mut url, _stream) = instances
+            .sdk
+            .new_key_handshake_url(None, None)
+            .await
+            .unwrap();
+        url.signature = None;
+
+        assert!(matches!(
+            instances.app.send_key_handshake(url).await,
+            Err(AppError::InvalidKeyHandshakeUrl(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_by_peer() {
+        let instances = PairedInstances::new().await.unwrap();
+        let app = Arc::clone(&instances.app);
+        let _listener = tokio::spawn(async move {
+            app.listen_for_auth_challenge(Arc::new(CountingAuthListener(AtomicUsize::new(0))))
+                .await
+        });
+        wait_for_capabilities(&instances, &[Capability::Auth]).await;
+        key_handshake(&instances).await;
+
+        let main_key = instances.app_keypair.public_key();
+        let capabilities = instances.sdk.peer_capabilities(&main_key).unwrap();
+        assert_eq!(capabilities, HashSet::from([Capability::Auth]));
+
+        let result = instances
+            .sdk
+            .close_recurring_payment(main_key, vec![], "subscription".to_string())
+            .await;
+        assert!(matches!(result, Err(PortalSDKError::UnsupportedByPeer(_))));
+    }
+
+    #[tokio::test]
+    async fn test_key_handshake_with_additional_identity() {
+        let instances = PairedInstances::new().await.unwrap();
+
+        let merchant_keypair = LocalKeypair::new(Keys::generate(), None);
+        let merchant = instances
+            .sdk
+            .with_identity(merchant_keypair.clone())
+            .await
+            .unwrap();
+
+        let (url, mut stream) = merchant.new_key_handshake_url(None, None).await.unwrap();
+        assert_eq!(url.send_to(), merchant_keypair.public_key());
+        instances.app.send_key_handshake(url).await.unwrap();
+
+        let event = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .expect("Timed out waiting for the key handshake")
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.main_key, instances.app_keypair.public_key());
+
+        // The capabilities are tracked separately for each identity
+        assert!(merchant.peer_capabilities(&event.main_key).is_some());
+        assert!(instances.sdk.peer_capabilities(&event.main_key).is_none());
+    }
+
+    struct CountingAuthListener(AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl AuthChallengeListener for CountingAuthListener {
+        async fn on_auth_challenge(
+            &self,
+            _event: AuthChallengeEvent,
+        ) -> Result<AuthResponseStatus, CallbackError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(AuthResponseStatus::Declined { reason: None })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_capabilities_follow_listeners() {
+        let instances = PairedInstances::new().await.unwrap();
+        assert!(instances.app.capabilities().is_empty());
+
+        let app = Arc::clone(&instances.app);
+        let listener = tokio::spawn(async move {
+            app.listen_for_auth_challenge(Arc::new(CountingAuthListener(AtomicUsize::new(0))))
+                .await
+        });
+        wait_for_capabilities(&instances, &[Capability::Auth]).await;
+        assert_eq!(instances.app.capabilities(), vec![Capability::Auth]);
+
+        // Restricting doesn't advertise anything without a listener
+        instances
+            .app
+            .set_capabilities(vec![Capability::Auth, Capability::Cashu]);
+        assert_eq!(instances.app.capabilities(), vec![Capability::Auth]);
+
+        listener.abort();
+        let _ = listener.await;
+        assert!(instances.app.capabilities().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_denied_service_does_not_reach_listener() {
+        let instances = PairedInstances::new().await.unwrap();
+        let listener = Arc::new(CountingAuthListener(AtomicUsize::new(0)));
+        let app = Arc::clone(&instances.app);
+        let _listener = tokio::spawn({
+            let listener = Arc::clone(&listener);
+            async move { app.listen_for_auth_challenge(listener).await }
+        });
+        wait_for_capabilities(&instances, &[Capability::Auth]).await;
+        key_handshake(&instances).await;
+
+        instances
+            .app
+            .set_denied_services(vec![instances.sdk_keypair.public_key().into()]);
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(1),
+            instances
+                .sdk
+                .authenticate_key(instances.app_keypair.public_key(), vec![]),
+        )
+        .await;
+        assert!(result.is_err(), "The challenge should have been dropped");
+        assert_eq!(listener.0.load(Ordering::SeqCst), 0);
+    }
+
+    #[derive(Default)]
+    struct MemoryCertificateStorage(std::sync::Mutex<HashMap<String, String>>);
+
+    #[async_trait::async_trait]
+    impl CertificateStorage for MemoryCertificateStorage {
+        async fn load(&self, key: String) -> Result<Option<String>, CallbackError> {
+            Ok(self.0.lock().unwrap().get(&key).cloned())
+        }
+
+        async fn save(&self, key: String, data: String) -> Result<(), CallbackError> {
+            self.0.lock().unwrap().insert(key, data);
+            Ok(())
+        }
+
+        async fn remove(&self, key: String) -> Result<(), CallbackError> {
+            self.0.lock().unwrap().remove(&key);
+            Ok(())
+        }
+    }
+
+    /// Reveals the `name` field of every certificate it is offered
+    struct RevealNameListener;
+
+    #[async_trait::async_trait]
+    impl CertificateRequestListener for RevealNameListener {
+        async fn on_certificate_request(
+            &self,
+            _event: CertificateRequestEvent,
+            certificates: Vec<CertificateSummary>,
+        ) -> Result<CertificateDisclosure, CallbackError> {
+            Ok(CertificateDisclosure::Approved {
+                selections: certificates
+                    .into_iter()
+                    .map(|c| FieldSelection {
+                        certificate_id: c.id,
+                        fields: vec!["name".to_string(), "age".to_string()],
+                    })
+                    .collect(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_certificate_disclosure() {
+        let instances = PairedInstances::new().await.unwrap();
+
+        let storage = Arc::new(MemoryCertificateStorage::default());
+        let vault = CertificateVault::new(
+            Arc::new(Keypair {
+                inner: instances.app_keypair.clone(),
+            }),
+            storage.clone(),
+        );
+        let app = Arc::clone(&instances.app);
+        let _certificates = tokio::spawn({
+            let vault = Arc::clone(&vault);
+            async move { app.listen_for_certificates(vault).await }
+        });
+        let app = Arc::clone(&instances.app);
+        let _requests = tokio::spawn({
+            let vault = Arc::clone(&vault);
+            async move {
+                app.listen_for_certificate_requests(vault, Arc::new(RevealNameListener))
+                    .await
+            }
+        });
+        wait_for_capabilities(&instances, &[Capability::Certificates]).await;
+        key_handshake(&instances).await;
+
+        let issuer = Issuer::new(
+            instances.sdk_keypair.get_keys().clone(),
+            MemoryIssuanceLog::default(),
+        );
+        let certificate = issuer
+            .issue(IssueRequest::new(
+                instances.app_keypair.public_key(),
+                CertificateData::Custom {
+                    data: serde_json::json!({ "name": "Alice", "age": 30 }),
+                },
+                VerificationLevel::High,
+                VerificationMethod::InPerson,
+                Timestamp::now_plus_seconds(60 * 60),
+            ))
+            .unwrap();
+        instances
+            .sdk
+            .send_certificate(
+                vec![],
+                CertificateIssueContent {
+                    certificate: certificate.clone(),
+                    replaces: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while vault.list().await.unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Timed out waiting for the certificate");
+        assert!(
+            storage
+                .0
+                .lock()
+                .unwrap()
+                .values()
+                .all(|stored| !stored.contains("Alice")),
+            "The vault must be encrypted"
+        );
+
+        let response = tokio::time::timeout(
+            Duration::from_secs(5),
+            instances.sdk.request_certificates(
+                instances.app_keypair.public_key(),
+                vec![],
+                CertificateRequestContent {
+                    request_id: "request".to_string(),
+                    requested_types: vec!["custom".to_string()],
+                    requested_fields: vec!["name".to_string()],
+                    requested_predicates: vec![],
+                    purpose: "Age verification".to_string(),
+                    require_status_proofs: None,
+                    expires_at: Timestamp::now_plus_seconds(60),
+                },
+            ),
+        )
+        .await
+        .expect("Timed out waiting for the response")
+        .unwrap()
+        .unwrap();
+
+        let partial: PartialCertificate =
+            serde_json::from_value(response.certificates[&certificate.id()].clone()).unwrap();
+        // `age` was selected but not requested, so it is not revealed
+        assert_eq!(
+            partial.verify().unwrap(),
+            serde_json::json!({ "name": "Alice" })
+        );
+
+        // The service only accepts the certificate once it trusts the issuer
+        let main_key = instances.app_keypair.public_key();
+        let decisions = instances
+            .sdk
+            .verify_certificate_response(&main_key, &response);
+        assert!(matches!(
+            decisions[&certificate.id()],
+            TrustDecision::UntrustedIssuer { .. }
+        ));
+        instances.sdk.add_trusted_issuer(TrustedIssuer::new(
+            issuer.public_key(),
+            VerificationLevel::Medium,
+        ));
+        let decisions = instances
+            .sdk
+            .verify_certificate_response(&main_key, &response);
+        assert!(decisions[&certificate.id()].is_trusted());
+
+        // Nobody else can present the disclosed certificate as their own
+        let decisions = instances
+            .sdk
+            .verify_certificate_response(&instances.sdk_keypair.public_key(), &response);
+        assert_eq!(
+            decisions[&certificate.id()],
+            TrustDecision::SubjectMismatch { subject: main_key }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_certificates_with_subkey() {
+        let main_keys = Keys::generate();
+        let (keys, subkey_proof) = main_keys
+            .create_subkey(&SubkeyMetadata {
+                name: "phone".to_string(),
+                nonce: Nonce::new([0u8; 32]),
+                valid_from: Timestamp::new(0),
+                expires_at: Timestamp::now_plus_seconds(60 * 60),
+                permissions: vec![],
+                version: 1,
+            })
+            .unwrap()
+            .split();
+        let instances = PairedInstances::new_with_app_keypair(
+            FaultConfig::default(),
+            LocalKeypair::new(keys, Some(subkey_proof)),
+        )
+        .await
+        .unwrap();
+        let subkeys = vec![instances.app_keypair.public_key()];
+
+        let vault = CertificateVault::new(
+            Arc::new(Keypair {
+                inner: instances.app_keypair.clone(),
+            }),
+            Arc::new(MemoryCertificateStorage::default()),
+        );
+        let app = Arc::clone(&instances.app);
+        let _certificates = tokio::spawn({
+            let vault = Arc::clone(&vault);
+            async move { app.listen_for_certificates(vault).await }
+        });
+        let app = Arc::clone(&instances.app);
+        let _requests = tokio::spawn({
+            let vault = Arc::clone(&vault);
+            async move {
+                app.listen_for_certificate_requests(vault, Arc::new(RevealNameListener))
+                    .await
+            }
+        });
+
+        // The certificate is issued to the main key, and delivered to the subkey
+        let issuer = Issuer::new(
+            instances.sdk_keypair.get_keys().clone(),
+            MemoryIssuanceLog::default(),
+        );
+        let certificate = issuer
+            .issue(IssueRequest::new(
+                main_keys.public_key(),
+                CertificateData::Custom {
+                    data: serde_json::json!({ "name": "Alice" }),
+                },
+                VerificationLevel::High,
+                VerificationMethod::InPerson,
+                Timestamp::now_plus_seconds(60 * 60),
+            ))
+            .unwrap();
+        instances
+            .sdk
+            .send_certificate(
+                subkeys.clone(),
+                CertificateIssueContent {
+                    certificate: certificate.clone(),
+                    replaces: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while vault.list().await.unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Timed out waiting for the certificate");
+
+        let response = tokio::time::timeout(
+            Duration::from_secs(5),
+            instances.sdk.request_certificates(
+                main_keys.public_key(),
+                subkeys,
+                CertificateRequestContent {
+                    request_id: "request".to_string(),
+                    requested_types: vec![],
+                    requested_fields: vec!["name".to_string()],
+                    requested_predicates: vec![],
+                    purpose: "Identification".to_string(),
+                    require_status_proofs: None,
+                    expires_at: Timestamp::now_plus_seconds(60),
+                },
+            ),
+        )
+        .await
+        .expect("Timed out waiting for the response")
+        .unwrap()
+        .unwrap();
+        instances.sdk.add_trusted_issuer(TrustedIssuer::new(
+            issuer.public_key(),
+            VerificationLevel::Medium,
+        ));
+        let decisions = instances
+            .sdk
+            .verify_certificate_response(&main_keys.public_key(), &response);
+        assert!(decisions[&certificate.id()].is_trusted());
+    }
+
+    #[tokio::test]
+    async fn test_vault_larger_than_a_nip44_payload() {
+        let keypair = Arc::new(Keypair {
+            inner: LocalKeypair::new(Keys::generate(), None),
+        });
+        let storage = Arc::new(MemoryCertificateStorage::default());
+        let vault = CertificateVault::new(Arc::clone(&keypair), storage.clone());
+
+        // Together the certificates are larger than the 65535 bytes a NIP-44 payload can hold
+        let issuer = Issuer::new(Keys::generate(), MemoryIssuanceLog::default());
+        for index in 0..5 {
+            let certificate = issuer
+                .issue(IssueRequest::new(
+                    keypair.inner.public_key(),
+                    CertificateData::Custom {
+                        data: serde_json::json!({ "index": index, "notes": "x".repeat(20_000) }),
+                    },
+                    VerificationLevel::High,
+                    VerificationMethod::InPerson,
+                    Timestamp::now_plus_seconds(60 * 60),
+                ))
+                .unwrap();
+            vault.add(certificate, None).await.unwrap();
+        }
+
+        let reloaded = CertificateVault::new(keypair, storage);
+        assert_eq!(reloaded.list().await.unwrap().len(), 5);
+    }
+
+    /// Presents the first ticket it is offered
+    struct FirstTicketListener;
+
+    #[async_trait::async_trait]
+    impl TicketScanListener for FirstTicketListener {
+        async fn on_ticket_scan(
+            &self,
+            _event: TicketScanRequestEvent,
+            tickets: Vec<CertificateSummary>,
+        ) -> Result<Option<String>, CallbackError> {
+            Ok(tickets.into_iter().next().map(|t| t.id))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ticket_scan() {
+        let instances = PairedInstances::new().await.unwrap();
+
+        let vault = CertificateVault::new(
+            Arc::new(Keypair {
+                inner: instances.app_keypair.clone(),
+            }),
+            Arc::new(MemoryCertificateStorage::default()),
+        );
+        let app = Arc::clone(&instances.app);
+        let _certificates = tokio::spawn({
+            let vault = Arc::clone(&vault);
+            async move { app.listen_for_certificates(vault).await }
+        });
+        let app = Arc::clone(&instances.app);
+        let _scans = tokio::spawn({
+            let vault = Arc::clone(&vault);
+            async move {
+                app.listen_for_ticket_scans(vault, Arc::new(FirstTicketListener))
+                    .await
+            }
+        });
+        wait_for_capabilities(&instances, &[Capability::Certificates]).await;
+        key_handshake(&instances).await;
+
+        let issuer = Issuer::new(
+            instances.sdk_keypair.get_keys().clone(),
+            MemoryIssuanceLog::default(),
+        );
+        let certificate = issuer
+            .issue_ticket(
+                instances.app_keypair.public_key(),
+                TicketData::new(
+                    "concert".to_string(),
+                    Timestamp::now(),
+                    Timestamp::now_plus_seconds(60 * 60),
+                )
+                .with_seat("B7".to_string()),
+            )
+            .unwrap();
+        instances
+            .sdk
+            .send_certificate(
+                vec![],
+                CertificateIssueContent {
+                    certificate: certificate.clone(),
+                    replaces: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while vault.tickets_for("concert").await.unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Timed out waiting for the ticket");
+
+        let scan = |challenge: &str| TicketScanRequestContent {
+            request_id: challenge.to_string(),
+            event_id: "concert".to_string(),
+            challenge: challenge.to_string(),
+            expires_at: Timestamp::now_plus_seconds(60),
+        };
+        let verifier = TicketVerifier::new("concert".to_string(), MemoryRedemptionList::new())
+            .with_issuer(issuer.public_key());
+
+        for challenge in ["first", "second"] {
+            let presentation = tokio::time::timeout(
+                Duration::from_secs(5),
+                instances.sdk.scan_ticket(
+                    instances.app_keypair.public_key(),
+                    vec![],
+                    scan(challenge),
+                ),
+            )
+            .await
+            .expect("Timed out waiting for the ticket")
+            .unwrap()
+            .unwrap();
+            assert_eq!(presentation.certificate.id(), certificate.id());
+
+            let result = verifier.redeem(&presentation, challenge);
+            if challenge == "first" {
+                assert_eq!(result.unwrap().seat.as_deref(), Some("B7"));
+            } else {
+                // Single use
+                assert!(matches!(result, Err(TicketError::AlreadyRedeemed(_))));
+            }
+        }
+    }
+
+    /// Pays every single payment request, and reports `preimage`
+    struct PayingListener {
+        preimage: String,
+        requests: AtomicUsize,
+    }
+
+    impl PayingListener {
+        fn new(preimage: [u8; 32]) -> Self {
+            Self {
+                preimage: preimage.as_slice().to_lower_hex_string(),
+                requests: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl PaymentRequestListener for PayingListener {
+        async fn on_single_payment_request(
+            &self,
+            event: SinglePaymentRequest,
+            notifier: Arc<dyn PaymentStatusNotifier>,
+        ) -> Result<(), CallbackError> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            notifier
+                .notify(PaymentResponseContent {
+                    request_id: event.content.request_id,
+                    status: PaymentStatus::Success {
+                        preimage: Some(self.preimage.clone()),
+                    },
+                })
+                .await
+        }
+
+        async fn on_recurring_payment_request(
+            &self,
+            _event: RecurringPaymentRequest,
+        ) -> Result<RecurringPaymentResponseContent, CallbackError> {
+            Err(CallbackError::Error("Unexpected request".to_string()))
+        }
+    }
+
+    /// A request to pay an invoice whose preimage is `preimage`
+    fn ticket_payment(preimage: [u8; 32]) -> SinglePaymentRequestContent {
+        let secret_key = SecretKey::from_slice(&[42; 32]).unwrap();
+        let invoice = InvoiceBuilder::new(lightning_invoice::Currency::Bitcoin)
+            .description("Concert ticket".to_string())
+            .payment_hash(sha256::Hash::hash(&preimage))
+            .payment_secret(PaymentSecret([0; 32]))
+            .current_timestamp()
+            .min_final_cltv_expiry_delta(144)
+            .amount_milli_satoshis(10_000)
+            .build_signed(|hash| Secp256k1::new().sign_ecdsa_recoverable(hash, &secret_key))
+            .unwrap();
+
+        SinglePaymentRequestContent {
+            amount: 10_000,
+            currency: Currency::Millisats,
+            current_exchange_rate: None,
+            invoice: invoice.to_string(),
+            auth_token: None,
+            expires_at: Timestamp::now_plus_seconds(60),
+            subscription_id: None,
+            description: Some("Concert ticket".to_string()),
+            request_id: "ticket".to_string(),
+        }
+    }
+
+    fn concert_ticket() -> TicketData {
+        TicketData::new(
+            "concert".to_string(),
+            Timestamp::now(),
+            Timestamp::now_plus_seconds(60 * 60),
+        )
+    }
+
+    /// Start paying with `listener`, and return the vault the tickets are delivered to
+    fn start_ticket_buyer(
+        instances: &PairedInstances,
+        listener: Arc<PayingListener>,
+    ) -> Arc<CertificateVault> {
+        let vault = CertificateVault::new(
+            Arc::new(Keypair {
+                inner: instances.app_keypair.clone(),
+            }),
+            Arc::new(MemoryCertificateStorage::default()),
+        );
+        let app = Arc::clone(&instances.app);
+        tokio::spawn({
+            let vault = Arc::clone(&vault);
+            async move { app.listen_for_certificates(vault).await }
+        });
+        let app = Arc::clone(&instances.app);
+        tokio::spawn(async move { app.listen_for_payment_request(listener).await });
+
+        vault
+    }
+
+    #[tokio::test]
+    async fn test_sell_ticket() {
+        let instances = PairedInstances::new().await.unwrap();
+        let preimage = [1u8; 32];
+        let vault = start_ticket_buyer(&instances, Arc::new(PayingListener::new(preimage)));
+        wait_for_capabilities(
+            &instances,
+            &[Capability::SinglePayment, Capability::Certificates],
+        )
+        .await;
+        key_handshake(&instances).await;
+
+        let issuer = Issuer::new(
+            instances.sdk_keypair.get_keys().clone(),
+            MemoryIssuanceLog::default(),
+        );
+        let certificate = tokio::time::timeout(
+            Duration::from_secs(5),
+            instances.sdk.sell_ticket(
+                instances.app_keypair.public_key(),
+                vec![],
+                ticket_payment(preimage),
+                &issuer,
+                concert_ticket(),
+            ),
+        )
+        .await
+        .expect("Timed out waiting for the payment")
+        .unwrap()
+        .expect("The ticket should have been issued");
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while vault.tickets_for("concert").await.unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Timed out waiting for the ticket");
+        assert_eq!(
+            vault.tickets_for("concert").await.unwrap()[0].id,
+            certificate.id()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sell_ticket_with_wrong_preimage() {
+        let instances = PairedInstances::new().await.unwrap();
+        start_ticket_buyer(&instances, Arc::new(PayingListener::new([2u8; 32])));
+        wait_for_capabilities(
+            &instances,
+            &[Capability::SinglePayment, Capability::Certificates],
+        )
+        .await;
+        key_handshake(&instances).await;
+
+        let issuer = Issuer::new(
+            instances.sdk_keypair.get_keys().clone(),
+            MemoryIssuanceLog::default(),
+        );
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            instances.sdk.sell_ticket(
+                instances.app_keypair.public_key(),
+                vec![],
+                ticket_payment([1u8; 32]),
+                &issuer,
+                concert_ticket(),
+            ),
+        )
+        .await
+        .expect("Timed out waiting for the payment")
+        .unwrap();
+        assert!(result.is_none());
+        assert!(issuer.records().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sell_ticket_without_certificates_capability() {
+        let instances = PairedInstances::new().await.unwrap();
+        let preimage = [1u8; 32];
+        let listener = Arc::new(PayingListener::new(preimage));
+        start_ticket_buyer(&instances, Arc::clone(&listener));
+        instances
+            .app
+            .set_capabilities(vec![Capability::Auth, Capability::SinglePayment]);
+        wait_for_capabilities(&instances, &[Capability::SinglePayment]).await;
+        key_handshake(&instances).await;
+
+        let issuer = Issuer::new(
+            instances.sdk_keypair.get_keys().clone(),
+            MemoryIssuanceLog::default(),
+        );
+        let result = instances
+            .sdk
+            .sell_ticket(
+                instances.app_keypair.public_key(),
+                vec![],
+                ticket_payment(preimage),
+                &issuer,
+                concert_ticket(),
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(PortalSDKError::UnsupportedByPeer(Capability::Certificates))
+        ));
+
+        // The user is not asked to pay for a ticket it can't receive
+        assert_eq!(listener.requests.load(Ordering::SeqCst), 0);
+        assert!(issuer.records().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_paired_key_handshake_with_faults() {
+        let faults = FaultConfig::new()
+            .duplicate(0.5)
+            .delay(Duration::from_millis(1), Duration::from_millis(20))
+            .seed(42);
+        let instances = PairedInstances::new_with_faults(faults).await.unwrap();
+        key_handshake(&instances).await;
+    }
+}
@@ -0,0 +1,459 @@
+//! Encrypted storage for the identity certificates issued to the user
+//!
+//! Every certificate is encrypted to the user's own key with NIP-44 and saved as its own entry in a
+//! [`CertificateStorage`], either a [`PortalDB`] or a store implemented by the application. An
+//! encrypted index lists the entries, so the size of the vault is not bound by the size limit of a
+//! single NIP-44 payload.
+
+use std::{collections::HashMap, sync::Arc};
+
+use nostr::nips::nip44;
+use portal::{
+    app::{certificates::CertificateRequestEvent, tickets::TicketScanRequestEvent},
+    protocol::{
+        identity::Certificate,
+        model::{
+            Timestamp,
+            auth::SubkeyProof,
+            bindings::PublicKey,
+            identity::{CertificateRequestContent, CertificateRevocationContent},
+            ticket::{TicketPresentationContent, TicketScanRequestContent},
+        },
+        ticket::{TicketData, TicketOwnershipProof},
+    },
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{AppError, CallbackError, Keypair, db::PortalDB};
+
+/// The key of the index of the vault, the certificates are saved under `certificate_vault/<id>`
+const VAULT_KEY: &str = "certificate_vault";
+
+fn entry_key(certificate_id: &str) -> String {
+    format!("{}/{}", VAULT_KEY, certificate_id)
+}
+
+/// Where the vault keeps its encrypted content
+#[uniffi::export(with_foreign)]
+#[async_trait::async_trait]
+pub trait CertificateStorage: Send + Sync {
+    /// The last content saved under `key`, `None` if nothing was saved yet
+    async fn load(&self, key: String) -> Result<Option<String>, CallbackError>;
+
+    async fn save(&self, key: String, data: String) -> Result<(), CallbackError>;
+
+    async fn remove(&self, key: String) -> Result<(), CallbackError>;
+}
+
+#[async_trait::async_trait]
+impl CertificateStorage for PortalDB {
+    async fn load(&self, key: String) -> Result<Option<String>, CallbackError> {
+        match self.read_if_exists(key).await {
+            Ok(Some(data)) if !data.is_empty() => Ok(Some(data)),
+            Ok(_) => Ok(None),
+            Err(e) => Err(CallbackError::Error(e.to_string())),
+        }
+    }
+
+    async fn save(&self, key: String, data: String) -> Result<(), CallbackError> {
+        self.store(key, &data)
+            .await
+            .map_err(|e| CallbackError::Error(e.to_string()))
+    }
+
+    async fn remove(&self, key: String) -> Result<(), CallbackError> {
+        PortalDB::remove(self, key)
+            .await
+            .map_err(|e| CallbackError::Error(e.to_string()))
+    }
+}
+
+/// A certificate kept in the vault
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct CertificateSummary {
+    pub id: String,
+    pub issuer: PublicKey,
+    /// `personal`, `business` or `custom`
+    pub certificate_type: String,
+    /// The fields that can be revealed
+    pub fields: Vec<String>,
+    pub issued_at: Timestamp,
+    pub expires_at: Timestamp,
+    pub revoked: bool,
+    pub revocation_reason: Option<String>,
+}
+
+/// The fields of a certificate the user agreed to reveal
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct FieldSelection {
+    pub certificate_id: String,
+    pub fields: Vec<String>,
+}
+
+/// The answer of the user to a certificate request
+#[derive(Debug, Clone, uniffi::Enum)]
+pub enum CertificateDisclosure {
+    Approved { selections: Vec<FieldSelection> },
+    Declined,
+}
+
+#[uniffi::export(with_foreign)]
+#[async_trait::async_trait]
+pub trait CertificateRequestListener: Send + Sync {
+    /// Ask the user which fields to reveal
+    ///
+    /// `certificates` are the certificates of the vault that can answer the request. Only the
+    /// fields listed in the request, and the claims of its predicates, can be revealed. Any other
+    /// field is ignored.
+    async fn on_certificate_request(
+        &self,
+        event: CertificateRequestEvent,
+        certificates: Vec<CertificateSummary>,
+    ) -> Result<CertificateDisclosure, CallbackError>;
+}
+
+#[uniffi::export(with_foreign)]
+#[async_trait::async_trait]
+pub trait TicketScanListener: Send + Sync {
+    /// Ask the user which ticket to present at the door
+    ///
+    /// `tickets` are the tickets of the vault for the event of the scan. Returns the id of the
+    /// ticket to present, or `None` to ignore the scan.
+    async fn on_ticket_scan(
+        &self,
+        event: TicketScanRequestEvent,
+        tickets: Vec<CertificateSummary>,
+    ) -> Result<Option<String>, CallbackError>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct VaultEntry {
+    certificate: Certificate,
+    revocation: Option<CertificateRevocationContent>,
+}
+
+impl VaultEntry {
+    fn summary(&self) -> CertificateSummary {
+        let certificate = &self.certificate;
+        let fields = certificate
+            .prepare_for_revealing()
+            .map(|prepared| prepared.fields.into_keys().collect())
+            .unwrap_or_default();
+
+        CertificateSummary {
+            id: certificate.id(),
+            issuer: certificate.metadata.issuer_pubkey.into(),
+            certificate_type: certificate.data.type_name().to_string(),
+            fields,
+            issued_at: certificate.metadata.issued_at,
+            expires_at: certificate.metadata.expires_at,
+            revoked: self.revocation.is_some(),
+            revocation_reason: self.revocation.as_ref().and_then(|r| r.reason.clone()),
+        }
+    }
+
+    /// Whether the certificate is a ticket for `event_id` that can still be used
+    fn is_ticket_for(&self, event_id: &str) -> bool {
+        TicketData::from_certificate(&self.certificate).is_ok_and(|ticket| {
+            ticket.event_id == event_id && ticket.valid_until > Timestamp::now()
+        }) && self.revocation.is_none()
+    }
+
+    /// Whether the certificate can be disclosed for `request`
+    fn matches(&self, request: &CertificateRequestContent) -> bool {
+        let certificate = &self.certificate;
+        let requested = request.disclosable_fields();
+        let has_requested_field = requested.is_empty()
+            || certificate.prepare_for_revealing().is_ok_and(|prepared| {
+                requested
+                    .iter()
+                    .any(|field| prepared.fields.contains_key(field))
+            });
+
+        has_requested_field
+            && self.revocation.is_none()
+            && certificate.metadata.expires_at > Timestamp::now()
+            && (request.requested_types.is_empty()
+                || request
+                    .requested_types
+                    .iter()
+                    .any(|t| t == certificate.data.type_name()))
+    }
+}
+
+/// Keeps the certificates issued to the user, encrypted with its key
+#[derive(uniffi::Object)]
+pub struct CertificateVault {
+    keys: nostr::Keys,
+    /// Set when the vault is used with a subkey, the certificates are issued to its main key
+    subkey_proof: Option<SubkeyProof>,
+    storage: Arc<dyn CertificateStorage>,
+    /// Loaded on first use
+    entries: Mutex<Option<Vec<VaultEntry>>>,
+}
+
+#[uniffi::export]
+impl CertificateVault {
+    #[uniffi::constructor]
+    pub fn new(keypair: Arc<Keypair>, storage: Arc<dyn CertificateStorage>) -> Arc<Self> {
+        Arc::new(Self {
+            keys: keypair.inner.get_keys().clone(),
+            subkey_proof: keypair.inner.subkey_proof().cloned(),
+            storage,
+            entries: Mutex::new(None),
+        })
+    }
+
+    /// A vault saved in the user's [`PortalDB`]
+    #[uniffi::constructor]
+    pub fn new_with_db(keypair: Arc<Keypair>, db: Arc<PortalDB>) -> Arc<Self> {
+        Self::new(keypair, db)
+    }
+
+    pub async fn list(&self) -> Result<Vec<CertificateSummary>, AppError> {
+        let mut entries = self.entries.lock().await;
+        let entries = self.loaded(&mut entries).await?;
+
+        Ok(entries.iter().map(VaultEntry::summary).collect())
+    }
+
+    pub async fn remove(&self, certificate_id: String) -> Result<(), AppError> {
+        let mut entries = self.entries.lock().await;
+        let entries = self.loaded(&mut entries).await?;
+
+        entries.retain(|e| e.certificate.id() != certificate_id);
+        self.save_index(entries).await?;
+        Ok(self.storage.remove(entry_key(&certificate_id)).await?)
+    }
+}
+
+impl CertificateVault {
+    /// Store a certificate, replacing the one it renews if it comes from the same issuer
+    pub async fn add(
+        &self,
+        certificate: Certificate,
+        replaces: Option<String>,
+    ) -> Result<(), AppError> {
+        let mut entries = self.entries.lock().await;
+        let entries = self.loaded(&mut entries).await?;
+
+        let id = certificate.id();
+        let replaced = entries
+            .iter()
+            .map(|e| e.certificate.id())
+            .filter(|e| *e != id && replaces.as_ref() == Some(e))
+            .collect::<Vec<_>>();
+        entries.retain(|e| {
+            let id_matches =
+                e.certificate.id() == id || replaces.as_ref() == Some(&e.certificate.id());
+            !(id_matches
+                && e.certificate.metadata.issuer_pubkey == certificate.metadata.issuer_pubkey)
+        });
+        let entry = VaultEntry {
+            certificate,
+            revocation: None,
+        };
+
+        // The entry is saved before the index, so the index never lists a missing entry
+        self.save_entry(&entry).await?;
+        entries.push(entry);
+        self.save_index(entries).await?;
+        for replaced in replaced {
+            if !entries.iter().any(|e| e.certificate.id() == replaced) {
+                self.storage.remove(entry_key(&replaced)).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Mark a certificate as revoked, if `issuer` is the one that issued it
+    pub async fn revoke(
+        &self,
+        issuer: PublicKey,
+        revocation: CertificateRevocationContent,
+    ) -> Result<(), AppError> {
+        let mut entries = self.entries.lock().await;
+        let entries = self.loaded(&mut entries).await?;
+
+        let Some(entry) = entries.iter_mut().find(|e| {
+            e.certificate.id() == revocation.certificate_id
+                && e.certificate.metadata.issuer_pubkey == *issuer
+        }) else {
+            log::warn!(
+                "Ignoring revocation of unknown certificate {}",
+                revocation.certificate_id
+            );
+            return Ok(());
+        };
+
+        entry.revocation = Some(revocation);
+        self.save_entry(entry).await
+    }
+
+    /// The certificates that can be disclosed for `request`
+    pub async fn matching(
+        &self,
+        request: &CertificateRequestContent,
+    ) -> Result<Vec<CertificateSummary>, AppError> {
+        let mut entries = self.entries.lock().await;
+        let entries = self.loaded(&mut entries).await?;
+
+        Ok(entries
+            .iter()
+            .filter(|e| e.matches(request))
+            .map(VaultEntry::summary)
+            .collect())
+    }
+
+    /// Build the partial certificates that reveal the fields selected by the user
+    ///
+    /// Fields that were not requested are never revealed.
+    pub async fn disclose(
+        &self,
+        request: &CertificateRequestContent,
+        selections: &[FieldSelection],
+    ) -> Result<HashMap<String, serde_json::Value>, AppError> {
+        let mut entries = self.entries.lock().await;
+        let entries = self.loaded(&mut entries).await?;
+
+        let requested = request.disclosable_fields();
+        let mut disclosed = HashMap::new();
+        for selection in selections {
+            let Some(entry) = entries
+                .iter()
+                .find(|e| e.certificate.id() == selection.certificate_id && e.matches(request))
+            else {
+                log::warn!(
+                    "Certificate {} can't be disclosed for this request",
+                    selection.certificate_id
+                );
+                continue;
+            };
+
+            let fields = selection
+                .fields
+                .iter()
+                .filter(|field| requested.contains(field))
+                .cloned()
+                .collect::<Vec<_>>();
+            if fields.len() < selection.fields.len() {
+                log::warn!("Ignoring the selected fields that were not requested");
+            }
+
+            let partial = entry
+                .certificate
+                .reveal(&fields)
+                .map_err(|e| AppError::CertificateError(e.to_string()))?;
+            // The compact proof keeps the response within the size limits of the relays
+            disclosed.insert(
+                selection.certificate_id.clone(),
+                partial
+                    .to_compact_value()
+                    .map_err(|e| AppError::CertificateError(e.to_string()))?,
+            );
+        }
+
+        Ok(disclosed)
+    }
+
+    /// The usable tickets of the vault for `event_id`
+    pub async fn tickets_for(&self, event_id: &str) -> Result<Vec<CertificateSummary>, AppError> {
+        let mut entries = self.entries.lock().await;
+        let entries = self.loaded(&mut entries).await?;
+
+        Ok(entries
+            .iter()
+            .filter(|e| e.is_ticket_for(event_id))
+            .map(VaultEntry::summary)
+            .collect())
+    }
+
+    /// Present a ticket for `request`, with the proof that we own it
+    ///
+    /// The proof is signed with our key. When it is a subkey, the subkey proof chains it to the
+    /// main key the ticket was issued to.
+    pub async fn present_ticket(
+        &self,
+        ticket_id: &str,
+        request: &TicketScanRequestContent,
+    ) -> Result<TicketPresentationContent, AppError> {
+        let mut entries = self.entries.lock().await;
+        let entries = self.loaded(&mut entries).await?;
+
+        let entry = entries
+            .iter()
+            .find(|e| e.certificate.id() == ticket_id && e.is_ticket_for(&request.event_id))
+            .ok_or_else(|| {
+                AppError::CertificateError(format!("No ticket {} for this event", ticket_id))
+            })?;
+        let proof = TicketOwnershipProof::sign(&self.keys, &entry.certificate, &request.challenge)
+            .map_err(|e| AppError::CertificateError(e.to_string()))?;
+
+        Ok(TicketPresentationContent {
+            request_id: request.request_id.clone(),
+            certificate: entry.certificate.clone(),
+            proof,
+            subkey_proof: self.subkey_proof.clone(),
+        })
+    }
+
+    async fn loaded<'a>(
+        &self,
+        entries: &'a mut Option<Vec<VaultEntry>>,
+    ) -> Result<&'a mut Vec<VaultEntry>, AppError> {
+        if entries.is_none() {
+            let ids: Vec<String> = match self.storage.load(VAULT_KEY.to_string()).await? {
+                Some(data) => self.decrypt(data)?,
+                None => Vec::new(),
+            };
+
+            let mut loaded = Vec::with_capacity(ids.len());
+            for id in ids {
+                match self.storage.load(entry_key(&id)).await? {
+                    Some(data) => loaded.push(self.decrypt(data)?),
+                    None => log::warn!("Certificate {} is missing from the vault", id),
+                }
+            }
+            *entries = Some(loaded);
+        }
+
+        Ok(entries.as_mut().unwrap())
+    }
+
+    async fn save_entry(&self, entry: &VaultEntry) -> Result<(), AppError> {
+        let data = self.encrypt(entry)?;
+        Ok(self
+            .storage
+            .save(entry_key(&entry.certificate.id()), data)
+            .await?)
+    }
+
+    async fn save_index(&self, entries: &[VaultEntry]) -> Result<(), AppError> {
+        let ids = entries
+            .iter()
+            .map(|e| e.certificate.id())
+            .collect::<Vec<_>>();
+        let data = self.encrypt(&ids)?;
+        Ok(self.storage.save(VAULT_KEY.to_string(), data).await?)
+    }
+
+    fn encrypt<T: Serialize>(&self, value: &T) -> Result<String, AppError> {
+        let json =
+            serde_json::to_string(value).map_err(|e| AppError::CertificateError(e.to_string()))?;
+        nip44::encrypt(
+            self.keys.secret_key(),
+            &self.keys.public_key(),
+            json,
+            nip44::Version::V2,
+        )
+        .map_err(|e| AppError::CertificateError(e.to_string()))
+    }
+
+    fn decrypt<T: serde::de::DeserializeOwned>(&self, data: String) -> Result<T, AppError> {
+        let json = nip44::decrypt(self.keys.secret_key(), &self.keys.public_key(), data)
+            .map_err(|e| AppError::CertificateError(e.to_string()))?;
+        serde_json::from_str(&json).map_err(|e| AppError::CertificateError(e.to_string()))
+    }
+}
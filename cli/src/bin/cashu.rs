@@ -27,7 +27,7 @@ impl CashuRequestListener for LogCashuRequestListener {
 async fn main() -> Result<(), CliError> {
     env_logger::init();
 
-    let relays = vec!["wss://relay.nostr.net".to_string()];
+    let relays = cli::relays(&["wss://relay.nostr.net"]);
 
     let (receiver_key, receiver) = create_app_instance(
         "Receiver",
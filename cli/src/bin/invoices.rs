@@ -38,7 +38,7 @@ impl InvoiceResponseListener for LogInvoiceResponseListener {
 async fn main() -> Result<(), CliError> {
     env_logger::init();
 
-    let relays = vec!["wss://relay.nostr.net".to_string()];
+    let relays = cli::relays(&["wss://relay.nostr.net"]);
 
     let (receiver_key, receiver) = create_app_instance(
         "Receiver",
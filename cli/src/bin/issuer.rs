@@ -0,0 +1,201 @@
+//! Issue, renew and revoke identity certificates
+//!
+//! ```text
+//! issuer issue <subject> <person|business|custom> <data.json> <level> <method> <days> [claim...]
+//! issuer renew <certificate-id> <days>
+//! issuer revoke <certificate-id> [reason]
+//! issuer list [subject]
+//! ```
+//!
+//! The issuer keys are derived from `ISSUER_MNEMONIC`, and the issuance log is kept in
+//! `ISSUER_LOG` (`issuance.jsonl` by default). Certificates and revocations are sent to their
+//! subject over the relays in `NOSTR_RELAYS`.
+
+use std::{str::FromStr, time::Duration};
+
+use app::Mnemonic;
+use cli::{CliError, create_sdk_instance};
+use portal::{
+    issuer::{CertificateStatus, FileIssuanceLog, IssueRequest, Issuer},
+    nostr::PublicKey,
+    protocol::{
+        identity::{
+            BusinessData, CertificateData, PersonData, VerificationLevel, VerificationMethod,
+        },
+        model::{Timestamp, identity::CertificateIssueContent},
+        predicate::Predicate,
+    },
+};
+
+const USAGE: &str = "Usage:
+  issuer issue <subject> <person|business|custom> <data.json> <level> <method> <days> [claim...]
+  issuer renew <certificate-id> <days>
+  issuer revoke <certificate-id> [reason]
+  issuer list [subject]
+
+  <level> is high, medium or low
+  <method> is in_person, video_call, document_upload, registry_check,
+  third_party_verification or custom:<description>
+  [claim...] are derived from the data, e.g. age_over_18 or resident_of_it";
+
+fn usage() -> CliError {
+    USAGE.into()
+}
+
+fn parse_data(kind: &str, path: &str) -> Result<CertificateData, CliError> {
+    let json = std::fs::read_to_string(path)?;
+    let data = match kind {
+        "person" => CertificateData::Person(serde_json::from_str::<PersonData>(&json)?),
+        "business" => CertificateData::Business(serde_json::from_str::<BusinessData>(&json)?),
+        "custom" => {
+            let data = serde_json::from_str::<serde_json::Value>(&json)?;
+            if !data.is_object() {
+                return Err("Custom data must be a JSON object".into());
+            }
+            CertificateData::Custom { data }
+        }
+        _ => return Err(usage()),
+    };
+
+    Ok(data)
+}
+
+fn parse_method(method: &str) -> Result<VerificationMethod, CliError> {
+    match method.strip_prefix("custom:") {
+        Some(description) => Ok(VerificationMethod::Custom(description.to_string())),
+        None => Ok(serde_json::from_value(serde_json::Value::String(
+            method.to_string(),
+        ))?),
+    }
+}
+
+fn expires_in(days: &str) -> Result<Timestamp, CliError> {
+    let days: u64 = days.parse()?;
+    Ok(Timestamp::now_plus_seconds(days * 24 * 60 * 60))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), CliError> {
+    env_logger::init();
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let mnemonic = std::env::var("ISSUER_MNEMONIC").map_err(|_| "ISSUER_MNEMONIC is not set")?;
+    let log_path = std::env::var("ISSUER_LOG").unwrap_or_else(|_| "issuance.jsonl".to_string());
+
+    let keypair = Mnemonic::new(&mnemonic)?.get_keypair()?;
+    let issuer = Issuer::new(
+        keypair.inner.get_keys().clone(),
+        FileIssuanceLog::open(&log_path)?,
+    );
+
+    match args.as_slice() {
+        [
+            "issue",
+            subject,
+            kind,
+            data,
+            level,
+            method,
+            days,
+            claims @ ..,
+        ] => {
+            let request = IssueRequest::new(
+                PublicKey::from_str(subject)?,
+                parse_data(kind, data)?,
+                serde_json::from_value::<VerificationLevel>(serde_json::Value::String(
+                    level.to_string(),
+                ))?,
+                parse_method(method)?,
+                expires_in(days)?,
+            )
+            .with_claims(
+                claims
+                    .iter()
+                    .map(|claim| claim.parse())
+                    .collect::<Result<Vec<Predicate>, _>>()?,
+            );
+            let certificate = issuer.issue(request)?;
+            log::info!("Issued certificate {}", certificate.id());
+
+            send(
+                &mnemonic,
+                CertificateIssueContent {
+                    certificate,
+                    replaces: None,
+                },
+            )
+            .await?;
+        }
+        ["renew", id, days] => {
+            let certificate = issuer.renew(id, expires_in(days)?)?;
+            log::info!("Renewed certificate {} as {}", id, certificate.id());
+
+            send(
+                &mnemonic,
+                CertificateIssueContent {
+                    certificate,
+                    replaces: Some(id.to_string()),
+                },
+            )
+            .await?;
+        }
+        ["revoke", id, reason @ ..] => {
+            let subject = issuer
+                .record(id)?
+                .ok_or("Unknown certificate")?
+                .certificate
+                .subject;
+            let reason = (!reason.is_empty()).then(|| reason.join(" "));
+            let revocation = issuer.revoke(id, reason)?;
+            log::info!("Revoked certificate {}", id);
+
+            let sdk =
+                create_sdk_instance(&mnemonic, cli::relays(&["wss://relay.nostr.net"])).await?;
+            sdk.send_certificate_revocation(subject, vec![], revocation)
+                .await?;
+            wait_for_delivery().await;
+        }
+        ["list", subject @ ..] => {
+            let records = match subject {
+                [] => issuer.records()?,
+                [subject] => issuer.records_for(&PublicKey::from_str(subject)?)?,
+                _ => return Err(usage()),
+            };
+
+            for record in records {
+                let status = match record.status {
+                    CertificateStatus::Active => "active".to_string(),
+                    CertificateStatus::Revoked { reason, .. } => {
+                        format!("revoked ({})", reason.unwrap_or_default())
+                    }
+                    CertificateStatus::Renewed { by } => format!("renewed by {}", by),
+                };
+                println!(
+                    "{} subject={} expires_at={} {}",
+                    record.certificate.id(),
+                    record.certificate.subject,
+                    record.certificate.metadata.expires_at.as_u64(),
+                    status
+                );
+            }
+        }
+        _ => return Err(usage()),
+    }
+
+    Ok(())
+}
+
+async fn send(mnemonic: &str, content: CertificateIssueContent) -> Result<(), CliError> {
+    let sdk = create_sdk_instance(mnemonic, cli::relays(&["wss://relay.nostr.net"])).await?;
+    sdk.send_certificate(vec![], content).await?;
+    wait_for_delivery().await;
+
+    Ok(())
+}
+
+/// The conversation publishes in the background, give it time before exiting
+async fn wait_for_delivery() {
+    tokio::time::sleep(Duration::from_secs(5)).await;
+}
@@ -8,14 +8,14 @@ async fn main() -> Result<(), CliError> {
     let (keypair0, app0) = create_app_instance(
         "Sender",
         "mass derive myself benefit shed true girl orange family spawn device theme",
-        vec!["wss://relay.nostr.net".to_string()],
+        cli::relays(&["wss://relay.nostr.net"]),
     )
     .await?;
 
     let (keypair1, app1) = create_app_instance(
         "Receiver",
         "draft sunny old taxi chimney ski tilt suffer subway bundle once story",
-        vec!["wss://relay.nostr.net".to_string()],
+        cli::relays(&["wss://relay.nostr.net"]),
     )
     .await?;
 
@@ -209,10 +209,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let app = PortalApp::new(
         keypair,
-        vec![
-            "wss://relay.nostr.net".to_string(),
-            "wss://relay.getportal.cc".to_string(),
-        ],
+        cli::relays(&["wss://relay.nostr.net", "wss://relay.getportal.cc"]),
         Arc::new(LogRelayStatusChange),
     )
     .await?;
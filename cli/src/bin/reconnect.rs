@@ -11,10 +11,7 @@ use cli::CliError;
 pub async fn main() -> Result<(), CliError> {
     env_logger::init();
 
-    let relays = vec![
-        "wss://relay.nostr.net".to_string(),
-        "wss://relay.damus.io".to_string(),
-    ];
+    let relays = cli::relays(&["wss://relay.nostr.net", "wss://relay.damus.io"]);
 
     let (keypair, app) = create_app_instance(
         "Reconnect",
@@ -21,10 +21,23 @@ impl RelayStatusListener for LogRelayStatusChange {
 
 pub type CliError = Box<dyn std::error::Error>;
 
+/// Relays used by the CLI binaries
+///
+/// Set `NOSTR_RELAYS` to a comma-separated list of urls to override the defaults, for example to
+/// point at a local relay started with `cargo run -p relay`.
+pub fn relays(default: &[&str]) -> Vec<String> {
+    match std::env::var("NOSTR_RELAYS") {
+        Ok(relays) if !relays.trim().is_empty() => {
+            relays.split(',').map(|s| s.trim().to_string()).collect()
+        }
+        _ => default.iter().map(|s| s.to_string()).collect(),
+    }
+}
+
 pub async fn create_app_instance(
     name: &str,
     mnemonic: &str,
-    _relays: Vec<String>,
+    relays: Vec<String>,
 ) -> Result<(Arc<Keypair>, Arc<PortalApp>), CliError> {
     log::info!("{}: Creating app instance", name);
 
@@ -33,15 +46,7 @@ pub async fn create_app_instance(
     // let mnemonic = generate_mnemonic()?;
     let keypair = Arc::new(mnemonic.get_keypair()?);
 
-    let app = PortalApp::new(
-        keypair.clone(),
-        vec![
-            "wss://relay.nostr.net".to_string(),
-            "wss://relay.damus.io".to_string(),
-        ],
-        Arc::new(LogRelayStatusChange),
-    )
-    .await?;
+    let app = PortalApp::new(keypair.clone(), relays, Arc::new(LogRelayStatusChange)).await?;
 
     let _app = Arc::clone(&app);
     tokio::spawn(async move {
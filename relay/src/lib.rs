@@ -0,0 +1,707 @@
+//! A small Nostr relay for development and tests
+//!
+//! It implements the subset of NIP-01 used by Portal (`EVENT`, `REQ`, `CLOSE` and `EOSE`) so that
+//! full flows can run offline. Events are kept in memory, or appended to a file on disk when a path
+//! is configured.
+//!
+//! # Example
+//! ```rust,no_run
+//! # async fn example() -> Result<(), relay::RelayError> {
+//! let relay = relay::LocalRelay::builder().build().await?;
+//! println!("Relay listening on {}", relay.url());
+//!
+//! relay.shutdown().await;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod store;
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::{SocketAddr, TcpListener},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use axum::{
+    Router,
+    extract::{
+        State, WebSocketUpgrade,
+        ws::{Message, WebSocket},
+    },
+    routing::get,
+};
+use futures::{SinkExt, StreamExt};
+use nostr::{
+    event::{Event, Kind},
+    filter::{Filter, MatchEventOptions},
+};
+use serde_json::{Value, json};
+use tokio::{
+    sync::{broadcast, oneshot, watch},
+    task::JoinHandle,
+};
+
+pub use store::{EventStore, FileStore, MemoryStore, StoreError};
+
+/// The address used by the relay binary when none is configured
+pub const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:7777";
+
+#[derive(Debug, thiserror::Error)]
+pub enum RelayError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Store error: {0}")]
+    Store(#[from] StoreError),
+
+    #[error("Server error: {0}")]
+    Server(String),
+
+    #[error("Invalid listen address: {0}")]
+    Address(#[from] std::net::AddrParseError),
+}
+
+#[derive(Debug, Default)]
+pub struct LocalRelayBuilder {
+    addr: Option<SocketAddr>,
+    path: Option<PathBuf>,
+}
+
+impl LocalRelayBuilder {
+    /// Listen on a specific address. By default the relay listens on a random port on localhost.
+    pub fn addr(mut self, addr: SocketAddr) -> Self {
+        self.addr = Some(addr);
+        self
+    }
+
+    /// Persist events to a file, instead of keeping them only in memory.
+    pub fn path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Start the relay
+    pub async fn build(self) -> Result<LocalRelay, RelayError> {
+        let store: Arc<dyn EventStore> = match self.path {
+            Some(path) => Arc::new(FileStore::open(path)?),
+            None => Arc::new(MemoryStore::new()),
+        };
+
+        let addr = self
+            .addr
+            .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 0)));
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+
+        let (events, _) = broadcast::channel(1024);
+        let requested_kinds = Arc::new(watch::channel(HashSet::new()).0);
+        let state = RelayState {
+            store,
+            events,
+            requested_kinds: Arc::clone(&requested_kinds),
+        };
+
+        let app = Router::new()
+            .route("/", get(handle_ws_upgrade))
+            .with_state(state);
+
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server = axum::Server::from_tcp(listener)
+            .map_err(|e| RelayError::Server(e.to_string()))?
+            .serve(app.into_make_service())
+            .with_graceful_shutdown(async {
+                let _ = shutdown_rx.await;
+            });
+
+        let handle = tokio::spawn(async move {
+            if let Err(e) = server.await {
+                log::error!("Relay server error: {}", e);
+            }
+        });
+
+        log::info!("Relay listening on {}", addr);
+
+        Ok(LocalRelay {
+            addr,
+            requested_kinds,
+            shutdown: Some(shutdown),
+            handle,
+        })
+    }
+}
+
+/// A running relay
+///
+/// The relay is stopped when this is dropped.
+pub struct LocalRelay {
+    addr: SocketAddr,
+    requested_kinds: Arc<watch::Sender<HashSet<Kind>>>,
+    shutdown: Option<oneshot::Sender<()>>,
+    handle: JoinHandle<()>,
+}
+
+impl LocalRelay {
+    pub fn builder() -> LocalRelayBuilder {
+        LocalRelayBuilder::default()
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The websocket url clients should connect to
+    pub fn url(&self) -> String {
+        format!("ws://{}", self.addr)
+    }
+
+    /// Wait until a client subscribes to events of `kind`
+    ///
+    /// Events of ephemeral kinds are only forwarded to the current subscribers, so this lets a
+    /// test make sure the receiving side is listening before the event is published.
+    pub async fn wait_for_subscription(&self, kind: Kind) {
+        let mut requested_kinds = self.requested_kinds.subscribe();
+        let _ = requested_kinds
+            .wait_for(|kinds| kinds.contains(&kind))
+            .await;
+    }
+
+    /// Stop accepting connections and wait for the server to terminate
+    pub async fn shutdown(mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        let _ = (&mut self.handle).await;
+    }
+}
+
+impl Drop for LocalRelay {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+#[derive(Clone)]
+struct RelayState {
+    store: Arc<dyn EventStore>,
+    events: broadcast::Sender<Event>,
+    /// Every kind explicitly requested by a subscription
+    requested_kinds: Arc<watch::Sender<HashSet<Kind>>>,
+}
+
+async fn handle_ws_upgrade(
+    ws: WebSocketUpgrade,
+    State(state): State<RelayState>,
+) -> impl axum::response::IntoResponse {
+    ws.on_upgrade(move |socket| handle_socket(socket, state))
+}
+
+async fn handle_socket(socket: WebSocket, state: RelayState) {
+    let (mut sender, mut receiver) = socket.split();
+    let mut live_events = state.events.subscribe();
+    let mut subscriptions = HashMap::new();
+
+    loop {
+        let replies = tokio::select! {
+            message = receiver.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    log::trace!("Received message: {}", text);
+                    handle_message(&state, &mut subscriptions, &text)
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            event = live_events.recv() => match event {
+                Ok(event) => subscriptions
+                    .iter()
+                    .filter(|(_, filters)| matches(filters, &event))
+                    .map(|(id, _)| json!(["EVENT", id, event]))
+                    .collect(),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    log::warn!("Connection lagging behind, skipped {} events", skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+
+        for reply in replies {
+            if sender.send(Message::Text(reply.to_string())).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn matches(filters: &[Filter], event: &Event) -> bool {
+    filters
+        .iter()
+        .any(|filter| filter.match_event(event, MatchEventOptions::default()))
+}
+
+fn handle_message(
+    state: &RelayState,
+    subscriptions: &mut HashMap<String, Vec<Filter>>,
+    text: &str,
+) -> Vec<Value> {
+    let message: Vec<Value> = match serde_json::from_str(text) {
+        Ok(message) => message,
+        Err(e) => return vec![json!(["NOTICE", format!("invalid: {}", e)])],
+    };
+
+    match message.first().and_then(Value::as_str) {
+        Some("EVENT") => vec![handle_event(state, message.get(1))],
+        Some("REQ") => {
+            let Some(id) = message.get(1).and_then(Value::as_str) else {
+                return vec![json!(["NOTICE", "invalid: missing subscription id"])];
+            };
+
+            let filters = message[2..]
+                .iter()
+                .cloned()
+                .map(serde_json::from_value)
+                .collect::<Result<Vec<Filter>, _>>();
+            let filters = match filters {
+                Ok(filters) => filters,
+                Err(e) => return vec![json!(["CLOSED", id, format!("invalid: {}", e)])],
+            };
+
+            let mut replies = match state.store.query(&filters) {
+                Ok(events) => events
+                    .into_iter()
+                    .map(|event| json!(["EVENT", id, event]))
+                    .collect::<Vec<_>>(),
+                Err(e) => return vec![json!(["CLOSED", id, format!("error: {}", e)])],
+            };
+            replies.push(json!(["EOSE", id]));
+
+            state.requested_kinds.send_if_modified(|kinds| {
+                let before = kinds.len();
+                kinds.extend(
+                    filters
+                        .iter()
+                        .flat_map(|f| f.kinds.iter().flatten().copied()),
+                );
+                kinds.len() != before
+            });
+
+            // A REQ with an existing id replaces the previous subscription
+            subscriptions.insert(id.to_string(), filters);
+            replies
+        }
+        Some("CLOSE") => {
+            if let Some(id) = message.get(1).and_then(Value::as_str) {
+                subscriptions.remove(id);
+            }
+            vec![]
+        }
+        _ => vec![json!(["NOTICE", "unsupported message"])],
+    }
+}
+
+fn handle_event(state: &RelayState, event: Option<&Value>) -> Value {
+    let event: Event = match event.cloned().map(serde_json::from_value) {
+        Some(Ok(event)) => event,
+        _ => return json!(["NOTICE", "invalid: malformed event"]),
+    };
+
+    if event.verify().is_err() {
+        return json!(["OK", event.id, false, "invalid: bad signature"]);
+    }
+
+    // Ephemeral events are only forwarded to the current subscribers
+    if event.kind.is_ephemeral() {
+        let _ = state.events.send(event.clone());
+        return json!(["OK", event.id, true, ""]);
+    }
+
+    match state.store.save(&event) {
+        Ok(true) => {
+            let _ = state.events.send(event.clone());
+            json!(["OK", event.id, true, ""])
+        }
+        Ok(false) => json!(["OK", event.id, true, "duplicate: already have this event"]),
+        Err(e) => json!(["OK", event.id, false, format!("error: {}", e)]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    use nostr::{EventBuilder, Keys};
+    use portal::protocol::{
+        LocalKeypair,
+        model::{
+            Timestamp,
+            auth::AuthResponseStatus,
+            event_kinds::{
+                AUTH_CHALLENGE, CASHU_DIRECT, CASHU_REQUEST, KEY_HANDSHAKE, PAYMENT_REQUEST,
+            },
+            payment::{
+                CashuDirectContent, CashuDirectContentWithKey, CashuRequestContent,
+                CashuRequestContentWithKey, CashuResponseStatus, Currency, PaymentResponseContent,
+                PaymentStatus, RecurringPaymentResponseContent, SinglePaymentRequestContent,
+            },
+        },
+    };
+
+    fn state() -> RelayState {
+        RelayState {
+            store: Arc::new(MemoryStore::new()),
+            events: broadcast::channel(16).0,
+            requested_kinds: Arc::new(watch::channel(HashSet::new()).0),
+        }
+    }
+
+    #[test]
+    fn test_event_req_close() {
+        let state = state();
+        let mut subscriptions = HashMap::new();
+
+        let event = EventBuilder::text_note("hello")
+            .sign_with_keys(&Keys::generate())
+            .unwrap();
+        let replies = handle_message(
+            &state,
+            &mut subscriptions,
+            &json!(["EVENT", event]).to_string(),
+        );
+        assert_eq!(replies, vec![json!(["OK", event.id, true, ""])]);
+
+        let filter = Filter::new().kind(Kind::TextNote);
+        let replies = handle_message(
+            &state,
+            &mut subscriptions,
+            &json!(["REQ", "sub", filter]).to_string(),
+        );
+        assert_eq!(
+            replies,
+            vec![json!(["EVENT", "sub", event]), json!(["EOSE", "sub"])]
+        );
+        assert!(subscriptions.contains_key("sub"));
+        assert!(state.requested_kinds.borrow().contains(&Kind::TextNote));
+
+        let replies = handle_message(
+            &state,
+            &mut subscriptions,
+            &json!(["CLOSE", "sub"]).to_string(),
+        );
+        assert!(replies.is_empty());
+        assert!(subscriptions.is_empty());
+    }
+
+    #[test]
+    fn test_reject_bad_signature() {
+        let state = state();
+        let mut subscriptions = HashMap::new();
+
+        let event = EventBuilder::text_note("hello")
+            .sign_with_keys(&Keys::generate())
+            .unwrap();
+        let mut tampered = serde_json::to_value(&event).unwrap();
+        tampered["content"] = json!("goodbye");
+
+        let replies = handle_message(
+            &state,
+            &mut subscriptions,
+            &json!(["EVENT", tampered]).to_string(),
+        );
+        assert_eq!(replies[0][2], json!(false));
+        assert!(state.store.query(&[Filter::new()]).unwrap().is_empty());
+    }
+
+    /// Reports when the app is connected to the relay
+    struct ConnectionWatcher(watch::Sender<bool>);
+
+    #[async_trait::async_trait]
+    impl app::RelayStatusListener for ConnectionWatcher {
+        async fn on_relay_status_change(
+            &self,
+            _relay_url: app::RelayUrl,
+            status: app::RelayStatus,
+        ) -> Result<(), app::CallbackError> {
+            if matches!(status, app::RelayStatus::Connected) {
+                self.0.send_replace(true);
+            }
+            Ok(())
+        }
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// A service (SDK) and a user (app) connected to the same local relay
+    struct Instances {
+        relay: LocalRelay,
+        sdk: sdk::PortalSDK,
+        app: Arc<app::PortalApp>,
+        app_keypair: LocalKeypair,
+    }
+
+    async fn connect() -> Instances {
+        let relay = LocalRelay::builder().build().await.unwrap();
+
+        let sdk = sdk::PortalSDK::new(LocalKeypair::new(Keys::generate(), None), vec![relay.url()])
+            .await
+            .unwrap();
+
+        let app_keypair = LocalKeypair::new(Keys::generate(), None);
+        let (connected, mut watcher) = watch::channel(false);
+        let app = app::PortalApp::new(
+            Arc::new(app::Keypair {
+                inner: app_keypair.clone(),
+            }),
+            vec![relay.url()],
+            Arc::new(ConnectionWatcher(connected)),
+        )
+        .await
+        .unwrap();
+        let _app = Arc::clone(&app);
+        tokio::spawn(async move { _app.listen().await });
+
+        tokio::time::timeout(TIMEOUT, watcher.wait_for(|connected| *connected))
+            .await
+            .expect("Timed out waiting for the app to connect")
+            .unwrap();
+
+        Instances {
+            relay,
+            sdk,
+            app,
+            app_keypair,
+        }
+    }
+
+    /// Wait until the receiving side of an ephemeral event of `kind` is listening
+    async fn wait_for_subscription(relay: &LocalRelay, kind: u16) {
+        tokio::time::timeout(TIMEOUT, relay.wait_for_subscription(Kind::from(kind)))
+            .await
+            .expect("Timed out waiting for the subscription");
+    }
+
+    /// The key handshake also makes sure that the SDK is connected
+    async fn key_handshake(instances: &Instances) {
+        let (url, mut stream) = instances
+            .sdk
+            .new_key_handshake_url(None, None)
+            .await
+            .unwrap();
+        wait_for_subscription(&instances.relay, KEY_HANDSHAKE).await;
+        instances.app.send_key_handshake(url).await.unwrap();
+
+        let event = tokio::time::timeout(TIMEOUT, stream.next())
+            .await
+            .expect("Timed out waiting for the key handshake")
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.main_key, instances.app_keypair.public_key());
+    }
+
+    #[tokio::test]
+    async fn test_key_handshake() {
+        let instances = connect().await;
+        key_handshake(&instances).await;
+
+        instances.relay.shutdown().await;
+    }
+
+    struct ApprovingAuthListener;
+
+    #[async_trait::async_trait]
+    impl app::AuthChallengeListener for ApprovingAuthListener {
+        async fn on_auth_challenge(
+            &self,
+            _event: app::AuthChallengeEvent,
+        ) -> Result<AuthResponseStatus, app::CallbackError> {
+            Ok(AuthResponseStatus::Approved {
+                granted_permissions: vec![],
+                session_token: "session".to_string(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_auth() {
+        let instances = connect().await;
+        let app = Arc::clone(&instances.app);
+        tokio::spawn(async move {
+            app.listen_for_auth_challenge(Arc::new(ApprovingAuthListener))
+                .await
+        });
+        wait_for_subscription(&instances.relay, AUTH_CHALLENGE).await;
+        key_handshake(&instances).await;
+
+        let response = tokio::time::timeout(
+            TIMEOUT,
+            instances
+                .sdk
+                .authenticate_key(instances.app_keypair.public_key(), vec![]),
+        )
+        .await
+        .expect("Timed out waiting for the auth response")
+        .unwrap();
+        assert!(matches!(
+            response.status,
+            AuthResponseStatus::Approved { session_token, .. } if session_token == "session"
+        ));
+
+        instances.relay.shutdown().await;
+    }
+
+    struct PayingListener;
+
+    #[async_trait::async_trait]
+    impl app::PaymentRequestListener for PayingListener {
+        async fn on_single_payment_request(
+            &self,
+            event: app::SinglePaymentRequest,
+            notifier: Arc<dyn app::PaymentStatusNotifier>,
+        ) -> Result<(), app::CallbackError> {
+            notifier
+                .notify(PaymentResponseContent {
+                    request_id: event.content.request_id,
+                    status: PaymentStatus::Success { preimage: None },
+                })
+                .await
+        }
+
+        async fn on_recurring_payment_request(
+            &self,
+            _event: app::RecurringPaymentRequest,
+        ) -> Result<RecurringPaymentResponseContent, app::CallbackError> {
+            Err(app::CallbackError::Error("Unexpected request".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_single_payment() {
+        let instances = connect().await;
+        let app = Arc::clone(&instances.app);
+        tokio::spawn(async move {
+            app.listen_for_payment_request(Arc::new(PayingListener))
+                .await
+        });
+        wait_for_subscription(&instances.relay, PAYMENT_REQUEST).await;
+        key_handshake(&instances).await;
+
+        let mut notifications = instances
+            .sdk
+            .request_single_payment(
+                instances.app_keypair.public_key(),
+                vec![],
+                SinglePaymentRequestContent {
+                    amount: 1000,
+                    currency: Currency::Millisats,
+                    current_exchange_rate: None,
+                    invoice: "lnbc10n1".to_string(),
+                    auth_token: None,
+                    expires_at: Timestamp::now_plus_seconds(60),
+                    subscription_id: None,
+                    description: None,
+                    request_id: "payment".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+
+        let response = tokio::time::timeout(TIMEOUT, notifications.next())
+            .await
+            .expect("Timed out waiting for the payment status")
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.request_id, "payment");
+        assert_eq!(response.status, PaymentStatus::Success { preimage: None });
+
+        instances.relay.shutdown().await;
+    }
+
+    /// Pays every cashu request and forwards the tokens it receives
+    struct CashuWallet(tokio::sync::mpsc::UnboundedSender<String>);
+
+    #[async_trait::async_trait]
+    impl app::CashuRequestListener for CashuWallet {
+        async fn on_cashu_request(
+            &self,
+            _event: CashuRequestContentWithKey,
+        ) -> Result<CashuResponseStatus, app::CallbackError> {
+            Ok(CashuResponseStatus::Success {
+                token: "cashuBrequested".to_string(),
+            })
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl app::CashuDirectListener for CashuWallet {
+        async fn on_cashu_direct(
+            &self,
+            event: CashuDirectContentWithKey,
+        ) -> Result<(), app::CallbackError> {
+            let _ = self.0.send(event.inner.token);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cashu() {
+        let instances = connect().await;
+        let (tokens, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let wallet = Arc::new(CashuWallet(tokens));
+        let app = Arc::clone(&instances.app);
+        let requests = Arc::clone(&wallet);
+        tokio::spawn(async move { app.listen_cashu_requests(requests).await });
+        let app = Arc::clone(&instances.app);
+        tokio::spawn(async move { app.listen_cashu_direct(wallet).await });
+        wait_for_subscription(&instances.relay, CASHU_REQUEST).await;
+        wait_for_subscription(&instances.relay, CASHU_DIRECT).await;
+        key_handshake(&instances).await;
+
+        let response = tokio::time::timeout(
+            TIMEOUT,
+            instances.sdk.request_cashu(
+                instances.app_keypair.public_key(),
+                vec![],
+                CashuRequestContent {
+                    request_id: "cashu".to_string(),
+                    mint_url: "https://mint.example.com".to_string(),
+                    unit: "sat".to_string(),
+                    amount: 10,
+                    expires_at: Timestamp::now_plus_seconds(60),
+                },
+            ),
+        )
+        .await
+        .expect("Timed out waiting for the cashu response")
+        .unwrap()
+        .expect("The request should have been answered");
+        assert_eq!(response.request.inner.request_id, "cashu");
+        assert!(matches!(
+            response.status,
+            CashuResponseStatus::Success { token } if token == "cashuBrequested"
+        ));
+
+        instances
+            .sdk
+            .send_cashu_direct(
+                instances.app_keypair.public_key(),
+                vec![],
+                CashuDirectContent {
+                    token: "cashuBdirect".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+        let token = tokio::time::timeout(TIMEOUT, received.recv())
+            .await
+            .expect("Timed out waiting for the token")
+            .unwrap();
+        assert_eq!(token, "cashuBdirect");
+
+        instances.relay.shutdown().await;
+    }
+}
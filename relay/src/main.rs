@@ -0,0 +1,32 @@
+use std::{env, net::SocketAddr};
+
+use relay::{DEFAULT_LISTEN_ADDR, LocalRelay, RelayError};
+
+/// Run a local relay
+///
+/// Configured through environment variables:
+/// - `RELAY_LISTEN`: the address to listen on, defaults to `127.0.0.1:7777`
+/// - `RELAY_DB`: a file to persist events to, if unset events are only kept in memory
+#[tokio::main]
+async fn main() -> Result<(), RelayError> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let addr: SocketAddr = env::var("RELAY_LISTEN")
+        .unwrap_or_else(|_| DEFAULT_LISTEN_ADDR.to_string())
+        .parse()?;
+
+    let mut builder = LocalRelay::builder().addr(addr);
+    if let Ok(path) = env::var("RELAY_DB") {
+        log::info!("Persisting events to {}", path);
+        builder = builder.path(path);
+    }
+
+    let relay = builder.build().await?;
+    log::info!("Connect to {}", relay.url());
+
+    tokio::signal::ctrl_c().await?;
+    log::info!("Shutting down");
+    relay.shutdown().await;
+
+    Ok(())
+}
@@ -0,0 +1,228 @@
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::{Mutex, RwLock},
+};
+
+use nostr::{
+    event::Event,
+    filter::{Filter, MatchEventOptions},
+};
+
+/// Storage for the events accepted by the relay
+pub trait EventStore: Send + Sync {
+    /// Stores an event, returns `false` if the event was a duplicate or was superseded by a newer
+    /// replaceable event.
+    fn save(&self, event: &Event) -> Result<bool, StoreError>;
+
+    /// Returns the stored events matching any of the filters, newest first.
+    fn query(&self, filters: &[Filter]) -> Result<Vec<Event>, StoreError>;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+/// Keeps every event in memory, everything is lost when the relay stops
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    events: RwLock<Vec<Event>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn replaces(existing: &Event, event: &Event) -> bool {
+        if existing.pubkey != event.pubkey || existing.kind != event.kind {
+            return false;
+        }
+
+        if event.kind.is_replaceable() {
+            true
+        } else if event.kind.is_addressable() {
+            existing.tags.identifier() == event.tags.identifier()
+        } else {
+            false
+        }
+    }
+}
+
+impl EventStore for MemoryStore {
+    fn save(&self, event: &Event) -> Result<bool, StoreError> {
+        let mut events = self.events.write().unwrap();
+        if events.iter().any(|e| e.id == event.id) {
+            return Ok(false);
+        }
+
+        // Replaceable and addressable events: keep only the newest one
+        if let Some(index) = events.iter().position(|e| Self::replaces(e, event)) {
+            if events[index].created_at >= event.created_at {
+                return Ok(false);
+            }
+            events.remove(index);
+        }
+
+        events.push(event.clone());
+        Ok(true)
+    }
+
+    fn query(&self, filters: &[Filter]) -> Result<Vec<Event>, StoreError> {
+        let events = self.events.read().unwrap();
+
+        let mut seen = HashSet::new();
+        let mut result = Vec::new();
+        for filter in filters {
+            let mut matching = events
+                .iter()
+                .filter(|e| filter.match_event(e, MatchEventOptions::default()))
+                .collect::<Vec<_>>();
+            matching.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+            if let Some(limit) = filter.limit {
+                matching.truncate(limit);
+            }
+
+            for event in matching {
+                if seen.insert(event.id) {
+                    result.push(event.clone());
+                }
+            }
+        }
+
+        result.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(result)
+    }
+}
+
+/// Appends every accepted event to a JSONL file, which is replayed when the relay starts
+///
+/// Queries are served from memory.
+#[derive(Debug)]
+pub struct FileStore {
+    inner: MemoryStore,
+    file: Mutex<File>,
+}
+
+impl FileStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        let path = path.as_ref();
+        let inner = MemoryStore::new();
+
+        if path.exists() {
+            let reader = BufReader::new(File::open(path)?);
+            for line in reader.lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                let event: Event = serde_json::from_str(&line)?;
+                inner.save(&event)?;
+            }
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            inner,
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl EventStore for FileStore {
+    fn save(&self, event: &Event) -> Result<bool, StoreError> {
+        let mut file = self.file.lock().unwrap();
+        if !self.inner.save(event)? {
+            return Ok(false);
+        }
+
+        writeln!(file, "{}", serde_json::to_string(event)?)?;
+        file.flush()?;
+        Ok(true)
+    }
+
+    fn query(&self, filters: &[Filter]) -> Result<Vec<Event>, StoreError> {
+        self.inner.query(filters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nostr::{EventBuilder, Keys, Kind, Metadata, Timestamp};
+
+    #[test]
+    fn test_query_limit_and_order() {
+        let keys = Keys::generate();
+        let store = MemoryStore::new();
+        for i in 0..5 {
+            let event = EventBuilder::text_note(format!("note {i}"))
+                .custom_created_at(Timestamp::from(1_000 + i))
+                .sign_with_keys(&keys)
+                .unwrap();
+            assert!(store.save(&event).unwrap());
+            assert!(!store.save(&event).unwrap());
+        }
+
+        let events = store
+            .query(&[Filter::new().kind(Kind::TextNote).limit(2)])
+            .unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].content, "note 4");
+        assert_eq!(events[1].content, "note 3");
+    }
+
+    #[test]
+    fn test_replaceable_events() {
+        let keys = Keys::generate();
+        let store = MemoryStore::new();
+
+        let old = EventBuilder::metadata(&Metadata::new().name("old"))
+            .custom_created_at(Timestamp::from(1_000))
+            .sign_with_keys(&keys)
+            .unwrap();
+        let new = EventBuilder::metadata(&Metadata::new().name("new"))
+            .custom_created_at(Timestamp::from(2_000))
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        assert!(store.save(&new).unwrap());
+        assert!(!store.save(&old).unwrap());
+
+        let events = store.query(&[Filter::new().kind(Kind::Metadata)]).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].id, new.id);
+    }
+
+    #[test]
+    fn test_file_store_reload() {
+        let path = std::env::temp_dir().join(format!(
+            "portal-relay-test-{}.jsonl",
+            Keys::generate().public_key()
+        ));
+
+        let event = EventBuilder::text_note("persisted")
+            .sign_with_keys(&Keys::generate())
+            .unwrap();
+        {
+            let store = FileStore::open(&path).unwrap();
+            assert!(store.save(&event).unwrap());
+        }
+
+        let store = FileStore::open(&path).unwrap();
+        let events = store.query(&[Filter::new().id(event.id)]).unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(!store.save(&event).unwrap());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+use portal::profile::Profile;
+use portal::protocol::model::payment::{
+    Currency, InvoiceRequestContent, RecurringPaymentRequestContent, SinglePaymentRequestContent,
+};
+use portal::protocol::model::Timestamp;
+use portal::qr::{QrFormat, QrOptions};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandWithId {
+    pub id: String,
+    /// The identity to run the command as, if the daemon serves more than one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    #[serde(flatten)]
+    pub cmd: Command,
+}
+
+// Commands that can be sent from client to server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "cmd", content = "params")]
+pub enum Command {
+    // Authentication command - must be first command sent
+    Auth(AuthParams),
+    // Take over the streams of a previous connection
+    Resume(ResumeParams),
+
+    // SDK methods
+    NewKeyHandshakeUrl(NewKeyHandshakeUrlParams),
+    AuthenticateKey(AuthenticateKeyParams),
+    RequestRecurringPayment(RequestRecurringPaymentParams),
+    RequestSinglePayment(RequestSinglePaymentParams),
+    RequestPaymentRaw(RequestPaymentRawParams),
+    FetchProfile(FetchProfileParams),
+    SetProfile(SetProfileParams),
+    CloseRecurringPayment(CloseRecurringPaymentParams),
+    ListenClosedRecurringPayment,
+    RequestInvoice(RequestInvoiceParams),
+    IssueJwt(IssueJwtParams),
+    VerifyJwt(VerifyJwtParams),
+    RequestCashu(RequestCashuParams),
+    SendCashuDirect(SendCashuDirectParams),
+    MintCashu(MintCashuParams),
+    BurnCashu(BurnCashuParams),
+    AddRelay(AddRelayParams),
+    RemoveRelay(RemoveRelayParams),
+}
+
+impl Command {
+    /// The name of the command, as in the `cmd` field
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Auth(_) => "Auth",
+            Command::Resume(_) => "Resume",
+            Command::NewKeyHandshakeUrl(_) => "NewKeyHandshakeUrl",
+            Command::AuthenticateKey(_) => "AuthenticateKey",
+            Command::RequestRecurringPayment(_) => "RequestRecurringPayment",
+            Command::RequestSinglePayment(_) => "RequestSinglePayment",
+            Command::RequestPaymentRaw(_) => "RequestPaymentRaw",
+            Command::FetchProfile(_) => "FetchProfile",
+            Command::SetProfile(_) => "SetProfile",
+            Command::CloseRecurringPayment(_) => "CloseRecurringPayment",
+            Command::ListenClosedRecurringPayment => "ListenClosedRecurringPayment",
+            Command::RequestInvoice(_) => "RequestInvoice",
+            Command::IssueJwt(_) => "IssueJwt",
+            Command::VerifyJwt(_) => "VerifyJwt",
+            Command::RequestCashu(_) => "RequestCashu",
+            Command::SendCashuDirect(_) => "SendCashuDirect",
+            Command::MintCashu(_) => "MintCashu",
+            Command::BurnCashu(_) => "BurnCashu",
+            Command::AddRelay(_) => "AddRelay",
+            Command::RemoveRelay(_) => "RemoveRelay",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct QrParams {
+    pub format: QrFormat,
+    #[serde(flatten)]
+    pub options: QrOptions,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SinglePaymentParams {
+    pub description: String,
+    pub amount: u64,
+    pub currency: Currency,
+    pub subscription_id: Option<String>,
+    pub auth_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AuthParams {
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ResumeParams {
+    pub stream_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NewKeyHandshakeUrlParams {
+    pub static_token: Option<String>,
+    #[serde(default)]
+    pub expires_at: Option<Timestamp>,
+    #[serde(default)]
+    pub qr: Option<QrParams>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AuthenticateKeyParams {
+    pub main_key: String,
+    pub subkeys: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RequestRecurringPaymentParams {
+    pub main_key: String,
+    pub subkeys: Vec<String>,
+    pub payment_request: RecurringPaymentRequestContent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RequestSinglePaymentParams {
+    pub main_key: String,
+    pub subkeys: Vec<String>,
+    pub payment_request: SinglePaymentParams,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RequestPaymentRawParams {
+    pub main_key: String,
+    pub subkeys: Vec<String>,
+    pub payment_request: SinglePaymentRequestContent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FetchProfileParams {
+    pub main_key: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SetProfileParams {
+    pub profile: Profile,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CloseRecurringPaymentParams {
+    pub main_key: String,
+    pub subkeys: Vec<String>,
+    pub subscription_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RequestInvoiceParams {
+    pub recipient_key: String,
+    pub subkeys: Vec<String>,
+    pub content: InvoiceRequestContent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct IssueJwtParams {
+    pub target_key: String,
+    pub duration_hours: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct VerifyJwtParams {
+    pub pubkey: String,
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RequestCashuParams {
+    pub recipient_key: String,
+    pub subkeys: Vec<String>,
+    pub mint_url: String,
+    pub unit: String,
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SendCashuDirectParams {
+    pub main_key: String,
+    pub subkeys: Vec<String>,
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MintCashuParams {
+    pub mint_url: String,
+    pub unit: String,
+    pub static_auth_token: Option<String>,
+    pub amount: u64,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BurnCashuParams {
+    pub mint_url: String,
+    pub unit: String,
+    pub static_auth_token: Option<String>,
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AddRelayParams {
+    pub relay: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RemoveRelayParams {
+    pub relay: String,
+}
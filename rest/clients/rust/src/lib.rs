@@ -0,0 +1,937 @@
+//! Client for the websocket protocol of the Portal rest daemon
+//!
+//! The commands and responses are the same types the daemon uses, so both sides always agree on
+//! the protocol. Every command gets a unique id that is used to match its response, and the
+//! notifications of a stream are delivered to the [`NotificationStream`] returned by the command
+//! that opened it.
+//!
+//! When the socket drops, the client reconnects, authenticates again and resumes its streams.
+//! Commands that were still waiting for a response fail with [`ClientError::Disconnected`].
+//!
+//! # Example
+//! ```rust,no_run
+//! # async fn example() -> Result<(), rest_client::ClientError> {
+//! use rest_client::{ClientConfig, PortalClient};
+//!
+//! let client = PortalClient::connect(ClientConfig::new("ws://localhost:3000/ws", "token")).await?;
+//!
+//! let (url, mut handshakes) = client.new_key_handshake_url(None, None).await?;
+//! println!("Scan {}", url);
+//! while let Some(handshake) = handshakes.next().await {
+//!     println!("{:?}", handshake);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod command;
+pub mod response;
+
+use std::collections::HashSet;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use dashmap::DashMap;
+use futures::{SinkExt, StreamExt};
+use portal::nostr::key::PublicKey;
+use portal::profile::Profile;
+use portal::protocol::model::payment::{
+    CashuResponseStatus, InvoiceRequestContent, RecurringPaymentRequestContent,
+    RecurringPaymentResponseContent, SinglePaymentRequestContent,
+};
+use portal::protocol::model::Timestamp;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::command::*;
+use crate::response::*;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+type Reply = Result<(ResponseData, Option<NotificationStream>), ClientError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("Websocket error: {0}")]
+    Websocket(#[from] tokio_tungstenite::tungstenite::Error),
+
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+
+    #[error("Authentication failed: {0}")]
+    Authentication(String),
+
+    /// The daemon replied with an error
+    #[error("Command failed: {0}")]
+    Command(String),
+
+    #[error("Unexpected response: {0:?}")]
+    UnexpectedResponse(Box<ResponseData>),
+
+    #[error("Disconnected from the daemon")]
+    Disconnected,
+
+    #[error("Timed out waiting for the response")]
+    Timeout,
+}
+
+/// How to reach and authenticate with the daemon
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    url: String,
+    token: String,
+    identity: Option<String>,
+    request_timeout: Duration,
+    reconnect_delay: Duration,
+    max_reconnect_delay: Duration,
+}
+
+impl ClientConfig {
+    /// Connect to the `/ws` endpoint at `url` with the `AUTH_TOKEN`, an identity token or an API
+    /// key
+    pub fn new(url: impl Into<String>, token: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            token: token.into(),
+            identity: None,
+            request_timeout: Duration::from_secs(300),
+            reconnect_delay: Duration::from_secs(1),
+            max_reconnect_delay: Duration::from_secs(30),
+        }
+    }
+
+    /// Run the commands as another identity served by the daemon
+    pub fn with_identity(mut self, identity: impl Into<String>) -> Self {
+        self.identity = Some(identity.into());
+        self
+    }
+
+    /// How long to wait for the response of a command
+    ///
+    /// Commands that wait for the user, like recurring payments, can take minutes.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// The delay before the first reconnection attempt, doubled after each failure up to `max`
+    pub fn with_reconnect_delay(mut self, delay: Duration, max: Duration) -> Self {
+        self.reconnect_delay = delay;
+        self.max_reconnect_delay = max;
+        self
+    }
+}
+
+/// The notifications of a stream opened by a command
+///
+/// The stream is closed when dropped, or when it expired while the client was disconnected.
+pub struct NotificationStream {
+    id: String,
+    rx: mpsc::UnboundedReceiver<NotificationData>,
+    shared: Weak<Shared>,
+}
+
+impl NotificationStream {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The next notification, `None` once the stream is closed
+    pub async fn next(&mut self) -> Option<NotificationData> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for NotificationStream {
+    fn drop(&mut self) {
+        if let Some(shared) = self.shared.upgrade() {
+            shared.streams.remove(&self.id);
+        }
+    }
+}
+
+/// The state shared by the client and its connection task
+struct Shared {
+    config: ClientConfig,
+    /// Commands waiting for their response, by id
+    pending: DashMap<String, oneshot::Sender<Reply>>,
+    /// Open streams, by id
+    streams: DashMap<String, mpsc::UnboundedSender<NotificationData>>,
+}
+
+impl Shared {
+    fn open_stream(self: &Arc<Self>, id: &str) -> NotificationStream {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.streams.insert(id.to_string(), tx);
+
+        NotificationStream {
+            id: id.to_string(),
+            rx,
+            shared: Arc::downgrade(self),
+        }
+    }
+}
+
+/// Owns the socket, reconnecting when it drops
+struct Connection {
+    shared: Arc<Shared>,
+    /// Ids and serialized commands to send
+    commands: mpsc::Receiver<(String, String)>,
+    /// Commands sent on the current socket that are still waiting for their response
+    in_flight: HashSet<String>,
+    /// The id of the `Resume` command sent after reconnecting
+    resume_id: Option<String>,
+}
+
+impl Connection {
+    /// Connect to the daemon and authenticate
+    async fn open(config: &ClientConfig) -> Result<Socket, ClientError> {
+        let (mut socket, _) = tokio_tungstenite::connect_async(config.url.as_str()).await?;
+
+        let id = Uuid::new_v4().to_string();
+        let auth = CommandWithId {
+            id: id.clone(),
+            identity: None,
+            cmd: Command::Auth(AuthParams {
+                token: config.token.clone(),
+            }),
+        };
+        socket
+            .send(Message::Text(serde_json::to_string(&auth)?.into()))
+            .await?;
+
+        while let Some(message) = socket.next().await {
+            let Message::Text(text) = message? else {
+                continue;
+            };
+            match serde_json::from_str(text.as_str())? {
+                Response::Success { id: reply_id, .. } if reply_id == id => return Ok(socket),
+                Response::Error {
+                    id: reply_id,
+                    message,
+                } if reply_id == id => return Err(ClientError::Authentication(message)),
+                _ => {}
+            }
+        }
+
+        Err(ClientError::Disconnected)
+    }
+
+    async fn run(mut self, mut socket: Socket) {
+        loop {
+            match self.serve(&mut socket).await {
+                Ok(()) => {
+                    // The client was dropped
+                    let _ = socket.close(None).await;
+                    return;
+                }
+                Err(e) => warn!("Connection to the daemon lost: {}", e),
+            }
+
+            for id in self.in_flight.drain() {
+                if let Some((_, tx)) = self.shared.pending.remove(&id) {
+                    let _ = tx.send(Err(ClientError::Disconnected));
+                }
+            }
+            self.resume_id = None;
+
+            let Some(new_socket) = self.reconnect().await else {
+                return;
+            };
+            socket = new_socket;
+
+            if let Err(e) = self.resume(&mut socket).await {
+                warn!("Failed to resume the streams: {}", e);
+            }
+        }
+    }
+
+    /// Try to connect again until it succeeds, `None` if the client was dropped in the meantime
+    async fn reconnect(&self) -> Option<Socket> {
+        let config = &self.shared.config;
+        let mut delay = config.reconnect_delay;
+
+        loop {
+            if self.commands.is_closed() {
+                return None;
+            }
+            tokio::time::sleep(delay).await;
+
+            match Self::open(config).await {
+                Ok(socket) => {
+                    debug!("Reconnected to the daemon");
+                    return Some(socket);
+                }
+                Err(ClientError::Authentication(e)) => {
+                    warn!("The daemon refused the token after reconnecting: {}", e);
+                    return None;
+                }
+                Err(e) => {
+                    debug!("Failed to reconnect to the daemon: {}", e);
+                    delay = (delay * 2).min(config.max_reconnect_delay);
+                }
+            }
+        }
+    }
+
+    /// Ask the daemon to resume the streams of the previous socket
+    async fn resume(&mut self, socket: &mut Socket) -> Result<(), ClientError> {
+        let stream_ids = self
+            .shared
+            .streams
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect::<Vec<_>>();
+        if stream_ids.is_empty() {
+            return Ok(());
+        }
+
+        let id = Uuid::new_v4().to_string();
+        let command = CommandWithId {
+            id: id.clone(),
+            identity: None,
+            cmd: Command::Resume(ResumeParams { stream_ids }),
+        };
+        socket
+            .send(Message::Text(serde_json::to_string(&command)?.into()))
+            .await?;
+        self.resume_id = Some(id);
+
+        Ok(())
+    }
+
+    /// Send commands and dispatch messages until the socket closes, or the client is dropped
+    async fn serve(&mut self, socket: &mut Socket) -> Result<(), ClientError> {
+        loop {
+            tokio::select! {
+                command = self.commands.recv() => {
+                    let Some((id, json)) = command else {
+                        return Ok(());
+                    };
+                    self.in_flight.insert(id);
+                    socket.send(Message::Text(json.into())).await?;
+                }
+                message = socket.next() => match message {
+                    Some(Ok(Message::Text(text))) => self.dispatch(text.as_str()),
+                    Some(Ok(Message::Close(_))) | None => return Err(ClientError::Disconnected),
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(e.into()),
+                },
+            }
+        }
+    }
+
+    fn dispatch(&mut self, text: &str) {
+        let response = match serde_json::from_str::<Response>(text) {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Failed to parse a message from the daemon: {}", e);
+                return;
+            }
+        };
+
+        match response {
+            Response::Notification { id, data } => match self.shared.streams.get(&id) {
+                Some(tx) => {
+                    let _ = tx.send(data);
+                }
+                None => debug!("Notification for unknown stream {}", id),
+            },
+            Response::Success { id, data } if self.resume_id.as_ref() == Some(&id) => {
+                self.resume_id = None;
+                if let ResponseData::Resumed { expired, .. } = data {
+                    for stream_id in expired {
+                        debug!("Stream {} expired while disconnected", stream_id);
+                        self.shared.streams.remove(&stream_id);
+                    }
+                }
+            }
+            Response::Error { id, message } if self.resume_id.as_ref() == Some(&id) => {
+                self.resume_id = None;
+                warn!("Failed to resume the streams: {}", message);
+            }
+            Response::Success { id, data } => self.reply(&id, Ok(data)),
+            Response::Error { id, message } => self.reply(&id, Err(ClientError::Command(message))),
+        }
+    }
+
+    fn reply(&mut self, id: &str, result: Result<ResponseData, ClientError>) {
+        self.in_flight.remove(id);
+        let Some((_, tx)) = self.shared.pending.remove(id) else {
+            debug!("Response for unknown command {}", id);
+            return;
+        };
+
+        // Open the stream right away, so that no notification is missed
+        let reply = result.map(|data| {
+            let stream = data
+                .stream_id()
+                .map(|stream_id| self.shared.open_stream(stream_id));
+            (data, stream)
+        });
+        let _ = tx.send(reply);
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Wake up everything that is still waiting on this connection
+        self.shared.pending.clear();
+        self.shared.streams.clear();
+    }
+}
+
+fn keys(keys: Vec<PublicKey>) -> Vec<String> {
+    keys.iter().map(PublicKey::to_string).collect()
+}
+
+macro_rules! expect_response {
+    ($data:expr, $pattern:pat => $value:expr) => {
+        match $data {
+            $pattern => Ok($value),
+            other => Err(ClientError::UnexpectedResponse(Box::new(other))),
+        }
+    };
+}
+
+/// A connection to the rest daemon
+///
+/// The methods mirror the ones of `PortalSDK`. Clones share the same socket, which is closed once
+/// all of them are dropped.
+#[derive(Clone)]
+pub struct PortalClient {
+    shared: Arc<Shared>,
+    commands: mpsc::Sender<(String, String)>,
+}
+
+impl PortalClient {
+    /// Connect and authenticate, failing if the daemon can't be reached or refuses the token
+    pub async fn connect(config: ClientConfig) -> Result<Self, ClientError> {
+        let socket = Connection::open(&config).await?;
+
+        let shared = Arc::new(Shared {
+            config,
+            pending: DashMap::new(),
+            streams: DashMap::new(),
+        });
+        let (tx, rx) = mpsc::channel(64);
+        let connection = Connection {
+            shared: Arc::clone(&shared),
+            commands: rx,
+            in_flight: HashSet::new(),
+            resume_id: None,
+        };
+        tokio::spawn(connection.run(socket));
+
+        Ok(Self {
+            shared,
+            commands: tx,
+        })
+    }
+
+    /// Send a command and wait for its response
+    pub async fn send(
+        &self,
+        cmd: Command,
+    ) -> Result<(ResponseData, Option<NotificationStream>), ClientError> {
+        let id = Uuid::new_v4().to_string();
+        let command = CommandWithId {
+            id: id.clone(),
+            identity: self.shared.config.identity.clone(),
+            cmd,
+        };
+        let json = serde_json::to_string(&command)?;
+
+        let (tx, rx) = oneshot::channel();
+        self.shared.pending.insert(id.clone(), tx);
+        if self.commands.send((id.clone(), json)).await.is_err() {
+            self.shared.pending.remove(&id);
+            return Err(ClientError::Disconnected);
+        }
+
+        match tokio::time::timeout(self.shared.config.request_timeout, rx).await {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(_)) => Err(ClientError::Disconnected),
+            Err(_) => {
+                self.shared.pending.remove(&id);
+                Err(ClientError::Timeout)
+            }
+        }
+    }
+
+    /// Send a command that opens a notification stream
+    async fn send_with_stream(
+        &self,
+        cmd: Command,
+    ) -> Result<(ResponseData, NotificationStream), ClientError> {
+        match self.send(cmd).await? {
+            (data, Some(stream)) => Ok((data, stream)),
+            (data, None) => Err(ClientError::UnexpectedResponse(Box::new(data))),
+        }
+    }
+
+    /// Create a key handshake url, the stream receives the key handshakes of the users
+    pub async fn new_key_handshake_url(
+        &self,
+        static_token: Option<String>,
+        expires_at: Option<Timestamp>,
+    ) -> Result<(String, NotificationStream), ClientError> {
+        let (data, stream) = self
+            .send_with_stream(Command::NewKeyHandshakeUrl(NewKeyHandshakeUrlParams {
+                static_token,
+                expires_at,
+                qr: None,
+            }))
+            .await?;
+
+        let url = expect_response!(data, ResponseData::KeyHandshakeUrl { url, .. } => url)?;
+        Ok((url, stream))
+    }
+
+    pub async fn authenticate_key(
+        &self,
+        main_key: PublicKey,
+        subkeys: Vec<PublicKey>,
+    ) -> Result<AuthResponseData, ClientError> {
+        let (data, _) = self
+            .send(Command::AuthenticateKey(AuthenticateKeyParams {
+                main_key: main_key.to_string(),
+                subkeys: keys(subkeys),
+            }))
+            .await?;
+
+        expect_response!(data, ResponseData::AuthResponse { event } => event)
+    }
+
+    pub async fn request_recurring_payment(
+        &self,
+        main_key: PublicKey,
+        subkeys: Vec<PublicKey>,
+        payment_request: RecurringPaymentRequestContent,
+    ) -> Result<RecurringPaymentResponseContent, ClientError> {
+        let (data, _) = self
+            .send(Command::RequestRecurringPayment(
+                RequestRecurringPaymentParams {
+                    main_key: main_key.to_string(),
+                    subkeys: keys(subkeys),
+                    payment_request,
+                },
+            ))
+            .await?;
+
+        expect_response!(data, ResponseData::RecurringPayment { status } => status)
+    }
+
+    /// Request a payment, the stream receives the status updates of the payment
+    pub async fn request_single_payment(
+        &self,
+        main_key: PublicKey,
+        subkeys: Vec<PublicKey>,
+        payment_request: SinglePaymentParams,
+    ) -> Result<NotificationStream, ClientError> {
+        let (_, stream) = self
+            .send_with_stream(Command::RequestSinglePayment(RequestSinglePaymentParams {
+                main_key: main_key.to_string(),
+                subkeys: keys(subkeys),
+                payment_request,
+            }))
+            .await?;
+
+        Ok(stream)
+    }
+
+    /// Request a payment of an existing invoice, the stream receives the status updates of the
+    /// payment
+    pub async fn request_payment_raw(
+        &self,
+        main_key: PublicKey,
+        subkeys: Vec<PublicKey>,
+        payment_request: SinglePaymentRequestContent,
+    ) -> Result<NotificationStream, ClientError> {
+        let (_, stream) = self
+            .send_with_stream(Command::RequestPaymentRaw(RequestPaymentRawParams {
+                main_key: main_key.to_string(),
+                subkeys: keys(subkeys),
+                payment_request,
+            }))
+            .await?;
+
+        Ok(stream)
+    }
+
+    pub async fn fetch_profile(&self, main_key: PublicKey) -> Result<Option<Profile>, ClientError> {
+        let (data, _) = self
+            .send(Command::FetchProfile(FetchProfileParams {
+                main_key: main_key.to_string(),
+            }))
+            .await?;
+
+        expect_response!(data, ResponseData::ProfileData { profile } => profile)
+    }
+
+    pub async fn set_profile(&self, profile: Profile) -> Result<(), ClientError> {
+        let (data, _) = self
+            .send(Command::SetProfile(SetProfileParams { profile }))
+            .await?;
+
+        expect_response!(data, ResponseData::ProfileData { .. } => ())
+    }
+
+    pub async fn close_recurring_payment(
+        &self,
+        main_key: PublicKey,
+        subkeys: Vec<PublicKey>,
+        subscription_id: String,
+    ) -> Result<(), ClientError> {
+        let (data, _) = self
+            .send(Command::CloseRecurringPayment(
+                CloseRecurringPaymentParams {
+                    main_key: main_key.to_string(),
+                    subkeys: keys(subkeys),
+                    subscription_id,
+                },
+            ))
+            .await?;
+
+        expect_response!(data, ResponseData::CloseRecurringPaymentSuccess { .. } => ())
+    }
+
+    /// The stream receives the recurring payments closed by the users
+    pub async fn listen_closed_recurring_payment(&self) -> Result<NotificationStream, ClientError> {
+        let (_, stream) = self
+            .send_with_stream(Command::ListenClosedRecurringPayment)
+            .await?;
+
+        Ok(stream)
+    }
+
+    /// Request an invoice, returns the invoice and its payment hash
+    pub async fn request_invoice(
+        &self,
+        recipient_key: PublicKey,
+        subkeys: Vec<PublicKey>,
+        content: InvoiceRequestContent,
+    ) -> Result<(String, Option<String>), ClientError> {
+        let (data, _) = self
+            .send(Command::RequestInvoice(RequestInvoiceParams {
+                recipient_key: recipient_key.to_string(),
+                subkeys: keys(subkeys),
+                content,
+            }))
+            .await?;
+
+        expect_response!(
+            data,
+            ResponseData::InvoicePayment { invoice, payment_hash } => (invoice, payment_hash)
+        )
+    }
+
+    pub async fn issue_jwt(
+        &self,
+        target_key: PublicKey,
+        duration_hours: i64,
+    ) -> Result<String, ClientError> {
+        let (data, _) = self
+            .send(Command::IssueJwt(IssueJwtParams {
+                target_key: target_key.to_string(),
+                duration_hours,
+            }))
+            .await?;
+
+        expect_response!(data, ResponseData::IssueJwt { token } => token)
+    }
+
+    /// Verify a token issued by `pubkey`, returns the key it was issued to
+    pub async fn verify_jwt(
+        &self,
+        pubkey: PublicKey,
+        token: String,
+    ) -> Result<String, ClientError> {
+        let (data, _) = self
+            .send(Command::VerifyJwt(VerifyJwtParams {
+                pubkey: pubkey.to_string(),
+                token,
+            }))
+            .await?;
+
+        expect_response!(data, ResponseData::VerifyJwt { target_key } => target_key)
+    }
+
+    pub async fn request_cashu(
+        &self,
+        recipient_key: PublicKey,
+        subkeys: Vec<PublicKey>,
+        mint_url: String,
+        unit: String,
+        amount: u64,
+    ) -> Result<CashuResponseStatus, ClientError> {
+        let (data, _) = self
+            .send(Command::RequestCashu(RequestCashuParams {
+                recipient_key: recipient_key.to_string(),
+                subkeys: keys(subkeys),
+                mint_url,
+                unit,
+                amount,
+            }))
+            .await?;
+
+        expect_response!(data, ResponseData::CashuResponse { status } => status)
+    }
+
+    pub async fn send_cashu_direct(
+        &self,
+        main_key: PublicKey,
+        subkeys: Vec<PublicKey>,
+        token: String,
+    ) -> Result<(), ClientError> {
+        let (data, _) = self
+            .send(Command::SendCashuDirect(SendCashuDirectParams {
+                main_key: main_key.to_string(),
+                subkeys: keys(subkeys),
+                token,
+            }))
+            .await?;
+
+        expect_response!(data, ResponseData::SendCashuDirectSuccess { .. } => ())
+    }
+
+    /// Mint a cashu token, returns the token
+    pub async fn mint_cashu(
+        &self,
+        mint_url: String,
+        static_auth_token: Option<String>,
+        unit: String,
+        amount: u64,
+        description: Option<String>,
+    ) -> Result<String, ClientError> {
+        let (data, _) = self
+            .send(Command::MintCashu(MintCashuParams {
+                mint_url,
+                unit,
+                static_auth_token,
+                amount,
+                description,
+            }))
+            .await?;
+
+        expect_response!(data, ResponseData::CashuMint { token } => token)
+    }
+
+    /// Burn a cashu token, returns the amount it was worth
+    pub async fn burn_cashu(
+        &self,
+        mint_url: String,
+        unit: String,
+        token: String,
+        static_auth_token: Option<String>,
+    ) -> Result<u64, ClientError> {
+        let (data, _) = self
+            .send(Command::BurnCashu(BurnCashuParams {
+                mint_url,
+                unit,
+                static_auth_token,
+                token,
+            }))
+            .await?;
+
+        expect_response!(data, ResponseData::CashuBurn { amount } => amount)
+    }
+
+    pub async fn add_relay(&self, relay: String) -> Result<(), ClientError> {
+        let (data, _) = self
+            .send(Command::AddRelay(AddRelayParams { relay }))
+            .await?;
+
+        expect_response!(data, ResponseData::AddRelay { .. } => ())
+    }
+
+    pub async fn remove_relay(&self, relay: String) -> Result<(), ClientError> {
+        let (data, _) = self
+            .send(Command::RemoveRelay(RemoveRelayParams { relay }))
+            .await?;
+
+        expect_response!(data, ResponseData::RemoveRelay { .. } => ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::net::TcpListener;
+
+    type ServerSocket = WebSocketStream<TcpStream>;
+
+    async fn accept(listener: &TcpListener) -> ServerSocket {
+        let (stream, _) = listener.accept().await.unwrap();
+        tokio_tungstenite::accept_async(stream).await.unwrap()
+    }
+
+    async fn recv_command(socket: &mut ServerSocket) -> CommandWithId {
+        loop {
+            if let Message::Text(text) = socket.next().await.unwrap().unwrap() {
+                return serde_json::from_str(text.as_str()).unwrap();
+            }
+        }
+    }
+
+    async fn reply(socket: &mut ServerSocket, response: Response) {
+        let json = serde_json::to_string(&response).unwrap();
+        socket.send(Message::Text(json.into())).await.unwrap();
+    }
+
+    async fn authenticate(socket: &mut ServerSocket) {
+        let command = recv_command(socket).await;
+        assert!(matches!(&command.cmd, Command::Auth(AuthParams { token }) if token == "token"));
+        reply(
+            socket,
+            Response::Success {
+                id: command.id,
+                data: ResponseData::AuthSuccess {
+                    message: "Authenticated successfully".to_string(),
+                },
+            },
+        )
+        .await;
+    }
+
+    fn closed(subscription_id: &str) -> Response {
+        Response::Notification {
+            id: "stream".to_string(),
+            data: NotificationData::ClosedRecurringPayment {
+                reason: None,
+                subscription_id: subscription_id.to_string(),
+                recipient: "recipient".to_string(),
+                main_key: "main_key".to_string(),
+            },
+        }
+    }
+
+    fn subscription_id(notification: Option<NotificationData>) -> String {
+        match notification {
+            Some(NotificationData::ClosedRecurringPayment {
+                subscription_id, ..
+            }) => subscription_id,
+            other => panic!("Unexpected notification: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_commands_and_resumed_streams() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        let daemon = tokio::spawn(async move {
+            let mut socket = accept(&listener).await;
+            authenticate(&mut socket).await;
+
+            let command = recv_command(&mut socket).await;
+            assert!(matches!(command.cmd, Command::ListenClosedRecurringPayment));
+            reply(
+                &mut socket,
+                Response::Success {
+                    id: command.id,
+                    data: ResponseData::ListenClosedRecurringPayment {
+                        stream_id: "stream".to_string(),
+                    },
+                },
+            )
+            .await;
+            reply(&mut socket, closed("first")).await;
+            socket.close(None).await.unwrap();
+
+            // The client comes back and takes over its stream
+            let mut socket = accept(&listener).await;
+            authenticate(&mut socket).await;
+            let command = recv_command(&mut socket).await;
+            match command.cmd {
+                Command::Resume(ResumeParams { stream_ids }) => assert_eq!(stream_ids, ["stream"]),
+                other => panic!("Unexpected command: {:?}", other),
+            }
+            reply(
+                &mut socket,
+                Response::Success {
+                    id: command.id,
+                    data: ResponseData::Resumed {
+                        resumed: vec!["stream".to_string()],
+                        expired: vec![],
+                    },
+                },
+            )
+            .await;
+            reply(&mut socket, closed("second")).await;
+
+            let command = recv_command(&mut socket).await;
+            reply(
+                &mut socket,
+                Response::Error {
+                    id: command.id,
+                    message: "Unknown relay".to_string(),
+                },
+            )
+            .await;
+            socket
+        });
+
+        let config = ClientConfig::new(url, "token")
+            .with_reconnect_delay(Duration::from_millis(10), Duration::from_millis(100));
+        let client = PortalClient::connect(config).await.unwrap();
+
+        let mut stream = client.listen_closed_recurring_payment().await.unwrap();
+        assert_eq!(stream.id(), "stream");
+        assert_eq!(subscription_id(stream.next().await), "first");
+        assert_eq!(subscription_id(stream.next().await), "second");
+
+        let result = client
+            .remove_relay("wss://relay.example.com".to_string())
+            .await;
+        assert!(matches!(result, Err(ClientError::Command(message)) if message == "Unknown relay"));
+
+        let _socket = daemon.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_refused_token() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let mut socket = accept(&listener).await;
+            let command = recv_command(&mut socket).await;
+            reply(
+                &mut socket,
+                Response::Error {
+                    id: command.id,
+                    message: "Authentication failed".to_string(),
+                },
+            )
+            .await;
+        });
+
+        let result = PortalClient::connect(ClientConfig::new(url, "wrong")).await;
+        assert!(matches!(result, Err(ClientError::Authentication(_))));
+    }
+
+    #[test]
+    fn test_command_format() {
+        let command = CommandWithId {
+            id: "1".to_string(),
+            identity: None,
+            cmd: Command::AddRelay(AddRelayParams {
+                relay: "wss://relay.nostr.net".to_string(),
+            }),
+        };
+
+        assert_eq!(
+            serde_json::to_value(&command).unwrap(),
+            serde_json::json!({
+                "id": "1",
+                "cmd": "AddRelay",
+                "params": { "relay": "wss://relay.nostr.net" },
+            })
+        );
+    }
+}
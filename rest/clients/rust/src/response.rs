@@ -1,10 +1,34 @@
 use portal::profile::Profile;
-use portal::protocol::model::auth::AuthResponseStatus;
+use portal::protocol::model::auth::{AuthResponseStatus, Capability};
 use portal::protocol::model::payment::{CashuResponseStatus, RecurringPaymentResponseContent};
-use serde::Serialize;
+use portal::qr::{QrFormat, QrImage};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct QrCodeData {
+    pub format: QrFormat,
+    /// SVG and terminal output as text, PNG encoded in base64
+    pub data: String,
+}
+
+impl From<QrImage> for QrCodeData {
+    fn from(image: QrImage) -> Self {
+        use base64::Engine;
+
+        let format = image.format();
+        let data = match image {
+            QrImage::Svg(data) | QrImage::Terminal(data) => data,
+            QrImage::Png(data) => base64::engine::general_purpose::STANDARD.encode(data),
+        };
+
+        Self { format, data }
+    }
+}
 
 // Response structs for each API
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(tag = "type")]
 pub enum Response {
     #[serde(rename = "error")]
@@ -17,14 +41,27 @@ pub enum Response {
     Notification { id: String, data: NotificationData },
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(tag = "type")]
 pub enum ResponseData {
     #[serde(rename = "auth_success")]
     AuthSuccess { message: String },
 
+    #[serde(rename = "resumed")]
+    Resumed {
+        resumed: Vec<String>,
+        /// Streams that expired, or that can't be resumed by this client
+        expired: Vec<String>,
+    },
+
     #[serde(rename = "key_handshake_url")]
-    KeyHandshakeUrl { url: String, stream_id: String },
+    KeyHandshakeUrl {
+        url: String,
+        stream_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        qr: Option<QrCodeData>,
+    },
 
     #[serde(rename = "auth_response")]
     AuthResponse { event: AuthResponseData },
@@ -77,7 +114,20 @@ pub enum ResponseData {
     RemoveRelay { relay: String },
 }
 
-#[derive(Debug, Serialize)]
+impl ResponseData {
+    /// The id of the notification stream opened by the command, if any
+    pub fn stream_id(&self) -> Option<&str> {
+        match self {
+            ResponseData::KeyHandshakeUrl { stream_id, .. }
+            | ResponseData::SinglePayment { stream_id }
+            | ResponseData::ListenClosedRecurringPayment { stream_id } => Some(stream_id),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct AuthResponseData {
     pub user_key: String,
     pub recipient: String,
@@ -85,13 +135,17 @@ pub struct AuthResponseData {
     pub status: AuthResponseStatus,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(tag = "type")]
 pub enum NotificationData {
     #[serde(rename = "key_handshake")]
     KeyHandshake {
         main_key: String,
         preferred_relays: Vec<String>,
+        /// Not set if the user's app doesn't advertise its capabilities
+        #[serde(skip_serializing_if = "Option::is_none")]
+        capabilities: Option<Vec<Capability>>,
     },
     #[serde(rename = "payment_status_update")]
     PaymentStatusUpdate { status: InvoiceStatus },
@@ -104,7 +158,8 @@ pub enum NotificationData {
     },
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(tag = "status", rename_all = "snake_case")]
 pub enum InvoiceStatus {
     Paid { preimage: Option<String> },
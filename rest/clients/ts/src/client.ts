@@ -19,6 +19,8 @@ import {
   CashuResponseContent,
   CashuRequestContent,
   CashuResponseStatus,
+  Timestamp,
+  Capability,
 } from './types';
 
 /**
@@ -119,6 +121,7 @@ export class PortalSDK {
     const command = {
       id,
       cmd,
+      ...(this.config.identity && cmd !== 'Auth' ? { identity: this.config.identity } : {}),
       ...(Object.keys(params).length > 0 ? { params } : {})
     };
 
@@ -246,21 +249,45 @@ export class PortalSDK {
     this.reconnectAttempts = 0; // Reset reconnect attempts on successful auth
   }
   
+  /**
+   * Resume the streams of a previous connection, after reconnecting and authenticating again
+   *
+   * Notifications sent while disconnected are replayed to the existing handlers. Returns the ids of
+   * the streams that expired, their handlers are removed.
+   */
+  public async resume(): Promise<string[]> {
+    const streamIds = Array.from(this.activeStreams.keys());
+    if (streamIds.length === 0) {
+      return [];
+    }
+
+    const response = await this.sendCommand('Resume', { stream_ids: streamIds });
+    if (response.type === 'resumed') {
+      for (const streamId of response.expired) {
+        this.activeStreams.delete(streamId);
+      }
+      return response.expired;
+    }
+    throw new Error('Unexpected response type');
+  }
+
   /**
    * Generate a new key handshake URL
+   *
+   * The URL is signed by the service key. If `expiresAt` is set the app refuses it after that time.
    */
-  public async newKeyHandshakeUrl(onKeyHandshake: (mainKey: string, preferredRelays: string[]) => void, staticToken: string | null = null): Promise<string> {
+  public async newKeyHandshakeUrl(onKeyHandshake: (mainKey: string, preferredRelays: string[], capabilities?: Capability[]) => void, staticToken: string | null = null, expiresAt: Timestamp | null = null): Promise<string> {
     const _self = this;
     let streamId = '';
 
     const handler = (data: NotificationData) => {
       if (data.type === 'key_handshake') {
-        onKeyHandshake(data.main_key, data.preferred_relays);
+        onKeyHandshake(data.main_key, data.preferred_relays, data.capabilities);
         _self.activeStreams.delete(streamId);
       }
     };
     
-    const response = await this.sendCommand('NewKeyHandshakeUrl', { static_token: staticToken });
+    const response = await this.sendCommand('NewKeyHandshakeUrl', { static_token: staticToken, expires_at: expiresAt });
     
     if (response.type === 'key_handshake_url') {
       const { url, stream_id } = response;
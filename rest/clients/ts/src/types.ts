@@ -7,6 +7,13 @@ export enum Currency {
   Millisats = "Millisats",
 }
 
+export interface QrParams {
+  format: 'svg' | 'png' | 'terminal';
+  error_correction?: 'low' | 'medium' | 'quartile' | 'high';
+  size?: number;
+  quiet_zone?: boolean;
+}
+
 // Custom Timestamp type that serializes to string
 export class Timestamp {
   private value: bigint;
@@ -145,7 +152,8 @@ export interface JwtClaims {
 // Command/Request types
 export type Command = 
   | { cmd: 'Auth', params: { token: string } }
-  | { cmd: 'NewKeyHandshakeUrl' }
+  | { cmd: 'Resume', params: { stream_ids: string[] } }
+  | { cmd: 'NewKeyHandshakeUrl', params: { static_token: string | null, expires_at?: Timestamp | null, qr?: QrParams | null } }
   | { cmd: 'AuthenticateKey', params: { main_key: string, subkeys: string[] } }
   | { cmd: 'RequestRecurringPayment', params: { main_key: string, subkeys: string[], payment_request: RecurringPaymentRequestContent } }
   | { cmd: 'RequestSinglePayment', params: { main_key: string, subkeys: string[], payment_request: SinglePaymentRequestContent } }
@@ -166,6 +174,7 @@ export type Command =
 // Response types
 export type ResponseData = 
   | { type: 'auth_success', message: string }
+  | { type: 'resumed', resumed: string[], expired: string[] }
   | { type: 'key_handshake_url', url: string, stream_id: string }
   | { type: 'auth_response', event: AuthResponseData }
   | { type: 'recurring_payment', status: RecurringPaymentStatusContent }
@@ -189,9 +198,12 @@ export type Response =
   | { type: 'success', id: string, data: ResponseData }
   | { type: 'notification', id: string, data: NotificationData };
 
+// Requests the user's app advertised during the key handshake
+export type Capability = 'auth' | 'single_payment' | 'recurring_payment' | 'invoice' | 'cashu' | 'unknown';
+
 // Notification data types
 export type NotificationData = 
-  | { type: 'key_handshake', main_key: string, preferred_relays: string[] }
+  | { type: 'key_handshake', main_key: string, preferred_relays: string[], capabilities?: Capability[] }
   | { type: 'payment_status_update', status: InvoiceStatus }
   | { type: 'closed_recurring_payment', reason: string | null, subscription_id: string, main_key: string, recipient: string }
   | { type: 'cashu_request', request: CashuRequestContentWithKey }
@@ -216,6 +228,8 @@ export interface EventCallbacks {
 export interface ClientConfig {
   serverUrl: string;
   connectTimeout?: number;
+  // The identity to run commands as, when the server serves more than one
+  identity?: string;
 }
 
 export interface Event {
@@ -0,0 +1,733 @@
+//! Authentication of clients and authorization of their commands
+//!
+//! Clients authenticate with the main `AUTH_TOKEN`, with the token of an identity, or with a scoped
+//! API key from `API_KEYS_FILE`. API keys can be limited to some commands, to an amount they can
+//! spend in each unit, to a rate of commands and to an expiry date. Every command is recorded in
+//! the audit log.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use cdk::nuts::Token;
+use portal::protocol::model::payment::Currency;
+use portal::protocol::model::Timestamp;
+use portal::router::rate_limit::{RateLimit, TokenBucket};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{error, info};
+
+use crate::command::{Command, CommandWithId};
+use crate::{AppState, Identity};
+
+/// The window of the daily spending limit
+const SPENDING_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid API keys file: {0}")]
+    Config(#[from] serde_json::Error),
+
+    #[error("Invalid API key {0}: {1}")]
+    InvalidKey(String, String),
+}
+
+/// Compare two byte strings without leaking where they differ
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn hash_token(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+/// Whether the command changes the relays shared by every identity of the daemon
+fn is_admin_only(command: &Command) -> bool {
+    matches!(command, Command::AddRelay(_) | Command::RemoveRelay(_))
+}
+
+/// The unit and the amount given away by the command, counted against the spending limits
+///
+/// The unit is `msat` for millisats, the code of the currency for fiat amounts (`EUR`) and the
+/// unit of the mint for Cashu (`sat`, `usd`), so that amounts in different units are never added
+/// together.
+fn spent_amount(command: &Command) -> Option<(String, u64)> {
+    match command {
+        Command::MintCashu(params) => Some((params.unit.clone(), params.amount)),
+        Command::RequestInvoice(params) => {
+            let unit = match &params.content.currency {
+                Currency::Millisats => "msat".to_string(),
+                Currency::Fiat(code) => code.clone(),
+            };
+            Some((unit, params.content.amount))
+        }
+        // Tokens that can't be parsed are refused when the command runs
+        Command::SendCashuDirect(params) => {
+            let token = Token::from_str(&params.token).ok()?;
+            let unit = token.unit()?.to_string();
+            let amount = *token.value().ok()?.as_ref();
+            Some((unit, amount))
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpendingLimit {
+    /// The largest amount of a single command
+    #[serde(default)]
+    pub max_amount: Option<u64>,
+    /// The total amount of the commands of the last 24 hours
+    #[serde(default)]
+    pub daily_amount: Option<u64>,
+}
+
+/// Applies to every command when used with the `*` key of `rate_limits`
+const ALL_COMMANDS: &str = "*";
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct RateLimitConfig {
+    /// How many commands can be sent in a burst
+    pub requests: u32,
+    /// How many seconds it takes to recover the whole burst
+    pub per_seconds: u64,
+}
+
+impl From<RateLimitConfig> for RateLimit {
+    fn from(config: RateLimitConfig) -> Self {
+        RateLimit::new(config.requests, Duration::from_secs(config.per_seconds))
+    }
+}
+
+/// An entry of the file pointed by `API_KEYS_FILE`
+#[derive(Debug, Deserialize)]
+pub struct ApiKeyConfig {
+    pub name: String,
+    /// The hex encoded SHA-256 of the key, so that the file doesn't contain the key itself
+    pub token_sha256: String,
+    /// The identity the key acts as. The main identity if not set.
+    #[serde(default)]
+    pub identity: Option<String>,
+    /// The commands the key can run, such as `RequestSinglePayment`. All of them if not set.
+    #[serde(default)]
+    pub commands: Option<Vec<String>>,
+    /// Limits by unit, such as `msat`, `sat` or `EUR`. Units that are not listed can't be spent
+    /// by a key that has spending limits.
+    #[serde(default)]
+    pub spending_limits: HashMap<String, SpendingLimit>,
+    /// Limits by command name, `*` limits all the commands of the key together
+    #[serde(default)]
+    pub rate_limits: HashMap<String, RateLimitConfig>,
+    #[serde(default)]
+    pub expires_at: Option<Timestamp>,
+}
+
+#[derive(Debug)]
+pub struct ApiKey {
+    pub name: String,
+    pub identity: Option<String>,
+    token_hash: [u8; 32],
+    commands: Option<Vec<String>>,
+    spending_limits: HashMap<String, SpendingLimit>,
+    expires_at: Option<Timestamp>,
+    /// Amounts spent within the spending window, oldest first
+    spent: Mutex<VecDeque<Spending>>,
+    next_spending: AtomicU64,
+    /// One bucket for each entry of `rate_limits`
+    rate_limits: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl ApiKey {
+    pub fn new(config: ApiKeyConfig) -> Result<Self, AuthError> {
+        let token_hash = hex::decode(&config.token_sha256)
+            .ok()
+            .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+            .ok_or_else(|| {
+                AuthError::InvalidKey(
+                    config.name.clone(),
+                    "token_sha256 is not a hex encoded SHA-256".to_string(),
+                )
+            })?;
+
+        Ok(Self {
+            name: config.name,
+            identity: config.identity,
+            token_hash,
+            commands: config.commands,
+            spending_limits: config.spending_limits,
+            expires_at: config.expires_at,
+            spent: Mutex::new(VecDeque::new()),
+            next_spending: AtomicU64::new(0),
+            rate_limits: Mutex::new(
+                config
+                    .rate_limits
+                    .into_iter()
+                    .map(|(command, limit)| (command, TokenBucket::new(limit.into())))
+                    .collect(),
+            ),
+        })
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Timestamp::now())
+    }
+
+    fn matches(&self, token_hash: &[u8; 32]) -> bool {
+        constant_time_eq(&self.token_hash, token_hash)
+    }
+
+    /// Count `command` against the limits of its name and of all the commands
+    fn check_rate_limits(&self, command: &Command) -> Result<(), String> {
+        let mut buckets = self.rate_limits.lock().unwrap();
+        let now = Instant::now();
+
+        for name in [command.name(), ALL_COMMANDS] {
+            if buckets
+                .get_mut(name)
+                .is_some_and(|bucket| !bucket.has_token(now))
+            {
+                return Err(format!("Rate limit of the API key reached for {}", name));
+            }
+        }
+        for name in [command.name(), ALL_COMMANDS] {
+            if let Some(bucket) = buckets.get_mut(name) {
+                bucket.try_acquire(now);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that the key can run `command`, and count its amount against the spending limit
+    ///
+    /// Returns the id of the amount counted, if any, so that it can be refunded if the command
+    /// fails. The spending limit is checked before the rate limits, so that a refused amount
+    /// doesn't use up a rate limit token.
+    fn authorize(&self, command: &Command) -> Result<Option<u64>, String> {
+        if self.is_expired() {
+            return Err("API key expired".to_string());
+        }
+
+        if let Some(commands) = &self.commands {
+            if !commands.iter().any(|c| c == command.name()) {
+                return Err(format!("API key can't run {}", command.name()));
+            }
+        }
+
+        let spending = spent_amount(command).filter(|_| !self.spending_limits.is_empty());
+        let Some((unit, amount)) = spending else {
+            self.check_rate_limits(command)?;
+            return Ok(None);
+        };
+
+        let limit = self
+            .spending_limits
+            .get(&unit)
+            .ok_or_else(|| format!("API key can't spend {}", unit))?;
+        if limit.max_amount.is_some_and(|max| amount > max) {
+            return Err(format!(
+                "Amount {} {} exceeds the limit of the API key",
+                amount, unit
+            ));
+        }
+
+        let mut spent = self.spent.lock().unwrap();
+        while spent
+            .front()
+            .is_some_and(|spending| spending.at.elapsed() > SPENDING_WINDOW)
+        {
+            spent.pop_front();
+        }
+
+        let total = spent
+            .iter()
+            .filter(|spending| spending.unit == unit)
+            .map(|spending| spending.amount)
+            .sum::<u64>();
+        if limit
+            .daily_amount
+            .is_some_and(|daily| total.saturating_add(amount) > daily)
+        {
+            return Err(format!(
+                "Daily spending limit of the API key reached for {}",
+                unit
+            ));
+        }
+
+        self.check_rate_limits(command)?;
+
+        let id = self.next_spending.fetch_add(1, Ordering::Relaxed);
+        spent.push_back(Spending {
+            id,
+            at: Instant::now(),
+            unit,
+            amount,
+        });
+        Ok(Some(id))
+    }
+
+    /// Stop counting the amount `id` against the spending limit
+    fn refund(&self, id: u64) {
+        self.spent
+            .lock()
+            .unwrap()
+            .retain(|spending| spending.id != id);
+    }
+}
+
+/// An amount counted against the spending limit of an API key
+#[derive(Debug)]
+struct Spending {
+    id: u64,
+    at: Instant,
+    unit: String,
+    amount: u64,
+}
+
+/// The amount an authorized command counts against the spending limit of its API key
+#[derive(Debug)]
+pub struct Charge {
+    key: Arc<ApiKey>,
+    id: u64,
+}
+
+impl Charge {
+    /// Give the amount back, for commands that failed
+    pub fn refund(self) {
+        self.key.refund(self.id);
+    }
+}
+
+pub fn load_api_keys<P: AsRef<Path>>(path: P) -> Result<Vec<Arc<ApiKey>>, AuthError> {
+    let configs: Vec<ApiKeyConfig> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    configs
+        .into_iter()
+        .map(|config| ApiKey::new(config).map(Arc::new))
+        .collect()
+}
+
+/// Who the client authenticated as
+#[derive(Debug, Clone)]
+pub enum Session {
+    Unauthenticated,
+    /// Authenticated with the main token, can act as any identity
+    Admin,
+    /// Authenticated with the token of an identity, can only act as that identity
+    Identity(String),
+    /// Authenticated with a scoped API key
+    ApiKey(Arc<ApiKey>),
+}
+
+impl Session {
+    /// Find the session granted by `token`, if any
+    pub(crate) fn authenticate(state: &AppState, token: &str) -> Option<Session> {
+        let token_hash = hash_token(token);
+
+        if let Some(auth_token) = &state.auth_token {
+            if constant_time_eq(&hash_token(auth_token), &token_hash) {
+                return Some(Session::Admin);
+            }
+        }
+
+        let identity = state.identities.iter().find(|(_, identity)| {
+            identity
+                .auth_token
+                .as_ref()
+                .is_some_and(|t| constant_time_eq(&hash_token(t), &token_hash))
+        });
+        if let Some((name, _)) = identity {
+            return Some(Session::Identity(name.clone()));
+        }
+
+        state
+            .api_keys
+            .iter()
+            .find(|key| key.matches(&token_hash) && !key.is_expired())
+            .map(|key| Session::ApiKey(key.clone()))
+    }
+
+    /// The name recorded in the audit log
+    pub fn client_name(&self) -> String {
+        match self {
+            Session::Unauthenticated => "unauthenticated".to_string(),
+            Session::Admin => "admin".to_string(),
+            Session::Identity(name) => format!("identity:{}", name),
+            Session::ApiKey(key) => format!("key:{}", key.name),
+        }
+    }
+
+    /// Whether this session can act on behalf of `other`
+    pub fn can_act_as(&self, other: &Session) -> bool {
+        match (self, other) {
+            (Session::Admin, _) => true,
+            (Session::Identity(own), Session::Identity(other)) => own == other,
+            (Session::ApiKey(own), Session::ApiKey(other)) => own.name == other.name,
+            _ => false,
+        }
+    }
+
+    /// Find the identity a command runs as. `None` means the main identity.
+    pub(crate) fn resolve<'a>(
+        &self,
+        state: &'a AppState,
+        requested: Option<&str>,
+    ) -> Result<Option<&'a Identity>, String> {
+        let own = match self {
+            Session::Identity(own) => Some(Some(own.as_str())),
+            Session::ApiKey(key) => Some(key.identity.as_deref()),
+            _ => None,
+        };
+
+        let name = match (own, requested) {
+            (Some(own), Some(requested)) if own != Some(requested) => {
+                return Err(format!("Not allowed to act as identity {}", requested));
+            }
+            (Some(Some(own)), _) => own,
+            (Some(None), _) | (None, None) => return Ok(None),
+            (None, Some(requested)) => requested,
+        };
+
+        state
+            .identities
+            .get(name)
+            .map(Some)
+            .ok_or_else(|| format!("Unknown identity: {}", name))
+    }
+
+    /// The name of the identity a command runs as, `None` for the main identity
+    pub fn identity_name(&self, requested: Option<&str>) -> Option<String> {
+        match self {
+            Session::Identity(own) => Some(own.clone()),
+            Session::ApiKey(key) => key.identity.clone(),
+            _ => requested.map(str::to_string),
+        }
+    }
+
+    /// Check that the session can run `command`, and charge its amount to the API key
+    pub fn authorize(&self, command: &Command) -> Result<Option<Charge>, String> {
+        match self {
+            Session::Unauthenticated => Err("Not authenticated".to_string()),
+            Session::Identity(_) if is_admin_only(command) => {
+                Err(format!("Only admin sessions can run {}", command.name()))
+            }
+            Session::ApiKey(key) if key.identity.is_some() && is_admin_only(command) => {
+                Err(format!("Only admin sessions can run {}", command.name()))
+            }
+            Session::ApiKey(key) => Ok(key.authorize(command)?.map(|id| Charge {
+                key: key.clone(),
+                id,
+            })),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    Websocket,
+    Http,
+}
+
+#[derive(Debug, Serialize)]
+struct AuditEntry<'a> {
+    timestamp: Timestamp,
+    client: String,
+    transport: Transport,
+    request_id: &'a str,
+    command: &'static str,
+    identity: Option<&'a str>,
+    allowed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'a str>,
+}
+
+/// A record of the commands run by each client
+///
+/// Entries are always logged with the `audit` target, and appended to a file as JSON lines when
+/// `AUDIT_LOG` is set.
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+}
+
+impl AuditLog {
+    pub fn new<P: AsRef<Path>>(path: Option<P>) -> Result<Self, AuthError> {
+        let file = match path {
+            Some(path) => Some(Mutex::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+            None => None,
+        };
+
+        Ok(Self { file })
+    }
+
+    fn record(&self, entry: &AuditEntry) {
+        info!(
+            target: "audit",
+            "{} ran {} ({}) over {:?}: {}",
+            entry.client,
+            entry.command,
+            entry.request_id,
+            entry.transport,
+            if entry.allowed { "allowed" } else { "denied" }
+        );
+
+        let Some(file) = &self.file else {
+            return;
+        };
+
+        let result = serde_json::to_string(entry)
+            .map_err(std::io::Error::from)
+            .and_then(|line| {
+                let mut file = file.lock().unwrap();
+                writeln!(file, "{}", line)?;
+                file.flush()
+            });
+        if let Err(e) = result {
+            error!("Failed to write the audit log: {}", e);
+        }
+    }
+}
+
+/// Check that `session` can run `command`, recording the outcome in the audit log
+///
+/// The returned charge should be refunded if the command fails.
+pub(crate) fn authorize(
+    state: &AppState,
+    session: &Session,
+    command: &CommandWithId,
+    transport: Transport,
+) -> Result<Option<Charge>, String> {
+    let result = session.authorize(&command.cmd);
+    let identity = session.identity_name(command.identity.as_deref());
+
+    state.audit_log.record(&AuditEntry {
+        timestamp: Timestamp::now(),
+        client: session.client_name(),
+        transport,
+        request_id: &command.id,
+        command: command.cmd.name(),
+        identity: identity.as_deref(),
+        allowed: result.is_ok(),
+        reason: result.as_ref().err().map(String::as_str),
+    });
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::command::{AddRelayParams, MintCashuParams};
+
+    fn api_key(
+        commands: Option<Vec<String>>,
+        spending_limits: HashMap<String, SpendingLimit>,
+    ) -> ApiKey {
+        ApiKey::new(ApiKeyConfig {
+            name: "test".to_string(),
+            token_sha256: hex::encode(hash_token("token")),
+            identity: None,
+            commands,
+            spending_limits,
+            rate_limits: HashMap::new(),
+            expires_at: None,
+        })
+        .unwrap()
+    }
+
+    fn mint(amount: u64) -> Command {
+        mint_in("sat", amount)
+    }
+
+    fn mint_in(unit: &str, amount: u64) -> Command {
+        Command::MintCashu(MintCashuParams {
+            mint_url: "https://mint.example.com".to_string(),
+            unit: unit.to_string(),
+            static_auth_token: None,
+            amount,
+            description: None,
+        })
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"token2"));
+    }
+
+    #[test]
+    fn test_allowed_commands() {
+        let key = api_key(Some(vec!["MintCashu".to_string()]), HashMap::new());
+        assert!(key.matches(&hash_token("token")));
+
+        assert!(key.authorize(&mint(10)).is_ok());
+        assert!(key
+            .authorize(&Command::AddRelay(AddRelayParams {
+                relay: "wss://relay.nostr.net".to_string(),
+            }))
+            .is_err());
+    }
+
+    #[test]
+    fn test_spending_limit() {
+        let key = api_key(
+            None,
+            HashMap::from([
+                (
+                    "sat".to_string(),
+                    SpendingLimit {
+                        max_amount: Some(100),
+                        daily_amount: Some(150),
+                    },
+                ),
+                (
+                    "msat".to_string(),
+                    SpendingLimit {
+                        max_amount: None,
+                        daily_amount: Some(1000),
+                    },
+                ),
+            ]),
+        );
+
+        assert!(key.authorize(&mint(101)).is_err());
+        assert!(key.authorize(&mint(100)).is_ok());
+        assert!(key.authorize(&mint(50)).is_ok());
+        assert!(key.authorize(&mint(1)).is_err());
+
+        // Each unit has its own limit, and units without a limit can't be spent
+        assert!(key.authorize(&mint_in("msat", 1000)).is_ok());
+        assert!(key.authorize(&mint_in("usd", 1)).is_err());
+    }
+
+    #[test]
+    fn test_refund() {
+        let key = Arc::new(api_key(
+            None,
+            HashMap::from([(
+                "sat".to_string(),
+                SpendingLimit {
+                    max_amount: None,
+                    daily_amount: Some(100),
+                },
+            )]),
+        ));
+        let session = Session::ApiKey(key.clone());
+
+        let charge = session.authorize(&mint(100)).unwrap().unwrap();
+        assert!(session.authorize(&mint(1)).is_err());
+
+        // The command failed, its amount can be spent again
+        charge.refund();
+        assert!(session.authorize(&mint(100)).is_ok());
+    }
+
+    #[test]
+    fn test_spending_limit_before_rate_limit() {
+        let key = ApiKey::new(ApiKeyConfig {
+            name: "test".to_string(),
+            token_sha256: hex::encode(hash_token("token")),
+            identity: None,
+            commands: None,
+            spending_limits: HashMap::from([(
+                "sat".to_string(),
+                SpendingLimit {
+                    max_amount: Some(10),
+                    daily_amount: None,
+                },
+            )]),
+            rate_limits: HashMap::from([(
+                "MintCashu".to_string(),
+                RateLimitConfig {
+                    requests: 1,
+                    per_seconds: 60,
+                },
+            )]),
+            expires_at: None,
+        })
+        .unwrap();
+
+        // The refused amount didn't use up the only token
+        assert!(key.authorize(&mint(11)).is_err());
+        assert!(key.authorize(&mint(10)).is_ok());
+    }
+
+    #[test]
+    fn test_rate_limits() {
+        let limit = |requests| RateLimitConfig {
+            requests,
+            per_seconds: 60,
+        };
+        let key = ApiKey::new(ApiKeyConfig {
+            name: "test".to_string(),
+            token_sha256: hex::encode(hash_token("token")),
+            identity: None,
+            commands: None,
+            spending_limits: HashMap::new(),
+            rate_limits: HashMap::from([
+                ("MintCashu".to_string(), limit(2)),
+                (ALL_COMMANDS.to_string(), limit(3)),
+            ]),
+            expires_at: None,
+        })
+        .unwrap();
+
+        let add_relay = Command::AddRelay(AddRelayParams {
+            relay: "wss://relay.nostr.net".to_string(),
+        });
+
+        assert!(key.authorize(&mint(1)).is_ok());
+        assert!(key.authorize(&mint(1)).is_ok());
+        assert!(key.authorize(&mint(1)).is_err());
+
+        // The refused command didn't count against the limit of all the commands
+        assert!(key.authorize(&add_relay).is_ok());
+        assert!(key.authorize(&add_relay).is_err());
+    }
+
+    #[test]
+    fn test_expired() {
+        let mut key = api_key(None, HashMap::new());
+        key.expires_at = Some(Timestamp::new(1));
+        assert!(key.is_expired());
+        assert!(key.authorize(&mint(1)).is_err());
+    }
+
+    #[test]
+    fn test_admin_only_commands() {
+        let add_relay = Command::AddRelay(AddRelayParams {
+            relay: "wss://relay.nostr.net".to_string(),
+        });
+
+        assert!(Session::Admin.authorize(&add_relay).is_ok());
+        assert!(Session::Identity("shop".to_string())
+            .authorize(&add_relay)
+            .is_err());
+
+        let mut key = api_key(None, HashMap::new());
+        key.identity = Some("shop".to_string());
+        assert!(Session::ApiKey(Arc::new(key))
+            .authorize(&add_relay)
+            .is_err());
+        assert!(Session::ApiKey(Arc::new(api_key(None, HashMap::new())))
+            .authorize(&add_relay)
+            .is_ok());
+    }
+}
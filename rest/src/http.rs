@@ -0,0 +1,728 @@
+//! HTTP endpoints for the commands
+//!
+//! Every command accepted on the websocket, except `Auth`, is also exposed as
+//! `POST /api/v1/<command>`, with the params of the command as the JSON body. The command runs in the
+//! background as an operation that can be polled with `GET /api/v1/operations/:id`. Operations keep
+//! collecting the notifications of the streams opened by their command until they are cancelled
+//! with `DELETE /api/v1/operations/:id`, or until they expire.
+//!
+//! The recent webhook deliveries of an identity are listed by `GET /api/v1/webhooks/deliveries`.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use dashmap::DashMap;
+use portal::profile::Profile;
+use portal::protocol::model::auth::{AuthResponseStatus, Capability};
+use portal::protocol::model::payment::{
+    CashuResponseStatus, Currency, ExchangeRate, InvoiceRequestContent, RecurrenceInfo,
+    RecurringPaymentRequestContent, RecurringPaymentResponseContent, RecurringPaymentStatus,
+    SinglePaymentRequestContent,
+};
+use portal::protocol::model::Timestamp;
+use sdk::qr::{QrErrorCorrection, QrFormat, QrOptions};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use utoipa::openapi::path::{OperationBuilder, ParameterBuilder, ParameterIn, PathItemType};
+use utoipa::openapi::request_body::RequestBodyBuilder;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{
+    ContentBuilder, ObjectBuilder, PathItem, Ref, Required, ResponseBuilder, SchemaType,
+};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::auth::{self, Transport};
+use crate::command::*;
+use crate::response::*;
+use crate::webhook::{Delivery, DeliveryStatus};
+use crate::ws::{self, ActiveStreams, SocketContext};
+use crate::{AppState, ErrorResponse, Session};
+
+/// The header used to pick the identity a command runs as
+pub const IDENTITY_HEADER: &str = "x-portal-identity";
+
+/// How long operations are kept after they are created
+const OPERATION_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// The most notifications kept by an operation, older ones are dropped first
+const MAX_OPERATION_NOTIFICATIONS: usize = 1000;
+
+/// The longest a request can wait for an operation to finish, in seconds
+const MAX_WAIT_SECS: u64 = 60;
+
+/// The commands exposed over HTTP, with the name of the schema of their params
+///
+/// Every variant of [`Command`] but `Auth` and `Resume` must be listed, which the tests check.
+const COMMANDS: &[(&str, Option<&str>)] = &[
+    ("NewKeyHandshakeUrl", Some("NewKeyHandshakeUrlParams")),
+    ("AuthenticateKey", Some("AuthenticateKeyParams")),
+    (
+        "RequestRecurringPayment",
+        Some("RequestRecurringPaymentParams"),
+    ),
+    ("RequestSinglePayment", Some("RequestSinglePaymentParams")),
+    ("RequestPaymentRaw", Some("RequestPaymentRawParams")),
+    ("FetchProfile", Some("FetchProfileParams")),
+    ("SetProfile", Some("SetProfileParams")),
+    ("CloseRecurringPayment", Some("CloseRecurringPaymentParams")),
+    ("ListenClosedRecurringPayment", None),
+    ("RequestInvoice", Some("RequestInvoiceParams")),
+    ("IssueJwt", Some("IssueJwtParams")),
+    ("VerifyJwt", Some("VerifyJwtParams")),
+    ("RequestCashu", Some("RequestCashuParams")),
+    ("SendCashuDirect", Some("SendCashuDirectParams")),
+    ("MintCashu", Some("MintCashuParams")),
+    ("BurnCashu", Some("BurnCashuParams")),
+    ("AddRelay", Some("AddRelayParams")),
+    ("RemoveRelay", Some("RemoveRelayParams")),
+];
+
+pub(crate) fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/api/v1/operations/:id",
+            get(get_operation).delete(cancel_operation),
+        )
+        .route("/api/v1/webhooks/deliveries", get(list_deliveries))
+        .route("/api/v1/:command", post(run_command))
+}
+
+pub(crate) async fn openapi_spec() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationStatus {
+    Pending,
+    Completed,
+    Failed,
+}
+
+/// A command started through the HTTP endpoints
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Operation {
+    pub id: String,
+    /// The command, as it appears in the path
+    pub command: String,
+    pub status: OperationStatus,
+    /// Set once the command completed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<ResponseData>)]
+    pub result: Option<Value>,
+    /// Set if the command failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Notifications of the streams opened by the command, oldest first
+    #[schema(value_type = Vec<NotificationData>)]
+    pub notifications: VecDeque<Value>,
+    /// How many of the oldest notifications were dropped to keep the most recent ones
+    pub dropped_notifications: u64,
+    pub created_at: Timestamp,
+    pub updated_at: Timestamp,
+}
+
+impl Operation {
+    fn new(id: String, command: String) -> Self {
+        let now = Timestamp::now();
+        Self {
+            id,
+            command,
+            status: OperationStatus::Pending,
+            result: None,
+            error: None,
+            notifications: VecDeque::new(),
+            dropped_notifications: 0,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn apply(&mut self, response: Response) {
+        match response {
+            Response::Success { data, .. } => {
+                self.status = OperationStatus::Completed;
+                self.result = serde_json::to_value(data).ok();
+            }
+            Response::Error { message, .. } => {
+                self.status = OperationStatus::Failed;
+                self.error = Some(message);
+            }
+            Response::Notification { data, .. } => {
+                if let Ok(data) = serde_json::to_value(data) {
+                    if self.notifications.len() >= MAX_OPERATION_NOTIFICATIONS {
+                        self.notifications.pop_front();
+                        self.dropped_notifications += 1;
+                    }
+                    self.notifications.push_back(data);
+                }
+            }
+        }
+        self.updated_at = Timestamp::now();
+    }
+}
+
+struct OperationEntry {
+    operation: Arc<watch::Sender<Operation>>,
+    /// The session that started the operation
+    owner: Session,
+    task: JoinHandle<()>,
+    streams: Arc<ActiveStreams>,
+    created: Instant,
+}
+
+impl OperationEntry {
+    fn visible_to(&self, session: &Session) -> bool {
+        session.can_act_as(&self.owner)
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.created) > OPERATION_TTL
+    }
+
+    fn abort(&self) {
+        self.task.abort();
+        self.streams.abort_all();
+    }
+}
+
+/// The operations started through the HTTP endpoints, by id
+pub(crate) struct Operations {
+    entries: DashMap<String, OperationEntry>,
+}
+
+impl Operations {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: DashMap::new(),
+        }
+    }
+
+    /// Stop and forget the operations that outlived [`OPERATION_TTL`]
+    fn remove_expired(&self, now: Instant) {
+        self.entries.retain(|_, entry| {
+            let expired = entry.is_expired(now);
+            if expired {
+                entry.abort();
+            }
+            !expired
+        });
+    }
+
+    fn insert(&self, id: String, entry: OperationEntry) {
+        self.remove_expired(Instant::now());
+        self.entries.insert(id, entry);
+    }
+
+    fn subscribe(&self, id: &str, session: &Session) -> Option<watch::Receiver<Operation>> {
+        self.remove_expired(Instant::now());
+        self.entries
+            .get(id)
+            .filter(|entry| entry.visible_to(session))
+            .map(|entry| entry.operation.subscribe())
+    }
+
+    fn cancel(&self, id: &str, session: &Session) -> bool {
+        match self
+            .entries
+            .remove_if(id, |_, entry| entry.visible_to(session))
+        {
+            Some((_, entry)) => {
+                entry.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WaitParams {
+    /// Wait up to this many seconds for the operation to finish before replying
+    wait: Option<u64>,
+}
+
+type HttpError = (StatusCode, Json<ErrorResponse>);
+
+fn http_error(status: StatusCode, error: impl ToString) -> HttpError {
+    (
+        status,
+        Json(ErrorResponse {
+            error: error.to_string(),
+        }),
+    )
+}
+
+fn kebab_case(name: &str) -> String {
+    let mut result = String::new();
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            if !result.is_empty() {
+                result.push('-');
+            }
+            result.push(c.to_ascii_lowercase());
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Build the command named `name` in the path from the body of the request
+fn parse_command(name: &str, body: &[u8]) -> Result<Command, HttpError> {
+    let (variant, params) = COMMANDS
+        .iter()
+        .find(|(variant, _)| kebab_case(variant) == name)
+        .ok_or_else(|| http_error(StatusCode::NOT_FOUND, format!("Unknown command: {}", name)))?;
+
+    let command = match params {
+        Some(_) => {
+            let params: Value = if body.is_empty() {
+                json!({})
+            } else {
+                serde_json::from_slice(body).map_err(|e| {
+                    http_error(StatusCode::BAD_REQUEST, format!("Invalid body: {}", e))
+                })?
+            };
+            json!({ "cmd": variant, "params": params })
+        }
+        None => json!({ "cmd": variant }),
+    };
+
+    serde_json::from_value(command)
+        .map_err(|e| http_error(StatusCode::BAD_REQUEST, format!("Invalid params: {}", e)))
+}
+
+/// Reply with the operation, once it finished or after waiting for up to `wait` seconds
+async fn reply(
+    mut operation: watch::Receiver<Operation>,
+    wait: Option<u64>,
+) -> (StatusCode, Json<Operation>) {
+    if let Some(wait) = wait {
+        let wait = Duration::from_secs(wait.min(MAX_WAIT_SECS));
+        let _ = tokio::time::timeout(
+            wait,
+            operation.wait_for(|operation| operation.status != OperationStatus::Pending),
+        )
+        .await;
+    }
+
+    let operation = operation.borrow().clone();
+    let status = match operation.status {
+        OperationStatus::Pending => StatusCode::ACCEPTED,
+        _ => StatusCode::OK,
+    };
+    (status, Json(operation))
+}
+
+/// The identity picked with the [`IDENTITY_HEADER`], if any
+fn requested_identity(headers: &HeaderMap) -> Result<Option<&str>, HttpError> {
+    match headers.get(IDENTITY_HEADER) {
+        Some(value) => value
+            .to_str()
+            .map(Some)
+            .map_err(|_| http_error(StatusCode::BAD_REQUEST, "Invalid identity header")),
+        None => Ok(None),
+    }
+}
+
+async fn run_command(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    Path(name): Path<String>,
+    Query(WaitParams { wait }): Query<WaitParams>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, Json<Operation>), HttpError> {
+    let cmd = parse_command(&name, &body)?;
+
+    let requested = requested_identity(&headers)?;
+    let identity = session
+        .resolve(&state, requested)
+        .map_err(|e| http_error(StatusCode::FORBIDDEN, e))?;
+
+    let id = Uuid::new_v4().to_string();
+    let command = CommandWithId {
+        id: id.clone(),
+        identity: session.identity_name(requested),
+        cmd,
+    };
+    let charge = auth::authorize(&state, &session, &command, Transport::Http)
+        .map_err(|e| http_error(StatusCode::FORBIDDEN, e))?;
+
+    let (tx_message, rx_message) = mpsc::channel(32);
+    let (tx_notification, rx_notification) = mpsc::channel(32);
+    let ctx = SocketContext::new(&state, tx_message, tx_notification);
+    let ctx = match identity {
+        Some(identity) => ctx.with_identity(identity),
+        None => ctx,
+    };
+    let streams = ctx.active_streams.clone();
+
+    let (operation, rx_operation) = watch::channel(Operation::new(id.clone(), name));
+    let operation = Arc::new(operation);
+
+    tokio::spawn(record(operation.clone(), rx_message, rx_notification));
+    let task = tokio::spawn(ws::handle_command(command, Arc::new(ctx), charge));
+
+    state.operations.insert(
+        id,
+        OperationEntry {
+            operation,
+            owner: session,
+            task,
+            streams,
+            created: Instant::now(),
+        },
+    );
+
+    Ok(reply(rx_operation, wait).await)
+}
+
+/// Update the operation with the responses and notifications of its command
+async fn record(
+    operation: Arc<watch::Sender<Operation>>,
+    mut rx_message: mpsc::Receiver<Response>,
+    mut rx_notification: mpsc::Receiver<Response>,
+) {
+    loop {
+        let response = tokio::select! {
+            Some(response) = rx_message.recv() => response,
+            Some(notification) = rx_notification.recv() => notification,
+            else => break,
+        };
+
+        operation.send_modify(|operation| operation.apply(response));
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/operations/{id}",
+    tag = "operations",
+    params(
+        ("id" = String, Path, description = "The id of the operation"),
+        WaitParams,
+    ),
+    responses(
+        (status = 200, description = "The command finished", body = Operation),
+        (status = 202, description = "The command is still running", body = Operation),
+        (status = 404, description = "Unknown operation", body = ErrorResponse),
+    ),
+)]
+async fn get_operation(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    Path(id): Path<String>,
+    Query(WaitParams { wait }): Query<WaitParams>,
+) -> Result<(StatusCode, Json<Operation>), HttpError> {
+    let operation = state
+        .operations
+        .subscribe(&id, &session)
+        .ok_or_else(|| http_error(StatusCode::NOT_FOUND, "Unknown operation"))?;
+
+    Ok(reply(operation, wait).await)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/operations/{id}",
+    tag = "operations",
+    params(("id" = String, Path, description = "The id of the operation")),
+    responses(
+        (status = 204, description = "The operation and its streams were stopped"),
+        (status = 404, description = "Unknown operation", body = ErrorResponse),
+    ),
+)]
+async fn cancel_operation(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, HttpError> {
+    if state.operations.cancel(&id, &session) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(http_error(StatusCode::NOT_FOUND, "Unknown operation"))
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/webhooks/deliveries",
+    tag = "webhooks",
+    params(
+        ("x-portal-identity" = Option<String>, Header, description = "The identity whose deliveries are listed"),
+    ),
+    responses(
+        (status = 200, description = "The pending and recent deliveries, most recently updated first", body = [Delivery]),
+        (status = 403, description = "Not allowed to act as the identity", body = ErrorResponse),
+    ),
+)]
+async fn list_deliveries(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<Delivery>>, HttpError> {
+    let requested = requested_identity(&headers)?;
+    session
+        .resolve(&state, requested)
+        .map_err(|e| http_error(StatusCode::FORBIDDEN, e))?;
+
+    let identity = session.identity_name(requested);
+    Ok(Json(state.webhooks.deliveries(identity.as_deref())))
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Portal REST API",
+        description = "Run Portal commands over HTTP. Commands run as operations that can be polled until they finish."
+    ),
+    paths(get_operation, cancel_operation, list_deliveries),
+    components(schemas(
+        Operation,
+        OperationStatus,
+        Delivery,
+        DeliveryStatus,
+        ErrorResponse,
+        ResponseData,
+        NotificationData,
+        AuthResponseData,
+        InvoiceStatus,
+        QrCodeData,
+        QrParams,
+        SinglePaymentParams,
+        NewKeyHandshakeUrlParams,
+        AuthenticateKeyParams,
+        RequestRecurringPaymentParams,
+        RequestSinglePaymentParams,
+        RequestPaymentRawParams,
+        FetchProfileParams,
+        SetProfileParams,
+        CloseRecurringPaymentParams,
+        RequestInvoiceParams,
+        IssueJwtParams,
+        VerifyJwtParams,
+        RequestCashuParams,
+        SendCashuDirectParams,
+        MintCashuParams,
+        BurnCashuParams,
+        AddRelayParams,
+        RemoveRelayParams,
+        Profile,
+        Capability,
+        AuthResponseStatus,
+        Currency,
+        Timestamp,
+        ExchangeRate,
+        RecurrenceInfo,
+        RecurringPaymentRequestContent,
+        RecurringPaymentResponseContent,
+        RecurringPaymentStatus,
+        SinglePaymentRequestContent,
+        InvoiceRequestContent,
+        CashuResponseStatus,
+        QrFormat,
+        QrOptions,
+        QrErrorCorrection,
+    )),
+    modifiers(&CommandPaths, &BearerAuth),
+    security(("bearer" = [])),
+)]
+struct ApiDoc;
+
+/// Adds a `POST` path for each of the [`COMMANDS`]
+struct CommandPaths;
+
+fn json_response(description: &str, schema: &str) -> utoipa::openapi::Response {
+    ResponseBuilder::new()
+        .description(description)
+        .content(
+            "application/json",
+            ContentBuilder::new()
+                .schema(Ref::from_schema_name(schema))
+                .build(),
+        )
+        .build()
+}
+
+impl Modify for CommandPaths {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for (variant, params) in COMMANDS {
+            let name = kebab_case(variant);
+
+            let mut operation = OperationBuilder::new()
+                .operation_id(Some(name.clone()))
+                .summary(Some(format!("Run the {} command", variant)))
+                .tag("commands")
+                .parameter(
+                    ParameterBuilder::new()
+                        .name(IDENTITY_HEADER)
+                        .parameter_in(ParameterIn::Header)
+                        .required(Required::False)
+                        .description(Some("The identity to run the command as"))
+                        .schema(Some(ObjectBuilder::new().schema_type(SchemaType::String))),
+                )
+                .response("200", json_response("The command finished", "Operation"))
+                .response(
+                    "202",
+                    json_response("The command is still running", "Operation"),
+                )
+                .response(
+                    "400",
+                    json_response("The params are invalid", "ErrorResponse"),
+                )
+                .response(
+                    "403",
+                    json_response(
+                        "Not allowed to run the command or to act as the identity",
+                        "ErrorResponse",
+                    ),
+                );
+            for parameter in WaitParams::into_params(|| Some(ParameterIn::Query)) {
+                operation = operation.parameter(parameter);
+            }
+            if let Some(params) = params {
+                operation = operation.request_body(Some(
+                    RequestBodyBuilder::new()
+                        .content(
+                            "application/json",
+                            ContentBuilder::new()
+                                .schema(Ref::from_schema_name(*params))
+                                .build(),
+                        )
+                        .required(Some(Required::True))
+                        .build(),
+                ));
+            }
+
+            openapi.paths.paths.insert(
+                format!("/api/v1/{}", name),
+                PathItem::new(PathItemType::Post, operation.build()),
+            );
+        }
+    }
+}
+
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kebab_case() {
+        assert_eq!(kebab_case("NewKeyHandshakeUrl"), "new-key-handshake-url");
+        assert_eq!(kebab_case("IssueJwt"), "issue-jwt");
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let command = parse_command("add-relay", br#"{"relay": "wss://relay.nostr.net"}"#).unwrap();
+        assert!(
+            matches!(command, Command::AddRelay(AddRelayParams { relay }) if relay == "wss://relay.nostr.net")
+        );
+
+        let command = parse_command("listen-closed-recurring-payment", b"").unwrap();
+        assert!(matches!(command, Command::ListenClosedRecurringPayment));
+
+        let (status, _) = parse_command("auth", br#"{"token": "token"}"#).unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = parse_command("add-relay", b"").unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_operation_keeps_latest_notifications() {
+        let mut operation = Operation::new("id".to_string(), "command".to_string());
+        for i in 0..MAX_OPERATION_NOTIFICATIONS + 5 {
+            operation.apply(Response::Notification {
+                id: "stream".to_string(),
+                data: NotificationData::PaymentStatusUpdate {
+                    status: InvoiceStatus::Error {
+                        reason: i.to_string(),
+                    },
+                },
+            });
+        }
+
+        assert_eq!(operation.notifications.len(), MAX_OPERATION_NOTIFICATIONS);
+        assert_eq!(operation.dropped_notifications, 5);
+        assert_eq!(operation.notifications[0]["status"]["reason"], json!("5"));
+    }
+
+    #[tokio::test]
+    async fn test_expired_operations_are_removed() {
+        let operations = Operations::new();
+        let (operation, _) =
+            watch::channel(Operation::new("id".to_string(), "command".to_string()));
+        let task = tokio::spawn(std::future::pending());
+        operations.insert(
+            "id".to_string(),
+            OperationEntry {
+                operation: Arc::new(operation),
+                owner: Session::Admin,
+                task,
+                streams: Arc::new(ActiveStreams::new()),
+                created: Instant::now(),
+            },
+        );
+        assert!(operations.subscribe("id", &Session::Admin).is_some());
+
+        operations.remove_expired(Instant::now() + OPERATION_TTL + Duration::from_secs(1));
+        assert!(operations.subscribe("id", &Session::Admin).is_none());
+    }
+
+    #[test]
+    fn test_openapi_has_command_paths() {
+        let spec = ApiDoc::openapi();
+        for (variant, _) in COMMANDS {
+            let path = format!("/api/v1/{}", kebab_case(variant));
+            assert!(spec.paths.paths.contains_key(&path), "Missing {}", path);
+        }
+        assert!(spec.paths.paths.contains_key("/api/v1/operations/{id}"));
+    }
+
+    #[test]
+    fn test_commands_cover_every_variant() {
+        // serde lists all the variants of `Command` when it meets an unknown one
+        let error = serde_json::from_value::<Command>(json!({ "cmd": "Unknown" }))
+            .unwrap_err()
+            .to_string();
+        let (_, variants) = error.split_once("expected one of ").unwrap();
+
+        for variant in variants.split(", ").map(|v| v.trim_matches('`')) {
+            let exposed = COMMANDS.iter().any(|(name, _)| *name == variant);
+            // Authentication only makes sense on the websocket
+            let websocket_only = matches!(variant, "Auth" | "Resume");
+            assert!(
+                exposed != websocket_only,
+                "{} is missing from COMMANDS",
+                variant
+            );
+        }
+    }
+}
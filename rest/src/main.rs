@@ -1,5 +1,7 @@
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{env, str::FromStr};
 
 use axum::{
@@ -11,16 +13,23 @@ use axum::{
     Json, Router,
 };
 use portal::protocol::LocalKeypair;
+use portal::router::{MessageRouterActorConfig, PowPolicy, RouterMetrics};
 use sdk::PortalSDK;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 use tracing::info;
 
-mod command;
-mod response;
+mod auth;
+mod http;
+mod metrics;
+mod streams;
+mod webhook;
 mod ws;
 
+use auth::Session;
+use rest_client::{command, response};
+
 // Re-export the portal types that we need
 pub use portal::nostr::key::PublicKey;
 use tracing_subscriber::layer::SubscriberExt;
@@ -58,18 +67,98 @@ impl From<ApiError> for (StatusCode, Json<ErrorResponse>) {
 #[derive(Clone)]
 struct AppState {
     sdk: Arc<PortalSDK>,
-    auth_token: String,
+    /// Grants full access, if set
+    auth_token: Option<String>,
+    nwc: Option<Arc<nwc::NWC>>,
+    /// Additional identities served by this daemon, by name
+    identities: Arc<HashMap<String, Identity>>,
+    /// Commands started through the HTTP endpoints
+    operations: Arc<http::Operations>,
+    webhooks: Arc<webhook::Webhooks>,
+    /// Scoped keys loaded from `API_KEYS_FILE`
+    api_keys: Arc<Vec<Arc<auth::ApiKey>>>,
+    audit_log: Arc<auth::AuditLog>,
+    /// Streams of closed sockets that can still be resumed
+    streams: Arc<streams::ResumableStreams>,
+    metrics: Arc<metrics::Metrics>,
+}
+
+/// A service key served next to the main one
+struct Identity {
+    name: String,
+    sdk: Arc<PortalSDK>,
     nwc: Option<Arc<nwc::NWC>>,
+    /// Clients authenticated with this token can only act as this identity
+    auth_token: Option<String>,
+}
+
+/// An entry of the file pointed by `IDENTITIES_FILE`
+#[derive(Deserialize)]
+struct IdentityConfig {
+    name: String,
+    nostr_key: String,
+    #[serde(default)]
+    subkey_proof: Option<portal::protocol::model::auth::SubkeyProof>,
+    #[serde(default)]
+    auth_token: Option<String>,
+    #[serde(default)]
+    nwc_url: Option<String>,
+    /// Webhooks receiving the notifications of this identity
+    #[serde(default)]
+    webhooks: Vec<webhook::WebhookConfig>,
+}
+
+async fn load_identities(
+    sdk: &PortalSDK,
+    path: &str,
+    webhooks: &mut Vec<(Option<String>, webhook::WebhookConfig)>,
+) -> anyhow::Result<HashMap<String, Identity>> {
+    let configs: Vec<IdentityConfig> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+
+    let mut identities = HashMap::new();
+    for config in configs {
+        let keys = portal::nostr::key::Keys::from_str(&config.nostr_key)?;
+        let keypair = LocalKeypair::new(keys, config.subkey_proof);
+        info!(
+            "Serving identity {} with keypair: {}",
+            config.name,
+            keypair.public_key()
+        );
+
+        let nwc = match config.nwc_url {
+            Some(url) => Some(Arc::new(nwc::NWC::new(url.parse()?))),
+            None => None,
+        };
+
+        webhooks.extend(
+            config
+                .webhooks
+                .into_iter()
+                .map(|webhook| (Some(config.name.clone()), webhook)),
+        );
+
+        let identity = Identity {
+            name: config.name.clone(),
+            sdk: Arc::new(sdk.with_identity(keypair).await?),
+            nwc,
+            auth_token: config.auth_token,
+        };
+        if identities.insert(config.name.clone(), identity).is_some() {
+            anyhow::bail!("Duplicate identity name: {}", config.name);
+        }
+    }
+
+    Ok(identities)
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, utoipa::ToSchema)]
 struct ErrorResponse {
     error: String,
 }
 
 async fn auth_middleware<B>(
     State(state): State<AppState>,
-    req: Request<B>,
+    mut req: Request<B>,
     next: Next<B>,
 ) -> std::result::Result<Response, (StatusCode, Json<ErrorResponse>)> {
     // Skip authentication for WebSocket upgrade requests
@@ -92,9 +181,12 @@ async fn auth_middleware<B>(
         },
     )?;
 
-    if token != state.auth_token {
-        return Err(ApiError::AuthenticationError("Invalid token".to_string()).into());
-    }
+    let session = Session::authenticate(&state, token).ok_or_else(
+        || -> (StatusCode, Json<ErrorResponse>) {
+            ApiError::AuthenticationError("Invalid token".to_string()).into()
+        },
+    )?;
+    req.extensions_mut().insert(session);
 
     Ok(next.run(req).await)
 }
@@ -126,7 +218,15 @@ async fn main() -> anyhow::Result<()> {
         .init();
 
     // Get environment variables
-    let auth_token = env::var("AUTH_TOKEN").expect("AUTH_TOKEN environment variable is required");
+    let auth_token = env::var("AUTH_TOKEN").ok();
+    let api_keys = match env::var("API_KEYS_FILE") {
+        Ok(path) => auth::load_api_keys(path)?,
+        Err(_) => Vec::new(),
+    };
+    if auth_token.is_none() && api_keys.is_empty() {
+        anyhow::bail!("Either AUTH_TOKEN or API_KEYS_FILE is required");
+    }
+    let audit_log = auth::AuditLog::new(env::var("AUDIT_LOG").ok())?;
     let nwc_url = env::var("NWC_URL").ok();
     let nostr_key = env::var("NOSTR_KEY").expect("NOSTR_KEY environment variable is required");
     let nostr_subkey_proof = env::var("NOSTR_SUBKEY_PROOF").ok();
@@ -158,8 +258,72 @@ async fn main() -> anyhow::Result<()> {
 
     info!("Running with keypair: {}", keypair.public_key());
 
+    // The router records its metrics in the registry of the daemon
+    let metrics = Arc::new(metrics::Metrics::new()?);
+    // NIP-13 proof of work on outbound events and required from inbound events
+    let mut pow = PowPolicy::new();
+    if let Ok(difficulty) = env::var("POW_DIFFICULTY") {
+        let difficulty = difficulty
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid POW_DIFFICULTY: {}", difficulty))?;
+        pow = pow.default_difficulty(difficulty);
+    }
+    if let Ok(difficulty) = env::var("POW_MIN_INBOUND_DIFFICULTY") {
+        let difficulty = difficulty
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid POW_MIN_INBOUND_DIFFICULTY: {}", difficulty))?;
+        pow = pow.min_inbound_difficulty(difficulty);
+    }
+    let router_config = MessageRouterActorConfig {
+        pow,
+        metrics: RouterMetrics::new(metrics.registry())?,
+        ..Default::default()
+    };
+
     // Initialize SDK
-    let sdk = PortalSDK::new(keypair, relays).await?;
+    let sdk = PortalSDK::new_with_config(keypair, relays, router_config).await?;
+
+    // Webhooks of the main identity
+    let mut webhooks = match env::var("WEBHOOKS_FILE") {
+        Ok(path) => {
+            let configs: Vec<webhook::WebhookConfig> =
+                serde_json::from_str(&std::fs::read_to_string(path)?)?;
+            configs.into_iter().map(|config| (None, config)).collect()
+        }
+        Err(_) => Vec::new(),
+    };
+
+    let identities = match env::var("IDENTITIES_FILE") {
+        Ok(path) => load_identities(&sdk, &path, &mut webhooks).await?,
+        Err(_) => HashMap::new(),
+    };
+    for key in &api_keys {
+        if let Some(identity) = &key.identity {
+            if !identities.contains_key(identity) {
+                anyhow::bail!("API key {} uses unknown identity {}", key.name, identity);
+            }
+        }
+    }
+
+    let (delivery_log, pending_deliveries) = match env::var("WEBHOOK_LOG") {
+        Ok(path) => {
+            let (log, pending) = webhook::DeliveryLog::open(path)?;
+            (Some(log), pending)
+        }
+        Err(_) => (None, Vec::new()),
+    };
+    let webhooks = Arc::new(webhook::Webhooks::new(webhooks, delivery_log)?);
+
+    let stream_grace_period = match env::var("STREAM_GRACE_PERIOD") {
+        Ok(secs) => {
+            let secs = secs
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid STREAM_GRACE_PERIOD: {}", secs))?;
+            Duration::from_secs(secs)
+        }
+        Err(_) => streams::DEFAULT_GRACE_PERIOD,
+    };
+    webhooks.resume(pending_deliveries);
 
     // Initialize NWC
     let nwc =
@@ -177,17 +341,31 @@ async fn main() -> anyhow::Result<()> {
     let state = AppState {
         sdk: Arc::new(sdk),
         auth_token,
+        api_keys: Arc::new(api_keys),
+        audit_log: Arc::new(audit_log),
         nwc,
+        identities: Arc::new(identities),
+        operations: Arc::new(http::Operations::new()),
+        webhooks,
+        streams: Arc::new(streams::ResumableStreams::new(
+            stream_grace_period,
+            metrics.clone(),
+        )),
+        metrics,
     };
 
     // Create router with middleware
     let app = Router::new()
         .route("/health", get(health_check))
         .route("/ws", get(handle_ws_upgrade))
+        .route("/metrics", get(metrics::metrics_handler))
+        .merge(http::router())
         .layer(middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
         ))
+        // The spec is public, so that tools can fetch it without a token
+        .route("/openapi.json", get(http::openapi_spec))
         .layer(
             CorsLayer::new()
                 .allow_origin(Any)
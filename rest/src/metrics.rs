@@ -0,0 +1,199 @@
+//! Prometheus metrics of the daemon, served on `/metrics`
+//!
+//! The registry also holds the metrics of the router, see [`portal::router::RouterMetrics`].
+
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode, Extension};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::response::InvoiceStatus;
+use crate::{AppState, Session};
+
+pub(crate) struct Metrics {
+    registry: Registry,
+    commands: IntCounterVec,
+    command_duration: HistogramVec,
+    payment_duration: HistogramVec,
+    streams_opened: IntCounter,
+    streams_detached: IntGauge,
+    streams_resumed: IntCounter,
+    streams_expired: IntCounter,
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+
+        let commands = IntCounterVec::new(
+            Opts::new(
+                "portal_rest_commands_total",
+                "Number of commands handled, by command and outcome",
+            ),
+            &["command", "outcome"],
+        )?;
+        let command_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "portal_rest_command_duration_seconds",
+                "Time spent handling a command, by command",
+            )
+            // Payments wait for the user to approve them
+            .buckets(vec![
+                0.01, 0.05, 0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+            ]),
+            &["command"],
+        )?;
+        let payment_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "portal_rest_payment_duration_seconds",
+                "Time from a payment request to its final status, by command and status",
+            )
+            .buckets(vec![1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0]),
+            &["command", "status"],
+        )?;
+        let streams_opened = IntCounter::new(
+            "portal_rest_streams_opened_total",
+            "Number of notification streams opened",
+        )?;
+        let streams_detached = IntGauge::new(
+            "portal_rest_streams_detached",
+            "Number of streams waiting to be resumed",
+        )?;
+        let streams_resumed = IntCounter::new(
+            "portal_rest_streams_resumed_total",
+            "Number of detached streams that were resumed",
+        )?;
+        let streams_expired = IntCounter::new(
+            "portal_rest_streams_expired_total",
+            "Number of detached streams that expired before being resumed",
+        )?;
+
+        registry.register(Box::new(commands.clone()))?;
+        registry.register(Box::new(command_duration.clone()))?;
+        registry.register(Box::new(payment_duration.clone()))?;
+        registry.register(Box::new(streams_opened.clone()))?;
+        registry.register(Box::new(streams_detached.clone()))?;
+        registry.register(Box::new(streams_resumed.clone()))?;
+        registry.register(Box::new(streams_expired.clone()))?;
+
+        Ok(Self {
+            registry,
+            commands,
+            command_duration,
+            payment_duration,
+            streams_opened,
+            streams_detached,
+            streams_resumed,
+            streams_expired,
+        })
+    }
+
+    pub(crate) fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    pub(crate) fn command_finished(&self, command: &str, succeeded: bool, duration: Duration) {
+        let outcome = if succeeded { "success" } else { "error" };
+        self.commands.with_label_values(&[command, outcome]).inc();
+        self.command_duration
+            .with_label_values(&[command])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// A payment request reached its final `status`, after `duration`
+    pub(crate) fn payment_finished(
+        &self,
+        command: &str,
+        status: &InvoiceStatus,
+        duration: Duration,
+    ) {
+        let status = match status {
+            InvoiceStatus::Paid { .. } => "paid",
+            InvoiceStatus::Timeout => "timeout",
+            InvoiceStatus::Error { .. } => "error",
+            InvoiceStatus::UserApproved => "user_approved",
+            InvoiceStatus::UserSuccess { .. } => "user_success",
+            InvoiceStatus::UserFailed { .. } => "user_failed",
+            InvoiceStatus::UserRejected { .. } => "user_rejected",
+        };
+        self.payment_duration
+            .with_label_values(&[command, status])
+            .observe(duration.as_secs_f64());
+    }
+
+    pub(crate) fn stream_opened(&self) {
+        self.streams_opened.inc();
+    }
+
+    pub(crate) fn stream_detached(&self) {
+        self.streams_detached.inc();
+    }
+
+    pub(crate) fn stream_resumed(&self) {
+        self.streams_detached.dec();
+        self.streams_resumed.inc();
+    }
+
+    pub(crate) fn stream_expired(&self) {
+        self.streams_detached.dec();
+        self.streams_expired.inc();
+    }
+
+    /// The metrics in the Prometheus text format
+    pub(crate) fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+/// `GET /metrics`, only available to the admin token since it covers every identity
+pub(crate) async fn metrics_handler(
+    State(state): State<AppState>,
+    Extension(session): Extension<Session>,
+) -> Result<String, StatusCode> {
+    if !matches!(session, Session::Admin) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    state.metrics.render().map_err(|e| {
+        tracing::error!("Failed to encode metrics: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new().unwrap();
+        metrics.command_finished("RequestSinglePayment", true, Duration::from_secs(3));
+        metrics.command_finished("RequestSinglePayment", false, Duration::from_millis(20));
+        metrics.payment_finished(
+            "RequestSinglePayment",
+            &InvoiceStatus::Paid { preimage: None },
+            Duration::from_secs(40),
+        );
+        metrics.stream_opened();
+        metrics.stream_detached();
+        metrics.stream_resumed();
+
+        let text = metrics.render().unwrap();
+        assert!(text.contains(
+            "portal_rest_commands_total{command=\"RequestSinglePayment\",outcome=\"success\"} 1"
+        ));
+        assert!(text.contains(
+            "portal_rest_commands_total{command=\"RequestSinglePayment\",outcome=\"error\"} 1"
+        ));
+        assert!(text.contains(
+            "portal_rest_payment_duration_seconds_count{command=\"RequestSinglePayment\",status=\"paid\"} 1"
+        ));
+        assert!(text.contains("portal_rest_streams_detached 0"));
+        assert!(text.contains("portal_rest_streams_resumed_total 1"));
+    }
+}
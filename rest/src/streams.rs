@@ -0,0 +1,441 @@
+//! Notification streams that outlive the socket that opened them
+//!
+//! When a websocket closes, its streams are detached instead of being aborted: they keep running
+//! for a grace period and their notifications are buffered. A client that reconnects in time sends
+//! `Resume` with the ids of its streams to replay the buffered notifications and keep receiving new
+//! ones on the new socket.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use tokio::sync::mpsc;
+use tokio::task::{AbortHandle, JoinHandle};
+use tracing::{debug, warn};
+
+use crate::metrics::Metrics;
+use crate::response::Response;
+use crate::ws::ActiveStreams;
+use crate::Session;
+
+/// The grace period used when `STREAM_GRACE_PERIOD` is not set
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(120);
+
+/// Notifications kept for each detached stream, the oldest are dropped first
+const MAX_BUFFERED: usize = 100;
+
+enum Output {
+    /// Sent to the socket that resumed the stream
+    Attached(mpsc::Sender<Response>),
+    /// Buffered until the stream is resumed or expires
+    Detached {
+        buffer: VecDeque<Response>,
+        since: Instant,
+    },
+    /// Buffered while the previous notifications are replayed
+    Resuming(VecDeque<Response>),
+}
+
+struct DetachedStream {
+    /// The session of the socket that detached the stream
+    owner: Session,
+    output: Output,
+    /// Held here while no socket owns the stream
+    task: Option<JoinHandle<()>>,
+}
+
+/// Aborts the task when dropped
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Where a notification should go
+pub(crate) enum Route {
+    /// The stream was never detached, send it to the socket that opened it
+    Origin(Response),
+    /// The stream was resumed by another socket
+    Socket(mpsc::Sender<Response>, Response),
+    /// The notification was buffered
+    Buffered,
+}
+
+/// The streams that were detached from their socket at least once, by stream id
+pub(crate) struct ResumableStreams {
+    streams: DashMap<String, DetachedStream>,
+    grace_period: Duration,
+    metrics: Arc<Metrics>,
+}
+
+impl ResumableStreams {
+    pub(crate) fn new(grace_period: Duration, metrics: Arc<Metrics>) -> Self {
+        Self {
+            streams: DashMap::new(),
+            grace_period,
+            metrics,
+        }
+    }
+
+    /// Find where to send a notification of `stream_id`, buffering it if the stream is detached
+    pub(crate) fn route(&self, stream_id: &str, notification: Response) -> Route {
+        let Some(mut stream) = self.streams.get_mut(stream_id) else {
+            return Route::Origin(notification);
+        };
+
+        match &mut stream.output {
+            Output::Attached(tx) => Route::Socket(tx.clone(), notification),
+            Output::Detached { buffer, .. } | Output::Resuming(buffer) => {
+                if buffer.len() >= MAX_BUFFERED {
+                    warn!(
+                        "Buffer of stream {} is full, dropping the oldest notification",
+                        stream_id
+                    );
+                    buffer.pop_front();
+                }
+                buffer.push_back(notification);
+                Route::Buffered
+            }
+        }
+    }
+
+    /// Detach the streams of a socket that was closed
+    pub(crate) fn detach(self: &Arc<Self>, session: &Session, active_streams: &ActiveStreams) {
+        for (stream_id, task) in active_streams.drain() {
+            if task.is_finished() {
+                self.streams.remove(&stream_id);
+                continue;
+            }
+
+            debug!("Detaching stream {}", stream_id);
+            self.streams.insert(
+                stream_id.clone(),
+                DetachedStream {
+                    owner: session.clone(),
+                    output: Output::Detached {
+                        buffer: VecDeque::new(),
+                        since: Instant::now(),
+                    },
+                    task: Some(task),
+                },
+            );
+            self.metrics.stream_detached();
+            self.expire_later(stream_id);
+        }
+    }
+
+    /// Expire the stream if it's still detached once the grace period passed
+    fn expire_later(self: &Arc<Self>, stream_id: String) {
+        let streams = Arc::clone(self);
+        tokio::spawn(async move {
+            tokio::time::sleep(streams.grace_period).await;
+            streams.expire(&stream_id);
+        });
+    }
+
+    /// Detach a stream again when the socket that was resuming it is gone
+    ///
+    /// `unsent` goes back in front of the notifications buffered during the replay, and the stream
+    /// is taken back from the socket so that it isn't aborted when the socket closes.
+    fn detach_resuming(
+        self: &Arc<Self>,
+        stream_id: &str,
+        mut unsent: VecDeque<Response>,
+        active_streams: &ActiveStreams,
+    ) {
+        {
+            let Some(mut stream) = self.streams.get_mut(stream_id) else {
+                return;
+            };
+            if let Output::Resuming(buffer) = &mut stream.output {
+                unsent.append(buffer);
+            }
+            // Same limit as while detached, the oldest are dropped first
+            while unsent.len() > MAX_BUFFERED {
+                unsent.pop_front();
+            }
+            stream.output = Output::Detached {
+                buffer: unsent,
+                since: Instant::now(),
+            };
+            stream.task = active_streams.take(stream_id);
+        }
+
+        debug!(
+            "Socket closed while resuming stream {}, detaching it",
+            stream_id
+        );
+        self.metrics.stream_detached();
+        self.expire_later(stream_id.to_string());
+    }
+
+    fn expire(&self, stream_id: &str) {
+        let expired = self
+            .streams
+            .remove_if(stream_id, |_, stream| match stream.output {
+                Output::Detached { since, .. } => since.elapsed() >= self.grace_period,
+                _ => false,
+            });
+
+        if let Some((_, stream)) = expired {
+            debug!("Stream {} expired", stream_id);
+            self.metrics.stream_expired();
+            if let Some(task) = stream.task {
+                task.abort();
+            }
+        }
+    }
+
+    /// Forget a resumed stream once it ended
+    fn remove_attached(&self, stream_id: &str) {
+        if self
+            .streams
+            .remove_if(stream_id, |_, stream| {
+                matches!(stream.output, Output::Attached(_))
+            })
+            .is_some()
+        {
+            debug!("Resumed stream {} ended", stream_id);
+        }
+    }
+
+    /// Resume a detached stream on the socket of `active_streams`
+    ///
+    /// The buffered notifications are replayed in order to `tx_notification` before new ones are
+    /// forwarded. Returns `false` if the stream expired or belongs to another identity, or if the
+    /// socket closed during the replay, in which case the stream is detached again.
+    pub(crate) async fn resume(
+        self: &Arc<Self>,
+        stream_id: &str,
+        session: &Session,
+        tx_notification: &mpsc::Sender<Response>,
+        active_streams: &ActiveStreams,
+    ) -> bool {
+        let task = {
+            let Some(mut stream) = self.streams.get_mut(stream_id) else {
+                return false;
+            };
+            if !session.can_act_as(&stream.owner) {
+                return false;
+            }
+
+            let buffer = match &mut stream.output {
+                Output::Detached { buffer, .. } => std::mem::take(buffer),
+                // Already resumed by another socket, or being resumed
+                _ => return false,
+            };
+            stream.output = Output::Resuming(buffer);
+            stream.task.take()
+        };
+        self.metrics.stream_resumed();
+        if let Some(task) = task {
+            let streams = Arc::clone(self);
+            let id = stream_id.to_string();
+            let watcher = tokio::spawn(async move {
+                // The socket aborts the watcher, make sure the stream goes with it
+                let _guard = AbortOnDrop(task.abort_handle());
+                let _ = task.await;
+                streams.remove_attached(&id);
+            });
+            active_streams.add_task(stream_id.to_string(), watcher);
+        }
+
+        loop {
+            let batch = {
+                let Some(mut stream) = self.streams.get_mut(stream_id) else {
+                    return false;
+                };
+                match &mut stream.output {
+                    Output::Resuming(buffer) if !buffer.is_empty() => std::mem::take(buffer),
+                    _ => {
+                        stream.output = Output::Attached(tx_notification.clone());
+                        return true;
+                    }
+                }
+            };
+
+            let mut batch = batch.into_iter();
+            while let Some(notification) = batch.next() {
+                if let Err(mpsc::error::SendError(notification)) =
+                    tx_notification.send(notification).await
+                {
+                    let unsent = std::iter::once(notification).chain(batch).collect();
+                    self.detach_resuming(stream_id, unsent, active_streams);
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::response::NotificationData;
+
+    fn notification(stream_id: &str) -> Response {
+        Response::Notification {
+            id: stream_id.to_string(),
+            data: NotificationData::KeyHandshake {
+                main_key: "main_key".to_string(),
+                preferred_relays: vec![],
+                capabilities: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_buffer_and_resume() {
+        let streams = Arc::new(ResumableStreams::new(
+            Duration::from_secs(60),
+            Arc::new(Metrics::new().unwrap()),
+        ));
+
+        let active_streams = ActiveStreams::new();
+        active_streams.add_task("stream".to_string(), tokio::spawn(std::future::pending()));
+        streams.detach(&Session::Admin, &active_streams);
+
+        assert!(matches!(
+            streams.route("stream", notification("stream")),
+            Route::Buffered
+        ));
+        assert!(matches!(
+            streams.route("other", notification("other")),
+            Route::Origin(_)
+        ));
+
+        // Streams of the main identity can't be resumed by another identity
+        let (tx, mut rx) = mpsc::channel(8);
+        let resumed = ActiveStreams::new();
+        assert!(
+            !streams
+                .resume(
+                    "stream",
+                    &Session::Identity("merchant-a".to_string()),
+                    &tx,
+                    &resumed
+                )
+                .await
+        );
+
+        assert!(
+            streams
+                .resume("stream", &Session::Admin, &tx, &resumed)
+                .await
+        );
+        assert!(matches!(rx.try_recv(), Ok(Response::Notification { id, .. }) if id == "stream"));
+        assert!(matches!(
+            streams.route("stream", notification("stream")),
+            Route::Socket(_, _)
+        ));
+        resumed.abort_all();
+    }
+
+    #[tokio::test]
+    async fn test_resume_on_closed_socket_keeps_notifications() {
+        let streams = Arc::new(ResumableStreams::new(
+            Duration::from_secs(60),
+            Arc::new(Metrics::new().unwrap()),
+        ));
+
+        let active_streams = ActiveStreams::new();
+        active_streams.add_task("stream".to_string(), tokio::spawn(std::future::pending()));
+        streams.detach(&Session::Admin, &active_streams);
+        for _ in 0..2 {
+            streams.route("stream", notification("stream"));
+        }
+
+        // The socket closed before the replay
+        let (tx, rx) = mpsc::channel(8);
+        drop(rx);
+        let closed = ActiveStreams::new();
+        assert!(
+            !streams
+                .resume("stream", &Session::Admin, &tx, &closed)
+                .await
+        );
+        // The stream is not aborted along with the closed socket
+        closed.abort_all();
+        assert!(closed.drain().is_empty());
+        assert!(matches!(
+            streams.route("stream", notification("stream")),
+            Route::Buffered
+        ));
+
+        let (tx, mut rx) = mpsc::channel(8);
+        let resumed = ActiveStreams::new();
+        assert!(
+            streams
+                .resume("stream", &Session::Admin, &tx, &resumed)
+                .await
+        );
+        for _ in 0..3 {
+            assert!(
+                matches!(rx.try_recv(), Ok(Response::Notification { id, .. }) if id == "stream")
+            );
+        }
+        assert!(rx.try_recv().is_err());
+        resumed.abort_all();
+    }
+
+    #[tokio::test]
+    async fn test_expire() {
+        let streams = Arc::new(ResumableStreams::new(
+            Duration::from_millis(10),
+            Arc::new(Metrics::new().unwrap()),
+        ));
+
+        let active_streams = ActiveStreams::new();
+        active_streams.add_task("stream".to_string(), tokio::spawn(std::future::pending()));
+        streams.detach(&Session::Admin, &active_streams);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let (tx, _rx) = mpsc::channel(8);
+        assert!(
+            !streams
+                .resume("stream", &Session::Admin, &tx, &ActiveStreams::new())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ended_stream_is_removed() {
+        let streams = Arc::new(ResumableStreams::new(
+            Duration::from_secs(60),
+            Arc::new(Metrics::new().unwrap()),
+        ));
+
+        let (end_tx, end_rx) = tokio::sync::oneshot::channel::<()>();
+        let active_streams = ActiveStreams::new();
+        active_streams.add_task(
+            "stream".to_string(),
+            tokio::spawn(async move {
+                let _ = end_rx.await;
+            }),
+        );
+        streams.detach(&Session::Admin, &active_streams);
+
+        let (tx, _rx) = mpsc::channel(8);
+        let resumed = ActiveStreams::new();
+        assert!(
+            streams
+                .resume("stream", &Session::Admin, &tx, &resumed)
+                .await
+        );
+        assert!(matches!(
+            streams.route("stream", notification("stream")),
+            Route::Socket(_, _)
+        ));
+
+        end_tx.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(matches!(
+            streams.route("stream", notification("stream")),
+            Route::Origin(_)
+        ));
+    }
+}
@@ -0,0 +1,598 @@
+//! Signed webhooks for stream notifications
+//!
+//! Every notification sent on a stream is also `POST`ed to the webhooks of the identity that opened
+//! the stream. The body is signed with the secret of the webhook: the `X-Portal-Signature` header
+//! contains `t=<unix timestamp>,v1=<hex HMAC-SHA256 of "<timestamp>.<body>">`.
+//!
+//! Failed deliveries are retried with an exponential backoff. When a log file is configured, the
+//! state of every delivery is appended to it, and deliveries still pending are resumed on startup.
+//! The log is compacted as it grows, keeping the pending deliveries and the recent history of the
+//! finished ones.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use portal::protocol::model::Timestamp;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use tracing::{debug, error, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::response::NotificationData;
+
+pub const SIGNATURE_HEADER: &str = "x-portal-signature";
+pub const DELIVERY_HEADER: &str = "x-portal-delivery";
+
+const MAX_ATTEMPTS: u32 = 10;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Lines written to the delivery log before it is compacted
+const COMPACT_AFTER_LINES: usize = 1000;
+/// The most delivered or failed deliveries kept in the log
+const MAX_FINISHED_DELIVERIES: usize = 1000;
+/// How long delivered or failed deliveries are kept in the log
+const FINISHED_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid delivery log: {0}")]
+    Log(#[from] serde_json::Error),
+
+    #[error("HTTP client error: {0}")]
+    Client(#[from] reqwest::Error),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// The secret used to sign the deliveries
+    pub secret: String,
+    /// Only deliver these notification types, such as `payment_status_update`. All by default.
+    #[serde(default)]
+    pub events: Option<Vec<String>>,
+}
+
+/// A webhook registered for an identity, `None` being the main identity
+struct Subscription {
+    identity: Option<String>,
+    config: WebhookConfig,
+}
+
+impl Subscription {
+    fn matches(&self, identity: Option<&str>, event: &str) -> bool {
+        self.identity.as_deref() == identity
+            && self
+                .config
+                .events
+                .as_ref()
+                .is_none_or(|events| events.iter().any(|e| e == event))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Delivery {
+    pub id: String,
+    pub url: String,
+    pub identity: Option<String>,
+    /// The body sent to the webhook
+    #[schema(value_type = Object)]
+    pub payload: Value,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub updated_at: Timestamp,
+}
+
+/// An append-only file with the state of every delivery, one JSON object per line
+///
+/// The file is compacted to the latest state of each delivery when it is opened, and again
+/// whenever it grows past [`COMPACT_AFTER_LINES`] lines, so that it doesn't grow forever on
+/// long-running daemons. Deliveries that ended are kept as a history for
+/// [`FINISHED_RETENTION`], up to [`MAX_FINISHED_DELIVERIES`].
+pub struct DeliveryLog {
+    path: PathBuf,
+    state: Mutex<LogState>,
+}
+
+#[derive(Default)]
+struct History {
+    pending: HashMap<String, Delivery>,
+    /// Delivered or failed, oldest first
+    finished: VecDeque<Delivery>,
+}
+
+impl History {
+    fn update(&mut self, delivery: &Delivery) {
+        if delivery.status == DeliveryStatus::Pending {
+            self.pending.insert(delivery.id.clone(), delivery.clone());
+        } else {
+            self.pending.remove(&delivery.id);
+            self.finished.push_back(delivery.clone());
+        }
+        self.prune();
+    }
+
+    /// Drop the finished deliveries past the retention period or count
+    fn prune(&mut self) {
+        let cutoff = Timestamp::now()
+            .as_u64()
+            .saturating_sub(FINISHED_RETENTION.as_secs());
+        while let Some(oldest) = self.finished.front() {
+            if self.finished.len() <= MAX_FINISHED_DELIVERIES
+                && oldest.updated_at.as_u64() >= cutoff
+            {
+                break;
+            }
+            self.finished.pop_front();
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.pending.len() + self.finished.len()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Delivery> {
+        self.finished.iter().chain(self.pending.values())
+    }
+}
+
+struct LogState {
+    file: File,
+    history: History,
+    /// Lines in the file, including the outdated ones
+    lines: usize,
+}
+
+impl DeliveryLog {
+    /// Open the log, returning the deliveries that are still pending
+    ///
+    /// The file is compacted to the latest state of each delivery, and the history of the
+    /// deliveries that ended is pruned.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<(Self, Vec<Delivery>), WebhookError> {
+        let path = path.as_ref().to_path_buf();
+
+        let mut history = History::default();
+        if path.exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                history.update(&serde_json::from_str(&line)?);
+            }
+        }
+
+        let file = compact(&path, &history)?;
+        let lines = history.len();
+        let resumed = history.pending.values().cloned().collect();
+
+        Ok((
+            Self {
+                path,
+                state: Mutex::new(LogState {
+                    file,
+                    history,
+                    lines,
+                }),
+            },
+            resumed,
+        ))
+    }
+
+    fn append(&self, delivery: &Delivery) -> Result<(), WebhookError> {
+        let line = serde_json::to_string(delivery)?;
+        let mut state = self.state.lock().unwrap();
+        state.history.update(delivery);
+
+        writeln!(state.file, "{}", line)?;
+        state.file.flush()?;
+        state.lines += 1;
+
+        if state.lines >= COMPACT_AFTER_LINES.max(state.history.len() * 2) {
+            debug!("Compacting the webhook delivery log");
+            state.file = compact(&self.path, &state.history)?;
+            state.lines = state.history.len();
+        }
+
+        Ok(())
+    }
+
+    /// The pending deliveries and the history of the finished ones, most recently updated first
+    pub fn deliveries(&self) -> Vec<Delivery> {
+        let state = self.state.lock().unwrap();
+        let mut deliveries = state.history.iter().cloned().collect::<Vec<_>>();
+        deliveries.sort_by_key(|delivery| std::cmp::Reverse(delivery.updated_at.as_u64()));
+        deliveries
+    }
+}
+
+/// Replace the log at `path` with the latest state of the deliveries in `history`, returning it
+/// opened for appending
+fn compact(path: &Path, history: &History) -> Result<File, WebhookError> {
+    // Written next to the log and renamed, so that a crash doesn't lose the pending deliveries
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let mut file = File::create(&tmp_path)?;
+    for delivery in history.iter() {
+        writeln!(file, "{}", serde_json::to_string(delivery)?)?;
+    }
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+
+    Ok(OpenOptions::new().append(true).open(path)?)
+}
+
+/// Sign `body`, returning the value of the signature header
+pub fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+
+    format!(
+        "t={},v1={}",
+        timestamp,
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
+
+/// How long to wait after the `attempts`-th failed attempt
+fn backoff(attempts: u32) -> Duration {
+    INITIAL_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+pub struct Webhooks {
+    subscriptions: Vec<Subscription>,
+    client: reqwest::Client,
+    log: Option<DeliveryLog>,
+}
+
+impl Webhooks {
+    /// `subscriptions` are the webhooks of each identity, `None` being the main identity
+    pub fn new(
+        subscriptions: Vec<(Option<String>, WebhookConfig)>,
+        log: Option<DeliveryLog>,
+    ) -> Result<Self, WebhookError> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+
+        Ok(Self {
+            subscriptions: subscriptions
+                .into_iter()
+                .map(|(identity, config)| Subscription { identity, config })
+                .collect(),
+            client,
+            log,
+        })
+    }
+
+    /// Retry the deliveries left pending by a previous run
+    pub fn resume(self: &Arc<Self>, pending: Vec<Delivery>) {
+        for mut delivery in pending {
+            let subscription = self
+                .subscriptions
+                .iter()
+                .find(|s| s.identity == delivery.identity && s.config.url == delivery.url);
+
+            match subscription {
+                Some(subscription) => {
+                    debug!("Resuming webhook delivery {}", delivery.id);
+                    tokio::spawn(
+                        Arc::clone(self).deliver(delivery, subscription.config.secret.clone()),
+                    );
+                }
+                None => {
+                    delivery.status = DeliveryStatus::Failed;
+                    delivery.last_error = Some("Webhook is not configured anymore".to_string());
+                    delivery.updated_at = Timestamp::now();
+                    self.record(&delivery);
+                }
+            }
+        }
+    }
+
+    /// Deliver a notification to the webhooks of `identity`
+    pub fn dispatch(
+        self: &Arc<Self>,
+        identity: Option<&str>,
+        stream_id: &str,
+        data: &NotificationData,
+    ) {
+        if self.subscriptions.is_empty() {
+            return;
+        }
+
+        let data = match serde_json::to_value(data) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Failed to serialize notification: {}", e);
+                return;
+            }
+        };
+        let event = data
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+
+        for subscription in self
+            .subscriptions
+            .iter()
+            .filter(|s| s.matches(identity, &event))
+        {
+            let id = Uuid::new_v4().to_string();
+            let delivery = Delivery {
+                id: id.clone(),
+                url: subscription.config.url.clone(),
+                identity: identity.map(str::to_string),
+                payload: json!({
+                    "id": id,
+                    "event": event,
+                    "identity": identity,
+                    "stream_id": stream_id,
+                    "created_at": Timestamp::now(),
+                    "data": data,
+                }),
+                status: DeliveryStatus::Pending,
+                attempts: 0,
+                last_error: None,
+                updated_at: Timestamp::now(),
+            };
+
+            self.record(&delivery);
+            tokio::spawn(Arc::clone(self).deliver(delivery, subscription.config.secret.clone()));
+        }
+    }
+
+    /// The recent deliveries to the webhooks of `identity`, most recently updated first
+    ///
+    /// Empty unless a delivery log is configured.
+    pub fn deliveries(&self, identity: Option<&str>) -> Vec<Delivery> {
+        self.log
+            .as_ref()
+            .map(|log| log.deliveries())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|delivery| delivery.identity.as_deref() == identity)
+            .collect()
+    }
+
+    fn record(&self, delivery: &Delivery) {
+        if let Some(log) = &self.log {
+            if let Err(e) = log.append(delivery) {
+                error!("Failed to write webhook delivery {}: {}", delivery.id, e);
+            }
+        }
+    }
+
+    async fn deliver(self: Arc<Self>, mut delivery: Delivery, secret: String) {
+        let body = match serde_json::to_vec(&delivery.payload) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Failed to serialize webhook delivery: {}", e);
+                return;
+            }
+        };
+
+        while delivery.status == DeliveryStatus::Pending {
+            delivery.attempts += 1;
+            match self.send(&delivery, &secret, &body).await {
+                Ok(()) => {
+                    delivery.status = DeliveryStatus::Delivered;
+                    delivery.last_error = None;
+                }
+                Err(e) => {
+                    warn!(
+                        "Webhook delivery {} to {} failed (attempt {}): {}",
+                        delivery.id, delivery.url, delivery.attempts, e
+                    );
+                    delivery.last_error = Some(e);
+                    if delivery.attempts >= MAX_ATTEMPTS {
+                        delivery.status = DeliveryStatus::Failed;
+                    }
+                }
+            }
+            delivery.updated_at = Timestamp::now();
+            self.record(&delivery);
+
+            if delivery.status == DeliveryStatus::Pending {
+                tokio::time::sleep(backoff(delivery.attempts)).await;
+            }
+        }
+    }
+
+    async fn send(&self, delivery: &Delivery, secret: &str, body: &[u8]) -> Result<(), String> {
+        // Signed at every attempt, so that receivers can reject old timestamps
+        let signature = sign(secret, Timestamp::now().as_u64(), body);
+
+        let response = self
+            .client
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(DELIVERY_HEADER, &delivery.id)
+            .header(SIGNATURE_HEADER, signature)
+            .body(body.to_vec())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Unexpected status {}", response.status()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            sign("secret", 1700000000, br#"{"hello":"world"}"#),
+            "t=1700000000,v1=654f06c856baf080af3fa272934823257a542d35cf1f88099338f850a60601a4"
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(4), Duration::from_secs(8));
+        assert_eq!(backoff(MAX_ATTEMPTS), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_subscription_matches() {
+        let subscription = Subscription {
+            identity: Some("merchant-a".to_string()),
+            config: WebhookConfig {
+                url: "https://example.com/webhook".to_string(),
+                secret: "secret".to_string(),
+                events: Some(vec!["payment_status_update".to_string()]),
+            },
+        };
+
+        assert!(subscription.matches(Some("merchant-a"), "payment_status_update"));
+        assert!(!subscription.matches(Some("merchant-a"), "key_handshake"));
+        assert!(!subscription.matches(None, "payment_status_update"));
+    }
+
+    #[test]
+    fn test_log_resumes_pending() {
+        let path = std::env::temp_dir().join(format!("webhooks-{}.log", Uuid::new_v4()));
+
+        let delivery = |id: &str, status| Delivery {
+            id: id.to_string(),
+            url: "https://example.com/webhook".to_string(),
+            identity: None,
+            payload: json!({}),
+            status,
+            attempts: 1,
+            last_error: None,
+            updated_at: Timestamp::now(),
+        };
+
+        let (log, pending) = DeliveryLog::open(&path).unwrap();
+        assert!(pending.is_empty());
+        log.append(&delivery("delivered", DeliveryStatus::Pending))
+            .unwrap();
+        log.append(&delivery("delivered", DeliveryStatus::Delivered))
+            .unwrap();
+        log.append(&delivery("pending", DeliveryStatus::Pending))
+            .unwrap();
+        drop(log);
+
+        let (log, pending) = DeliveryLog::open(&path).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, "pending");
+
+        // The final state of the delivered one is kept as history
+        let deliveries = log.deliveries();
+        assert_eq!(deliveries.len(), 2);
+        assert!(deliveries
+            .iter()
+            .any(|d| d.id == "delivered" && d.status == DeliveryStatus::Delivered));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_log_drops_old_history() {
+        let path = std::env::temp_dir().join(format!("webhooks-{}.log", Uuid::new_v4()));
+
+        let delivery = |id: &str, updated_at| Delivery {
+            id: id.to_string(),
+            url: "https://example.com/webhook".to_string(),
+            identity: None,
+            payload: json!({}),
+            status: DeliveryStatus::Failed,
+            attempts: MAX_ATTEMPTS,
+            last_error: Some("Unexpected status 500".to_string()),
+            updated_at,
+        };
+
+        let (log, _) = DeliveryLog::open(&path).unwrap();
+        let expired = Timestamp::now().as_u64() - FINISHED_RETENTION.as_secs() - 60;
+        log.append(&delivery("old", Timestamp::new(expired)))
+            .unwrap();
+        log.append(&delivery("recent", Timestamp::now())).unwrap();
+
+        let deliveries = log.deliveries();
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].id, "recent");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_log_is_compacted() {
+        let path = std::env::temp_dir().join(format!("webhooks-{}.log", Uuid::new_v4()));
+
+        let delivery = |id: String, status| Delivery {
+            id,
+            url: "https://example.com/webhook".to_string(),
+            identity: None,
+            payload: json!({}),
+            status,
+            attempts: 1,
+            last_error: None,
+            updated_at: Timestamp::now(),
+        };
+
+        let (log, _) = DeliveryLog::open(&path).unwrap();
+        log.append(&delivery("pending".to_string(), DeliveryStatus::Pending))
+            .unwrap();
+        let count = MAX_FINISHED_DELIVERIES + COMPACT_AFTER_LINES;
+        for i in 0..count {
+            log.append(&delivery(i.to_string(), DeliveryStatus::Pending))
+                .unwrap();
+            log.append(&delivery(i.to_string(), DeliveryStatus::Delivered))
+                .unwrap();
+        }
+
+        let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+        assert!(lines < 2 * count);
+        drop(log);
+
+        let (log, pending) = DeliveryLog::open(&path).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, "pending");
+
+        // Only the most recent finished deliveries are kept
+        let deliveries = log.deliveries();
+        assert_eq!(deliveries.len(), MAX_FINISHED_DELIVERIES + 1);
+        assert!(deliveries.iter().any(|d| d.id == (count - 1).to_string()));
+        assert!(!deliveries.iter().any(|d| d.id == "0"));
+        let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+        assert_eq!(lines, MAX_FINISHED_DELIVERIES + 1);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,9 +1,15 @@
+use std::cell::Cell;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Instant;
 
-use crate::command::{Command, CommandWithId};
+use crate::auth::{self, Charge, Transport};
+use crate::command::*;
+use crate::metrics::Metrics;
 use crate::response::*;
-use crate::{AppState, PublicKey};
+use crate::streams::{ResumableStreams, Route};
+use crate::webhook::Webhooks;
+use crate::{AppState, Identity, PublicKey, Session};
 use axum::extract::ws::{Message, WebSocket};
 use cdk::amount::SplitTarget;
 use cdk::mint_url::MintUrl;
@@ -27,74 +33,133 @@ use tokio::task::JoinHandle;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-struct SocketContext {
+tokio::task_local! {
+    /// Set when the command handled by the current task replies with an error
+    static COMMAND_FAILED: Cell<bool>;
+}
+
+/// Sends the notifications of a stream to the client and to the webhooks of its identity
+///
+/// Notifications of streams that were detached from their socket are buffered, or sent to the
+/// socket that resumed them.
+#[derive(Clone)]
+pub(crate) struct Notifier {
+    tx_notification: mpsc::Sender<Response>,
+    webhooks: Arc<Webhooks>,
+    streams: Arc<ResumableStreams>,
+    /// `None` for the main identity
+    identity: Option<String>,
+}
+
+impl Notifier {
+    async fn send(&self, notification: Response) -> Result<(), mpsc::error::SendError<Response>> {
+        let stream_id = match &notification {
+            Response::Notification { id, data } => {
+                self.webhooks.dispatch(self.identity.as_deref(), id, data);
+                id.clone()
+            }
+            _ => return self.tx_notification.send(notification).await,
+        };
+
+        match self.streams.route(&stream_id, notification) {
+            Route::Origin(notification) => self.tx_notification.send(notification).await,
+            Route::Socket(tx, notification) => tx.send(notification).await,
+            Route::Buffered => Ok(()),
+        }
+    }
+}
+
+/// The state shared by the commands of a client
+///
+/// Responses are sent to `tx_message` and stream notifications to `notifier`: the websocket
+/// forwards them to the socket, while HTTP operations record them.
+pub(crate) struct SocketContext {
     sdk: Arc<PortalSDK>,
     nwc: Option<Arc<nwc::NWC>>,
-    tx_message: mpsc::Sender<Message>,
-    tx_notification: mpsc::Sender<Response>,
-    active_streams: ActiveStreams,
+    tx_message: mpsc::Sender<Response>,
+    notifier: Notifier,
+    pub(crate) active_streams: Arc<ActiveStreams>,
+    metrics: Arc<Metrics>,
 }
 
 impl SocketContext {
-    fn new(
-        sdk: Arc<PortalSDK>,
-        nwc: Option<Arc<nwc::NWC>>,
-        tx_message: mpsc::Sender<Message>,
+    /// A context acting as the main identity
+    pub(crate) fn new(
+        state: &AppState,
+        tx_message: mpsc::Sender<Response>,
         tx_notification: mpsc::Sender<Response>,
     ) -> Self {
         Self {
-            sdk,
-            nwc,
+            sdk: state.sdk.clone(),
+            nwc: state.nwc.clone(),
             tx_message,
-            tx_notification,
-            active_streams: ActiveStreams::new(),
+            notifier: Notifier {
+                tx_notification,
+                webhooks: state.webhooks.clone(),
+                streams: state.streams.clone(),
+                identity: None,
+            },
+            active_streams: Arc::new(ActiveStreams::new()),
+            metrics: state.metrics.clone(),
+        }
+    }
+
+    /// A context on the same socket that acts as another identity
+    pub(crate) fn with_identity(&self, identity: &Identity) -> Self {
+        Self {
+            sdk: identity.sdk.clone(),
+            nwc: identity.nwc.clone(),
+            tx_message: self.tx_message.clone(),
+            notifier: Notifier {
+                identity: Some(identity.name.clone()),
+                ..self.notifier.clone()
+            },
+            active_streams: self.active_streams.clone(),
+            metrics: self.metrics.clone(),
         }
     }
 
     /// Helper to send a message to the client
     async fn send_message(&self, msg: Response) -> bool {
-        match serde_json::to_string(&msg) {
-            Ok(json) => match self.tx_message.send(Message::Text(json)).await {
-                Ok(_) => true,
-                Err(e) => {
-                    error!("Error sending message: {}", e);
-                    false
-                }
-            },
+        match self.tx_message.send(msg).await {
+            Ok(_) => true,
             Err(e) => {
-                error!("Failed to serialize message: {}", e);
+                error!("Error sending message: {}", e);
                 false
             }
         }
     }
 
     async fn send_error_message(&self, request_id: &str, message: &str) -> bool {
+        let _ = COMMAND_FAILED.try_with(|failed| failed.set(true));
         let response = Response::Error {
             id: request_id.to_string(),
             message: message.to_string(),
         };
 
-        match serde_json::to_string(&response) {
-            Ok(json) => match self.tx_message.send(Message::Text(json)).await {
-                Ok(_) => true,
-                Err(e) => {
-                    error!("Error sending error response: {}", e);
-                    false
-                }
-            },
-            Err(e) => {
-                error!("Failed to serialize error response: {}", e);
-                false
-            }
-        }
+        self.send_message(response).await
+    }
+
+    /// Track a new notification stream of this client
+    fn add_stream(&self, stream_id: String, task: JoinHandle<()>) {
+        self.metrics.stream_opened();
+        self.active_streams.add_task(stream_id, task);
     }
 
     async fn create_outgoing_task(
         mut sender: SplitSink<WebSocket, Message>,
-        mut rx_message: mpsc::Receiver<Message>,
+        mut rx_message: mpsc::Receiver<Response>,
     ) {
         while let Some(msg) = rx_message.recv().await {
-            if let Err(e) = sender.send(msg).await {
+            let json = match serde_json::to_string(&msg) {
+                Ok(json) => json,
+                Err(e) => {
+                    error!("Failed to serialize message: {}", e);
+                    continue;
+                }
+            };
+
+            if let Err(e) = sender.send(Message::Text(json)).await {
                 error!("Failed to send message to client: {}", e);
                 break;
             }
@@ -103,18 +168,13 @@ impl SocketContext {
     }
 
     async fn create_notification_task(
-        tx_message: mpsc::Sender<Message>,
+        tx_message: mpsc::Sender<Response>,
         mut rx_notification: mpsc::Receiver<Response>,
     ) {
         while let Some(notification) = rx_notification.recv().await {
-            match serde_json::to_string(&notification) {
-                Ok(json) => {
-                    if let Err(e) = tx_message.send(Message::Text(json)).await {
-                        error!("Failed to forward notification: {}", e);
-                        break;
-                    }
-                }
-                Err(e) => error!("Failed to serialize notification: {}", e),
+            if let Err(e) = tx_message.send(notification).await {
+                error!("Failed to forward notification: {}", e);
+                break;
             }
         }
         debug!("Notification forwarder task ending");
@@ -122,24 +182,47 @@ impl SocketContext {
 }
 
 // Struct to track active notification streams
-struct ActiveStreams {
+pub(crate) struct ActiveStreams {
     // Map of stream ID to cancellation handle
     tasks: DashMap<String, JoinHandle<()>>,
 }
 
 impl ActiveStreams {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
             tasks: DashMap::new(),
         }
     }
 
-    fn add_task(&self, id: String, handle: JoinHandle<()>) {
+    pub(crate) fn add_task(&self, id: String, handle: JoinHandle<()>) {
         if let Some(old_handle) = self.tasks.insert(id, handle) {
             old_handle.abort();
         }
     }
 
+    pub(crate) fn abort_all(&self) {
+        for handle in self.tasks.iter() {
+            handle.abort();
+        }
+    }
+
+    /// Take the task of a stream out, without aborting it
+    pub(crate) fn take(&self, id: &str) -> Option<JoinHandle<()>> {
+        self.tasks.remove(id).map(|(_, handle)| handle)
+    }
+
+    /// Take all the tasks out, without aborting them
+    pub(crate) fn drain(&self) -> Vec<(String, JoinHandle<()>)> {
+        let ids = self
+            .tasks
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect::<Vec<_>>();
+        ids.into_iter()
+            .filter_map(|id| self.tasks.remove(&id))
+            .collect()
+    }
+
     // fn remove_task(&mut self, id: &str) {
     //     if let Some((_, handle)) = self.tasks.remove(id) {
     //         handle.abort();
@@ -154,8 +237,7 @@ pub async fn handle_socket(socket: WebSocket, state: AppState) {
     let (tx_message, rx_message) = mpsc::channel(32);
 
     let ctx = Arc::new(SocketContext::new(
-        state.sdk.clone(),
-        state.nwc,
+        &state,
         tx_message.clone(),
         tx_notification,
     ));
@@ -170,7 +252,7 @@ pub async fn handle_socket(socket: WebSocket, state: AppState) {
         rx_notification,
     ));
 
-    let mut authenticated = false;
+    let mut session = Session::Unauthenticated;
 
     // Process incoming messages
     while let Some(Ok(message)) = receiver.next().await {
@@ -181,10 +263,11 @@ pub async fn handle_socket(socket: WebSocket, state: AppState) {
             match serde_json::from_str(&text) {
                 Ok(CommandWithId {
                     id,
-                    cmd: Command::Auth { token },
+                    cmd: Command::Auth(AuthParams { token }),
+                    ..
                 }) => {
-                    if token == state.auth_token {
-                        authenticated = true;
+                    if let Some(authenticated) = Session::authenticate(&state, &token) {
+                        session = authenticated;
                         let response = Response::Success {
                             id: id.clone(),
                             data: ResponseData::AuthSuccess {
@@ -200,19 +283,92 @@ pub async fn handle_socket(socket: WebSocket, state: AppState) {
                         break; // Close connection on auth failure
                     }
                 }
+                Ok(
+                    command @ CommandWithId {
+                        cmd: Command::Resume(_),
+                        ..
+                    },
+                ) if !matches!(session, Session::Unauthenticated) => {
+                    if let Err(e) =
+                        auth::authorize(&state, &session, &command, Transport::Websocket)
+                    {
+                        if !ctx.send_error_message(&command.id, &e).await {
+                            break;
+                        }
+                        continue;
+                    }
+
+                    let CommandWithId {
+                        id,
+                        cmd: Command::Resume(ResumeParams { stream_ids }),
+                        ..
+                    } = command
+                    else {
+                        unreachable!("matched above");
+                    };
+
+                    let mut resumed = Vec::new();
+                    let mut expired = Vec::new();
+                    for stream_id in stream_ids {
+                        if state
+                            .streams
+                            .resume(
+                                &stream_id,
+                                &session,
+                                &ctx.notifier.tx_notification,
+                                &ctx.active_streams,
+                            )
+                            .await
+                        {
+                            resumed.push(stream_id);
+                        } else {
+                            expired.push(stream_id);
+                        }
+                    }
+
+                    let response = Response::Success {
+                        id,
+                        data: ResponseData::Resumed { resumed, expired },
+                    };
+                    if !ctx.send_message(response).await {
+                        break;
+                    }
+                }
                 Ok(command) => {
-                    if !authenticated {
+                    if matches!(session, Session::Unauthenticated) {
                         let _ = ctx
                             .send_error_message(&command.id, "Not authenticated")
                             .await;
                         break; // Close connection
                     }
 
-                    let ctx_clone = ctx.clone();
+                    // Resolved first, so that refused identities don't count against the
+                    // spending limit of API keys
+                    let ctx_clone = match session.resolve(&state, command.identity.as_deref()) {
+                        Ok(Some(identity)) => Arc::new(ctx.with_identity(identity)),
+                        Ok(None) => ctx.clone(),
+                        Err(e) => {
+                            if !ctx.send_error_message(&command.id, &e).await {
+                                break;
+                            }
+                            continue;
+                        }
+                    };
+
+                    let charge =
+                        match auth::authorize(&state, &session, &command, Transport::Websocket) {
+                            Ok(charge) => charge,
+                            Err(e) => {
+                                if !ctx.send_error_message(&command.id, &e).await {
+                                    break;
+                                }
+                                continue;
+                            }
+                        };
 
                     tokio::task::spawn(async move {
                         // Handle authenticated commands
-                        handle_command(command, ctx_clone).await;
+                        handle_command(command, ctx_clone, charge).await;
                     });
                 }
                 Err(e) => {
@@ -237,12 +393,11 @@ pub async fn handle_socket(socket: WebSocket, state: AppState) {
         }
     }
 
-    // Clean up notification streams when the socket is closed
-    {
-        // let mut active_streams = active_streams.lock().unwrap();
-        for handle in ctx.active_streams.tasks.iter() {
-            handle.abort();
-        }
+    // Keep the streams running for a while, so that the client can resume them after reconnecting
+    if matches!(session, Session::Unauthenticated) {
+        ctx.active_streams.abort_all();
+    } else {
+        state.streams.detach(&session, &ctx.active_streams);
     }
 
     // Also abort all tasks
@@ -252,19 +407,82 @@ pub async fn handle_socket(socket: WebSocket, state: AppState) {
     info!("WebSocket connection closed");
 }
 
-async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
+/// Run an authorized command, refunding its `charge` to the API key if it fails
+pub(crate) async fn handle_command(
+    command: CommandWithId,
+    ctx: Arc<SocketContext>,
+    charge: Option<Charge>,
+) {
+    let name = command.cmd.name();
+    let started = Instant::now();
+    let metrics = ctx.metrics.clone();
+
+    let failed = COMMAND_FAILED
+        .scope(Cell::new(false), async move {
+            execute_command(command, ctx).await;
+            COMMAND_FAILED.with(Cell::get)
+        })
+        .await;
+    metrics.command_finished(name, !failed, started.elapsed());
+
+    if failed {
+        if let Some(charge) = charge {
+            charge.refund();
+        }
+    }
+}
+
+async fn execute_command(command: CommandWithId, ctx: Arc<SocketContext>) {
     match command.cmd {
-        Command::Auth { .. } => {
+        Command::Auth(_) | Command::Resume(_) => {
             // Already handled in the outer function
         }
-        Command::NewKeyHandshakeUrl { static_token } => {
-            match ctx.sdk.new_key_handshake_url(static_token).await {
-                Ok((url, notification_stream)) => {
+        Command::NewKeyHandshakeUrl(NewKeyHandshakeUrlParams {
+            static_token,
+            expires_at,
+            qr,
+        }) => {
+            let url = match ctx.sdk.prepare_key_handshake_url(static_token, expires_at) {
+                Ok(url) => url,
+                Err(e) => {
+                    let _ = ctx
+                        .send_error_message(
+                            &command.id,
+                            &format!("Failed to create auth init URL: {}", e),
+                        )
+                        .await;
+                    return;
+                }
+            };
+
+            // Render the QR code before listening, so a failure doesn't leave a live conversation behind
+            let qr = match qr {
+                Some(params) => {
+                    match sdk::qr::render(&url.to_string(), params.format, &params.options) {
+                        Ok(image) => Some(image.into()),
+                        Err(e) => {
+                            let _ = ctx
+                                .send_error_message(
+                                    &command.id,
+                                    &format!("Failed to render QR code: {}", e),
+                                )
+                                .await;
+                            return;
+                        }
+                    }
+                }
+                None => None,
+            };
+
+            match ctx.sdk.listen_for_key_handshake(&url).await {
+                Ok(notification_stream) => {
+                    let url = url.to_string();
+
                     // Generate a unique stream ID
                     let stream_id = Uuid::new_v4().to_string();
 
                     // Setup notification forwarding
-                    let tx_clone = ctx.tx_notification.clone();
+                    let tx_clone = ctx.notifier.clone();
                     let relay_pool = ctx.sdk.relay_pool();
                     let stream_id_clone = stream_id.clone();
 
@@ -303,6 +521,7 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                                 data: NotificationData::KeyHandshake {
                                     main_key: event.main_key.to_string(),
                                     preferred_relays,
+                                    capabilities: event.client_info.capabilities,
                                 },
                             };
 
@@ -317,15 +536,12 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                     });
 
                     // Store the task
-                    ctx.active_streams.add_task(stream_id.clone(), task);
+                    ctx.add_stream(stream_id.clone(), task);
 
                     // Convert the URL to a proper response struct
                     let response = Response::Success {
                         id: command.id,
-                        data: ResponseData::KeyHandshakeUrl {
-                            url: url.to_string(),
-                            stream_id,
-                        },
+                        data: ResponseData::KeyHandshakeUrl { url, stream_id, qr },
                     };
 
                     let _ = ctx.send_message(response).await;
@@ -340,7 +556,7 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                 }
             }
         }
-        Command::AuthenticateKey { main_key, subkeys } => {
+        Command::AuthenticateKey(AuthenticateKeyParams { main_key, subkeys }) => {
             // Parse keys
             let main_key = match hex_to_pubkey(&main_key) {
                 Ok(key) => key,
@@ -388,11 +604,11 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                 }
             }
         }
-        Command::RequestRecurringPayment {
+        Command::RequestRecurringPayment(RequestRecurringPaymentParams {
             main_key,
             subkeys,
             payment_request,
-        } => {
+        }) => {
             // Parse keys
             let main_key = match hex_to_pubkey(&main_key) {
                 Ok(key) => key,
@@ -437,11 +653,11 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                 }
             }
         }
-        Command::RequestSinglePayment {
+        Command::RequestSinglePayment(RequestSinglePaymentParams {
             main_key,
             subkeys,
             payment_request,
-        } => {
+        }) => {
             let nwc = match &ctx.nwc {
                 Some(nwc) => nwc,
                 None => {
@@ -523,46 +739,43 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
 
             // Generate a unique stream ID
             let stream_id = Uuid::new_v4().to_string();
-            let tx_clone = ctx.tx_notification.clone();
+            let tx_clone = ctx.notifier.clone();
             let nwc_clone = nwc.clone();
 
             let stream_id_clone = stream_id.clone();
             let monitor = Mutex::new(None);
+            let metrics = ctx.metrics.clone();
+            let started = Instant::now();
             let task = tokio::spawn(async move {
                 while let Some(notification) = notifications.next().await {
                     match notification {
                         Ok(status) => {
-                            let notification = match &status.status {
-                                PaymentStatus::Failed { reason } => {
-                                    NotificationData::PaymentStatusUpdate {
-                                        status: InvoiceStatus::UserFailed {
-                                            reason: reason.clone(),
-                                        },
-                                    }
-                                }
-                                PaymentStatus::Rejected { reason } => {
-                                    NotificationData::PaymentStatusUpdate {
-                                        status: InvoiceStatus::UserRejected {
-                                            reason: reason.clone(),
-                                        },
-                                    }
-                                }
-                                PaymentStatus::Success { preimage } => {
-                                    NotificationData::PaymentStatusUpdate {
-                                        status: InvoiceStatus::UserSuccess {
-                                            preimage: preimage.clone(),
-                                        },
-                                    }
-                                }
-                                PaymentStatus::Approved => NotificationData::PaymentStatusUpdate {
-                                    status: InvoiceStatus::UserApproved,
+                            let invoice_status = match &status.status {
+                                PaymentStatus::Failed { reason } => InvoiceStatus::UserFailed {
+                                    reason: reason.clone(),
+                                },
+                                PaymentStatus::Rejected { reason } => InvoiceStatus::UserRejected {
+                                    reason: reason.clone(),
                                 },
+                                PaymentStatus::Success { preimage } => InvoiceStatus::UserSuccess {
+                                    preimage: preimage.clone(),
+                                },
+                                PaymentStatus::Approved => InvoiceStatus::UserApproved,
                             };
+                            if status.status.is_final() {
+                                metrics.payment_finished(
+                                    "RequestSinglePayment",
+                                    &invoice_status,
+                                    started.elapsed(),
+                                );
+                            }
 
                             // Convert the event to a notification response
                             let notification = Response::Notification {
                                 id: stream_id_clone.clone(),
-                                data: notification,
+                                data: NotificationData::PaymentStatusUpdate {
+                                    status: invoice_status,
+                                },
                             };
 
                             // Send the notification to the client
@@ -585,21 +798,18 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                             let tx_clone = tx_clone.clone();
                             let nwc_clone = nwc_clone.clone();
                             let invoice_clone = invoice.invoice.clone();
+                            let metrics = metrics.clone();
 
                             *monitor.lock().await = Some(tokio::spawn(async move {
                                 let mut count = 0;
-                                let notification = loop {
+                                let invoice_status = loop {
                                     if Timestamp::now() > expires_at {
-                                        break NotificationData::PaymentStatusUpdate {
-                                            status: InvoiceStatus::Timeout,
-                                        };
+                                        break InvoiceStatus::Timeout;
                                     }
 
                                     count += 1;
                                     if std::env::var("FAKE_PAYMENTS").is_ok() && count > 3 {
-                                        break NotificationData::PaymentStatusUpdate {
-                                            status: InvoiceStatus::Paid { preimage: None },
-                                        };
+                                        break InvoiceStatus::Paid { preimage: None };
                                     }
 
                                     let invoice = nwc_clone
@@ -614,10 +824,8 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                                     match invoice {
                                         Ok(invoice) => {
                                             if invoice.settled_at.is_some() {
-                                                break NotificationData::PaymentStatusUpdate {
-                                                    status: InvoiceStatus::Paid {
-                                                        preimage: invoice.preimage,
-                                                    },
+                                                break InvoiceStatus::Paid {
+                                                    preimage: invoice.preimage,
                                                 };
                                             } else {
                                                 // TODO: incremental delay
@@ -631,19 +839,24 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                                         }
                                         Err(e) => {
                                             error!("Failed to lookup invoice: {}", e);
-                                            break NotificationData::PaymentStatusUpdate {
-                                                status: InvoiceStatus::Error {
-                                                    reason: e.to_string(),
-                                                },
+                                            break InvoiceStatus::Error {
+                                                reason: e.to_string(),
                                             };
                                         }
                                     }
                                 };
+                                metrics.payment_finished(
+                                    "RequestSinglePayment",
+                                    &invoice_status,
+                                    started.elapsed(),
+                                );
 
                                 // Convert the event to a notification response
                                 let notification = Response::Notification {
                                     id: stream_id_clone.clone(),
-                                    data: notification,
+                                    data: NotificationData::PaymentStatusUpdate {
+                                        status: invoice_status,
+                                    },
                                 };
 
                                 // Send the notification to the client
@@ -660,7 +873,7 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
             });
 
             // Store the task
-            ctx.active_streams.add_task(stream_id.clone(), task);
+            ctx.add_stream(stream_id.clone(), task);
 
             let response = Response::Success {
                 id: command.id,
@@ -669,11 +882,11 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
 
             let _ = ctx.send_message(response).await;
         }
-        Command::RequestPaymentRaw {
+        Command::RequestPaymentRaw(RequestPaymentRawParams {
             main_key,
             subkeys,
             payment_request,
-        } => {
+        }) => {
             // Parse keys
             let main_key = match hex_to_pubkey(&main_key) {
                 Ok(key) => key,
@@ -714,39 +927,43 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
 
             // Generate a unique stream ID
             let stream_id = Uuid::new_v4().to_string();
-            let tx_clone = ctx.tx_notification.clone();
+            let tx_clone = ctx.notifier.clone();
 
             // Setup notification forwarding
             let stream_id_clone = stream_id.clone();
+            let metrics = ctx.metrics.clone();
+            let started = Instant::now();
             let task = tokio::spawn(async move {
                 while let Some(notification) = notifications.next().await {
                     match notification {
                         Ok(status) => {
-                            let notification = match status.status {
+                            let is_final = status.status.is_final();
+                            let invoice_status = match status.status {
                                 PaymentStatus::Failed { reason } => {
-                                    NotificationData::PaymentStatusUpdate {
-                                        status: InvoiceStatus::UserFailed { reason },
-                                    }
+                                    InvoiceStatus::UserFailed { reason }
                                 }
                                 PaymentStatus::Rejected { reason } => {
-                                    NotificationData::PaymentStatusUpdate {
-                                        status: InvoiceStatus::UserRejected { reason },
-                                    }
+                                    InvoiceStatus::UserRejected { reason }
                                 }
                                 PaymentStatus::Success { preimage } => {
-                                    NotificationData::PaymentStatusUpdate {
-                                        status: InvoiceStatus::UserSuccess { preimage },
-                                    }
+                                    InvoiceStatus::UserSuccess { preimage }
                                 }
-                                PaymentStatus::Approved => NotificationData::PaymentStatusUpdate {
-                                    status: InvoiceStatus::UserApproved,
-                                },
+                                PaymentStatus::Approved => InvoiceStatus::UserApproved,
                             };
+                            if is_final {
+                                metrics.payment_finished(
+                                    "RequestPaymentRaw",
+                                    &invoice_status,
+                                    started.elapsed(),
+                                );
+                            }
 
                             // Convert the event to a notification response
                             let notification = Response::Notification {
                                 id: stream_id_clone.clone(),
-                                data: notification,
+                                data: NotificationData::PaymentStatusUpdate {
+                                    status: invoice_status,
+                                },
                             };
 
                             // Send the notification to the client
@@ -762,7 +979,7 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
             });
 
             // Store the task
-            ctx.active_streams.add_task(stream_id.clone(), task);
+            ctx.add_stream(stream_id.clone(), task);
 
             let response = Response::Success {
                 id: command.id,
@@ -771,7 +988,7 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
 
             let _ = ctx.send_message(response).await;
         }
-        Command::FetchProfile { main_key } => {
+        Command::FetchProfile(FetchProfileParams { main_key }) => {
             // Parse key
             let main_key = match hex_to_pubkey(&main_key) {
                 Ok(key) => key,
@@ -799,23 +1016,25 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                 }
             }
         }
-        Command::SetProfile { profile } => match ctx.sdk.set_profile(profile.clone()).await {
-            Ok(_) => {
-                let response = Response::Success {
-                    id: command.id,
-                    data: ResponseData::ProfileData {
-                        profile: Some(profile),
-                    },
-                };
+        Command::SetProfile(SetProfileParams { profile }) => {
+            match ctx.sdk.set_profile(profile.clone()).await {
+                Ok(_) => {
+                    let response = Response::Success {
+                        id: command.id,
+                        data: ResponseData::ProfileData {
+                            profile: Some(profile),
+                        },
+                    };
 
-                let _ = ctx.send_message(response).await;
-            }
-            Err(e) => {
-                let _ = ctx
-                    .send_error_message(&command.id, &format!("Failed to set profile: {}", e))
-                    .await;
+                    let _ = ctx.send_message(response).await;
+                }
+                Err(e) => {
+                    let _ = ctx
+                        .send_error_message(&command.id, &format!("Failed to set profile: {}", e))
+                        .await;
+                }
             }
-        },
+        }
         Command::ListenClosedRecurringPayment => {
             match ctx.sdk.listen_closed_recurring_payment().await {
                 Ok(notification_stream) => {
@@ -823,7 +1042,7 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                     let stream_id = Uuid::new_v4().to_string();
 
                     // Setup notification forwarding
-                    let tx_clone = ctx.tx_notification.clone();
+                    let tx_clone = ctx.notifier.clone();
                     let stream_id_clone = stream_id.clone();
 
                     // Create a task to handle the notification stream
@@ -859,7 +1078,7 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                     });
 
                     // Store the task
-                    ctx.active_streams.add_task(stream_id.clone(), task);
+                    ctx.add_stream(stream_id.clone(), task);
 
                     // Convert the URL to a proper response struct
                     let response = Response::Success {
@@ -879,11 +1098,11 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                 }
             }
         }
-        Command::CloseRecurringPayment {
+        Command::CloseRecurringPayment(CloseRecurringPaymentParams {
             main_key,
             subkeys,
             subscription_id,
-        } => {
+        }) => {
             // Parse keys
             let main_key = match hex_to_pubkey(&main_key) {
                 Ok(key) => key,
@@ -930,11 +1149,11 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                 }
             }
         }
-        Command::RequestInvoice {
+        Command::RequestInvoice(RequestInvoiceParams {
             recipient_key,
             subkeys,
             content,
-        } => {
+        }) => {
             // Parse keys
             let recipient_key = match hex_to_pubkey(&recipient_key) {
                 Ok(key) => key,
@@ -999,10 +1218,10 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                 }
             }
         }
-        Command::IssueJwt {
+        Command::IssueJwt(IssueJwtParams {
             target_key,
             duration_hours,
-        } => {
+        }) => {
             let target_key = match hex_to_pubkey(&target_key) {
                 Ok(key) => key,
                 Err(e) => {
@@ -1032,7 +1251,7 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                 }
             }
         }
-        Command::VerifyJwt { pubkey, token } => {
+        Command::VerifyJwt(VerifyJwtParams { pubkey, token }) => {
             let public_key = match hex_to_pubkey(&pubkey) {
                 Ok(key) => key,
                 Err(e) => {
@@ -1061,13 +1280,13 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                 }
             }
         }
-        Command::RequestCashu {
+        Command::RequestCashu(RequestCashuParams {
             recipient_key,
             subkeys,
             mint_url,
             unit,
             amount,
-        } => {
+        }) => {
             // Parse keys
             let recipient_key = match hex_to_pubkey(&recipient_key) {
                 Ok(key) => key,
@@ -1120,11 +1339,11 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
             }
         }
 
-        Command::SendCashuDirect {
+        Command::SendCashuDirect(SendCashuDirectParams {
             main_key,
             subkeys,
             token,
-        } => {
+        }) => {
             // Parse keys
             let main_key = match hex_to_pubkey(&main_key) {
                 Ok(key) => key,
@@ -1171,13 +1390,13 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                 }
             }
         }
-        Command::MintCashu {
+        Command::MintCashu(MintCashuParams {
             mint_url,
             static_auth_token,
             unit,
             amount,
             description,
-        } => {
+        }) => {
             // Mint tokens using cdk wallet
             let ctx_clone = ctx.clone();
             let command_id = command.id.clone();
@@ -1280,12 +1499,12 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                 let _ = ctx_clone.send_message(response).await;
             });
         }
-        Command::BurnCashu {
+        Command::BurnCashu(BurnCashuParams {
             mint_url,
             unit,
             token,
             static_auth_token,
-        } => {
+        }) => {
             // Burn tokens using cdk wallet
             let ctx_clone = ctx.clone();
             let command_id = command.id.clone();
@@ -1347,7 +1566,7 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                 let _ = ctx_clone.send_message(response).await;
             });
         }
-        Command::AddRelay { relay } => {
+        Command::AddRelay(AddRelayParams { relay }) => {
             let command_id = command.id.clone();
             let ctx_clone = ctx.clone();
 
@@ -1366,7 +1585,7 @@ async fn handle_command(command: CommandWithId, ctx: Arc<SocketContext>) {
                 let _ = ctx_clone.send_message(response).await;
             });
         }
-        Command::RemoveRelay { relay } => {
+        Command::RemoveRelay(RemoveRelayParams { relay }) => {
             let command_id = command.id.clone();
             let ctx_clone = ctx.clone();
 
@@ -1,60 +1,148 @@
-use std::sync::Arc;
+pub mod qr;
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+};
 
 use chrono::Duration;
+use lightning_invoice::Bolt11Invoice;
 use portal::{
     cashu::{CashuDirectSenderConversation, CashuRequestSenderConversation},
     close_subscription::{
         CloseRecurringPaymentConversation, CloseRecurringPaymentReceiverConversation,
     },
     invoice::InvoiceRequestConversation,
+    issuer::{
+        IssuanceLog, Issuer, IssuerError,
+        conversation::{
+            CertificateIssueSenderConversation, CertificateRevocationSenderConversation,
+        },
+    },
     nostr::key::PublicKey,
-    nostr_relay_pool::{RelayOptions, RelayPool},
+    nostr_relay_pool::{RelayOptions, RelayPool, monitor::Monitor},
     profile::{FetchProfileInfoConversation, Profile, SetProfileConversation},
     protocol::{
         LocalKeypair,
-        key_handshake::KeyHandshakeUrl,
-        model::payment::{
-            CashuDirectContent, CashuRequestContent, CashuResponseContent,
-            CloseRecurringPaymentContent, CloseRecurringPaymentResponse, InvoiceRequestContent,
-            InvoiceResponse, PaymentResponseContent, RecurringPaymentRequestContent,
-            RecurringPaymentResponseContent, SinglePaymentRequestContent,
+        identity::{Certificate, PartialCertificate},
+        key_handshake::{self, KeyHandshakeUrl},
+        model::{
+            Timestamp,
+            auth::Capability,
+            identity::{
+                CertificateIssueContent, CertificateRequestContent, CertificateResponseContent,
+                CertificateRevocationContent,
+            },
+            payment::{
+                CashuDirectContent, CashuRequestContent, CashuResponseContent,
+                CloseRecurringPaymentContent, CloseRecurringPaymentResponse, InvoiceRequestContent,
+                InvoiceResponse, PaymentResponseContent, PaymentStatus,
+                RecurringPaymentRequestContent, RecurringPaymentResponseContent,
+                SinglePaymentRequestContent,
+            },
+            ticket::{TicketPresentationContent, TicketScanRequestContent},
         },
+        ticket::TicketData,
+        trust::{SharedTrustStore, TrustDecision, TrustedIssuer},
     },
     router::{
-        ConversationError, MessageRouter, MessageRouterActorError, MultiKeyListenerAdapter,
-        MultiKeySenderAdapter, NotificationStream, adapters::one_shot::OneShotSenderAdapter,
+        ConversationError, MessageRouter, MessageRouterActorConfig, MessageRouterActorError,
+        MultiKeyListenerAdapter, MultiKeySenderAdapter, NotificationStream,
+        adapters::one_shot::OneShotSenderAdapter, channel::Channel,
     },
     sdk::{
         auth::{
             AuthChallengeSenderConversation, AuthResponseEvent, KeyHandshakeEvent,
-            KeyHandshakeReceiverConversation,
+            KeyHandshakeReceiverConversation, PeerCapabilities,
         },
+        certificates::CertificateRequestSenderConversation,
         payments::{
             RecurringPaymentRequestSenderConversation, SinglePaymentRequestSenderConversation,
         },
+        tickets::TicketScanSenderConversation,
     },
     utils::verify_nip05,
 };
+use sha2::{Digest, Sha256};
 use tokio::task::JoinHandle;
 
-pub struct PortalSDK {
-    router: Arc<MessageRouter<Arc<RelayPool>>>,
+pub struct PortalSDK<C = Arc<RelayPool>> {
+    router: Arc<MessageRouter<C>>,
+    /// The identity this instance acts as, either the main keypair of the router or one of its
+    /// additional identities
+    keypair: LocalKeypair,
     prefererred_relays: Vec<String>,
-    relay_pool: Arc<RelayPool>,
-    _listener: JoinHandle<Result<(), MessageRouterActorError>>,
+    peers: PeerCapabilities,
+    /// The issuers whose certificates this identity accepts
+    trust: SharedTrustStore,
+    _listener: Arc<JoinHandle<Result<(), MessageRouterActorError>>>,
 }
 
 impl PortalSDK {
     pub async fn new(keypair: LocalKeypair, relays: Vec<String>) -> Result<Self, PortalSDKError> {
-        let relay_pool = RelayPool::new();
+        Self::new_with_config(keypair, relays, MessageRouterActorConfig::default()).await
+    }
+
+    /// Create an SDK instance whose router uses `config`
+    ///
+    /// If `config` carries enabled metrics, the status changes of the relays are recorded too.
+    pub async fn new_with_config(
+        keypair: LocalKeypair,
+        relays: Vec<String>,
+        config: MessageRouterActorConfig,
+    ) -> Result<Self, PortalSDKError> {
+        let relay_pool = if config.metrics.is_enabled() {
+            let relay_pool = RelayPool::builder().monitor(Monitor::new(4096)).build();
+            if let Some(monitor) = relay_pool.monitor() {
+                config.metrics.watch_relays(monitor.subscribe());
+            }
+            relay_pool
+        } else {
+            RelayPool::new()
+        };
         for relay in &relays {
             relay_pool.add_relay(relay, RelayOptions::default()).await?;
         }
         relay_pool.connect().await;
         let relay_pool = Arc::new(relay_pool);
 
-        let router = Arc::new(MessageRouter::new(Arc::clone(&relay_pool), keypair.clone()));
+        let router = Arc::new(MessageRouter::new_with_config(relay_pool, keypair, config));
+        Self::new_with_router(router, relays).await
+    }
 
+    pub async fn add_relay(&self, url: String) -> Result<(), PortalSDKError> {
+        let relay_pool = self.router.channel();
+        relay_pool.add_relay(&url, RelayOptions::default()).await?;
+        relay_pool.connect_relay(&url).await?;
+        self.router.add_relay(url, true).await?;
+        Ok(())
+    }
+
+    pub async fn remove_relay(&self, url: String) -> Result<(), PortalSDKError> {
+        self.router.channel().remove_relay(&url).await?;
+        self.router.remove_relay(url).await?;
+        Ok(())
+    }
+
+    pub fn relay_pool(&self) -> Arc<RelayPool> {
+        Arc::clone(self.router.channel().as_ref())
+    }
+}
+
+impl<C> PortalSDK<C>
+where
+    C: Channel + Send + Sync + 'static,
+    C::Error: From<portal::nostr::types::url::Error>,
+{
+    /// Create an SDK instance on top of an existing router.
+    ///
+    /// This allows running the SDK on any [`Channel`], for example the simulated channel
+    /// exported by the `testing` feature.
+    pub async fn new_with_router(
+        router: Arc<MessageRouter<C>>,
+        relays: Vec<String>,
+    ) -> Result<Self, PortalSDKError> {
         for relay in &relays {
             router.add_relay(relay.clone(), false).await?;
         }
@@ -63,17 +151,60 @@ impl PortalSDK {
         let _listener = tokio::spawn(async move { _router.listen().await });
 
         Ok(Self {
+            keypair: router.keypair().clone(),
             router,
-            relay_pool,
             prefererred_relays: relays,
-            _listener,
+            peers: PeerCapabilities::new(),
+            trust: SharedTrustStore::default(),
+            _listener: Arc::new(_listener),
+        })
+    }
+
+    /// Create an SDK instance that acts as another identity on the same router
+    ///
+    /// The new instance shares the relays of this one, but signs, encrypts and receives
+    /// messages with `keypair`. This allows a single process to serve many services.
+    pub async fn with_identity(&self, keypair: LocalKeypair) -> Result<Self, PortalSDKError> {
+        self.router.add_identity(keypair.clone()).await?;
+
+        Ok(Self {
+            router: Arc::clone(&self.router),
+            keypair,
+            prefererred_relays: self.prefererred_relays.clone(),
+            peers: PeerCapabilities::new(),
+            trust: SharedTrustStore::default(),
+            _listener: Arc::clone(&self._listener),
         })
     }
 
+    /// The public key of the identity this instance acts as
+    pub fn public_key(&self) -> PublicKey {
+        self.keypair.public_key()
+    }
+
+    /// Create a new key handshake url, signed by our key
+    ///
+    /// If `expires_at` is set, the url is refused by the app after that time and handshakes
+    /// received after that time are ignored.
     pub async fn new_key_handshake_url(
         &self,
         static_token: Option<String>,
+        expires_at: Option<Timestamp>,
     ) -> Result<(KeyHandshakeUrl, NotificationStream<KeyHandshakeEvent>), PortalSDKError> {
+        let url = self.prepare_key_handshake_url(static_token, expires_at)?;
+        let event = self.listen_for_key_handshake(&url).await?;
+
+        Ok((url, event))
+    }
+
+    /// Build and sign a key handshake url without listening for it yet
+    ///
+    /// Use [`Self::listen_for_key_handshake`] once the url is ready to be shown to the user.
+    pub fn prepare_key_handshake_url(
+        &self,
+        static_token: Option<String>,
+        expires_at: Option<Timestamp>,
+    ) -> Result<KeyHandshakeUrl, PortalSDKError> {
         let token = static_token.unwrap_or_else(|| {
             format!(
                 "token_{}",
@@ -84,35 +215,74 @@ impl PortalSDK {
             )
         });
 
-        let inner = KeyHandshakeReceiverConversation::new(
-            self.router.keypair().public_key(),
-            token.clone(),
-        );
-        let event = self
-            .router
-            .add_and_subscribe(Box::new(MultiKeyListenerAdapter::new(
-                inner,
-                self.router.keypair().subkey_proof().cloned(),
-            )))
-            .await?;
-
-        let (main_key, subkey) = if let Some(subkey_proof) = self.router.keypair().subkey_proof() {
+        let (main_key, subkey) = if let Some(subkey_proof) = self.keypair.subkey_proof() {
             (
                 subkey_proof.main_key.into(),
-                Some(self.router.keypair().public_key()),
+                Some(self.keypair.public_key()),
             )
         } else {
-            (self.router.keypair().public_key(), None)
+            (self.keypair.public_key(), None)
         };
 
-        let url = KeyHandshakeUrl {
+        let mut url = KeyHandshakeUrl {
             main_key: main_key.into(),
             relays: self.prefererred_relays.clone(),
-            token: token.clone(),
+            token,
             subkey: subkey.map(|k| k.into()),
+            expires_at,
+            signature: None,
         };
+        url.sign(&self.keypair)?;
 
-        Ok((url, event))
+        Ok(url)
+    }
+
+    /// Listen for the key handshakes sent to a url built by [`Self::prepare_key_handshake_url`]
+    pub async fn listen_for_key_handshake(
+        &self,
+        url: &KeyHandshakeUrl,
+    ) -> Result<NotificationStream<KeyHandshakeEvent>, PortalSDKError> {
+        let inner =
+            KeyHandshakeReceiverConversation::new(self.keypair.public_key(), url.token.clone())
+                .with_expiration(url.expires_at)
+                .with_peer_capabilities(self.peers.clone());
+        let event = self
+            .router
+            .add_and_subscribe_as(
+                self.keypair.public_key(),
+                Box::new(MultiKeyListenerAdapter::new(
+                    inner,
+                    self.keypair.subkey_proof().cloned(),
+                )),
+            )
+            .await?;
+
+        Ok(event)
+    }
+
+    /// The capabilities advertised by `main_key` during the key handshake
+    ///
+    /// Returns `None` if no handshake was received from this user, or if the user's client
+    /// doesn't advertise its capabilities.
+    pub fn peer_capabilities(&self, main_key: &PublicKey) -> Option<HashSet<Capability>> {
+        self.peers.get(main_key)
+    }
+
+    /// Restore the capabilities of a user, for example after a restart
+    pub fn set_peer_capabilities(&self, main_key: PublicKey, capabilities: Vec<Capability>) {
+        self.peers.insert(main_key, capabilities);
+    }
+
+    fn require_capability(
+        &self,
+        main_key: &PublicKey,
+        capability: Capability,
+    ) -> Result<(), PortalSDKError> {
+        if self.peers.supports(main_key, capability) {
+            Ok(())
+        } else {
+            Err(PortalSDKError::UnsupportedByPeer(capability))
+        }
     }
 
     pub async fn authenticate_key(
@@ -120,16 +290,21 @@ impl PortalSDK {
         main_key: PublicKey,
         subkeys: Vec<PublicKey>,
     ) -> Result<AuthResponseEvent, PortalSDKError> {
+        self.require_capability(&main_key, Capability::Auth)?;
+
         let conv = AuthChallengeSenderConversation::new(
-            self.router.keypair().public_key(),
-            self.router.keypair().subkey_proof().cloned(),
+            self.keypair.public_key(),
+            self.keypair.subkey_proof().cloned(),
         );
 
         let mut event = self
             .router
-            .add_and_subscribe(Box::new(MultiKeySenderAdapter::new_with_user(
-                main_key, subkeys, conv,
-            )))
+            .add_and_subscribe_as(
+                self.keypair.public_key(),
+                Box::new(MultiKeySenderAdapter::new_with_user(
+                    main_key, subkeys, conv,
+                )),
+            )
             .await?;
         Ok(event.next().await.ok_or(PortalSDKError::Timeout)??)
     }
@@ -140,17 +315,22 @@ impl PortalSDK {
         subkeys: Vec<PublicKey>,
         payment_request: RecurringPaymentRequestContent,
     ) -> Result<RecurringPaymentResponseContent, PortalSDKError> {
+        self.require_capability(&main_key, Capability::RecurringPayment)?;
+
         let conv = RecurringPaymentRequestSenderConversation::new(
-            self.router.keypair().public_key(),
-            self.router.keypair().subkey_proof().cloned(),
+            self.keypair.public_key(),
+            self.keypair.subkey_proof().cloned(),
             payment_request,
         );
 
         let mut event = self
             .router
-            .add_and_subscribe(Box::new(MultiKeySenderAdapter::new_with_user(
-                main_key, subkeys, conv,
-            )))
+            .add_and_subscribe_as(
+                self.keypair.public_key(),
+                Box::new(MultiKeySenderAdapter::new_with_user(
+                    main_key, subkeys, conv,
+                )),
+            )
             .await?;
         Ok(event.next().await.ok_or(PortalSDKError::Timeout)??)
     }
@@ -161,17 +341,22 @@ impl PortalSDK {
         subkeys: Vec<PublicKey>,
         payment_request: SinglePaymentRequestContent,
     ) -> Result<NotificationStream<PaymentResponseContent>, PortalSDKError> {
+        self.require_capability(&main_key, Capability::SinglePayment)?;
+
         let conv = SinglePaymentRequestSenderConversation::new(
-            self.router.keypair().public_key(),
-            self.router.keypair().subkey_proof().cloned(),
+            self.keypair.public_key(),
+            self.keypair.subkey_proof().cloned(),
             payment_request,
         );
 
         let event = self
             .router
-            .add_and_subscribe(Box::new(MultiKeySenderAdapter::new_with_user(
-                main_key, subkeys, conv,
-            )))
+            .add_and_subscribe_as(
+                self.keypair.public_key(),
+                Box::new(MultiKeySenderAdapter::new_with_user(
+                    main_key, subkeys, conv,
+                )),
+            )
             .await?;
         Ok(event)
     }
@@ -181,7 +366,10 @@ impl PortalSDK {
         main_key: PublicKey,
     ) -> Result<Option<Profile>, PortalSDKError> {
         let conv = FetchProfileInfoConversation::new(main_key);
-        let mut event = self.router.add_and_subscribe(Box::new(conv)).await?;
+        let mut event = self
+            .router
+            .add_and_subscribe_as(self.keypair.public_key(), Box::new(conv))
+            .await?;
         let profile: Option<Profile> = event.next().await.ok_or(PortalSDKError::Timeout)??;
 
         if let Some(mut profile) = profile {
@@ -198,18 +386,21 @@ impl PortalSDK {
     }
 
     pub async fn set_profile(&self, profile: Profile) -> Result<(), PortalSDKError> {
-        if self.router.keypair().subkey_proof().is_some() {
+        if self.keypair.subkey_proof().is_some() {
             return Err(PortalSDKError::MasterKeyRequired);
         }
 
         let conv = SetProfileConversation::new(profile);
         let _ = self
             .router
-            .add_conversation(Box::new(OneShotSenderAdapter::new_with_user(
-                self.router.keypair().public_key().into(),
-                vec![],
-                conv,
-            )))
+            .add_conversation_as(
+                self.keypair.public_key(),
+                Box::new(OneShotSenderAdapter::new_with_user(
+                    self.keypair.public_key().into(),
+                    vec![],
+                    conv,
+                )),
+            )
             .await?;
 
         Ok(())
@@ -218,14 +409,16 @@ impl PortalSDK {
     pub async fn listen_closed_recurring_payment(
         &self,
     ) -> Result<NotificationStream<CloseRecurringPaymentResponse>, PortalSDKError> {
-        let inner =
-            CloseRecurringPaymentReceiverConversation::new(self.router.keypair().public_key());
+        let inner = CloseRecurringPaymentReceiverConversation::new(self.keypair.public_key());
         let event = self
             .router
-            .add_and_subscribe(Box::new(MultiKeyListenerAdapter::new(
-                inner,
-                self.router.keypair().subkey_proof().cloned(),
-            )))
+            .add_and_subscribe_as(
+                self.keypair.public_key(),
+                Box::new(MultiKeyListenerAdapter::new(
+                    inner,
+                    self.keypair.subkey_proof().cloned(),
+                )),
+            )
             .await?;
         Ok(event)
     }
@@ -236,6 +429,8 @@ impl PortalSDK {
         subkeys: Vec<PublicKey>,
         subscription_id: String,
     ) -> Result<(), PortalSDKError> {
+        self.require_capability(&main_key, Capability::RecurringPayment)?;
+
         let content = CloseRecurringPaymentContent {
             subscription_id,
             reason: None,
@@ -244,9 +439,12 @@ impl PortalSDK {
 
         let conv = CloseRecurringPaymentConversation::new(content);
         self.router
-            .add_conversation(Box::new(MultiKeySenderAdapter::new_with_user(
-                main_key, subkeys, conv,
-            )))
+            .add_conversation_as(
+                self.keypair.public_key(),
+                Box::new(MultiKeySenderAdapter::new_with_user(
+                    main_key, subkeys, conv,
+                )),
+            )
             .await?;
         Ok(())
     }
@@ -257,16 +455,21 @@ impl PortalSDK {
         subkeys: Vec<PublicKey>,
         content: InvoiceRequestContent,
     ) -> Result<Option<InvoiceResponse>, PortalSDKError> {
+        self.require_capability(&recipient, Capability::Invoice)?;
+
         let conv = InvoiceRequestConversation::new(
-            self.router.keypair().public_key(),
-            self.router.keypair().subkey_proof().cloned(),
+            self.keypair.public_key(),
+            self.keypair.subkey_proof().cloned(),
             content,
         );
         let mut rx = self
             .router
-            .add_and_subscribe(Box::new(MultiKeySenderAdapter::new_with_user(
-                recipient, subkeys, conv,
-            )))
+            .add_and_subscribe_as(
+                self.keypair.public_key(),
+                Box::new(MultiKeySenderAdapter::new_with_user(
+                    recipient, subkeys, conv,
+                )),
+            )
             .await?;
 
         if let Ok(invoice_response) = rx.next().await.ok_or(PortalSDKError::Timeout)? {
@@ -281,9 +484,8 @@ impl PortalSDK {
         claims: portal::protocol::jwt::CustomClaims,
         duration: Duration,
     ) -> Result<String, PortalSDKError> {
-        let token =
-            portal::protocol::jwt::encode(&self.router.keypair().secret_key(), claims, duration)
-                .map_err(PortalSDKError::JwtError)?;
+        let token = portal::protocol::jwt::encode(&self.keypair.secret_key(), claims, duration)
+            .map_err(PortalSDKError::JwtError)?;
         Ok(token)
     }
 
@@ -303,16 +505,21 @@ impl PortalSDK {
         subkeys: Vec<PublicKey>,
         content: CashuRequestContent,
     ) -> Result<Option<CashuResponseContent>, PortalSDKError> {
+        self.require_capability(&main_key, Capability::Cashu)?;
+
         let conv = CashuRequestSenderConversation::new(
-            self.router.keypair().public_key(),
-            self.router.keypair().subkey_proof().cloned(),
+            self.keypair.public_key(),
+            self.keypair.subkey_proof().cloned(),
             content,
         );
         let mut rx: NotificationStream<CashuResponseContent> = self
             .router
-            .add_and_subscribe(Box::new(MultiKeySenderAdapter::new_with_user(
-                main_key, subkeys, conv,
-            )))
+            .add_and_subscribe_as(
+                self.keypair.public_key(),
+                Box::new(MultiKeySenderAdapter::new_with_user(
+                    main_key, subkeys, conv,
+                )),
+            )
             .await?;
 
         if let Ok(cashu_response) = rx.next().await.ok_or(PortalSDKError::Timeout)? {
@@ -327,33 +534,250 @@ impl PortalSDK {
         subkeys: Vec<PublicKey>,
         content: CashuDirectContent,
     ) -> Result<(), PortalSDKError> {
+        self.require_capability(&main_key, Capability::Cashu)?;
+
         let conv = CashuDirectSenderConversation::new(content);
         self.router
-            .add_conversation(Box::new(MultiKeySenderAdapter::new_with_user(
-                main_key, subkeys, conv,
-            )))
+            .add_conversation_as(
+                self.keypair.public_key(),
+                Box::new(MultiKeySenderAdapter::new_with_user(
+                    main_key, subkeys, conv,
+                )),
+            )
             .await?;
         Ok(())
     }
 
-    pub async fn add_relay(&self, url: String) -> Result<(), PortalSDKError> {
-        self.relay_pool
-            .add_relay(&url, RelayOptions::default())
+    /// Ask a user to disclose some of its certificates
+    pub async fn request_certificates(
+        &self,
+        main_key: PublicKey,
+        subkeys: Vec<PublicKey>,
+        content: CertificateRequestContent,
+    ) -> Result<Option<CertificateResponseContent>, PortalSDKError> {
+        self.require_capability(&main_key, Capability::Certificates)?;
+
+        let conv = CertificateRequestSenderConversation::new(
+            self.keypair.public_key(),
+            self.keypair.subkey_proof().cloned(),
+            content,
+        );
+        let mut rx: NotificationStream<CertificateResponseContent> = self
+            .router
+            .add_and_subscribe_as(
+                self.keypair.public_key(),
+                Box::new(MultiKeySenderAdapter::new_with_user(
+                    main_key, subkeys, conv,
+                )),
+            )
+            .await?;
+
+        if let Ok(response) = rx.next().await.ok_or(PortalSDKError::Timeout)? {
+            return Ok(Some(response));
+        }
+        Ok(None)
+    }
+
+    /// Accept the certificates of `issuer`, according to its policy
+    pub fn add_trusted_issuer(&self, issuer: TrustedIssuer) {
+        self.trust.write().unwrap().add_issuer(issuer);
+    }
+
+    pub fn remove_trusted_issuer(&self, pubkey: &PublicKey) {
+        self.trust.write().unwrap().remove_issuer(pubkey);
+    }
+
+    /// Refuse the certificate with this id from now on
+    pub fn mark_certificate_revoked(&self, certificate_id: String) {
+        self.trust.write().unwrap().mark_revoked(certificate_id);
+    }
+
+    /// The trust store used to verify certificates, shared with this instance
+    pub fn trust_store(&self) -> SharedTrustStore {
+        Arc::clone(&self.trust)
+    }
+
+    /// Check a certificate disclosed by `main_key` against the trusted issuers
+    pub fn verify_certificate(
+        &self,
+        main_key: &PublicKey,
+        certificate: &PartialCertificate,
+    ) -> TrustDecision {
+        self.trust.read().unwrap().evaluate(certificate, main_key)
+    }
+
+    /// Check every certificate of a response to [`Self::request_certificates`]
+    ///
+    /// `main_key` is the user the certificates were requested from, certificates issued to
+    /// anybody else are [`TrustDecision::SubjectMismatch`]. Certificates that can't be parsed, or
+    /// whose id doesn't match, are [`TrustDecision::Invalid`].
+    pub fn verify_certificate_response(
+        &self,
+        main_key: &PublicKey,
+        response: &CertificateResponseContent,
+    ) -> HashMap<String, TrustDecision> {
+        let trust = self.trust.read().unwrap();
+
+        response
+            .certificates
+            .iter()
+            .map(|(id, certificate)| {
+                let decision =
+                    match serde_json::from_value::<PartialCertificate>(certificate.clone()) {
+                        Ok(certificate) if certificate.id() == *id => {
+                            trust.evaluate(&certificate, main_key)
+                        }
+                        Ok(_) => TrustDecision::Invalid {
+                            reason: "Certificate id mismatch".to_string(),
+                        },
+                        Err(e) => TrustDecision::Invalid {
+                            reason: e.to_string(),
+                        },
+                    };
+                (id.clone(), decision)
+            })
+            .collect()
+    }
+
+    /// Hand a certificate built with [`portal::issuer::Issuer`] to its subject
+    pub async fn send_certificate(
+        &self,
+        subkeys: Vec<PublicKey>,
+        content: CertificateIssueContent,
+    ) -> Result<(), PortalSDKError> {
+        self.require_capability(&content.certificate.subject, Capability::Certificates)?;
+
+        let conv = CertificateIssueSenderConversation::new(content.clone());
+        self.router
+            .add_conversation_as(
+                self.keypair.public_key(),
+                Box::new(MultiKeySenderAdapter::new_with_user(
+                    content.certificate.subject,
+                    subkeys,
+                    conv,
+                )),
+            )
             .await?;
-        self.relay_pool.connect_relay(&url).await?;
-        self.router.add_relay(url, true).await?;
         Ok(())
     }
 
-    pub async fn remove_relay(&self, url: String) -> Result<(), PortalSDKError> {
-        self.relay_pool.remove_relay(&url).await?;
-        self.router.remove_relay(url).await?;
+    /// Tell the subject of a certificate that it was revoked
+    pub async fn send_certificate_revocation(
+        &self,
+        main_key: PublicKey,
+        subkeys: Vec<PublicKey>,
+        content: CertificateRevocationContent,
+    ) -> Result<(), PortalSDKError> {
+        let conv = CertificateRevocationSenderConversation::new(content);
+        self.router
+            .add_conversation_as(
+                self.keypair.public_key(),
+                Box::new(MultiKeySenderAdapter::new_with_user(
+                    main_key, subkeys, conv,
+                )),
+            )
+            .await?;
         Ok(())
     }
 
-    pub fn relay_pool(&self) -> Arc<RelayPool> {
-        self.relay_pool.clone()
+    /// Ask `main_key` to pay for a ticket, and issue it once the payment succeeds
+    ///
+    /// The ticket is handed to the user with [`Self::send_certificate`]. It is only issued if the
+    /// preimage reported by the user matches the payment hash of the invoice. Returns `None` if
+    /// the payment was rejected, failed, or came without a valid preimage.
+    ///
+    /// Everything that can be checked before the payment is: the peer must support
+    /// certificates, `issuer` must sign with the key of this instance, and `ticket` must be
+    /// issuable. If the ticket is issued but can't be delivered, it is returned in
+    /// [`PortalSDKError::TicketNotDelivered`] so that the delivery can be retried.
+    pub async fn sell_ticket<L: IssuanceLog>(
+        &self,
+        main_key: PublicKey,
+        subkeys: Vec<PublicKey>,
+        payment_request: SinglePaymentRequestContent,
+        issuer: &Issuer<L>,
+        ticket: TicketData,
+    ) -> Result<Option<Certificate>, PortalSDKError> {
+        self.require_capability(&main_key, Capability::Certificates)?;
+        // The app drops certificates that are not sent by their issuer
+        if issuer.public_key() != self.keypair.public_key() {
+            return Err(PortalSDKError::IssuerKeyMismatch);
+        }
+        issuer.check_ticket(main_key, &ticket)?;
+        let invoice = Bolt11Invoice::from_str(&payment_request.invoice)
+            .map_err(|e| PortalSDKError::InvalidInvoice(e.to_string()))?;
+
+        let mut notifications = self
+            .request_single_payment(main_key, subkeys.clone(), payment_request)
+            .await?;
+
+        while let Some(response) = notifications.next().await {
+            match response?.status {
+                PaymentStatus::Success { preimage } => {
+                    if !preimage.is_some_and(|preimage| is_preimage_of(&preimage, &invoice)) {
+                        return Ok(None);
+                    }
+
+                    let certificate = issuer.issue_ticket(main_key, ticket)?;
+                    let content = CertificateIssueContent {
+                        certificate: certificate.clone(),
+                        replaces: None,
+                    };
+                    if let Err(error) = self.send_certificate(subkeys, content).await {
+                        return Err(PortalSDKError::TicketNotDelivered {
+                            certificate: Box::new(certificate),
+                            error: Box::new(error),
+                        });
+                    }
+                    return Ok(Some(certificate));
+                }
+                status if status.is_final() => return Ok(None),
+                _ => {}
+            }
+        }
+
+        Err(PortalSDKError::Timeout)
     }
+
+    /// Ask the holder of a ticket to present it, and prove that it owns it
+    ///
+    /// Check the presentation with a [`portal::protocol::ticket::TicketVerifier`] and the
+    /// challenge of `content`.
+    pub async fn scan_ticket(
+        &self,
+        main_key: PublicKey,
+        subkeys: Vec<PublicKey>,
+        content: TicketScanRequestContent,
+    ) -> Result<Option<TicketPresentationContent>, PortalSDKError> {
+        self.require_capability(&main_key, Capability::Certificates)?;
+
+        let conv = TicketScanSenderConversation::new(
+            self.keypair.public_key(),
+            self.keypair.subkey_proof().cloned(),
+            content,
+        );
+        let mut rx: NotificationStream<TicketPresentationContent> = self
+            .router
+            .add_and_subscribe_as(
+                self.keypair.public_key(),
+                Box::new(MultiKeySenderAdapter::new_with_user(
+                    main_key, subkeys, conv,
+                )),
+            )
+            .await?;
+
+        if let Ok(presentation) = rx.next().await.ok_or(PortalSDKError::Timeout)? {
+            return Ok(Some(presentation));
+        }
+        Ok(None)
+    }
+}
+
+/// Whether the hex encoded `preimage` hashes to the payment hash of `invoice`
+fn is_preimage_of(preimage: &str, invoice: &Bolt11Invoice) -> bool {
+    hex::decode(preimage).is_ok_and(|preimage| {
+        hex::encode(Sha256::digest(preimage)) == invoice.payment_hash().to_string()
+    })
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -378,4 +802,25 @@ pub enum PortalSDKError {
 
     #[error("JWT error: {0}")]
     JwtError(#[from] portal::protocol::jwt::JwtError),
+
+    #[error("Key handshake signing error: {0}")]
+    KeyHandshakeSign(#[from] key_handshake::SignError),
+
+    #[error("Unsupported by peer: {0}")]
+    UnsupportedByPeer(Capability),
+
+    #[error("Issuer error: {0}")]
+    Issuer(#[from] IssuerError),
+
+    #[error("Invalid invoice: {0}")]
+    InvalidInvoice(String),
+
+    #[error("The issuer doesn't sign with the key of this instance")]
+    IssuerKeyMismatch,
+
+    #[error("The ticket was issued but not delivered: {error}")]
+    TicketNotDelivered {
+        certificate: Box<Certificate>,
+        error: Box<PortalSDKError>,
+    },
 }
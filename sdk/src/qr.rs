@@ -0,0 +1,124 @@
+//! QR code rendering for the urls generated by the SDK
+//!
+//! # Example
+//! ```rust,no_run
+//! # async fn example(sdk: sdk::PortalSDK) -> Result<(), Box<dyn std::error::Error>> {
+//! use sdk::qr::{QrFormat, QrImage, QrOptions};
+//!
+//! let (url, _stream) = sdk.new_key_handshake_url(None, None).await?;
+//! if let QrImage::Terminal(text) = sdk::qr::render(&url.to_string(), QrFormat::Terminal, &QrOptions::default())? {
+//!     println!("{}", text);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io::Cursor;
+
+use qrcode::{
+    EcLevel, QrCode,
+    render::{svg, unicode},
+};
+
+pub use portal::qr::{MAX_SIZE, QrErrorCorrection, QrFormat, QrImage, QrOptions};
+
+fn ec_level(level: QrErrorCorrection) -> EcLevel {
+    match level {
+        QrErrorCorrection::Low => EcLevel::L,
+        QrErrorCorrection::Medium => EcLevel::M,
+        QrErrorCorrection::Quartile => EcLevel::Q,
+        QrErrorCorrection::High => EcLevel::H,
+    }
+}
+
+/// Encode `data` as a QR code in the requested format
+pub fn render(data: &str, format: QrFormat, options: &QrOptions) -> Result<QrImage, QrError> {
+    let code = QrCode::with_error_correction_level(data, ec_level(options.error_correction))?;
+    let size = options.size.min(MAX_SIZE);
+
+    match format {
+        QrFormat::Svg => Ok(QrImage::Svg(
+            code.render::<svg::Color>()
+                .min_dimensions(size, size)
+                .quiet_zone(options.quiet_zone)
+                .build(),
+        )),
+        QrFormat::Png => {
+            let image = code
+                .render::<image::Luma<u8>>()
+                .min_dimensions(size, size)
+                .quiet_zone(options.quiet_zone)
+                .build();
+
+            let mut bytes = Vec::new();
+            image.write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)?;
+            Ok(QrImage::Png(bytes))
+        }
+        QrFormat::Terminal => Ok(QrImage::Terminal(
+            code.render::<unicode::Dense1x2>()
+                .dark_color(unicode::Dense1x2::Light)
+                .light_color(unicode::Dense1x2::Dark)
+                .quiet_zone(options.quiet_zone)
+                .build(),
+        )),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum QrError {
+    #[error("Encoding error: {0}")]
+    Encode(#[from] qrcode::types::QrError),
+
+    #[error("Image error: {0}")]
+    Image(#[from] image::ImageError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "portal://npub1w9llyw8c3qnn7h27u3msjlet8xyjz5phdycr5rz335r2j5hj5a0qvs3tur?relays=wss%3A%2F%2Frelay.nostr.net&token=token";
+
+    #[test]
+    fn test_svg() {
+        let QrImage::Svg(svg) = render(URL, QrFormat::Svg, &QrOptions::default()).unwrap() else {
+            panic!("Expected svg");
+        };
+        assert!(svg.contains("<svg"));
+    }
+
+    #[test]
+    fn test_png() {
+        let QrImage::Png(png) = render(URL, QrFormat::Png, &QrOptions::default()).unwrap() else {
+            panic!("Expected png");
+        };
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    }
+
+    #[test]
+    fn test_error_correction_increases_size() {
+        let render_terminal = |level| match render(
+            URL,
+            QrFormat::Terminal,
+            &QrOptions::default().error_correction(level),
+        )
+        .unwrap()
+        {
+            QrImage::Terminal(text) => text.lines().count(),
+            _ => panic!("Expected terminal output"),
+        };
+
+        assert!(render_terminal(QrErrorCorrection::High) > render_terminal(QrErrorCorrection::Low));
+    }
+
+    #[test]
+    fn test_size_is_clamped() {
+        let QrImage::Png(png) =
+            render(URL, QrFormat::Png, &QrOptions::default().size(u32::MAX)).unwrap()
+        else {
+            panic!("Expected png");
+        };
+        let width = u32::from_be_bytes(png[16..20].try_into().unwrap());
+        assert!(width <= MAX_SIZE * 2);
+    }
+}
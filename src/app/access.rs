@@ -0,0 +1,83 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+};
+
+use crate::protocol::model::bindings::PublicKey;
+
+/// The services allowed to send requests to the app
+///
+/// Requests from denied services are dropped by the listeners before they are notified, so they
+/// never reach the application. Services are identified by their main key.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceAccessList {
+    /// Only these services are allowed, if set
+    allowed: Option<HashSet<PublicKey>>,
+    denied: HashSet<PublicKey>,
+}
+
+impl ServiceAccessList {
+    /// A list that allows every service
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only allow `services`, or every service that is not denied if `None`
+    pub fn set_allowed(&mut self, services: Option<Vec<PublicKey>>) {
+        self.allowed = services.map(|services| services.into_iter().collect());
+    }
+
+    pub fn set_denied(&mut self, services: Vec<PublicKey>) {
+        self.denied = services.into_iter().collect();
+    }
+
+    pub fn deny(&mut self, service: PublicKey) {
+        self.denied.insert(service);
+    }
+
+    pub fn is_allowed(&self, service: &PublicKey) -> bool {
+        !self.denied.contains(service)
+            && self
+                .allowed
+                .as_ref()
+                .is_none_or(|allowed| allowed.contains(service))
+    }
+}
+
+/// A [`ServiceAccessList`] shared between the app and its listeners
+pub type SharedServiceAccessList = Arc<RwLock<ServiceAccessList>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nostr::Keys;
+
+    fn service() -> PublicKey {
+        Keys::generate().public_key().into()
+    }
+
+    #[test]
+    fn test_deny_list() {
+        let (good, bad) = (service(), service());
+        let mut list = ServiceAccessList::new();
+        list.deny(bad);
+
+        assert!(list.is_allowed(&good));
+        assert!(!list.is_allowed(&bad));
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let (known, unknown) = (service(), service());
+        let mut list = ServiceAccessList::new();
+        list.set_allowed(Some(vec![known]));
+
+        assert!(list.is_allowed(&known));
+        assert!(!list.is_allowed(&unknown));
+
+        // Denying takes precedence
+        list.deny(known);
+        assert!(!list.is_allowed(&known));
+    }
+}
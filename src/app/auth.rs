@@ -4,12 +4,13 @@ use nostr::{Tag, event::Kind, filter::Filter, key::PublicKey};
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    app::access::SharedServiceAccessList,
     protocol::{
         key_handshake::KeyHandshakeUrl,
         model::{
             auth::{
-                AuthChallengeContent, AuthResponseContent, AuthResponseStatus, ClientInfo,
-                KeyHandshakeContent, SubkeyProof,
+                AuthChallengeContent, AuthResponseContent, AuthResponseStatus, Capability,
+                ClientInfo, KeyHandshakeContent, PROTOCOL_VERSION, SubkeyProof,
             },
             bindings,
             event_kinds::{AUTH_CHALLENGE, AUTH_RESPONSE, KEY_HANDSHAKE},
@@ -25,6 +26,16 @@ use crate::{
 pub struct KeyHandshakeConversation {
     pub url: KeyHandshakeUrl,
     pub relays: Vec<String>,
+    #[new(value = "Capability::all()")]
+    pub capabilities: Vec<Capability>,
+}
+
+impl KeyHandshakeConversation {
+    /// Advertise only `capabilities` to the service, instead of everything implemented by this library
+    pub fn with_capabilities(mut self, capabilities: Vec<Capability>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
 }
 
 impl OneShotSender for KeyHandshakeConversation {
@@ -38,6 +49,8 @@ impl OneShotSender for KeyHandshakeConversation {
             client_info: ClientInfo {
                 version: env!("CARGO_PKG_VERSION").to_string(),
                 name: "Portal".to_string(),
+                protocol_version: Some(PROTOCOL_VERSION),
+                capabilities: Some(state.capabilities.clone()),
             },
             preferred_relays: state.relays.clone(),
         };
@@ -63,11 +76,21 @@ impl OneShotSender for KeyHandshakeConversation {
 
 pub struct AuthChallengeListenerConversation {
     local_key: PublicKey,
+    access: SharedServiceAccessList,
 }
 
 impl AuthChallengeListenerConversation {
     pub fn new(local_key: PublicKey) -> Self {
-        Self { local_key }
+        Self {
+            local_key,
+            access: SharedServiceAccessList::default(),
+        }
+    }
+
+    /// Drop the requests of the services that are not allowed by `access`
+    pub fn with_access_list(mut self, access: SharedServiceAccessList) -> Self {
+        self.access = access;
+        self
     }
 }
 
@@ -101,7 +124,7 @@ impl MultiKeyListener for AuthChallengeListenerConversation {
     }
 
     fn on_message(
-        _state: &mut crate::router::MultiKeyListenerAdapter<Self>,
+        state: &mut crate::router::MultiKeyListenerAdapter<Self>,
         event: &crate::router::CleartextEvent,
         content: &Self::Message,
     ) -> Result<Response, Self::Error> {
@@ -127,6 +150,14 @@ impl MultiKeyListener for AuthChallengeListenerConversation {
             event.pubkey.into()
         };
 
+        if !state.access.read().unwrap().is_allowed(&service_key) {
+            log::warn!(
+                "Ignoring auth challenge from denied service {:?}",
+                service_key
+            );
+            return Ok(Response::default());
+        }
+
         let response = Response::new().notify(AuthChallengeEvent {
             service_key,
             recipient: event.pubkey.into(),
@@ -0,0 +1,287 @@
+use std::collections::HashSet;
+
+use nostr::{
+    event::{Kind, Tag},
+    filter::Filter,
+    key::PublicKey,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::access::SharedServiceAccessList,
+    protocol::model::{
+        bindings,
+        event_kinds::{
+            CERTIFICATE_ISSUE, CERTIFICATE_REQUEST, CERTIFICATE_RESPONSE, CERTIFICATE_REVOCATION,
+        },
+        identity::{
+            CertificateIssueContent, CertificateRequestContent, CertificateResponseContent,
+            CertificateRevocationContent,
+        },
+    },
+    router::{
+        CleartextEvent, ConversationError, MultiKeyListener, MultiKeyListenerAdapter, Response,
+        adapters::{
+            ConversationWithNotification,
+            one_shot::{OneShotSender, OneShotSenderAdapter},
+        },
+    },
+};
+
+/// Our main key, the one the certificates are issued to
+fn subject_key<Inner>(state: &MultiKeyListenerAdapter<Inner>, local_key: PublicKey) -> PublicKey {
+    state
+        .subkey_proof
+        .as_ref()
+        .map(|proof| proof.main_key.into())
+        .unwrap_or(local_key)
+}
+
+pub(crate) fn listener_filter<Inner>(
+    state: &MultiKeyListenerAdapter<Inner>,
+    local_key: PublicKey,
+    kind: u16,
+) -> Filter {
+    let mut filter = Filter::new()
+        .kinds(vec![Kind::Custom(kind)])
+        .pubkey(local_key);
+
+    if let Some(subkey_proof) = &state.subkey_proof {
+        filter = filter.pubkey(subkey_proof.main_key.into());
+    }
+
+    filter
+}
+
+/// A certificate received from its issuer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CertificateIssuedEvent {
+    pub issuer_key: bindings::PublicKey,
+    pub content: CertificateIssueContent,
+}
+
+/// Listens for the certificates issued to us.
+///
+/// Certificates that are not issued to our main key, that are not signed by the sender, or that
+/// don't verify are dropped.
+pub struct CertificateIssueListenerConversation {
+    local_key: PublicKey,
+    access: SharedServiceAccessList,
+}
+
+impl CertificateIssueListenerConversation {
+    pub fn new(local_key: PublicKey) -> Self {
+        Self {
+            local_key,
+            access: SharedServiceAccessList::default(),
+        }
+    }
+
+    /// Drop the certificates of the issuers that are not allowed by `access`
+    pub fn with_access_list(mut self, access: SharedServiceAccessList) -> Self {
+        self.access = access;
+        self
+    }
+}
+
+impl MultiKeyListener for CertificateIssueListenerConversation {
+    const VALIDITY_SECONDS: Option<u64> = None;
+
+    type Error = ConversationError;
+    type Message = CertificateIssueContent;
+
+    fn init(state: &MultiKeyListenerAdapter<Self>) -> Result<Response, Self::Error> {
+        Ok(Response::new().filter(listener_filter(state, state.local_key, CERTIFICATE_ISSUE)))
+    }
+
+    fn on_message(
+        state: &mut MultiKeyListenerAdapter<Self>,
+        event: &CleartextEvent,
+        message: &Self::Message,
+    ) -> Result<Response, Self::Error> {
+        // The issuer signs with its own key, our subkey proof only covers us
+        let issuer_key: bindings::PublicKey = event.pubkey.into();
+
+        if !state.access.read().unwrap().is_allowed(&issuer_key) {
+            log::warn!("Ignoring certificate from denied issuer {:?}", issuer_key);
+            return Ok(Response::default());
+        }
+
+        let certificate = &message.certificate;
+        if certificate.metadata.issuer_pubkey != *issuer_key {
+            log::warn!("Ignoring certificate not issued by its sender");
+            return Ok(Response::default());
+        }
+        if certificate.subject != subject_key(state, state.local_key) {
+            log::warn!("Ignoring certificate issued to another key");
+            return Ok(Response::default());
+        }
+        if let Err(e) = certificate.verify() {
+            log::warn!("Ignoring invalid certificate: {}", e);
+            return Ok(Response::default());
+        }
+
+        Ok(Response::new().notify(CertificateIssuedEvent {
+            issuer_key,
+            content: message.clone(),
+        }))
+    }
+}
+
+impl ConversationWithNotification
+    for MultiKeyListenerAdapter<CertificateIssueListenerConversation>
+{
+    type Notification = CertificateIssuedEvent;
+}
+
+/// A revocation received from the issuer of a certificate
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CertificateRevokedEvent {
+    pub issuer_key: bindings::PublicKey,
+    pub content: CertificateRevocationContent,
+}
+
+/// Listens for the revocations of our certificates.
+///
+/// The issuer is not checked here, since only the vault knows who issued each certificate.
+#[derive(derive_new::new)]
+pub struct CertificateRevocationListenerConversation {
+    local_key: PublicKey,
+}
+
+impl MultiKeyListener for CertificateRevocationListenerConversation {
+    const VALIDITY_SECONDS: Option<u64> = None;
+
+    type Error = ConversationError;
+    type Message = CertificateRevocationContent;
+
+    fn init(state: &MultiKeyListenerAdapter<Self>) -> Result<Response, Self::Error> {
+        Ok(Response::new().filter(listener_filter(
+            state,
+            state.local_key,
+            CERTIFICATE_REVOCATION,
+        )))
+    }
+
+    fn on_message(
+        state: &mut MultiKeyListenerAdapter<Self>,
+        event: &CleartextEvent,
+        message: &Self::Message,
+    ) -> Result<Response, Self::Error> {
+        let issuer_key: bindings::PublicKey = event.pubkey.into();
+
+        Ok(Response::new().notify(CertificateRevokedEvent {
+            issuer_key,
+            content: message.clone(),
+        }))
+    }
+}
+
+impl ConversationWithNotification
+    for MultiKeyListenerAdapter<CertificateRevocationListenerConversation>
+{
+    type Notification = CertificateRevokedEvent;
+}
+
+/// A service asking us to disclose some certificates
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+pub struct CertificateRequestEvent {
+    pub service_key: bindings::PublicKey,
+    pub recipient: bindings::PublicKey,
+    pub content: CertificateRequestContent,
+    pub event_id: String,
+}
+
+/// Listens for certificate requests from services.
+pub struct CertificateRequestListenerConversation {
+    local_key: PublicKey,
+    access: SharedServiceAccessList,
+}
+
+impl CertificateRequestListenerConversation {
+    pub fn new(local_key: PublicKey) -> Self {
+        Self {
+            local_key,
+            access: SharedServiceAccessList::default(),
+        }
+    }
+
+    /// Drop the requests of the services that are not allowed by `access`
+    pub fn with_access_list(mut self, access: SharedServiceAccessList) -> Self {
+        self.access = access;
+        self
+    }
+}
+
+impl MultiKeyListener for CertificateRequestListenerConversation {
+    const VALIDITY_SECONDS: Option<u64> = None;
+
+    type Error = ConversationError;
+    type Message = CertificateRequestContent;
+
+    fn init(state: &MultiKeyListenerAdapter<Self>) -> Result<Response, Self::Error> {
+        Ok(Response::new().filter(listener_filter(state, state.local_key, CERTIFICATE_REQUEST)))
+    }
+
+    fn on_message(
+        state: &mut MultiKeyListenerAdapter<Self>,
+        event: &CleartextEvent,
+        content: &Self::Message,
+    ) -> Result<Response, Self::Error> {
+        if content.expires_at.as_u64() < nostr::Timestamp::now().as_u64() {
+            log::warn!("Ignoring expired certificate request");
+            return Ok(Response::default());
+        }
+
+        let service_key: bindings::PublicKey = event.pubkey.into();
+
+        if !state.access.read().unwrap().is_allowed(&service_key) {
+            log::warn!(
+                "Ignoring certificate request from denied service {:?}",
+                service_key
+            );
+            return Ok(Response::default());
+        }
+
+        Ok(Response::new().notify(CertificateRequestEvent {
+            service_key,
+            recipient: event.pubkey.into(),
+            content: content.clone(),
+            event_id: event.id.to_string(),
+        }))
+    }
+}
+
+impl ConversationWithNotification
+    for MultiKeyListenerAdapter<CertificateRequestListenerConversation>
+{
+    type Notification = CertificateRequestEvent;
+}
+
+/// Sends the certificates disclosed for a [`CertificateRequestEvent`]
+#[derive(derive_new::new)]
+pub struct CertificateResponseSenderConversation {
+    request: CertificateRequestEvent,
+    content: CertificateResponseContent,
+}
+
+impl OneShotSender for CertificateResponseSenderConversation {
+    type Error = ConversationError;
+
+    fn send(state: &mut OneShotSenderAdapter<Self>) -> Result<Response, Self::Error> {
+        let mut keys = HashSet::new();
+        keys.insert(state.request.service_key);
+        keys.insert(state.request.recipient);
+
+        let tags = keys.iter().map(|k| Tag::public_key(**k)).collect();
+        Ok(Response::new()
+            .reply_to(
+                state.request.recipient.into(),
+                Kind::Custom(CERTIFICATE_RESPONSE),
+                tags,
+                state.content.clone(),
+            )
+            .finish())
+    }
+}
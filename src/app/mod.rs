@@ -1,2 +1,5 @@
+pub mod access;
 pub mod auth;
+pub mod certificates;
 pub mod payments;
+pub mod tickets;
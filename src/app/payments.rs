@@ -8,6 +8,7 @@ use nostr::{
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    app::access::SharedServiceAccessList,
     protocol::model::{
         Timestamp,
         bindings::{self},
@@ -28,11 +29,21 @@ use crate::{
 
 pub struct PaymentRequestListenerConversation {
     local_key: PublicKey,
+    access: SharedServiceAccessList,
 }
 
 impl PaymentRequestListenerConversation {
     pub fn new(local_key: PublicKey) -> Self {
-        Self { local_key }
+        Self {
+            local_key,
+            access: SharedServiceAccessList::default(),
+        }
+    }
+
+    /// Drop the requests of the services that are not allowed by `access`
+    pub fn with_access_list(mut self, access: SharedServiceAccessList) -> Self {
+        self.access = access;
+        self
     }
 }
 
@@ -84,6 +95,14 @@ impl MultiKeyListener for PaymentRequestListenerConversation {
             event.pubkey.into()
         };
 
+        if !state.access.read().unwrap().is_allowed(&service_key) {
+            log::warn!(
+                "Ignoring payment request from denied service {:?}",
+                service_key
+            );
+            return Ok(Response::default());
+        }
+
         let response = Response::new().notify(PaymentRequestEvent {
             service_key,
             recipient: event.pubkey.into(),
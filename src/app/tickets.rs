@@ -0,0 +1,123 @@
+use std::collections::HashSet;
+
+use nostr::{
+    event::{Kind, Tag},
+    key::PublicKey,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::{access::SharedServiceAccessList, certificates::listener_filter},
+    protocol::model::{
+        bindings,
+        event_kinds::{TICKET_PRESENTATION, TICKET_SCAN_REQUEST},
+        ticket::{TicketPresentationContent, TicketScanRequestContent},
+    },
+    router::{
+        CleartextEvent, ConversationError, MultiKeyListener, MultiKeyListenerAdapter, Response,
+        adapters::{
+            ConversationWithNotification,
+            one_shot::{OneShotSender, OneShotSenderAdapter},
+        },
+    },
+};
+
+/// A door asking us to present a ticket
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+pub struct TicketScanRequestEvent {
+    pub service_key: bindings::PublicKey,
+    pub recipient: bindings::PublicKey,
+    pub content: TicketScanRequestContent,
+    pub event_id: String,
+}
+
+/// Listens for ticket scans from doors.
+pub struct TicketScanRequestListenerConversation {
+    local_key: PublicKey,
+    access: SharedServiceAccessList,
+}
+
+impl TicketScanRequestListenerConversation {
+    pub fn new(local_key: PublicKey) -> Self {
+        Self {
+            local_key,
+            access: SharedServiceAccessList::default(),
+        }
+    }
+
+    /// Drop the scans of the services that are not allowed by `access`
+    pub fn with_access_list(mut self, access: SharedServiceAccessList) -> Self {
+        self.access = access;
+        self
+    }
+}
+
+impl MultiKeyListener for TicketScanRequestListenerConversation {
+    const VALIDITY_SECONDS: Option<u64> = None;
+
+    type Error = ConversationError;
+    type Message = TicketScanRequestContent;
+
+    fn init(state: &MultiKeyListenerAdapter<Self>) -> Result<Response, Self::Error> {
+        Ok(Response::new().filter(listener_filter(state, state.local_key, TICKET_SCAN_REQUEST)))
+    }
+
+    fn on_message(
+        state: &mut MultiKeyListenerAdapter<Self>,
+        event: &CleartextEvent,
+        content: &Self::Message,
+    ) -> Result<Response, Self::Error> {
+        if content.expires_at.as_u64() < nostr::Timestamp::now().as_u64() {
+            log::warn!("Ignoring expired ticket scan");
+            return Ok(Response::default());
+        }
+
+        let service_key: bindings::PublicKey = event.pubkey.into();
+
+        if !state.access.read().unwrap().is_allowed(&service_key) {
+            log::warn!("Ignoring ticket scan from denied service {:?}", service_key);
+            return Ok(Response::default());
+        }
+
+        Ok(Response::new().notify(TicketScanRequestEvent {
+            service_key,
+            recipient: event.pubkey.into(),
+            content: content.clone(),
+            event_id: event.id.to_string(),
+        }))
+    }
+}
+
+impl ConversationWithNotification
+    for MultiKeyListenerAdapter<TicketScanRequestListenerConversation>
+{
+    type Notification = TicketScanRequestEvent;
+}
+
+/// Sends the ticket presented for a [`TicketScanRequestEvent`]
+#[derive(derive_new::new)]
+pub struct TicketPresentationSenderConversation {
+    request: TicketScanRequestEvent,
+    content: TicketPresentationContent,
+}
+
+impl OneShotSender for TicketPresentationSenderConversation {
+    type Error = ConversationError;
+
+    fn send(state: &mut OneShotSenderAdapter<Self>) -> Result<Response, Self::Error> {
+        let mut keys = HashSet::new();
+        keys.insert(state.request.service_key);
+        keys.insert(state.request.recipient);
+
+        let tags = keys.iter().map(|k| Tag::public_key(**k)).collect();
+        Ok(Response::new()
+            .reply_to(
+                state.request.recipient.into(),
+                Kind::Custom(TICKET_PRESENTATION),
+                tags,
+                state.content.clone(),
+            )
+            .finish())
+    }
+}
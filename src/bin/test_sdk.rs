@@ -93,6 +93,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         relays,
         token: token.clone(),
         subkey: subkey.map(|k| k.into()),
+        expires_at: None,
+        signature: None,
     };
 
     log::info!("Auth init URL: {}", url);
@@ -7,8 +7,10 @@ use nostr::{
 };
 
 use crate::{
+    app::access::SharedServiceAccessList,
     protocol::model::{
         auth::SubkeyProof,
+        bindings,
         event_kinds::{CASHU_DIRECT, CASHU_REQUEST, CASHU_RESPONSE},
         payment::{
             CashuDirectContent, CashuDirectContentWithKey, CashuRequestContent,
@@ -104,6 +106,16 @@ impl ConversationWithNotification for MultiKeySenderAdapter<CashuRequestSenderCo
 #[derive(derive_new::new)]
 pub struct CashuRequestReceiverConversation {
     local_key: PublicKey,
+    #[new(default)]
+    access: SharedServiceAccessList,
+}
+
+impl CashuRequestReceiverConversation {
+    /// Drop the requests of the services that are not allowed by `access`
+    pub fn with_access_list(mut self, access: SharedServiceAccessList) -> Self {
+        self.access = access;
+        self
+    }
 }
 
 impl MultiKeyListener for CashuRequestReceiverConversation {
@@ -139,6 +151,14 @@ impl MultiKeyListener for CashuRequestReceiverConversation {
             event.pubkey.into()
         };
 
+        if !state.access.read().unwrap().is_allowed(&sender_key) {
+            log::warn!(
+                "Ignoring cashu request from denied service {:?}",
+                sender_key
+            );
+            return Ok(Response::default());
+        }
+
         let res = CashuRequestContentWithKey {
             inner: message.clone(),
             main_key: sender_key,
@@ -245,6 +265,16 @@ impl MultiKeySender for CashuDirectSenderConversation {
 #[derive(derive_new::new)]
 pub struct CashuDirectReceiverConversation {
     local_key: PublicKey,
+    #[new(default)]
+    access: SharedServiceAccessList,
+}
+
+impl CashuDirectReceiverConversation {
+    /// Drop the requests of the services that are not allowed by `access`
+    pub fn with_access_list(mut self, access: SharedServiceAccessList) -> Self {
+        self.access = access;
+        self
+    }
 }
 
 impl MultiKeyListener for CashuDirectReceiverConversation {
@@ -270,11 +300,16 @@ impl MultiKeyListener for CashuDirectReceiverConversation {
         event: &crate::router::CleartextEvent,
         message: &Self::Message,
     ) -> Result<Response, Self::Error> {
-        let main_key = match &state.subkey_proof {
+        let main_key: bindings::PublicKey = match &state.subkey_proof {
             Some(subkey_proof) => subkey_proof.main_key.into(),
             None => event.pubkey.into(),
         };
 
+        if !state.access.read().unwrap().is_allowed(&main_key) {
+            log::warn!("Ignoring cashu token from denied service {:?}", main_key);
+            return Ok(Response::default());
+        }
+
         let res = CashuDirectContentWithKey {
             inner: message.clone(),
             main_key,
@@ -5,7 +5,9 @@ use nostr::{
 };
 
 use crate::{
+    app::access::SharedServiceAccessList,
     protocol::model::{
+        bindings,
         event_kinds::RECURRING_PAYMENT_CANCEL,
         payment::{CloseRecurringPaymentContent, CloseRecurringPaymentResponse},
     },
@@ -79,11 +81,21 @@ impl MultiKeySender for CloseRecurringPaymentConversation {
 
 pub struct CloseRecurringPaymentReceiverConversation {
     local_key: PublicKey,
+    access: SharedServiceAccessList,
 }
 
 impl CloseRecurringPaymentReceiverConversation {
     pub fn new(local_key: PublicKey) -> Self {
-        Self { local_key }
+        Self {
+            local_key,
+            access: SharedServiceAccessList::default(),
+        }
+    }
+
+    /// Drop the requests of the services that are not allowed by `access`
+    pub fn with_access_list(mut self, access: SharedServiceAccessList) -> Self {
+        self.access = access;
+        self
     }
 }
 
@@ -111,11 +123,19 @@ impl MultiKeyListener for CloseRecurringPaymentReceiverConversation {
         event: &crate::router::CleartextEvent,
         message: &Self::Message,
     ) -> Result<Response, Self::Error> {
-        let main_key = match &state.subkey_proof {
+        let main_key: bindings::PublicKey = match &state.subkey_proof {
             Some(subkey_proof) => subkey_proof.main_key.into(),
             None => event.pubkey.into(),
         };
 
+        if !state.access.read().unwrap().is_allowed(&main_key) {
+            log::warn!(
+                "Ignoring recurring payment closure from denied service {:?}",
+                main_key
+            );
+            return Ok(Response::default());
+        }
+
         let res = CloseRecurringPaymentResponse {
             content: message.clone(),
             main_key,
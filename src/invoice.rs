@@ -5,6 +5,7 @@ use nostr::{Tag, event::Kind, filter::Filter, key::PublicKey};
 use derive_new::new;
 
 use crate::{
+    app::access::SharedServiceAccessList,
     protocol::model::{
         auth::SubkeyProof,
         event_kinds::{INVOICE_REQUEST, INVOICE_RESPONSE},
@@ -93,6 +94,16 @@ impl ConversationWithNotification for MultiKeySenderAdapter<InvoiceRequestConver
 #[derive(new)]
 pub struct InvoiceReceiverConversation {
     local_key: PublicKey,
+    #[new(default)]
+    access: SharedServiceAccessList,
+}
+
+impl InvoiceReceiverConversation {
+    /// Drop the requests of the services that are not allowed by `access`
+    pub fn with_access_list(mut self, access: SharedServiceAccessList) -> Self {
+        self.access = access;
+        self
+    }
 }
 
 impl MultiKeyListener for InvoiceReceiverConversation {
@@ -128,6 +139,14 @@ impl MultiKeyListener for InvoiceReceiverConversation {
             event.pubkey.into()
         };
 
+        if !state.access.read().unwrap().is_allowed(&sender_key) {
+            log::warn!(
+                "Ignoring invoice request from denied service {:?}",
+                sender_key
+            );
+            return Ok(Response::default());
+        }
+
         let res = InvoiceRequestContentWithKey {
             inner: message.clone(),
             main_key: sender_key,
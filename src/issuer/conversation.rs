@@ -0,0 +1,116 @@
+use nostr::{
+    event::{EventId, Kind, Tag},
+    filter::Filter,
+    key::PublicKey,
+};
+use serde::Serialize;
+
+use crate::{
+    protocol::model::{
+        event_kinds::{CERTIFICATE_ISSUE, CERTIFICATE_REVOCATION},
+        identity::{CertificateIssueContent, CertificateRevocationContent},
+    },
+    router::{CleartextEvent, ConversationError, MultiKeySender, MultiKeySenderAdapter, Response},
+};
+
+/// Send `content` to the subject, and to its new key if it switched to a subkey
+fn deliver<Inner, S: Serialize>(
+    state: &MultiKeySenderAdapter<Inner>,
+    new_key: Option<PublicKey>,
+    kind: u16,
+    content: &S,
+) -> Response {
+    let tags = state
+        .subkeys
+        .iter()
+        .chain([&state.user])
+        .map(|k| Tag::public_key(*k))
+        .collect();
+
+    match new_key {
+        Some(new_key) => Response::new().subscribe_to_subkey_proofs().reply_to(
+            new_key,
+            Kind::Custom(kind),
+            tags,
+            content,
+        ),
+        None => Response::new().subscribe_to_subkey_proofs().reply_all(
+            Kind::Custom(kind),
+            tags,
+            content,
+        ),
+    }
+}
+
+/// Sender conversation to hand a certificate to its subject.
+///
+/// The subject doesn't reply, the conversation ends when it expires.
+#[derive(derive_new::new)]
+pub struct CertificateIssueSenderConversation {
+    content: CertificateIssueContent,
+}
+
+impl MultiKeySender for CertificateIssueSenderConversation {
+    const VALIDITY_SECONDS: Option<u64> = Some(60 * 5);
+
+    type Error = ConversationError;
+    type Message = ();
+
+    fn get_filter(_state: &MultiKeySenderAdapter<Self>) -> Result<Filter, Self::Error> {
+        // Empty filter that will not match any events
+        Ok(Filter::new().id(EventId::all_zeros()))
+    }
+
+    fn build_initial_message(
+        state: &mut MultiKeySenderAdapter<Self>,
+        new_key: Option<PublicKey>,
+    ) -> Result<Response, Self::Error> {
+        Ok(deliver(state, new_key, CERTIFICATE_ISSUE, &state.content))
+    }
+
+    fn on_message(
+        _state: &mut MultiKeySenderAdapter<Self>,
+        _event: &CleartextEvent,
+        _message: &Self::Message,
+    ) -> Result<Response, Self::Error> {
+        Ok(Response::default())
+    }
+}
+
+/// Sender conversation to tell the subject that one of its certificates was revoked.
+#[derive(derive_new::new)]
+pub struct CertificateRevocationSenderConversation {
+    content: CertificateRevocationContent,
+}
+
+impl MultiKeySender for CertificateRevocationSenderConversation {
+    const VALIDITY_SECONDS: Option<u64> = Some(60 * 5);
+
+    type Error = ConversationError;
+    type Message = ();
+
+    fn get_filter(_state: &MultiKeySenderAdapter<Self>) -> Result<Filter, Self::Error> {
+        // Empty filter that will not match any events
+        Ok(Filter::new().id(EventId::all_zeros()))
+    }
+
+    fn build_initial_message(
+        state: &mut MultiKeySenderAdapter<Self>,
+        new_key: Option<PublicKey>,
+    ) -> Result<Response, Self::Error> {
+        Ok(deliver(
+            state,
+            new_key,
+            CERTIFICATE_REVOCATION,
+            &state.content,
+        ))
+    }
+
+    fn on_message(
+        _state: &mut MultiKeySenderAdapter<Self>,
+        _event: &CleartextEvent,
+        _message: &Self::Message,
+    ) -> Result<Response, Self::Error> {
+        Ok(Response::default())
+    }
+}
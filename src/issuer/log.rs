@@ -0,0 +1,115 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::{Mutex, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{identity::Certificate, model::Timestamp};
+
+/// An entry of the [`IssuanceLog`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IssuanceEntry {
+    Issued {
+        certificate: Certificate,
+        /// The id of the certificate renewed by this one
+        replaces: Option<String>,
+        at: Timestamp,
+    },
+    Revoked {
+        certificate_id: String,
+        reason: Option<String>,
+        at: Timestamp,
+    },
+}
+
+/// Append-only record of everything done by an issuer
+///
+/// The log keeps the full certificates, so that they can be renewed or revoked later.
+pub trait IssuanceLog: Send + Sync {
+    fn append(&self, entry: IssuanceEntry) -> Result<(), IssuanceLogError>;
+
+    /// Every entry, oldest first
+    fn entries(&self) -> Result<Vec<IssuanceEntry>, IssuanceLogError>;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum IssuanceLogError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+/// Keeps the log in memory, everything is lost when the issuer stops
+#[derive(Debug, Default)]
+pub struct MemoryIssuanceLog {
+    entries: RwLock<Vec<IssuanceEntry>>,
+}
+
+impl MemoryIssuanceLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl IssuanceLog for MemoryIssuanceLog {
+    fn append(&self, entry: IssuanceEntry) -> Result<(), IssuanceLogError> {
+        self.entries.write().unwrap().push(entry);
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<IssuanceEntry>, IssuanceLogError> {
+        Ok(self.entries.read().unwrap().clone())
+    }
+}
+
+/// Appends every entry to a JSONL file, which is loaded when the log is opened
+#[derive(Debug)]
+pub struct FileIssuanceLog {
+    inner: MemoryIssuanceLog,
+    file: Mutex<File>,
+}
+
+impl FileIssuanceLog {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, IssuanceLogError> {
+        let path = path.as_ref();
+        let inner = MemoryIssuanceLog::new();
+
+        if path.exists() {
+            let reader = BufReader::new(File::open(path)?);
+            for line in reader.lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                inner.append(serde_json::from_str(&line)?)?;
+            }
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            inner,
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl IssuanceLog for FileIssuanceLog {
+    fn append(&self, entry: IssuanceEntry) -> Result<(), IssuanceLogError> {
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        file.flush()?;
+
+        self.inner.append(entry)
+    }
+
+    fn entries(&self) -> Result<Vec<IssuanceEntry>, IssuanceLogError> {
+        self.inner.entries()
+    }
+}
@@ -0,0 +1,617 @@
+//! Issuing identity certificates
+//!
+//! An [`Issuer`] builds, salts and signs [`Certificate`]s, and records everything it does in an
+//! [`IssuanceLog`] so that certificates can later be renewed or revoked. The certificates and
+//! revocations are handed to their subject with the conversations in [`conversation`].
+//!
+//! # Example
+//! ```rust,no_run
+//! use portal::issuer::{IssueRequest, Issuer, MemoryIssuanceLog};
+//! use portal::protocol::identity::{CertificateData, VerificationLevel, VerificationMethod};
+//! use portal::protocol::model::Timestamp;
+//!
+//! # fn example(subject: nostr::PublicKey) -> Result<(), portal::issuer::IssuerError> {
+//! let issuer = Issuer::new(nostr::Keys::generate(), MemoryIssuanceLog::new());
+//! let certificate = issuer.issue(IssueRequest::new(
+//!     subject,
+//!     CertificateData::Custom { data: serde_json::json!({ "member_since": "2024" }) },
+//!     VerificationLevel::Medium,
+//!     VerificationMethod::VideoCall,
+//!     Timestamp::now_plus_seconds(365 * 24 * 60 * 60),
+//! ))?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod conversation;
+pub mod log;
+#[cfg(test)]
+pub(crate) mod testing;
+
+use std::collections::BTreeMap;
+
+use rand::{RngCore, thread_rng};
+
+use crate::protocol::{
+    identity::{
+        CERTIFICATE_VERSION, Certificate, CertificateData, CertificateMetadata, MerkleRoot,
+        RevealError, SaltSequence, SignError, VerificationLevel, VerificationMethod,
+    },
+    model::{Timestamp, identity::CertificateRevocationContent},
+    predicate::{Predicate, PredicateError},
+    ticket::{TicketData, TicketError},
+};
+
+pub use log::{FileIssuanceLog, IssuanceEntry, IssuanceLog, IssuanceLogError, MemoryIssuanceLog};
+
+/// Size in bytes of the salt of each field
+const SALT_SIZE: usize = 32;
+
+/// What to certify about a subject
+#[derive(Debug, Clone, derive_new::new)]
+pub struct IssueRequest {
+    pub subject: nostr::PublicKey,
+    pub data: CertificateData,
+    pub verification_level: VerificationLevel,
+    pub verification_method: VerificationMethod,
+    pub expires_at: Timestamp,
+    /// Claims to derive from `data`, so that they can be proven without revealing it
+    #[new(default)]
+    pub claims: Vec<Predicate>,
+}
+
+impl IssueRequest {
+    pub fn with_claims(mut self, claims: Vec<Predicate>) -> Self {
+        self.claims = claims;
+        self
+    }
+}
+
+/// The current state of an issued certificate, as recorded in the log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CertificateStatus {
+    Active,
+    Revoked {
+        reason: Option<String>,
+        at: Timestamp,
+    },
+    /// Replaced by the certificate with id `by`
+    Renewed {
+        by: String,
+    },
+}
+
+/// A certificate issued by an [`Issuer`]
+#[derive(Debug, Clone)]
+pub struct IssuanceRecord {
+    pub certificate: Certificate,
+    pub issued_at: Timestamp,
+    /// The id of the certificate renewed by this one
+    pub replaces: Option<String>,
+    pub status: CertificateStatus,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum IssuerError {
+    #[error("Reveal error: {0}")]
+    Reveal(#[from] RevealError),
+
+    #[error("Sign error: {0}")]
+    Sign(#[from] SignError),
+
+    #[error("Issuance log error: {0}")]
+    Log(#[from] IssuanceLogError),
+
+    #[error("The expiration is in the past")]
+    InvalidExpiration,
+
+    #[error("Unknown certificate: {0}")]
+    UnknownCertificate(String),
+
+    #[error("Certificate {0} was revoked")]
+    Revoked(String),
+
+    #[error("Certificate {id} was already renewed by {by}")]
+    AlreadyRenewed { id: String, by: String },
+
+    #[error("Predicate error: {0}")]
+    Predicate(#[from] PredicateError),
+
+    #[error("Ticket error: {0}")]
+    Ticket(#[from] TicketError),
+}
+
+/// Issues certificates signed with its keys
+pub struct Issuer<L = MemoryIssuanceLog> {
+    keys: nostr::Keys,
+    log: L,
+}
+
+impl<L: IssuanceLog> Issuer<L> {
+    pub fn new(keys: nostr::Keys, log: L) -> Self {
+        Self { keys, log }
+    }
+
+    pub fn public_key(&self) -> nostr::PublicKey {
+        self.keys.public_key()
+    }
+
+    pub fn log(&self) -> &L {
+        &self.log
+    }
+
+    /// Build, salt and sign a certificate, and record it in the log
+    pub fn issue(&self, request: IssueRequest) -> Result<Certificate, IssuerError> {
+        let certificate = self.build(request)?;
+        self.log.append(IssuanceEntry::Issued {
+            certificate: certificate.clone(),
+            replaces: None,
+            at: Timestamp::now(),
+        })?;
+
+        Ok(certificate)
+    }
+
+    /// Issue a new certificate with the same data as `certificate_id`, until `expires_at`
+    ///
+    /// The new certificate has new salts, and the old one is marked as renewed.
+    pub fn renew(
+        &self,
+        certificate_id: &str,
+        expires_at: Timestamp,
+    ) -> Result<Certificate, IssuerError> {
+        let record = self.active_record(certificate_id)?;
+        let metadata = record.certificate.metadata;
+        // The claims are derived again, they may have changed since
+        let claims = record
+            .certificate
+            .claims
+            .keys()
+            .map(|name| name.parse())
+            .collect::<Result<Vec<Predicate>, _>>()?;
+        let certificate = self.build(
+            IssueRequest::new(
+                record.certificate.subject,
+                record.certificate.data,
+                metadata.verification_level,
+                metadata.verification_method,
+                expires_at,
+            )
+            .with_claims(claims),
+        )?;
+
+        self.log.append(IssuanceEntry::Issued {
+            certificate: certificate.clone(),
+            replaces: Some(certificate_id.to_string()),
+            at: Timestamp::now(),
+        })?;
+
+        Ok(certificate)
+    }
+
+    /// Issue `ticket` to `subject`, valid until the end of the ticket
+    pub fn issue_ticket(
+        &self,
+        subject: nostr::PublicKey,
+        ticket: TicketData,
+    ) -> Result<Certificate, IssuerError> {
+        self.issue(Self::ticket_request(subject, &ticket)?)
+    }
+
+    /// Build `ticket` without recording it, to find out whether [`Self::issue_ticket`] would fail
+    pub fn check_ticket(
+        &self,
+        subject: nostr::PublicKey,
+        ticket: &TicketData,
+    ) -> Result<(), IssuerError> {
+        self.build(Self::ticket_request(subject, ticket)?)?;
+        Ok(())
+    }
+
+    /// Re-issue a transferable ticket to `new_subject`
+    ///
+    /// The new certificate keeps the nonce of the ticket, so it can still be redeemed only once,
+    /// and the old one is revoked. The returned content is meant to be sent to the old subject.
+    ///
+    /// The old ticket is revoked before the new one is recorded: if recording fails, no ticket is
+    /// valid rather than two.
+    pub fn transfer_ticket(
+        &self,
+        ticket_id: &str,
+        new_subject: nostr::PublicKey,
+    ) -> Result<(Certificate, CertificateRevocationContent), IssuerError> {
+        let record = self.active_record(ticket_id)?;
+        let ticket = TicketData::from_certificate(&record.certificate)?;
+        if !ticket.transferable {
+            return Err(TicketError::NotTransferable.into());
+        }
+
+        let metadata = record.certificate.metadata;
+        let certificate = self.build(IssueRequest::new(
+            new_subject,
+            record.certificate.data,
+            metadata.verification_level,
+            metadata.verification_method,
+            metadata.expires_at,
+        ))?;
+
+        let revocation = self.revoke(ticket_id, Some("Transferred".to_string()))?;
+        self.log.append(IssuanceEntry::Issued {
+            certificate: certificate.clone(),
+            replaces: Some(ticket_id.to_string()),
+            at: Timestamp::now(),
+        })?;
+
+        Ok((certificate, revocation))
+    }
+
+    /// Revoke a certificate, the returned content is meant to be sent to the subject
+    pub fn revoke(
+        &self,
+        certificate_id: &str,
+        reason: Option<String>,
+    ) -> Result<CertificateRevocationContent, IssuerError> {
+        let record = self
+            .record(certificate_id)?
+            .ok_or_else(|| IssuerError::UnknownCertificate(certificate_id.to_string()))?;
+        if matches!(record.status, CertificateStatus::Revoked { .. }) {
+            return Err(IssuerError::Revoked(certificate_id.to_string()));
+        }
+
+        let revoked_at = Timestamp::now();
+        self.log.append(IssuanceEntry::Revoked {
+            certificate_id: certificate_id.to_string(),
+            reason: reason.clone(),
+            at: revoked_at,
+        })?;
+
+        Ok(CertificateRevocationContent {
+            certificate_id: certificate_id.to_string(),
+            reason,
+            revoked_at,
+        })
+    }
+
+    /// Every certificate issued, oldest first
+    pub fn records(&self) -> Result<Vec<IssuanceRecord>, IssuerError> {
+        let mut records: Vec<IssuanceRecord> = Vec::new();
+        for entry in self.log.entries()? {
+            match entry {
+                IssuanceEntry::Issued {
+                    certificate,
+                    replaces,
+                    at,
+                } => {
+                    if let Some(replaced) = &replaces {
+                        // A transferred ticket is revoked before it is replaced, and stays so
+                        if let Some(old) = records.iter_mut().find(|r| {
+                            &r.certificate.id() == replaced
+                                && !matches!(r.status, CertificateStatus::Revoked { .. })
+                        }) {
+                            old.status = CertificateStatus::Renewed {
+                                by: certificate.id(),
+                            };
+                        }
+                    }
+
+                    records.push(IssuanceRecord {
+                        certificate,
+                        issued_at: at,
+                        replaces,
+                        status: CertificateStatus::Active,
+                    });
+                }
+                IssuanceEntry::Revoked {
+                    certificate_id,
+                    reason,
+                    at,
+                } => {
+                    if let Some(record) = records
+                        .iter_mut()
+                        .find(|r| r.certificate.id() == certificate_id)
+                    {
+                        record.status = CertificateStatus::Revoked { reason, at };
+                    }
+                }
+            }
+        }
+
+        Ok(records)
+    }
+
+    pub fn record(&self, certificate_id: &str) -> Result<Option<IssuanceRecord>, IssuerError> {
+        Ok(self
+            .records()?
+            .into_iter()
+            .find(|r| r.certificate.id() == certificate_id))
+    }
+
+    /// The certificates issued to `subject`, oldest first
+    pub fn records_for(
+        &self,
+        subject: &nostr::PublicKey,
+    ) -> Result<Vec<IssuanceRecord>, IssuerError> {
+        Ok(self
+            .records()?
+            .into_iter()
+            .filter(|r| &r.certificate.subject == subject)
+            .collect())
+    }
+
+    fn active_record(&self, certificate_id: &str) -> Result<IssuanceRecord, IssuerError> {
+        let record = self
+            .record(certificate_id)?
+            .ok_or_else(|| IssuerError::UnknownCertificate(certificate_id.to_string()))?;
+
+        match record.status {
+            CertificateStatus::Active => Ok(record),
+            CertificateStatus::Revoked { .. } => {
+                Err(IssuerError::Revoked(certificate_id.to_string()))
+            }
+            CertificateStatus::Renewed { by } => Err(IssuerError::AlreadyRenewed {
+                id: certificate_id.to_string(),
+                by,
+            }),
+        }
+    }
+
+    fn ticket_request(
+        subject: nostr::PublicKey,
+        ticket: &TicketData,
+    ) -> Result<IssueRequest, IssuerError> {
+        let (level, method) = TicketData::verification();
+        Ok(IssueRequest::new(
+            subject,
+            ticket.to_certificate_data()?,
+            level,
+            method,
+            ticket.valid_until,
+        ))
+    }
+
+    fn build(&self, request: IssueRequest) -> Result<Certificate, IssuerError> {
+        let issued_at = Timestamp::now();
+        if request.expires_at <= issued_at {
+            return Err(IssuerError::InvalidExpiration);
+        }
+
+        let mut metadata = CertificateMetadata {
+            issuer_pubkey: self.keys.public_key(),
+            issued_at,
+            expires_at: request.expires_at,
+            verification_level: request.verification_level,
+            verification_method: request.verification_method,
+            salt_sequence: SaltSequence::new(SALT_SIZE, vec![]),
+            // Computed by `Certificate::new`
+            merkle_root: MerkleRoot::new([0u8; 32]),
+        };
+
+        let claims = request
+            .claims
+            .iter()
+            .map(|predicate| {
+                let value = predicate.derive(&request.data, issued_at)?;
+                Ok((predicate.name(), serde_json::Value::Bool(value)))
+            })
+            .collect::<Result<BTreeMap<_, _>, PredicateError>>()?;
+
+        // One salt for each revealable field
+        let unsalted = Certificate {
+            version: CERTIFICATE_VERSION,
+            subject: request.subject,
+            data: request.data,
+            claims,
+            metadata: metadata.clone(),
+            signature: String::new(),
+        };
+        let fields = unsalted.prepare_for_revealing()?.fields.len();
+        let mut salts = vec![0u8; SALT_SIZE * fields];
+        thread_rng().fill_bytes(&mut salts);
+        metadata.salt_sequence = SaltSequence::new(SALT_SIZE, salts);
+
+        let mut certificate = Certificate::new_with_claims(
+            CERTIFICATE_VERSION,
+            unsalted.subject,
+            unsalted.data,
+            unsalted.claims,
+            metadata,
+            String::new(),
+        )?;
+        certificate.sign(&self.keys)?;
+
+        Ok(certificate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use base64::Engine;
+
+    use crate::protocol::identity::{PartialCertificate, PersonData};
+
+    fn request(subject: nostr::PublicKey) -> IssueRequest {
+        IssueRequest::new(
+            subject,
+            CertificateData::Custom {
+                data: serde_json::json!({ "name": "Alice", "member": { "since": "2024" } }),
+            },
+            VerificationLevel::Medium,
+            VerificationMethod::VideoCall,
+            Timestamp::now_plus_seconds(3600),
+        )
+    }
+
+    #[test]
+    fn test_issued_certificate_verifies() {
+        let issuer = Issuer::new(nostr::Keys::generate(), MemoryIssuanceLog::new());
+        let subject = nostr::Keys::generate().public_key();
+        let certificate = issuer.issue(request(subject)).unwrap();
+
+        assert_eq!(certificate.subject, subject);
+        assert_eq!(certificate.metadata.issuer_pubkey, issuer.public_key());
+
+        let proof = certificate
+            .prepare_for_revealing()
+            .unwrap()
+            .create_proof(&certificate.metadata.salt_sequence, &["name".to_string()])
+            .unwrap();
+        let partial = PartialCertificate {
+            version: certificate.version,
+            subject: certificate.subject,
+            metadata: certificate.metadata.signed(),
+            signature: certificate.signature.clone(),
+            merkle_proof: proof,
+        };
+        assert_eq!(partial.verify().unwrap()["name"], "Alice");
+
+        // Same data, different salts
+        let other = issuer.issue(request(subject)).unwrap();
+        assert_ne!(certificate.id(), other.id());
+        assert_eq!(issuer.records_for(&subject).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_prove_claim_without_data() {
+        let issuer = Issuer::new(nostr::Keys::generate(), MemoryIssuanceLog::new());
+        let subject = nostr::Keys::generate().public_key();
+        let person = IssueRequest::new(
+            subject,
+            CertificateData::Person(PersonData {
+                full_name: "John Doe".to_string(),
+                date_of_birth: "1990-01-01".to_string(),
+                nationality: "US".to_string(),
+                document_type: "passport".to_string(),
+                document_number: "123456789".to_string(),
+                place_of_birth: None,
+                gender: None,
+                issue_date: None,
+                expiry_date: None,
+                address: None,
+            }),
+            VerificationLevel::High,
+            VerificationMethod::InPerson,
+            Timestamp::now_plus_seconds(3600),
+        )
+        .with_claims(vec![Predicate::AgeOver(18)]);
+        let certificate = issuer.issue(person).unwrap();
+        assert_eq!(certificate.claims["age_over_18"], true);
+
+        let partial = certificate
+            .reveal(&[Predicate::AgeOver(18).field()])
+            .unwrap();
+        let revealed = partial.verify().unwrap();
+        assert_eq!(
+            revealed,
+            serde_json::json!({ "claims": { "age_over_18": true } })
+        );
+        assert_eq!(Predicate::AgeOver(18).evaluate(&revealed), Some(true));
+
+        // Only the salt of the revealed claim is disclosed, the date of birth can't be brute forced
+        let birth = certificate
+            .prepare_for_revealing()
+            .unwrap()
+            .fields
+            .keys()
+            .position(|field| field == "date_of_birth")
+            .unwrap();
+        let salt = certificate.metadata.salt_sequence.get_salt(birth).unwrap();
+        let json = serde_json::to_string(&partial).unwrap();
+        assert!(!json.contains("salt_sequence"));
+        assert!(!json.contains(&base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(salt)));
+        assert!(
+            !partial
+                .merkle_proof
+                .to_compact()
+                .unwrap()
+                .windows(salt.len())
+                .any(|window| window == salt)
+        );
+
+        // Renewing derives the claims again
+        let renewed = issuer
+            .renew(&certificate.id(), Timestamp::now_plus_seconds(7200))
+            .unwrap();
+        assert_eq!(renewed.claims, certificate.claims);
+
+        // Claims that can't be derived from the data are refused
+        assert!(matches!(
+            issuer.issue(request(subject).with_claims(vec![Predicate::AgeOver(18)])),
+            Err(IssuerError::Predicate(PredicateError::Unsupported(_)))
+        ));
+    }
+
+    #[test]
+    fn test_renew_and_revoke() {
+        let issuer = Issuer::new(nostr::Keys::generate(), MemoryIssuanceLog::new());
+        let subject = nostr::Keys::generate().public_key();
+        let original = issuer.issue(request(subject)).unwrap();
+
+        let renewed = issuer
+            .renew(&original.id(), Timestamp::now_plus_seconds(7200))
+            .unwrap();
+        assert_eq!(renewed.subject, subject);
+        assert_eq!(
+            issuer.record(&original.id()).unwrap().unwrap().status,
+            CertificateStatus::Renewed { by: renewed.id() }
+        );
+        assert!(matches!(
+            issuer.renew(&original.id(), Timestamp::now_plus_seconds(7200)),
+            Err(IssuerError::AlreadyRenewed { .. })
+        ));
+
+        let revocation = issuer
+            .revoke(&renewed.id(), Some("Document expired".to_string()))
+            .unwrap();
+        assert_eq!(revocation.certificate_id, renewed.id());
+        let record = issuer.record(&renewed.id()).unwrap().unwrap();
+        assert_eq!(record.replaces, Some(original.id()));
+        assert!(matches!(record.status, CertificateStatus::Revoked { .. }));
+
+        assert!(matches!(
+            issuer.revoke(&renewed.id(), None),
+            Err(IssuerError::Revoked(_))
+        ));
+        assert!(matches!(
+            issuer.revoke("unknown", None),
+            Err(IssuerError::UnknownCertificate(_))
+        ));
+    }
+
+    #[test]
+    fn test_expiration_in_the_past() {
+        let issuer = Issuer::new(nostr::Keys::generate(), MemoryIssuanceLog::new());
+        let mut request = request(nostr::Keys::generate().public_key());
+        request.expires_at = Timestamp::new(0);
+
+        assert!(matches!(
+            issuer.issue(request),
+            Err(IssuerError::InvalidExpiration)
+        ));
+    }
+
+    #[test]
+    fn test_file_log_is_reloaded() {
+        let path = std::env::temp_dir().join(format!(
+            "portal-issuer-test-{}.jsonl",
+            crate::utils::random_string(8)
+        ));
+        let keys = nostr::Keys::generate();
+        let subject = nostr::Keys::generate().public_key();
+
+        let id = {
+            let issuer = Issuer::new(keys.clone(), FileIssuanceLog::open(&path).unwrap());
+            let certificate = issuer.issue(request(subject)).unwrap();
+            issuer.revoke(&certificate.id(), None).unwrap();
+            certificate.id()
+        };
+
+        let issuer = Issuer::new(keys, FileIssuanceLog::open(&path).unwrap());
+        let record = issuer.record(&id).unwrap().unwrap();
+        assert_eq!(record.certificate.subject, subject);
+        assert!(matches!(record.status, CertificateStatus::Revoked { .. }));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,32 @@
+//! Fixtures for the tests of the modules that handle certificates
+
+use nostr::Keys;
+
+use crate::protocol::identity::{
+    Certificate, CertificateData, VerificationLevel, VerificationMethod,
+};
+use crate::protocol::model::Timestamp;
+
+use super::{IssueRequest, Issuer, MemoryIssuanceLog};
+
+/// An issuer with new keys and an in-memory log
+pub(crate) fn issuer() -> Issuer {
+    Issuer::new(Keys::generate(), MemoryIssuanceLog::new())
+}
+
+/// A request for `data` about a random subject, verified at [`VerificationLevel::High`] with a
+/// document upload and valid for an hour
+pub(crate) fn request(data: CertificateData) -> IssueRequest {
+    IssueRequest::new(
+        Keys::generate().public_key(),
+        data,
+        VerificationLevel::High,
+        VerificationMethod::DocumentUpload,
+        Timestamp::now_plus_seconds(60 * 60),
+    )
+}
+
+/// Issue `data` with a new issuer, see [`request`]
+pub(crate) fn certificate(data: CertificateData) -> Certificate {
+    issuer().issue(request(data)).unwrap()
+}
@@ -2,8 +2,10 @@ pub mod app;
 pub mod cashu;
 pub mod close_subscription;
 pub mod invoice;
+pub mod issuer;
 pub mod profile;
 pub mod protocol;
+pub mod qr;
 pub mod router;
 pub mod sdk;
 pub mod utils;
@@ -14,5 +16,5 @@ pub use nostr_relay_pool;
 #[cfg(feature = "bindings")]
 uniffi::setup_scaffolding!();
 
-#[cfg(test)]
-mod test_framework;
+#[cfg(any(test, feature = "testing"))]
+pub mod test_framework;
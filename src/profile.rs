@@ -82,6 +82,7 @@ impl OneShotSender for SetProfileConversation {
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct Profile {
     pub name: Option<String>,
     pub display_name: Option<String>,
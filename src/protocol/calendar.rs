@@ -12,12 +12,16 @@ use super::model::Timestamp;
 pub struct Calendar {
     /// Optional weekday specification (Mon,Tue..Fri)
     weekdays: Option<Vec<Weekday>>,
+    /// Optional occurrences of weekdays in the month (Tue#2, Fri#L)
+    nth_weekdays: Option<Vec<NthWeekday>>,
     /// Year component (can be * or specific years)
     year: TimeComponent<1970, 2099>,
     /// Month component (can be * or 1-12)
     month: TimeComponent<1, 12>,
     /// Day component (can be * or 1-31)
     day: TimeComponent<1, 31>,
+    /// Whether `day` counts from the end of the month (`~`), 1 being the last day
+    day_from_end: bool,
     /// Hour component (can be * or 0-23)
     hour: TimeComponent<0, 23>,
     /// Minute component (can be * or 0-59)
@@ -121,6 +125,24 @@ impl<const MIN: u32, const MAX: u32> TimeComponent<MIN, MAX> {
             }
         }
     }
+
+    /// Iterate the values in descending order, starting at `to`
+    pub fn iter_rev(&self, to: Option<u32>) -> Box<dyn Iterator<Item = u32>> {
+        let to = to.unwrap_or(MAX);
+        match self {
+            TimeComponent::Any => Box::new((MIN..=to).rev()),
+            TimeComponent::Values(values) => {
+                Box::new(values.clone().into_iter().rev().filter(move |v| *v <= to))
+            }
+            TimeComponent::Range { start, end, step } => {
+                let range = (*start..=*end)
+                    .step_by(step.unwrap_or(1) as usize)
+                    .filter(|v| *v <= to)
+                    .collect::<Vec<_>>();
+                Box::new(range.into_iter().rev())
+            }
+        }
+    }
 }
 
 // #[cfg(feature = "bindings")]
@@ -208,6 +230,61 @@ pub enum Weekday {
     Sun = 6,
 }
 
+/// Which occurrence of a weekday in the month
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WeekdayOccurrence {
+    /// The first to fifth occurrence
+    Nth(u32),
+    /// The last occurrence, the fourth or the fifth
+    Last,
+}
+
+impl WeekdayOccurrence {
+    /// Whether day `day` of a month with `days_in_month` days is this occurrence of its weekday
+    fn matches(&self, day: u32, days_in_month: u32) -> bool {
+        match self {
+            WeekdayOccurrence::Nth(n) => (day - 1) / 7 + 1 == *n,
+            WeekdayOccurrence::Last => day + 7 > days_in_month,
+        }
+    }
+}
+
+/// A weekday of the month, like the second Tuesday (`Tue#2`) or the last Friday (`Fri#L`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NthWeekday {
+    pub weekday: Weekday,
+    pub occurrence: WeekdayOccurrence,
+}
+
+impl FromStr for NthWeekday {
+    type Err = CalendarError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (weekday, occurrence) = s.split_once('#').ok_or(CalendarError::InvalidFormat)?;
+        let occurrence = match occurrence.to_lowercase().as_str() {
+            "l" | "last" => WeekdayOccurrence::Last,
+            n => match n.parse() {
+                Ok(n) if (1..=5).contains(&n) => WeekdayOccurrence::Nth(n),
+                _ => return Err(CalendarError::InvalidOccurrence(occurrence.to_string())),
+            },
+        };
+
+        Ok(NthWeekday {
+            weekday: weekday.parse()?,
+            occurrence,
+        })
+    }
+}
+
+impl fmt::Display for NthWeekday {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.occurrence {
+            WeekdayOccurrence::Nth(n) => write!(f, "{}#{}", self.weekday, n),
+            WeekdayOccurrence::Last => write!(f, "{}#L", self.weekday),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 #[cfg_attr(feature = "bindings", derive(uniffi::Error))]
 pub enum CalendarError {
@@ -221,6 +298,12 @@ pub enum CalendarError {
     InvalidRange { start: u32, end: u32 },
     #[error("Invalid timezone: {0}")]
     InvalidTimezone(String),
+    #[error("Invalid weekday occurrence: {0}")]
+    InvalidOccurrence(String),
+    #[error("Invalid RRULE: {0}")]
+    InvalidRRule(String),
+    #[error("RRULE cannot be represented: {0}")]
+    UnsupportedRRule(String),
 }
 
 impl FromStr for Weekday {
@@ -241,6 +324,58 @@ impl FromStr for Weekday {
     }
 }
 
+impl Weekday {
+    fn full_name(&self) -> &'static str {
+        match self {
+            Weekday::Mon => "Monday",
+            Weekday::Tue => "Tuesday",
+            Weekday::Wed => "Wednesday",
+            Weekday::Thu => "Thursday",
+            Weekday::Fri => "Friday",
+            Weekday::Sat => "Saturday",
+            Weekday::Sun => "Sunday",
+        }
+    }
+
+    /// The two-letter code used by iCalendar rules
+    fn rrule_code(&self) -> &'static str {
+        match self {
+            Weekday::Mon => "MO",
+            Weekday::Tue => "TU",
+            Weekday::Wed => "WE",
+            Weekday::Thu => "TH",
+            Weekday::Fri => "FR",
+            Weekday::Sat => "SA",
+            Weekday::Sun => "SU",
+        }
+    }
+
+    fn from_rrule_code(code: &str) -> Result<Self, CalendarError> {
+        match code.to_uppercase().as_str() {
+            "MO" => Ok(Weekday::Mon),
+            "TU" => Ok(Weekday::Tue),
+            "WE" => Ok(Weekday::Wed),
+            "TH" => Ok(Weekday::Thu),
+            "FR" => Ok(Weekday::Fri),
+            "SA" => Ok(Weekday::Sat),
+            "SU" => Ok(Weekday::Sun),
+            _ => Err(CalendarError::InvalidWeekday(code.to_string())),
+        }
+    }
+
+    fn from_chrono(weekday: chrono::Weekday) -> Self {
+        match weekday {
+            chrono::Weekday::Mon => Weekday::Mon,
+            chrono::Weekday::Tue => Weekday::Tue,
+            chrono::Weekday::Wed => Weekday::Wed,
+            chrono::Weekday::Thu => Weekday::Thu,
+            chrono::Weekday::Fri => Weekday::Fri,
+            chrono::Weekday::Sat => Weekday::Sat,
+            chrono::Weekday::Sun => Weekday::Sun,
+        }
+    }
+}
+
 impl fmt::Display for Weekday {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -380,9 +515,11 @@ impl Calendar {
     pub fn minutely(timezone: Option<chrono_tz::Tz>) -> Self {
         Calendar {
             weekdays: None,
+            nth_weekdays: None,
             year: TimeComponent::Any.into(),
             month: TimeComponent::Any.into(),
             day: TimeComponent::Any.into(),
+            day_from_end: false,
             hour: TimeComponent::Any.into(),
             minute: TimeComponent::Any.into(),
             second: TimeComponent::Values(vec![0]).into(),
@@ -393,9 +530,11 @@ impl Calendar {
     pub fn hourly(timezone: Option<chrono_tz::Tz>) -> Self {
         Calendar {
             weekdays: None,
+            nth_weekdays: None,
             year: TimeComponent::Any.into(),
             month: TimeComponent::Any.into(),
             day: TimeComponent::Any.into(),
+            day_from_end: false,
             hour: TimeComponent::Any.into(),
             minute: TimeComponent::Values(vec![0]).into(),
             second: TimeComponent::Values(vec![0]).into(),
@@ -406,9 +545,11 @@ impl Calendar {
     pub fn daily(timezone: Option<chrono_tz::Tz>) -> Self {
         Calendar {
             weekdays: None,
+            nth_weekdays: None,
             year: TimeComponent::Any.into(),
             month: TimeComponent::Any.into(),
             day: TimeComponent::Any.into(),
+            day_from_end: false,
             hour: TimeComponent::Values(vec![0]).into(),
             minute: TimeComponent::Values(vec![0]).into(),
             second: TimeComponent::Values(vec![0]).into(),
@@ -419,9 +560,11 @@ impl Calendar {
     pub fn weekly(timezone: Option<chrono_tz::Tz>) -> Self {
         Calendar {
             weekdays: Some(vec![Weekday::Mon]),
+            nth_weekdays: None,
             year: TimeComponent::Any.into(),
             month: TimeComponent::Any.into(),
             day: TimeComponent::Any.into(),
+            day_from_end: false,
             hour: TimeComponent::Values(vec![0]).into(),
             minute: TimeComponent::Values(vec![0]).into(),
             second: TimeComponent::Values(vec![0]).into(),
@@ -432,9 +575,11 @@ impl Calendar {
     pub fn monthly(timezone: Option<chrono_tz::Tz>) -> Self {
         Calendar {
             weekdays: None,
+            nth_weekdays: None,
             year: TimeComponent::Any.into(),
             month: TimeComponent::Any.into(),
             day: TimeComponent::Values(vec![1]).into(),
+            day_from_end: false,
             hour: TimeComponent::Values(vec![0]).into(),
             minute: TimeComponent::Values(vec![0]).into(),
             second: TimeComponent::Values(vec![0]).into(),
@@ -445,9 +590,11 @@ impl Calendar {
     pub fn yearly(timezone: Option<chrono_tz::Tz>) -> Self {
         Calendar {
             weekdays: None,
+            nth_weekdays: None,
             year: TimeComponent::Any.into(),
             month: TimeComponent::Values(vec![1]).into(),
             day: TimeComponent::Values(vec![1]).into(),
+            day_from_end: false,
             hour: TimeComponent::Values(vec![0]).into(),
             minute: TimeComponent::Values(vec![0]).into(),
             second: TimeComponent::Values(vec![0]).into(),
@@ -458,9 +605,11 @@ impl Calendar {
     pub fn quarterly(timezone: Option<chrono_tz::Tz>) -> Self {
         Calendar {
             weekdays: None,
+            nth_weekdays: None,
             year: TimeComponent::Any.into(),
             month: TimeComponent::Values(vec![1, 4, 7, 10]).into(),
             day: TimeComponent::Values(vec![1]).into(),
+            day_from_end: false,
             hour: TimeComponent::Values(vec![0]).into(),
             minute: TimeComponent::Values(vec![0]).into(),
             second: TimeComponent::Values(vec![0]).into(),
@@ -471,9 +620,11 @@ impl Calendar {
     pub fn semiannually(timezone: Option<chrono_tz::Tz>) -> Self {
         Calendar {
             weekdays: None,
+            nth_weekdays: None,
             year: TimeComponent::Any.into(),
             month: TimeComponent::Values(vec![1, 7]).into(),
             day: TimeComponent::Values(vec![1]).into(),
+            day_from_end: false,
             hour: TimeComponent::Values(vec![0]).into(),
             minute: TimeComponent::Values(vec![0]).into(),
             second: TimeComponent::Values(vec![0]).into(),
@@ -511,6 +662,37 @@ impl Calendar {
     }
 
     fn get_frequency_text(&self) -> Option<String> {
+        // Days counted from the end of the month
+        if self.day_from_end {
+            return match self.day {
+                TimeComponent::Values(ref days) if days == &[1] => {
+                    Some(format!("On the last day of {}", self.months_text()))
+                }
+                TimeComponent::Values(ref days) if days.len() == 1 => Some(format!(
+                    "On the {} to last day of {}",
+                    Self::ordinal(days[0]),
+                    self.months_text()
+                )),
+                _ => Some("On a custom schedule".to_string()),
+            };
+        }
+
+        // Occurrences of weekdays in the month
+        if let Some(ref nth_weekdays) = self.nth_weekdays {
+            let days = nth_weekdays
+                .iter()
+                .map(|n| {
+                    let occurrence = match n.occurrence {
+                        WeekdayOccurrence::Nth(n) => Self::ordinal(n),
+                        WeekdayOccurrence::Last => "last".to_string(),
+                    };
+                    format!("{} {}", occurrence, n.weekday.full_name())
+                })
+                .collect::<Vec<_>>()
+                .join(" and ");
+            return Some(format!("On the {} of {}", days, self.months_text()));
+        }
+
         // If not following a specific pattern, describe the recurring schedule
         // Based on what components are Any vs specific
         if matches!(self.day, TimeComponent::Any)
@@ -524,8 +706,9 @@ impl Calendar {
         if let TimeComponent::Values(ref days) = self.day {
             if days.len() == 1 {
                 return Some(format!(
-                    "On the {} day of each month",
-                    Self::ordinal(days[0])
+                    "On the {} day of {}",
+                    Self::ordinal(days[0]),
+                    self.months_text()
                 ));
             }
         }
@@ -534,6 +717,78 @@ impl Calendar {
         Some("On a custom schedule".to_string())
     }
 
+    /// Whether the date matches the day rules that depend on the month: the days counted from
+    /// the end, the weekdays and their occurrences
+    fn matches_day(&self, year: u32, month: u32, day: u32) -> bool {
+        let days_in_month = days_in_month(year, month);
+        if self.day_from_end && !self.day.contains(days_in_month - day + 1) {
+            return false;
+        }
+
+        let weekday = NaiveDate::from_ymd_opt(year as i32, month, day)
+            .expect("Invalid date")
+            .weekday() as u8;
+        let weekday_matches = self
+            .weekdays
+            .as_ref()
+            .map(|l| l.iter().any(|d| *d as u8 == weekday))
+            .unwrap_or(true);
+        let nth_weekday_matches = self
+            .nth_weekdays
+            .as_ref()
+            .map(|l| {
+                l.iter()
+                    .any(|n| n.weekday as u8 == weekday && n.occurrence.matches(day, days_in_month))
+            })
+            .unwrap_or(true);
+
+        weekday_matches && nth_weekday_matches
+    }
+
+    /// Iterate the occurrences from `from` included to `until` excluded, computed lazily
+    pub fn occurrences(
+        &self,
+        from: Timestamp,
+        until: Timestamp,
+    ) -> impl Iterator<Item = Timestamp> + '_ {
+        let mut from = Some(from);
+        std::iter::from_fn(move || {
+            let next = self.next_occurrence(from?).filter(|next| *next < until);
+            from = next.map(|next| Timestamp::new(next.as_u64() + 1));
+            next
+        })
+    }
+
+    // "each month", or the names of the months the calendar is restricted to
+    fn months_text(&self) -> String {
+        const MONTH_NAMES: [&str; 12] = [
+            "January",
+            "February",
+            "March",
+            "April",
+            "May",
+            "June",
+            "July",
+            "August",
+            "September",
+            "October",
+            "November",
+            "December",
+        ];
+
+        let months = self
+            .month
+            .iter(None)
+            .map(|m| MONTH_NAMES[m as usize - 1])
+            .collect::<Vec<_>>();
+        match months.as_slice() {
+            [] => "no month".to_string(),
+            [month] => month.to_string(),
+            _ if months.len() == MONTH_NAMES.len() => "each month".to_string(),
+            [rest @ .., last] => format!("{} and {}", rest.join(", "), last),
+        }
+    }
+
     // Helper function to convert numbers to ordinals (1st, 2nd, 3rd, etc.)
     fn ordinal(n: u32) -> String {
         let suffix = match (n % 10, n % 100) {
@@ -593,84 +848,155 @@ impl Calendar {
         let from = DateTime::from_timestamp(from.as_u64() as i64, 0)
             .expect("Invalid timestamp")
             .with_timezone(timezone);
-
-        let is_leap_year = |year: u32| year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
-        let days_in_month = |year: u32, month: u32| match month {
-            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
-            4 | 6 | 9 | 11 => 30,
-            2 => {
-                if is_leap_year(year) {
-                    29
-                } else {
-                    28
-                }
-            }
-            _ => unreachable!(),
-        };
+        // The local times skipped when the clocks go forward occur at the change, which can be
+        // `from` itself: start the walk before them and compare the resolved instants
+        let start = from.naive_local() - clock_change(&from).max(chrono::Duration::zero());
 
         self.year
-            .iter(Some(from.year() as u32))
+            .iter(Some(start.year() as u32))
             .map(|y| {
-                let is_current_year = y == from.year() as u32;
-                let from = (is_current_year).then_some(from.month());
+                let is_current_year = y == start.year() as u32;
+                let from = (is_current_year).then_some(start.month());
                 self.month.iter(from).map(move |m| (is_current_year, y, m))
             })
             .flatten()
             .map(|(is_current_year, y, m)| {
-                let is_current_month = is_current_year && m == from.month();
-                let from = (is_current_month).then_some(from.day());
+                let is_current_month = is_current_year && m == start.month();
+                let from = (is_current_month).then_some(start.day());
                 let days_in_month = days_in_month(y, m);
-                self.day
-                    .iter(from)
-                    .map(move |d| (is_current_month, y, m, d))
+                // Days counted from the end depend on the length of the month
+                let days: Box<dyn Iterator<Item = u32>> = if self.day_from_end {
+                    Box::new(from.unwrap_or(1)..=days_in_month)
+                } else {
+                    self.day.iter(from)
+                };
+                days.map(move |d| (is_current_month, y, m, d))
                     .filter(move |(_, _, _, d)| *d <= days_in_month)
             })
             .flatten()
-            .filter(|(_, y, m, d)| {
-                let weekday = NaiveDate::from_ymd_opt(*y as i32, *m, *d)
-                    .expect("Invalid date")
-                    .weekday() as u8;
-                self.weekdays
-                    .as_ref()
-                    .map(|l| l.iter().any(|d| *d as u8 == weekday))
-                    .unwrap_or(true)
-            })
+            .filter(|(_, y, m, d)| self.matches_day(*y, *m, *d))
             .map(|(is_current_month, y, m, d)| {
-                let is_current_day = is_current_month && d == from.day();
-                let from = (is_current_day).then_some(from.hour());
+                let is_current_day = is_current_month && d == start.day();
+                let from = (is_current_day).then_some(start.hour());
                 self.hour
                     .iter(from)
                     .map(move |h| (is_current_day, y, m, d, h))
             })
             .flatten()
             .map(|(is_current_day, y, m, d, h)| {
-                let is_current_hour = is_current_day && h == from.hour();
-                let from = (is_current_hour).then_some(from.minute());
+                let is_current_hour = is_current_day && h == start.hour();
+                let from = (is_current_hour).then_some(start.minute());
                 self.minute
                     .iter(from)
                     .map(move |mi| (is_current_hour, y, m, d, h, mi))
             })
             .flatten()
             .map(|(is_current_hour, y, m, d, h, mi)| {
-                let is_current_minute = is_current_hour && mi == from.minute();
-                let from = (is_current_minute).then_some(from.second());
+                let is_current_minute = is_current_hour && mi == start.minute();
+                let from = (is_current_minute).then_some(start.second());
                 self.second
                     .iter(from)
                     .map(move |s| (is_current_minute, y, m, d, h, mi, s))
             })
             .flatten()
-            .filter_map(|(_, y, m, d, h, mi, s)| {
-                NaiveDateTime::new(
-                    NaiveDate::from_ymd_opt(y as i32, m as u32, d as u32).expect("Invalid date"),
-                    NaiveTime::from_hms_opt(h as u32, mi as u32, s as u32).expect("Invalid time"),
+            .map(|(_, y, m, d, h, mi, s)| {
+                resolve_local(
+                    NaiveDateTime::new(
+                        NaiveDate::from_ymd_opt(y as i32, m as u32, d as u32)
+                            .expect("Invalid date"),
+                        NaiveTime::from_hms_opt(h as u32, mi as u32, s as u32)
+                            .expect("Invalid time"),
+                    ),
+                    *timezone,
+                )
+            })
+            // A repeated local time only occurs the first time, which can be before `from`
+            .filter(|dt| *dt >= from)
+            .map(|dt| Timestamp::new(dt.timestamp() as u64))
+            .next()
+    }
+
+    /// The last occurrence strictly before `before`
+    pub fn previous_occurrence(&self, before: Timestamp) -> Option<Timestamp> {
+        let timezone = self.timezone.as_ref().unwrap_or(&chrono_tz::Tz::UTC);
+
+        let before = DateTime::from_timestamp(before.as_u64() as i64, 0)
+            .expect("Invalid timestamp")
+            .with_timezone(timezone);
+        // The local times repeated when the clocks go back occur the first time, which can be
+        // before `before` even if they come after its local time: end the walk after them
+        let end = before.naive_local() + (-clock_change(&before)).max(chrono::Duration::zero());
+
+        self.year
+            .iter_rev(Some(end.year() as u32))
+            .map(|y| {
+                let is_current_year = y == end.year() as u32;
+                let to = (is_current_year).then_some(end.month());
+                self.month
+                    .iter_rev(to)
+                    .map(move |m| (is_current_year, y, m))
+            })
+            .flatten()
+            .map(|(is_current_year, y, m)| {
+                let is_current_month = is_current_year && m == end.month();
+                let to = (is_current_month).then_some(end.day());
+                let days_in_month = days_in_month(y, m);
+                let days: Box<dyn Iterator<Item = u32>> = if self.day_from_end {
+                    Box::new((1..=to.unwrap_or(days_in_month).min(days_in_month)).rev())
+                } else {
+                    self.day.iter_rev(to)
+                };
+                days.map(move |d| (is_current_month, y, m, d))
+                    .filter(move |(_, _, _, d)| *d <= days_in_month)
+            })
+            .flatten()
+            .filter(|(_, y, m, d)| self.matches_day(*y, *m, *d))
+            .map(|(is_current_month, y, m, d)| {
+                let is_current_day = is_current_month && d == end.day();
+                let to = (is_current_day).then_some(end.hour());
+                self.hour
+                    .iter_rev(to)
+                    .map(move |h| (is_current_day, y, m, d, h))
+            })
+            .flatten()
+            .map(|(is_current_day, y, m, d, h)| {
+                let is_current_hour = is_current_day && h == end.hour();
+                let to = (is_current_hour).then_some(end.minute());
+                self.minute
+                    .iter_rev(to)
+                    .map(move |mi| (is_current_hour, y, m, d, h, mi))
+            })
+            .flatten()
+            .map(|(is_current_hour, y, m, d, h, mi)| {
+                let is_current_minute = is_current_hour && mi == end.minute();
+                let to = (is_current_minute).then_some(end.second());
+                self.second
+                    .iter_rev(to)
+                    .map(move |s| (is_current_minute, y, m, d, h, mi, s))
+            })
+            .flatten()
+            .map(|(_, y, m, d, h, mi, s)| {
+                resolve_local(
+                    NaiveDateTime::new(
+                        NaiveDate::from_ymd_opt(y as i32, m as u32, d as u32)
+                            .expect("Invalid date"),
+                        NaiveTime::from_hms_opt(h as u32, mi as u32, s as u32)
+                            .expect("Invalid time"),
+                    ),
+                    *timezone,
                 )
-                .and_local_timezone(*timezone)
-                .earliest()
             })
+            // Timestamps can't go before the epoch
+            .filter(|dt| *dt < before && dt.timestamp() >= 0)
             .map(|dt| Timestamp::new(dt.timestamp() as u64))
             .next()
     }
 
+    /// Count the occurrences from `from` included to `until` excluded
+    pub fn count_between(&self, from: Timestamp, until: Timestamp) -> u64 {
+        self.occurrences(from, until).count() as u64
+    }
+
     /// Convert a calendar to a human-readable description
     ///
     /// * `show_timezone` - Whether to include the timezone in the description (defaults to true)
@@ -719,6 +1045,76 @@ impl Calendar {
     pub fn to_calendar_string(&self) -> String {
         self.to_string()
     }
+
+    /// Convert the calendar to an iCalendar `DTSTART` and `RRULE`
+    ///
+    /// The `DTSTART` is the first occurrence at or after `start`.
+    pub fn to_rrule(&self, start: Timestamp) -> Result<String, CalendarError> {
+        RRule {
+            calendar: self.clone(),
+            start: Some(self.next_occurrence(start).unwrap_or(start)),
+            until: None,
+            count: None,
+        }
+        .to_rrule_string()
+    }
+}
+
+/// How far the clocks moved forward in the day before `at`, negative if they went back
+fn clock_change(at: &DateTime<chrono_tz::Tz>) -> chrono::Duration {
+    let day_before = *at - chrono::Duration::days(1);
+    let offset = |dt: &DateTime<chrono_tz::Tz>| dt.offset().fix().local_minus_utc() as i64;
+    chrono::Duration::seconds(offset(at) - offset(&day_before))
+}
+
+/// Resolve a local time in `timezone`
+///
+/// A time repeated when the clocks go back occurs the first time, a time skipped when the clocks
+/// go forward occurs right when the clocks change, so that no occurrence is lost.
+fn resolve_local(datetime: NaiveDateTime, timezone: chrono_tz::Tz) -> DateTime<chrono_tz::Tz> {
+    if let Some(dt) = datetime.and_local_timezone(timezone).earliest() {
+        return dt;
+    }
+
+    // Find the first instant whose local time is after `datetime`: offsets are always within a
+    // day, and the local time grows with the instant around the change
+    let is_after = |timestamp: i64| {
+        DateTime::from_timestamp(timestamp, 0)
+            .expect("Invalid timestamp")
+            .with_timezone(&timezone)
+            .naive_local()
+            >= datetime
+    };
+    let mut low = (datetime - chrono::Duration::days(1)).and_utc().timestamp();
+    let mut high = (datetime + chrono::Duration::days(1)).and_utc().timestamp();
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if is_after(mid) {
+            high = mid;
+        } else {
+            low = mid;
+        }
+    }
+
+    DateTime::from_timestamp(high, 0)
+        .expect("Invalid timestamp")
+        .with_timezone(&timezone)
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    let is_leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 => {
+            if is_leap_year {
+                29
+            } else {
+                28
+            }
+        }
+        _ => unreachable!(),
+    }
 }
 
 impl FromStr for Calendar {
@@ -744,7 +1140,9 @@ impl FromStr for Calendar {
         }
 
         let mut weekdays = None;
+        let mut nth_weekdays = None;
         let mut date_parts = None;
+        let mut day_from_end = false;
         let mut time_parts = None;
         let mut timezone = None;
 
@@ -752,16 +1150,37 @@ impl FromStr for Calendar {
             if part.contains(':') {
                 // Time part
                 time_parts = Some(part.split(':').collect::<Vec<_>>());
-            } else if part.contains('-') || part == "*" {
+            } else if part.contains('-') || part.contains('~') || part == "*" {
                 if time_parts.is_some() {
                     return Err(CalendarError::InvalidFormat);
                 }
 
-                // Date part
-                date_parts = Some(part.split('-').collect::<Vec<_>>());
+                // Date part, the day is counted from the end of the month after a `~`
+                date_parts = Some(match part.split_once('~') {
+                    Some((year_month, day)) => {
+                        day_from_end = true;
+                        let mut parts = year_month.split('-').collect::<Vec<_>>();
+                        if parts.len() > 2 {
+                            return Err(CalendarError::InvalidFormat);
+                        }
+                        if parts.len() == 1 {
+                            parts.insert(0, "*");
+                        }
+                        parts.push(day);
+                        parts
+                    }
+                    None => part.split('-').collect::<Vec<_>>(),
+                });
             // } else if part.contains('/') || part.contains('.') {
             //     // Explicitly reject parts with slashes or periods before trying weekday parsing
             //     return Err(CalendarError::InvalidFormat);
+            } else if i == 0 && part.contains('#') {
+                // Occurrences of weekdays in the month
+                nth_weekdays = Some(
+                    part.split(',')
+                        .map(NthWeekday::from_str)
+                        .collect::<Result<Vec<_>, _>>()?,
+                );
             } else if i == 0 {
                 // Try parsing as weekday part (single, list, or range)
                 let weekday_list = if part.contains("..") {
@@ -822,9 +1241,11 @@ impl FromStr for Calendar {
 
         Ok(Calendar {
             weekdays,
+            nth_weekdays,
             year: year.into(),
             month: month.into(),
             day: day.into(),
+            day_from_end,
             hour: hour.into(),
             minute: minute.into(),
             second: second.into(),
@@ -844,11 +1265,31 @@ impl fmt::Display for Calendar {
             write!(f, "{} ", weekday_str)?;
         }
 
+        if let Some(ref nth_weekdays) = self.nth_weekdays {
+            let nth_weekday_str = nth_weekdays
+                .iter()
+                .map(|w| w.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            write!(f, "{} ", nth_weekday_str)?;
+        }
+
         // Always include date part
-        write!(f, "{}-{}-{} ", self.year, self.month, self.day)?;
+        let day_separator = if self.day_from_end { '~' } else { '-' };
+        write!(
+            f,
+            "{}-{}{}{} ",
+            self.year, self.month, day_separator, self.day
+        )?;
 
         // Time part
-        write!(f, "{}:{}:{}", self.hour, self.minute, self.second)
+        write!(f, "{}:{}:{}", self.hour, self.minute, self.second)?;
+
+        if let Some(ref timezone) = self.timezone {
+            write!(f, " {}", timezone)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -871,6 +1312,615 @@ impl<'de> Deserialize<'de> for Calendar {
     }
 }
 
+/// A recurrence rule in the iCalendar format (RFC 5545)
+///
+/// Only the rules that repeat with the same pattern every year can be represented by a
+/// [`Calendar`]: `BYSETPOS`, `BYYEARDAY`, `BYWEEKNO`, daily and weekly intervals and the
+/// intervals that don't divide their period evenly (every 5 months) are rejected with
+/// [`CalendarError::UnsupportedRRule`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RRule {
+    pub calendar: Calendar,
+    /// The `DTSTART` of the rule, also its first occurrence
+    pub start: Option<Timestamp>,
+    /// The `UNTIL` bound
+    pub until: Option<Timestamp>,
+    /// The `COUNT` bound
+    pub count: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum RRuleFrequency {
+    Secondly,
+    Minutely,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+impl FromStr for RRuleFrequency {
+    type Err = CalendarError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "SECONDLY" => Ok(RRuleFrequency::Secondly),
+            "MINUTELY" => Ok(RRuleFrequency::Minutely),
+            "HOURLY" => Ok(RRuleFrequency::Hourly),
+            "DAILY" => Ok(RRuleFrequency::Daily),
+            "WEEKLY" => Ok(RRuleFrequency::Weekly),
+            "MONTHLY" => Ok(RRuleFrequency::Monthly),
+            "YEARLY" => Ok(RRuleFrequency::Yearly),
+            _ => Err(CalendarError::InvalidRRule(format!("invalid FREQ: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for RRuleFrequency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                RRuleFrequency::Secondly => "SECONDLY",
+                RRuleFrequency::Minutely => "MINUTELY",
+                RRuleFrequency::Hourly => "HOURLY",
+                RRuleFrequency::Daily => "DAILY",
+                RRuleFrequency::Weekly => "WEEKLY",
+                RRuleFrequency::Monthly => "MONTHLY",
+                RRuleFrequency::Yearly => "YEARLY",
+            }
+        )
+    }
+}
+
+/// Parse a `DATE` or `DATE-TIME` value, returning whether it is in UTC
+fn parse_rrule_datetime(value: &str) -> Result<(NaiveDateTime, bool), CalendarError> {
+    let (value, utc) = match value.strip_suffix('Z') {
+        Some(value) => (value, true),
+        None => (value, false),
+    };
+
+    let datetime = if value.contains('T') {
+        NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()
+    } else {
+        NaiveDate::parse_from_str(value, "%Y%m%d")
+            .ok()
+            .and_then(|d| d.and_hms_opt(0, 0, 0))
+    };
+
+    datetime
+        .map(|datetime| (datetime, utc))
+        .ok_or_else(|| CalendarError::InvalidRRule(format!("invalid date: {}", value)))
+}
+
+fn rrule_timestamp(
+    datetime: NaiveDateTime,
+    timezone: Option<chrono_tz::Tz>,
+) -> Result<Timestamp, CalendarError> {
+    datetime
+        .and_local_timezone(timezone.unwrap_or(chrono_tz::Tz::UTC))
+        .earliest()
+        .map(|dt| Timestamp::new(dt.timestamp() as u64))
+        .ok_or_else(|| CalendarError::InvalidRRule(format!("invalid date: {}", datetime)))
+}
+
+fn parse_rrule_number(key: &str, value: &str) -> Result<u32, CalendarError> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(CalendarError::InvalidRRule(format!(
+            "invalid {}: {}",
+            key, value
+        ))),
+    }
+}
+
+fn parse_rrule_component<const MIN: u32, const MAX: u32>(
+    key: &str,
+    value: &str,
+) -> Result<TimeComponent<MIN, MAX>, CalendarError> {
+    let mut values = value
+        .split(',')
+        .map(|v| match v.parse::<u32>() {
+            Ok(v) if (MIN..=MAX).contains(&v) => Ok(v),
+            _ => Err(CalendarError::InvalidRRule(format!(
+                "invalid {} value: {}",
+                key, v
+            ))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    values.sort();
+    values.dedup();
+
+    Ok(TimeComponent::Values(values))
+}
+
+/// The component repeating every `interval` units from `anchor`, as long as it repeats with the
+/// same pattern in the next unit up (every 6 hours, every 3 months)
+fn rrule_interval<const MIN: u32, const MAX: u32>(
+    anchor: u32,
+    interval: u32,
+) -> Result<TimeComponent<MIN, MAX>, CalendarError> {
+    if interval == 1 {
+        return Ok(TimeComponent::Any);
+    }
+    if (MAX - MIN + 1) % interval != 0 {
+        return Err(CalendarError::UnsupportedRRule(format!(
+            "INTERVAL={} does not divide the period evenly",
+            interval
+        )));
+    }
+
+    Ok(TimeComponent::Range {
+        start: MIN + (anchor - MIN) % interval,
+        end: MAX,
+        step: Some(interval),
+    })
+}
+
+fn format_rrule_component<const MIN: u32, const MAX: u32>(
+    component: &TimeComponent<MIN, MAX>,
+    negate: bool,
+) -> String {
+    component
+        .iter(None)
+        .map(|v| {
+            if negate {
+                format!("-{}", v)
+            } else {
+                v.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+impl FromStr for RRule {
+    type Err = CalendarError;
+
+    /// Parse an `RRULE`, optionally preceded by a `DTSTART` line. The property name can be
+    /// omitted for a bare rule, such as `FREQ=MONTHLY;BYMONTHDAY=1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut start = None;
+        let mut timezone = None;
+        let mut rule = None;
+
+        for line in s.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let (name, value) = line.split_once(':').unwrap_or(("RRULE", line));
+            let mut params = name.split(';');
+            match params.next().unwrap_or_default().to_uppercase().as_str() {
+                "DTSTART" => {
+                    for param in params {
+                        if let Some(tzid) = param.strip_prefix("TZID=") {
+                            timezone =
+                                Some(tzid.parse::<chrono_tz::Tz>().map_err(|_| {
+                                    CalendarError::InvalidTimezone(tzid.to_string())
+                                })?);
+                        }
+                    }
+
+                    let (datetime, utc) = parse_rrule_datetime(value)?;
+                    if utc {
+                        timezone = None;
+                    }
+                    start = Some(datetime);
+                }
+                "RRULE" if rule.is_none() => rule = Some(value),
+                "RRULE" => {
+                    return Err(CalendarError::UnsupportedRRule(
+                        "more than one RRULE".to_string(),
+                    ));
+                }
+                other => {
+                    return Err(CalendarError::UnsupportedRRule(format!(
+                        "{} property",
+                        other
+                    )));
+                }
+            }
+        }
+        let rule = rule.ok_or_else(|| CalendarError::InvalidRRule("missing RRULE".to_string()))?;
+
+        let mut freq = None;
+        let mut interval = 1;
+        let mut count = None;
+        let mut until = None;
+        let mut by_month = None;
+        let mut by_month_day = None;
+        let mut by_day = None;
+        let mut by_hour = None;
+        let mut by_minute = None;
+        let mut by_second = None;
+
+        for part in rule.split(';').filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| CalendarError::InvalidRRule(format!("invalid part: {}", part)))?;
+            match key.to_uppercase().as_str() {
+                "FREQ" => freq = Some(value.parse::<RRuleFrequency>()?),
+                "INTERVAL" => interval = parse_rrule_number(key, value)?,
+                "COUNT" => count = Some(parse_rrule_number(key, value)?),
+                "UNTIL" => until = Some(parse_rrule_datetime(value)?),
+                "BYMONTH" => by_month = Some(value),
+                "BYMONTHDAY" => by_month_day = Some(value),
+                "BYDAY" => by_day = Some(value),
+                "BYHOUR" => by_hour = Some(value),
+                "BYMINUTE" => by_minute = Some(value),
+                "BYSECOND" => by_second = Some(value),
+                // Only affects weekly rules with an interval, which are not supported
+                "WKST" => {}
+                "BYSETPOS" | "BYYEARDAY" | "BYWEEKNO" => {
+                    return Err(CalendarError::UnsupportedRRule(format!(
+                        "{} is not supported",
+                        key
+                    )));
+                }
+                _ => {
+                    return Err(CalendarError::InvalidRRule(format!(
+                        "unknown part: {}",
+                        key
+                    )));
+                }
+            }
+        }
+
+        let freq = freq.ok_or_else(|| CalendarError::InvalidRRule("missing FREQ".to_string()))?;
+        if count.is_some() && until.is_some() {
+            return Err(CalendarError::InvalidRRule(
+                "COUNT and UNTIL are mutually exclusive".to_string(),
+            ));
+        }
+        if freq == RRuleFrequency::Weekly && by_month_day.is_some() {
+            return Err(CalendarError::InvalidRRule(
+                "BYMONTHDAY is not allowed in weekly rules".to_string(),
+            ));
+        }
+
+        // Intervals are anchored on the start, they can't be combined with a list of values
+        // for the same unit
+        let by_same_unit = match freq {
+            RRuleFrequency::Secondly => by_second.is_some(),
+            RRuleFrequency::Minutely => by_minute.is_some(),
+            RRuleFrequency::Hourly => by_hour.is_some(),
+            RRuleFrequency::Monthly => by_month.is_some(),
+            _ => false,
+        };
+        if interval > 1
+            && (by_same_unit || matches!(freq, RRuleFrequency::Daily | RRuleFrequency::Weekly))
+        {
+            return Err(CalendarError::UnsupportedRRule(format!(
+                "INTERVAL={} with FREQ={}",
+                interval, freq
+            )));
+        }
+
+        // The parts missing from the rule are taken from the start
+        let (year, month, day, hour, minute, second) = match start {
+            Some(start) => (
+                Some(start.year() as u32),
+                start.month(),
+                start.day(),
+                start.hour(),
+                start.minute(),
+                start.second(),
+            ),
+            None => (None, 1, 1, 0, 0, 0),
+        };
+        let weekday = start
+            .map(|start| Weekday::from_chrono(start.weekday()))
+            .unwrap_or(Weekday::Mon);
+
+        let mut weekdays = None;
+        let mut nth_weekdays = None;
+        if let Some(by_day) = by_day {
+            let mut plain = Vec::new();
+            let mut nth = Vec::new();
+            for item in by_day.split(',') {
+                if !item.is_ascii() || item.len() < 2 {
+                    return Err(CalendarError::InvalidWeekday(item.to_string()));
+                }
+                let (occurrence, code) = item.split_at(item.len() - 2);
+                let weekday = Weekday::from_rrule_code(code)?;
+                let occurrence = match occurrence {
+                    "" => {
+                        plain.push(weekday);
+                        continue;
+                    }
+                    n => match n.parse::<i32>() {
+                        Ok(-1) => WeekdayOccurrence::Last,
+                        Ok(n @ 1..=5) => WeekdayOccurrence::Nth(n as u32),
+                        Ok(n) if (-5..=-2).contains(&n) => {
+                            return Err(CalendarError::UnsupportedRRule(format!(
+                                "weekday occurrence {}",
+                                item
+                            )));
+                        }
+                        _ => return Err(CalendarError::InvalidOccurrence(item.to_string())),
+                    },
+                };
+                nth.push(NthWeekday {
+                    weekday,
+                    occurrence,
+                });
+            }
+
+            // The occurrences are counted in the month only for monthly rules and yearly rules
+            // limited to some months
+            let in_month = freq == RRuleFrequency::Monthly
+                || (freq == RRuleFrequency::Yearly && by_month.is_some());
+            match (plain.is_empty(), nth.is_empty()) {
+                (false, true) => weekdays = Some(plain),
+                (true, false) if in_month => nth_weekdays = Some(nth),
+                (true, false) => {
+                    return Err(CalendarError::UnsupportedRRule(format!(
+                        "weekday occurrences with FREQ={}",
+                        freq
+                    )));
+                }
+                _ => {
+                    return Err(CalendarError::UnsupportedRRule(
+                        "BYDAY mixing weekdays and their occurrences".to_string(),
+                    ));
+                }
+            }
+        } else if freq == RRuleFrequency::Weekly {
+            weekdays = Some(vec![weekday]);
+        }
+
+        let (day, day_from_end) = match by_month_day {
+            Some(value) => {
+                let days = value
+                    .split(',')
+                    .map(|v| match v.parse::<i32>() {
+                        Ok(d) if d != 0 && (-31..=31).contains(&d) => Ok(d),
+                        _ => Err(CalendarError::InvalidRRule(format!(
+                            "invalid BYMONTHDAY value: {}",
+                            v
+                        ))),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let day_from_end = days[0] < 0;
+                if days.iter().any(|d| (*d < 0) != day_from_end) {
+                    return Err(CalendarError::UnsupportedRRule(
+                        "BYMONTHDAY mixing days from the start and the end of the month"
+                            .to_string(),
+                    ));
+                }
+
+                let mut days = days
+                    .into_iter()
+                    .map(|d| d.unsigned_abs())
+                    .collect::<Vec<_>>();
+                days.sort();
+                days.dedup();
+                (TimeComponent::Values(days), day_from_end)
+            }
+            None if matches!(freq, RRuleFrequency::Monthly | RRuleFrequency::Yearly)
+                && by_day.is_none() =>
+            {
+                (TimeComponent::Values(vec![day]), false)
+            }
+            None => (TimeComponent::Any, false),
+        };
+
+        let month = match by_month {
+            Some(value) => parse_rrule_component("BYMONTH", value)?,
+            None if freq == RRuleFrequency::Monthly => rrule_interval(month, interval)?,
+            None if freq == RRuleFrequency::Yearly
+                && by_day.is_none()
+                && by_month_day.is_none() =>
+            {
+                TimeComponent::Values(vec![month])
+            }
+            None => TimeComponent::Any,
+        };
+
+        let year = match (freq, year) {
+            (RRuleFrequency::Yearly, _) if interval == 1 => TimeComponent::Any,
+            (RRuleFrequency::Yearly, Some(year)) if (1970..=2099).contains(&year) => {
+                TimeComponent::Range {
+                    start: year,
+                    end: 2099,
+                    step: Some(interval),
+                }
+            }
+            (RRuleFrequency::Yearly, _) => {
+                return Err(CalendarError::UnsupportedRRule(
+                    "yearly INTERVAL without a DTSTART".to_string(),
+                ));
+            }
+            _ => TimeComponent::Any,
+        };
+
+        let hour = match by_hour {
+            Some(value) => parse_rrule_component("BYHOUR", value)?,
+            None if freq == RRuleFrequency::Hourly => rrule_interval(hour, interval)?,
+            None if freq < RRuleFrequency::Hourly => TimeComponent::Any,
+            None => TimeComponent::Values(vec![hour]),
+        };
+        let minute = match by_minute {
+            Some(value) => parse_rrule_component("BYMINUTE", value)?,
+            None if freq == RRuleFrequency::Minutely => rrule_interval(minute, interval)?,
+            None if freq < RRuleFrequency::Minutely => TimeComponent::Any,
+            None => TimeComponent::Values(vec![minute]),
+        };
+        let second = match by_second {
+            Some(value) => parse_rrule_component("BYSECOND", value)?,
+            None if freq == RRuleFrequency::Secondly => rrule_interval(second, interval)?,
+            None => TimeComponent::Values(vec![second]),
+        };
+
+        let calendar = Calendar {
+            weekdays,
+            nth_weekdays,
+            year,
+            month,
+            day,
+            day_from_end,
+            hour,
+            minute,
+            second,
+            timezone,
+        };
+
+        // A floating `UNTIL` is in the timezone of the start
+        let until = match until {
+            Some((until, true)) => Some(rrule_timestamp(until, None)?),
+            Some((until, false)) => Some(rrule_timestamp(until, timezone)?),
+            None => None,
+        };
+        let start = match start {
+            Some(start) => Some(rrule_timestamp(start, timezone)?),
+            None => None,
+        };
+
+        Ok(RRule {
+            calendar,
+            start,
+            until,
+            count,
+        })
+    }
+}
+
+impl RRule {
+    /// Format the rule as a `DTSTART` line, when there is a start, followed by the `RRULE`
+    pub fn to_rrule_string(&self) -> Result<String, CalendarError> {
+        let calendar = &self.calendar;
+        if !matches!(calendar.year, TimeComponent::Any) {
+            return Err(CalendarError::UnsupportedRRule(
+                "calendars limited to some years".to_string(),
+            ));
+        }
+        if calendar.weekdays.is_some() && calendar.nth_weekdays.is_some() {
+            return Err(CalendarError::UnsupportedRRule(
+                "calendars with both weekdays and their occurrences".to_string(),
+            ));
+        }
+
+        // The finest unit that matches every value sets the frequency, the other parts limit or
+        // expand it
+        let day_restricted = !matches!(calendar.day, TimeComponent::Any)
+            || calendar.weekdays.is_some()
+            || calendar.nth_weekdays.is_some();
+        let freq = if matches!(calendar.second, TimeComponent::Any) {
+            RRuleFrequency::Secondly
+        } else if matches!(calendar.minute, TimeComponent::Any) {
+            RRuleFrequency::Minutely
+        } else if matches!(calendar.hour, TimeComponent::Any) {
+            RRuleFrequency::Hourly
+        } else if !day_restricted {
+            RRuleFrequency::Daily
+        } else if matches!(calendar.day, TimeComponent::Any) && calendar.nth_weekdays.is_none() {
+            RRuleFrequency::Weekly
+        } else if matches!(calendar.month, TimeComponent::Any) {
+            RRuleFrequency::Monthly
+        } else {
+            RRuleFrequency::Yearly
+        };
+        if calendar.nth_weekdays.is_some() && freq < RRuleFrequency::Daily {
+            return Err(CalendarError::UnsupportedRRule(
+                "weekday occurrences in calendars repeating within a day".to_string(),
+            ));
+        }
+
+        let mut parts = vec![format!("FREQ={}", freq)];
+        if let Some(count) = self.count {
+            parts.push(format!("COUNT={}", count));
+        }
+        if let Some(until) = self.until {
+            let until =
+                DateTime::from_timestamp(until.as_u64() as i64, 0).expect("Invalid timestamp");
+            parts.push(format!("UNTIL={}", until.format("%Y%m%dT%H%M%SZ")));
+        }
+        if !matches!(calendar.month, TimeComponent::Any) {
+            parts.push(format!(
+                "BYMONTH={}",
+                format_rrule_component(&calendar.month, false)
+            ));
+        }
+        if !matches!(calendar.day, TimeComponent::Any) {
+            parts.push(format!(
+                "BYMONTHDAY={}",
+                format_rrule_component(&calendar.day, calendar.day_from_end)
+            ));
+        }
+        if let Some(ref weekdays) = calendar.weekdays {
+            let codes = weekdays
+                .iter()
+                .map(|w| w.rrule_code())
+                .collect::<Vec<_>>()
+                .join(",");
+            parts.push(format!("BYDAY={}", codes));
+        }
+        if let Some(ref nth_weekdays) = calendar.nth_weekdays {
+            let codes = nth_weekdays
+                .iter()
+                .map(|n| match n.occurrence {
+                    WeekdayOccurrence::Nth(i) => format!("{}{}", i, n.weekday.rrule_code()),
+                    WeekdayOccurrence::Last => format!("-1{}", n.weekday.rrule_code()),
+                })
+                .collect::<Vec<_>>()
+                .join(",");
+            parts.push(format!("BYDAY={}", codes));
+        }
+        if !matches!(calendar.hour, TimeComponent::Any) {
+            parts.push(format!(
+                "BYHOUR={}",
+                format_rrule_component(&calendar.hour, false)
+            ));
+        }
+        if !matches!(calendar.minute, TimeComponent::Any) {
+            parts.push(format!(
+                "BYMINUTE={}",
+                format_rrule_component(&calendar.minute, false)
+            ));
+        }
+        if !matches!(calendar.second, TimeComponent::Any) {
+            parts.push(format!(
+                "BYSECOND={}",
+                format_rrule_component(&calendar.second, false)
+            ));
+        }
+        let rule = format!("RRULE:{}", parts.join(";"));
+
+        let start = match self.start {
+            Some(start) => start,
+            None => return Ok(rule),
+        };
+        let start = DateTime::from_timestamp(start.as_u64() as i64, 0).expect("Invalid timestamp");
+        let start = match calendar.timezone {
+            Some(timezone) => format!(
+                "DTSTART;TZID={}:{}",
+                timezone,
+                start.with_timezone(&timezone).format("%Y%m%dT%H%M%S")
+            ),
+            None => format!("DTSTART:{}", start.format("%Y%m%dT%H%M%SZ")),
+        };
+
+        Ok(format!("{}\n{}", start, rule))
+    }
+}
+
+impl Calendar {
+    /// Parse an iCalendar `RRULE`, with an optional `DTSTART` line providing the parts missing
+    /// from the rule and the timezone
+    ///
+    /// A calendar has no end, rules bounded by `COUNT` or `UNTIL` are rejected: parse them
+    /// with [`RRule`] or into a `RecurrenceInfo` instead.
+    pub fn from_rrule(s: &str) -> Result<Self, CalendarError> {
+        let rule = RRule::from_str(s)?;
+        if rule.count.is_some() || rule.until.is_some() {
+            return Err(CalendarError::UnsupportedRRule(
+                "COUNT and UNTIL bound the recurrence".to_string(),
+            ));
+        }
+
+        Ok(rule.calendar)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -964,9 +2014,11 @@ mod tests {
     fn test_format_calendar() {
         let cal = Calendar {
             weekdays: Some(vec![Weekday::Mon, Weekday::Wed, Weekday::Fri]),
+            nth_weekdays: None,
             year: TimeComponent::Any.into(),
             month: TimeComponent::Any.into(),
             day: TimeComponent::Any.into(),
+            day_from_end: false,
             hour: TimeComponent::Values(vec![0]).into(),
             minute: TimeComponent::Values(vec![0]).into(),
             second: TimeComponent::Values(vec![0]).into(),
@@ -1054,6 +2106,434 @@ mod tests {
         assert!(next_occurrence.unwrap() > Timestamp::new(x));
     }
 
+    #[test]
+    fn test_parse_month_rules() {
+        let cal: Calendar = "*-*~01 00:00:00".parse().unwrap();
+        assert!(cal.day_from_end);
+        assert_eq!(cal.day, TimeComponent::Values(vec![1]));
+        assert_eq!(cal.to_string(), "*-*~01 00:00:00");
+
+        // The year can be omitted, as in `*-02~03`
+        let cal: Calendar = "02~03 12:00".parse().unwrap();
+        assert_eq!(cal.month, TimeComponent::Values(vec![2]));
+        assert_eq!(cal.day, TimeComponent::Values(vec![3]));
+
+        let cal: Calendar = "Tue#2,Fri#L *-*-* 10:00:00 Europe/Rome".parse().unwrap();
+        assert_eq!(
+            cal.nth_weekdays,
+            Some(vec![
+                NthWeekday {
+                    weekday: Weekday::Tue,
+                    occurrence: WeekdayOccurrence::Nth(2),
+                },
+                NthWeekday {
+                    weekday: Weekday::Fri,
+                    occurrence: WeekdayOccurrence::Last,
+                },
+            ])
+        );
+        assert_eq!(cal.to_string(), "Tue#2,Fri#L *-*-* 10:00:00 Europe/Rome");
+        assert_eq!(cal.to_string().parse::<Calendar>().unwrap(), cal);
+
+        assert!(matches!(
+            Calendar::from_str("Tue#6 *-*-* 10:00:00"),
+            Err(CalendarError::InvalidOccurrence(_))
+        ));
+        assert!(matches!(
+            Calendar::from_str("*-*~32 10:00:00"),
+            Err(CalendarError::InvalidTimeComponent(_))
+        ));
+        assert!(Calendar::from_str("2024-*-*~01 10:00:00").is_err());
+    }
+
+    #[test]
+    fn test_next_occurrence_last_day_of_month() {
+        let cal: Calendar = "*-*~01 00:00:00".parse().unwrap();
+        // 2024-01-31T12:00:00Z, February is short in a leap year
+        let next = cal.next_occurrence(Timestamp::new(1706702400)).unwrap();
+        assert_eq!(next, Timestamp::new(1709164800));
+        // 2024-03-31
+        assert_eq!(
+            cal.next_occurrence(Timestamp::new(next.as_u64() + 1)),
+            Some(Timestamp::new(1711843200))
+        );
+        // 2023-02-01 to 2023-02-28
+        assert_eq!(
+            cal.next_occurrence(Timestamp::new(1675209600)),
+            Some(Timestamp::new(1677542400))
+        );
+
+        // Third to last day of February 2025
+        let cal: Calendar = "*-02~03 00:00:00".parse().unwrap();
+        assert_eq!(
+            cal.next_occurrence(Timestamp::new(1738368000)),
+            Some(Timestamp::new(1740528000))
+        );
+
+        // The last day in New York, already February in UTC
+        let cal: Calendar = "*-*~01 23:30:00 America/New_York".parse().unwrap();
+        assert_eq!(
+            cal.next_occurrence(Timestamp::new(1738324800)),
+            Some(Timestamp::new(1738384200))
+        );
+    }
+
+    #[test]
+    fn test_next_occurrence_nth_weekday() {
+        // 2025-04-18 to the second Tuesday of May
+        let cal: Calendar = "Tue#2 *-*-* 10:00:00".parse().unwrap();
+        assert_eq!(
+            cal.next_occurrence(Timestamp::new(1744934400)),
+            Some(Timestamp::new(1747130400))
+        );
+
+        // 2025-04-18 is a Friday, but not the last one
+        let cal: Calendar = "Fri#L *-*-* 09:00:00 Europe/Rome".parse().unwrap();
+        assert_eq!(
+            cal.next_occurrence(Timestamp::new(1744934400)),
+            Some(Timestamp::new(1745564400))
+        );
+
+        // January and February 2025 have no fifth Monday
+        let cal: Calendar = "Mon#5 *-*-* 00:00:00".parse().unwrap();
+        assert_eq!(
+            cal.next_occurrence(Timestamp::new(1735689600)),
+            Some(Timestamp::new(1743379200))
+        );
+    }
+
+    #[test]
+    fn test_human_readable_month_rules() {
+        let cal: Calendar = "*-*~01 00:00:00".parse().unwrap();
+        assert_eq!(
+            cal.to_human_readable(true),
+            "On the last day of each month at midnight"
+        );
+
+        let cal: Calendar = "*-*~02 09:00:00".parse().unwrap();
+        assert_eq!(
+            cal.to_human_readable(true),
+            "On the 2nd to last day of each month at 09:00 AM"
+        );
+
+        let cal: Calendar = "Tue#2,Fri#L *-*-* 14:30:00 Europe/London".parse().unwrap();
+        assert_eq!(
+            cal.to_human_readable(true),
+            "On the 2nd Tuesday and last Friday of each month at 02:30 PM (Europe/London)"
+        );
+
+        let cal: Calendar = "*-02~03 00:00:00".parse().unwrap();
+        assert_eq!(
+            cal.to_human_readable(true),
+            "On the 3rd to last day of February at midnight"
+        );
+
+        let cal: Calendar = "Mon#1 *-01,04,07,10-* 09:00:00".parse().unwrap();
+        assert_eq!(
+            cal.to_human_readable(true),
+            "On the 1st Monday of January, April, July and October at 09:00 AM"
+        );
+
+        let cal: Calendar = "*-06,12-15 09:00:00".parse().unwrap();
+        assert_eq!(
+            cal.to_human_readable(true),
+            "On the 15th day of June and December at 09:00 AM"
+        );
+    }
+
+    #[test]
+    fn test_from_rrule() {
+        let cases = [
+            (
+                "FREQ=MONTHLY;BYMONTHDAY=-1;BYHOUR=9;BYMINUTE=0;BYSECOND=0",
+                "*-*~01 09:00:00",
+            ),
+            (
+                "DTSTART;TZID=Europe/Rome:20250115T090000\nRRULE:FREQ=MONTHLY",
+                "*-*-15 09:00:00 Europe/Rome",
+            ),
+            (
+                "DTSTART:20250106T083000Z\nRRULE:FREQ=WEEKLY;BYDAY=MO,WE,FR",
+                "Mon,Wed,Fri *-*-* 08:30:00",
+            ),
+            (
+                "RRULE:FREQ=MONTHLY;BYDAY=2TU,-1FR;BYHOUR=10;BYMINUTE=0;BYSECOND=0",
+                "Tue#2,Fri#L *-*-* 10:00:00",
+            ),
+            (
+                "DTSTART:20250301T000000Z\nRRULE:FREQ=YEARLY",
+                "*-03-01 00:00:00",
+            ),
+            (
+                "DTSTART:20250101T030000Z\nRRULE:FREQ=HOURLY;INTERVAL=6",
+                "*-*-* 03..23/6:00:00",
+            ),
+            (
+                "DTSTART:20250201T000000Z\nRRULE:FREQ=MONTHLY;INTERVAL=3",
+                "*-02..12/3-01 00:00:00",
+            ),
+        ];
+        for (rrule, expected) in cases {
+            assert_eq!(
+                Calendar::from_rrule(rrule).unwrap(),
+                expected.parse::<Calendar>().unwrap(),
+                "{}",
+                rrule
+            );
+        }
+
+        let rule: RRule = "DTSTART;TZID=Europe/Rome:20250115T090000\nRRULE:FREQ=MONTHLY;COUNT=12"
+            .parse()
+            .unwrap();
+        assert_eq!(rule.start, Some(Timestamp::new(1736928000)));
+        assert_eq!(rule.count, Some(12));
+        assert!(matches!(
+            Calendar::from_rrule("FREQ=MONTHLY;COUNT=12"),
+            Err(CalendarError::UnsupportedRRule(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_rrule_errors() {
+        let unsupported = [
+            "FREQ=DAILY;INTERVAL=2",
+            "FREQ=MONTHLY;INTERVAL=5",
+            "FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1",
+            "FREQ=MONTHLY;BYDAY=-2MO",
+            "FREQ=WEEKLY;BYDAY=1MO",
+            "FREQ=MONTHLY;BYMONTHDAY=1,-1",
+            "FREQ=YEARLY;INTERVAL=2",
+        ];
+        for rrule in unsupported {
+            assert!(
+                matches!(
+                    Calendar::from_rrule(rrule),
+                    Err(CalendarError::UnsupportedRRule(_))
+                ),
+                "{}",
+                rrule
+            );
+        }
+
+        let invalid = [
+            "FREQ=FORTNIGHTLY",
+            "BYMONTHDAY=1",
+            "FREQ=MONTHLY;BYHOUR=24",
+            "FREQ=MONTHLY;COUNT=3;UNTIL=20250101T000000Z",
+            "FREQ=WEEKLY;BYMONTHDAY=1",
+        ];
+        for rrule in invalid {
+            assert!(
+                matches!(
+                    Calendar::from_rrule(rrule),
+                    Err(CalendarError::InvalidRRule(_))
+                ),
+                "{}",
+                rrule
+            );
+        }
+    }
+
+    #[test]
+    fn test_to_rrule() {
+        let from = Timestamp::new(1736467200); // 2025-01-10 00:00:00 UTC
+        let cases = [
+            (
+                "*-*~01 09:00:00 Europe/Rome",
+                "DTSTART;TZID=Europe/Rome:20250131T090000\nRRULE:FREQ=MONTHLY;BYMONTHDAY=-1;BYHOUR=9;BYMINUTE=0;BYSECOND=0",
+            ),
+            (
+                "monthly",
+                "DTSTART:20250201T000000Z\nRRULE:FREQ=MONTHLY;BYMONTHDAY=1;BYHOUR=0;BYMINUTE=0;BYSECOND=0",
+            ),
+            (
+                "Mon,Wed,Fri *-*-* 08:30:00",
+                "DTSTART:20250110T083000Z\nRRULE:FREQ=WEEKLY;BYDAY=MO,WE,FR;BYHOUR=8;BYMINUTE=30;BYSECOND=0",
+            ),
+            (
+                "quarterly",
+                "DTSTART:20250401T000000Z\nRRULE:FREQ=YEARLY;BYMONTH=1,4,7,10;BYMONTHDAY=1;BYHOUR=0;BYMINUTE=0;BYSECOND=0",
+            ),
+            (
+                "Tue#2,Fri#L *-*-* 10:00:00",
+                "DTSTART:20250114T100000Z\nRRULE:FREQ=MONTHLY;BYDAY=2TU,-1FR;BYHOUR=10;BYMINUTE=0;BYSECOND=0",
+            ),
+            (
+                "*-*-* *:*:00",
+                "DTSTART:20250110T000000Z\nRRULE:FREQ=MINUTELY;BYSECOND=0",
+            ),
+        ];
+        for (calendar, expected) in cases {
+            let calendar = calendar.parse::<Calendar>().unwrap();
+            assert_eq!(calendar.to_rrule(from).unwrap(), expected);
+            assert_eq!(Calendar::from_rrule(expected).unwrap(), calendar);
+        }
+
+        let unsupported = ["2025-*-* 00:00:00", "Tue#2 *-*-* *:00:00"];
+        for calendar in unsupported {
+            assert!(matches!(
+                calendar.parse::<Calendar>().unwrap().to_rrule(from),
+                Err(CalendarError::UnsupportedRRule(_))
+            ));
+        }
+
+        // The bounds are kept in UTC
+        let rule = RRule {
+            calendar: Calendar::monthly(Some(chrono_tz::Europe::Rome)),
+            start: Some(Timestamp::new(1736928000)),
+            until: Some(Timestamp::new(1767222000)),
+            count: None,
+        };
+        let s = rule.to_rrule_string().unwrap();
+        assert_eq!(
+            s,
+            "DTSTART;TZID=Europe/Rome:20250115T090000\nRRULE:FREQ=MONTHLY;UNTIL=20251231T230000Z;BYMONTHDAY=1;BYHOUR=0;BYMINUTE=0;BYSECOND=0"
+        );
+        assert_eq!(s.parse::<RRule>().unwrap(), rule);
+    }
+
+    #[test]
+    fn test_occurrences() {
+        let cal = Calendar::monthly(None);
+        let from = Timestamp::new(1736467200); // 2025-01-10 00:00:00 UTC
+        let until = Timestamp::new(1767225600); // 2026-01-01 00:00:00 UTC
+        let occurrences = cal.occurrences(from, until).collect::<Vec<_>>();
+        assert_eq!(occurrences.len(), 11);
+        assert_eq!(occurrences[0], cal.next_occurrence(from).unwrap());
+        assert!(occurrences.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(cal.count_between(from, until), 11);
+        assert_eq!(cal.count_between(until, from), 0);
+
+        // The iterator is lazy, unbounded calendars can be iterated
+        let cal: Calendar = "*-*-* *:*:00".parse().unwrap();
+        assert_eq!(
+            cal.occurrences(from, Timestamp::new(u64::MAX))
+                .take(3)
+                .collect::<Vec<_>>(),
+            vec![
+                Timestamp::new(1736467200),
+                Timestamp::new(1736467260),
+                Timestamp::new(1736467320)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_previous_occurrence() {
+        let cal = Calendar::monthly(None);
+        assert_eq!(
+            cal.previous_occurrence(Timestamp::new(1736467200)), // 2025-01-10 00:00:00 UTC
+            Some(Timestamp::new(1735689600))                     // 2025-01-01 00:00:00 UTC
+        );
+        // The bound is excluded
+        assert_eq!(
+            cal.previous_occurrence(Timestamp::new(1735689600)),
+            Some(Timestamp::new(1733011200)) // 2024-12-01 00:00:00 UTC
+        );
+
+        let cal: Calendar = "*-*~01 00:00:00".parse().unwrap();
+        assert_eq!(
+            cal.previous_occurrence(Timestamp::new(1741996800)), // 2025-03-15 00:00:00 UTC
+            Some(Timestamp::new(1740700800))                     // 2025-02-28 00:00:00 UTC
+        );
+
+        // Walking back from every occurrence finds the one before
+        let from = Timestamp::new(1735689600);
+        let until = Timestamp::new(1767225600);
+        for s in [
+            "Tue#2,Fri#L *-*-* 10:00:00 Europe/Rome",
+            "Mon,Wed,Fri *-*-* 08:30:00 America/New_York",
+            "*-*-* 02:30:00 Europe/Rome",
+            "*-02..12/3~02 12:00:00 Asia/Tokyo",
+        ] {
+            let cal: Calendar = s.parse().unwrap();
+            let occurrences = cal.occurrences(from, until).collect::<Vec<_>>();
+            assert!(!occurrences.is_empty(), "{}", s);
+            for w in occurrences.windows(2) {
+                assert_eq!(cal.previous_occurrence(w[1]), Some(w[0]), "{}", s);
+            }
+        }
+    }
+
+    #[test]
+    fn test_occurrences_dst() {
+        // Europe/Rome moves from +01:00 to +02:00 on 2025-03-30 at 02:00
+        let from = Timestamp::new(1743206400); // 2025-03-29 00:00:00 UTC
+        let until = Timestamp::new(1743465600); // 2025-04-01 00:00:00 UTC
+
+        let cal: Calendar = "*-*-* 09:00:00 Europe/Rome".parse().unwrap();
+        assert_eq!(
+            cal.occurrences(from, until).collect::<Vec<_>>(),
+            vec![
+                Timestamp::new(1743235200), // 2025-03-29 08:00:00 UTC
+                Timestamp::new(1743318000), // 2025-03-30 07:00:00 UTC
+                Timestamp::new(1743404400), // 2025-03-31 07:00:00 UTC
+            ]
+        );
+
+        // 02:30 doesn't exist on 2025-03-30, it occurs when the clocks skip to 03:00
+        let cal: Calendar = "*-*-* 02:30:00 Europe/Rome".parse().unwrap();
+        assert_eq!(
+            cal.occurrences(from, until).collect::<Vec<_>>(),
+            vec![
+                Timestamp::new(1743211800), // 2025-03-29 01:30:00 UTC
+                Timestamp::new(1743296400), // 2025-03-30 01:00:00 UTC
+                Timestamp::new(1743381000), // 2025-03-31 00:30:00 UTC
+            ]
+        );
+        assert_eq!(
+            cal.previous_occurrence(Timestamp::new(1743336000)), // 2025-03-30 12:00:00 UTC
+            Some(Timestamp::new(1743296400))
+        );
+        // Starting right at the change, which is already 03:00 on the wall clock
+        assert_eq!(
+            cal.next_occurrence(Timestamp::new(1743296400)),
+            Some(Timestamp::new(1743296400))
+        );
+        assert_eq!(
+            cal.occurrences(Timestamp::new(1743296400), until).next(),
+            Some(Timestamp::new(1743296400))
+        );
+
+        // America/New_York moves from -04:00 to -05:00 on 2025-11-02 at 02:00, 01:30 occurs
+        // once
+        let from = Timestamp::new(1761955200); // 2025-11-01 00:00:00 UTC
+        let until = Timestamp::new(1762214400); // 2025-11-04 00:00:00 UTC
+        let cal: Calendar = "*-*-* 01:30:00 America/New_York".parse().unwrap();
+        assert_eq!(
+            cal.occurrences(from, until).collect::<Vec<_>>(),
+            vec![
+                Timestamp::new(1761975000), // 2025-11-01 05:30:00 UTC
+                Timestamp::new(1762061400), // 2025-11-02 05:30:00 UTC
+                Timestamp::new(1762151400), // 2025-11-03 06:30:00 UTC
+            ]
+        );
+        // During the repeated hour the next occurrence is the following day
+        assert_eq!(
+            cal.next_occurrence(Timestamp::new(1762063800)), // 2025-11-02 06:10:00 UTC
+            Some(Timestamp::new(1762151400))
+        );
+        assert_eq!(
+            cal.previous_occurrence(Timestamp::new(1762066800)), // 2025-11-02 07:00:00 UTC
+            Some(Timestamp::new(1762061400))
+        );
+        // 01:30 EDT is before 01:10 EST, even if it comes after it on the wall clock
+        assert_eq!(
+            cal.previous_occurrence(Timestamp::new(1762063800)), // 2025-11-02 06:10:00 UTC
+            Some(Timestamp::new(1762061400))
+        );
+
+        // Hourly, the repeated 01:00 only counts once
+        let cal: Calendar = "*-*-* *:00:00 America/New_York".parse().unwrap();
+        assert_eq!(
+            cal.count_between(
+                Timestamp::new(1762056000), // 2025-11-02 04:00:00 UTC
+                Timestamp::new(1762070400)  // 2025-11-02 08:00:00 UTC
+            ),
+            3
+        );
+    }
+
     #[test]
     fn test_serialize() {
         let cal = Calendar::minutely(None);
@@ -1089,9 +2569,11 @@ mod tests {
         // Test timezone parameter
         let cal_with_tz = Calendar {
             weekdays: None,
+            nth_weekdays: None,
             year: TimeComponent::Any,
             month: TimeComponent::Any,
             day: TimeComponent::Values(vec![15]),
+            day_from_end: false,
             hour: TimeComponent::Values(vec![14]),
             minute: TimeComponent::Values(vec![30]),
             second: TimeComponent::Values(vec![0]),
@@ -1109,9 +2591,11 @@ mod tests {
         // Test ordinal day formats
         let cal_1st = Calendar {
             weekdays: None,
+            nth_weekdays: None,
             year: TimeComponent::Any,
             month: TimeComponent::Any,
             day: TimeComponent::Values(vec![1]),
+            day_from_end: false,
             hour: TimeComponent::Values(vec![9]),
             minute: TimeComponent::Values(vec![0]),
             second: TimeComponent::Values(vec![0]),
@@ -1124,9 +2608,11 @@ mod tests {
 
         let cal_2nd = Calendar {
             weekdays: None,
+            nth_weekdays: None,
             year: TimeComponent::Any,
             month: TimeComponent::Any,
             day: TimeComponent::Values(vec![2]),
+            day_from_end: false,
             hour: TimeComponent::Values(vec![14]),
             minute: TimeComponent::Values(vec![30]),
             second: TimeComponent::Values(vec![0]),
@@ -1139,9 +2625,11 @@ mod tests {
 
         let cal_3rd = Calendar {
             weekdays: None,
+            nth_weekdays: None,
             year: TimeComponent::Any,
             month: TimeComponent::Any,
             day: TimeComponent::Values(vec![3]),
+            day_from_end: false,
             hour: TimeComponent::Values(vec![0]),
             minute: TimeComponent::Values(vec![0]),
             second: TimeComponent::Values(vec![0]),
@@ -1154,9 +2642,11 @@ mod tests {
 
         let cal_4th = Calendar {
             weekdays: None,
+            nth_weekdays: None,
             year: TimeComponent::Any,
             month: TimeComponent::Any,
             day: TimeComponent::Values(vec![4]),
+            day_from_end: false,
             hour: TimeComponent::Values(vec![12]),
             minute: TimeComponent::Values(vec![0]),
             second: TimeComponent::Values(vec![0]),
@@ -1169,9 +2659,11 @@ mod tests {
 
         let cal_11th = Calendar {
             weekdays: None,
+            nth_weekdays: None,
             year: TimeComponent::Any,
             month: TimeComponent::Any,
             day: TimeComponent::Values(vec![11]),
+            day_from_end: false,
             hour: TimeComponent::Values(vec![9]),
             minute: TimeComponent::Values(vec![15]),
             second: TimeComponent::Values(vec![0]),
@@ -1184,9 +2676,11 @@ mod tests {
 
         let cal_21st = Calendar {
             weekdays: None,
+            nth_weekdays: None,
             year: TimeComponent::Any,
             month: TimeComponent::Any,
             day: TimeComponent::Values(vec![21]),
+            day_from_end: false,
             hour: TimeComponent::Values(vec![9]),
             minute: TimeComponent::Values(vec![15]),
             second: TimeComponent::Values(vec![0]),
@@ -1200,9 +2694,11 @@ mod tests {
         // Test weekday formatting
         let cal_weekday = Calendar {
             weekdays: Some(vec![Weekday::Mon, Weekday::Wed, Weekday::Fri]),
+            nth_weekdays: None,
             year: TimeComponent::Any,
             month: TimeComponent::Any,
             day: TimeComponent::Any,
+            day_from_end: false,
             hour: TimeComponent::Values(vec![17]),
             minute: TimeComponent::Values(vec![0]),
             second: TimeComponent::Values(vec![0]),
@@ -1216,9 +2712,11 @@ mod tests {
         // Test with timezone
         let cal_tz = Calendar {
             weekdays: None,
+            nth_weekdays: None,
             year: TimeComponent::Any,
             month: TimeComponent::Any,
             day: TimeComponent::Values(vec![15]),
+            day_from_end: false,
             hour: TimeComponent::Values(vec![14]),
             minute: TimeComponent::Values(vec![30]),
             second: TimeComponent::Values(vec![0]),
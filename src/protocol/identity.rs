@@ -1,4 +1,8 @@
-use crate::protocol::model::Timestamp;
+use crate::protocol::{
+    model::Timestamp,
+    predicate::CLAIMS_FIELD,
+    proof::{ProofEncodingError, ProofSize},
+};
 use hex;
 use nostr;
 use nostr::secp256k1::Secp256k1;
@@ -6,6 +10,15 @@ use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use thiserror;
 
+/// The version of the certificates signed over their metadata without the salt sequence
+///
+/// Version 1 certificates are signed over the metadata with the salt sequence. They can still be
+/// verified as a whole, but not disclosed: a partial certificate would have to carry every salt.
+pub const CERTIFICATE_VERSION: u32 = 2;
+
+/// The version of the certificates signed over the metadata with the salt sequence
+const LEGACY_CERTIFICATE_VERSION: u32 = 1;
+
 #[derive(Debug, thiserror::Error)]
 pub enum VerifyError {
     #[error("Invalid merkle root")]
@@ -31,9 +44,12 @@ pub enum VerifyError {
 
     #[error("Certificate is not yet valid")]
     NotYetValid,
+
+    #[error("Unsupported certificate version: {0}")]
+    UnsupportedVersion(u32),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum VerificationLevel {
     High,
@@ -41,7 +57,30 @@ pub enum VerificationLevel {
     Low,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl VerificationLevel {
+    fn rank(&self) -> u8 {
+        match self {
+            VerificationLevel::Low => 0,
+            VerificationLevel::Medium => 1,
+            VerificationLevel::High => 2,
+        }
+    }
+}
+
+/// Ordered by strength, `Low < Medium < High`
+impl PartialOrd for VerificationLevel {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for VerificationLevel {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.rank().cmp(&other.rank())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum VerificationMethod {
     InPerson,
@@ -58,6 +97,9 @@ pub struct Certificate {
     pub version: u32,
     pub subject: nostr::PublicKey,
     pub data: CertificateData,
+    /// Claims derived by the issuer, see [`predicate`](crate::protocol::predicate)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub claims: BTreeMap<String, serde_json::Value>,
     pub metadata: CertificateMetadata,
     pub signature: String,
 }
@@ -66,15 +108,29 @@ pub struct Certificate {
 pub struct SignedCertificateData {
     pub version: u32,
     pub subject: nostr::PublicKey,
-    pub metadata: CertificateMetadata,
+    pub metadata: SignedMetadata,
 }
 
+/// The data signed by the issuer of a version 1 certificate
+#[derive(Serialize)]
+struct LegacySignedCertificateData<'a> {
+    version: u32,
+    subject: nostr::PublicKey,
+    metadata: &'a CertificateMetadata,
+}
+
+/// A certificate that only reveals some of its fields
+///
+/// It carries the salts of the revealed leaves only, in the merkle proof: with the salt of a
+/// hidden leaf anybody could brute force its value from the hash.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PartialCertificate {
     pub version: u32,
     pub subject: nostr::PublicKey,
-    pub metadata: CertificateMetadata,
+    pub metadata: SignedMetadata,
     pub signature: String,
+    /// Either the JSON tree or the compact form, see [`proof`](crate::protocol::proof)
+    #[serde(deserialize_with = "crate::protocol::proof::either_serde::deserialize")]
     pub merkle_proof: MerkleProofNode,
 }
 
@@ -92,6 +148,17 @@ pub enum CertificateData {
     },
 }
 
+impl CertificateData {
+    /// The `type` of the data, as serialized
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            CertificateData::Person(_) => "personal",
+            CertificateData::Business(_) => "business",
+            CertificateData::Custom { .. } => "custom",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SaltSequence {
     pub salt_size: usize,
@@ -277,12 +344,25 @@ impl Certificate {
         data: CertificateData,
         metadata: CertificateMetadata,
         signature: String,
+    ) -> Result<Self, RevealError> {
+        Self::new_with_claims(version, subject, data, BTreeMap::new(), metadata, signature)
+    }
+
+    /// Creates a new certificate that also commits to `claims`, each one in its own leaf
+    pub fn new_with_claims(
+        version: u32,
+        subject: nostr::PublicKey,
+        data: CertificateData,
+        claims: BTreeMap<String, serde_json::Value>,
+        metadata: CertificateMetadata,
+        signature: String,
     ) -> Result<Self, RevealError> {
         // Create a temporary certificate to compute the merkle root
         let temp = Self {
             version,
             subject,
             data,
+            claims,
             metadata,
             signature,
         };
@@ -303,6 +383,7 @@ impl Certificate {
             version: temp.version,
             subject: temp.subject,
             data: temp.data,
+            claims: temp.claims,
             metadata,
             signature: temp.signature,
         })
@@ -317,20 +398,95 @@ impl Certificate {
         let mut fields = BTreeMap::new();
         flatten_json("", &value, &mut fields)?;
 
+        // The claims are only revealed one by one, there's no leaf for the whole object
+        if fields
+            .keys()
+            .any(|field| field.split('.').next() == Some(CLAIMS_FIELD))
+        {
+            return Err(RevealError::ReservedField(CLAIMS_FIELD));
+        }
+        for (name, value) in &self.claims {
+            if name.is_empty() || name.contains('.') {
+                return Err(RevealError::InvalidField);
+            }
+            fields.insert(
+                format!("{}.{}", CLAIMS_FIELD, name),
+                RevealableField::new(value)?,
+            );
+        }
+
         Ok(PreparedCertificate {
             version: self.version,
             fields,
         })
     }
 
+    /// Identifies the certificate, the hex encoded merkle root
+    ///
+    /// The root commits to the salts, so two certificates with the same data have different ids.
+    pub fn id(&self) -> String {
+        hex::encode(self.metadata.merkle_root.as_bytes())
+    }
+
     pub fn get_signed_data(&self) -> SignedCertificateData {
         SignedCertificateData {
             version: self.version,
             subject: self.subject,
-            metadata: self.metadata.clone(),
+            metadata: self.metadata.signed(),
+        }
+    }
+
+    /// The JSON signed by the issuer, `None` if the version is not supported
+    fn signed_json(&self) -> Option<Result<String, serde_json::Error>> {
+        match self.version {
+            LEGACY_CERTIFICATE_VERSION => {
+                Some(serde_json::to_string(&LegacySignedCertificateData {
+                    version: self.version,
+                    subject: self.subject,
+                    metadata: &self.metadata,
+                }))
+            }
+            CERTIFICATE_VERSION => Some(serde_json::to_string(&self.get_signed_data())),
+            _ => None,
         }
     }
 
+    /// Checks that the fields match the merkle root, and that the issuer signed it
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        let prepared = self.prepare_for_revealing()?;
+        if self.metadata.merkle_root.0.as_slice()
+            != &prepared.compute_merkle_root(&self.metadata.salt_sequence)?
+        {
+            return Err(VerifyError::InvalidMerkleRoot);
+        }
+
+        let signed = self
+            .signed_json()
+            .ok_or(VerifyError::UnsupportedVersion(self.version))??;
+        verify_signature(&signed, &self.metadata.issuer_pubkey, &self.signature)
+    }
+
+    /// Builds a [`PartialCertificate`] that only reveals `fields`
+    ///
+    /// Version 1 certificates can't be disclosed, see [`CERTIFICATE_VERSION`].
+    pub fn reveal(&self, fields: &[String]) -> Result<PartialCertificate, RevealError> {
+        if self.version != CERTIFICATE_VERSION {
+            return Err(RevealError::UnsupportedVersion(self.version));
+        }
+
+        let merkle_proof = self
+            .prepare_for_revealing()?
+            .create_multiproof(&self.metadata.salt_sequence, fields)?;
+
+        Ok(PartialCertificate {
+            version: self.version,
+            subject: self.subject,
+            metadata: self.metadata.signed(),
+            signature: self.signature.clone(),
+            merkle_proof,
+        })
+    }
+
     pub fn sign(&mut self, issuer_key: &nostr::Keys) -> Result<(), SignError> {
         use sha2::{Digest, Sha256};
 
@@ -352,7 +508,9 @@ impl Certificate {
             return Err(SignError::AlreadySigned);
         }
 
-        let certificate = serde_json::to_string(&self.get_signed_data())
+        let certificate = self
+            .signed_json()
+            .ok_or(SignError::UnsupportedVersion(self.version))?
             .map_err(|e| SignError::Serialization(e))?;
 
         let mut hasher = Sha256::new();
@@ -367,6 +525,18 @@ impl Certificate {
 }
 
 impl PartialCertificate {
+    /// The certificate as JSON, with the proof in the compact form
+    pub fn to_compact_value(&self) -> Result<serde_json::Value, ProofEncodingError> {
+        let mut value = serde_json::to_value(self)?;
+        value["merkle_proof"] = serde_json::Value::String(self.merkle_proof.to_compact_base64()?);
+        Ok(value)
+    }
+
+    /// The id of the full certificate, see [`Certificate::id`]
+    pub fn id(&self) -> String {
+        hex::encode(self.metadata.merkle_root.as_bytes())
+    }
+
     pub fn get_signed_data(&self) -> SignedCertificateData {
         SignedCertificateData {
             version: self.version,
@@ -376,10 +546,10 @@ impl PartialCertificate {
     }
 
     pub fn verify(&self) -> Result<serde_json::Value, VerifyError> {
-        use sha2::{Digest, Sha256};
-
-        let certificate = serde_json::to_string(&self.get_signed_data())
-            .map_err(|e| VerifyError::Serialization(e))?;
+        // Version 1 signatures cover the whole salt sequence, which is not disclosed
+        if self.version != CERTIFICATE_VERSION {
+            return Err(VerifyError::UnsupportedVersion(self.version));
+        }
 
         // Check merkle root matches
         if self.metadata.merkle_root.0.as_slice() != &self.merkle_proof.compute_hash() {
@@ -388,23 +558,37 @@ impl PartialCertificate {
 
         // TODO: check timestamp
 
-        let secp = Secp256k1::new();
-
-        let mut hasher = Sha256::new();
-        hasher.update(certificate.as_bytes());
-        let message = nostr::secp256k1::Message::from_digest_slice(&hasher.finalize().to_vec())?;
-        let signature = nostr::secp256k1::schnorr::Signature::from_slice(
-            &hex::decode(&self.signature).map_err(|_| VerifyError::InvalidSignature)?,
-        )
-        .map_err(|e| VerifyError::Secp256k1(e))?;
-        secp.verify_schnorr(&signature, &message, &self.metadata.issuer_pubkey.xonly()?)
-            .map_err(|_| VerifyError::InvalidSignature)?;
+        let signed = serde_json::to_string(&self.get_signed_data())?;
+        verify_signature(&signed, &self.metadata.issuer_pubkey, &self.signature)?;
 
         let json = self.merkle_proof.to_prepared_certificate().to_json()?;
         Ok(json)
     }
 }
 
+/// Checks the issuer signature over the signed JSON of a certificate
+fn verify_signature(
+    certificate: &str,
+    issuer: &nostr::PublicKey,
+    signature: &str,
+) -> Result<(), VerifyError> {
+    use sha2::{Digest, Sha256};
+
+    let secp = Secp256k1::new();
+
+    let mut hasher = Sha256::new();
+    hasher.update(certificate.as_bytes());
+    let message = nostr::secp256k1::Message::from_digest_slice(&hasher.finalize().to_vec())?;
+    let signature = nostr::secp256k1::schnorr::Signature::from_slice(
+        &hex::decode(signature).map_err(|_| VerifyError::InvalidSignature)?,
+    )
+    .map_err(|e| VerifyError::Secp256k1(e))?;
+    secp.verify_schnorr(&signature, &message, &issuer.xonly()?)
+        .map_err(|_| VerifyError::InvalidSignature)?;
+
+    Ok(())
+}
+
 impl RevealableField {
     pub fn new<T: Serialize>(value: &T) -> Result<Self, RevealError> {
         let value = serde_json::to_value(value)?;
@@ -481,6 +665,15 @@ pub enum RevealError {
 
     #[error("Insufficient salts in sequence")]
     InsufficientSalts,
+
+    #[error("Field {0} is reserved")]
+    ReservedField(&'static str),
+
+    #[error("Proof encoding error: {0}")]
+    ProofEncoding(#[from] ProofEncodingError),
+
+    #[error("Certificates of version {0} can't be disclosed")]
+    UnsupportedVersion(u32),
 }
 
 #[derive(Debug, Clone)]
@@ -538,6 +731,36 @@ impl PreparedCertificate {
         Ok(field_hashes.into_iter().next().unwrap())
     }
 
+    /// Like [`Self::create_proof`], but every subtree that reveals nothing is folded into a single
+    /// hash, see [`MerkleProofNode::multiproof`]
+    pub fn create_multiproof(
+        &self,
+        salt_sequence: &SaltSequence,
+        reveal_fields: &[String],
+    ) -> Result<MerkleProofNode, RevealError> {
+        Ok(self
+            .create_proof(salt_sequence, reveal_fields)?
+            .multiproof())
+    }
+
+    /// How large the multiproof revealing `reveal_fields` would be, with salts of `salt_size`
+    /// bytes
+    ///
+    /// The size doesn't depend on the value of the salts, so it can be estimated before the
+    /// certificate is salted.
+    pub fn estimate_proof_size(
+        &self,
+        salt_size: usize,
+        reveal_fields: &[String],
+    ) -> Result<ProofSize, RevealError> {
+        if salt_size == 0 {
+            return Err(RevealError::InvalidSalt);
+        }
+
+        let salts = SaltSequence::new(salt_size, vec![0u8; salt_size * self.fields.len()]);
+        Ok(self.create_multiproof(&salts, reveal_fields)?.size()?)
+    }
+
     /// Constructs a Merkle proof for the specified fields.
     ///
     /// The proof includes the revealed fields with their salts, and the minimum set of hashes
@@ -708,6 +931,49 @@ pub struct CertificateMetadata {
     pub merkle_root: MerkleRoot,
 }
 
+impl CertificateMetadata {
+    /// The metadata signed by the issuer, without the salts
+    pub fn signed(&self) -> SignedMetadata {
+        SignedMetadata {
+            issuer_pubkey: self.issuer_pubkey,
+            issued_at: self.issued_at,
+            expires_at: self.expires_at,
+            verification_level: self.verification_level,
+            verification_method: self.verification_method.clone(),
+            merkle_root: self.merkle_root.clone(),
+        }
+    }
+}
+
+/// The metadata of a certificate as signed by the issuer
+///
+/// The salts are left out: the merkle root already commits to them, and they are only disclosed
+/// one by one with the fields they salt.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedMetadata {
+    pub issuer_pubkey: nostr::PublicKey,
+    pub issued_at: Timestamp,
+    pub expires_at: Timestamp,
+    pub verification_level: VerificationLevel,
+    pub verification_method: VerificationMethod,
+    pub merkle_root: MerkleRoot,
+}
+
+impl SignedMetadata {
+    /// The full metadata of the certificate salted with `salt_sequence`
+    pub fn with_salts(self, salt_sequence: SaltSequence) -> CertificateMetadata {
+        CertificateMetadata {
+            issuer_pubkey: self.issuer_pubkey,
+            issued_at: self.issued_at,
+            expires_at: self.expires_at,
+            verification_level: self.verification_level,
+            verification_method: self.verification_method,
+            salt_sequence,
+            merkle_root: self.merkle_root,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PersonData {
     pub full_name: String,
@@ -778,6 +1044,9 @@ pub enum SignError {
 
     #[error("Secp256k1 error: {0}")]
     Secp256k1(#[from] nostr::secp256k1::Error),
+
+    #[error("Unsupported certificate version: {0}")]
+    UnsupportedVersion(u32),
 }
 
 #[cfg(test)]
@@ -814,7 +1083,7 @@ mod tests {
         let issuer = nostr::PublicKey::from_str(TEST_KEY).unwrap();
 
         Certificate::new(
-            1,
+            CERTIFICATE_VERSION,
             subject,
             CertificateData::Person(PersonData {
                 full_name: "John Doe".to_string(),
@@ -853,7 +1122,7 @@ mod tests {
         let issuer = nostr::PublicKey::from_str(TEST_KEY).unwrap();
 
         Certificate::new(
-            1,
+            CERTIFICATE_VERSION,
             subject,
             CertificateData::Business(BusinessData {
                 legal_name: "Acme Corp".to_string(),
@@ -904,7 +1173,7 @@ mod tests {
         });
 
         Certificate::new(
-            1,
+            CERTIFICATE_VERSION,
             subject,
             CertificateData::Custom { data: custom_data },
             CertificateMetadata {
@@ -927,7 +1196,7 @@ mod tests {
         let prepared = cert.prepare_for_revealing().unwrap();
 
         // Check version
-        assert_eq!(prepared.version, 1);
+        assert_eq!(prepared.version, CERTIFICATE_VERSION);
 
         // Check that essential fields exist
         let essential_fields = [
@@ -1153,6 +1422,7 @@ mod tests {
             version: 1,
             subject,
             data: CertificateData::Custom { data: custom_data },
+            claims: BTreeMap::new(),
             metadata: CertificateMetadata {
                 issuer_pubkey: issuer,
                 issued_at: Timestamp::new(1234567890),
@@ -1270,6 +1540,7 @@ mod tests {
             version: 1,
             subject,
             data: CertificateData::Custom { data: nested_value },
+            claims: BTreeMap::new(),
             metadata: CertificateMetadata {
                 issuer_pubkey: issuer,
                 issued_at: Timestamp::new(1234567890),
@@ -1325,6 +1596,7 @@ mod tests {
             version: 1,
             subject,
             data: CertificateData::Custom { data: custom_data },
+            claims: BTreeMap::new(),
             metadata: CertificateMetadata {
                 issuer_pubkey: issuer,
                 issued_at: Timestamp::new(1234567890),
@@ -1445,6 +1717,7 @@ mod tests {
             version: 1,
             subject,
             data: CertificateData::Custom { data: custom_data },
+            claims: BTreeMap::new(),
             metadata: CertificateMetadata {
                 issuer_pubkey: issuer,
                 issued_at: Timestamp::new(1234567890),
@@ -1594,6 +1867,57 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_verify_full_certificate() {
+        let mut cert = create_test_person_certificate();
+        let issuer_key = nostr::Keys::generate();
+        cert.metadata.issuer_pubkey = issuer_key.public_key();
+        cert.sign(&issuer_key).unwrap();
+        cert.verify().unwrap();
+
+        let partial = cert.reveal(&["nationality".to_string()]).unwrap();
+        assert_eq!(partial.verify().unwrap()["nationality"], "US");
+
+        // Tampering with a field breaks the merkle root
+        if let CertificateData::Person(person) = &mut cert.data {
+            person.nationality = "FR".to_string();
+        }
+        assert!(matches!(cert.verify(), Err(VerifyError::InvalidMerkleRoot)));
+    }
+
+    #[test]
+    fn test_verify_legacy_certificate() {
+        let mut cert = create_test_person_certificate();
+        cert.version = LEGACY_CERTIFICATE_VERSION;
+        let issuer_key = nostr::Keys::generate();
+        cert.metadata.issuer_pubkey = issuer_key.public_key();
+        cert.sign(&issuer_key).unwrap();
+        cert.verify().unwrap();
+
+        // The signature covers the salts, which a partial certificate doesn't carry
+        assert!(matches!(
+            cert.reveal(&["nationality".to_string()]),
+            Err(RevealError::UnsupportedVersion(LEGACY_CERTIFICATE_VERSION))
+        ));
+
+        // The signatures of the two versions are not interchangeable
+        cert.version = CERTIFICATE_VERSION;
+        assert!(matches!(cert.verify(), Err(VerifyError::InvalidSignature)));
+
+        let mut partial = cert.reveal(&["nationality".to_string()]).unwrap();
+        partial.version = LEGACY_CERTIFICATE_VERSION;
+        assert!(matches!(
+            partial.verify(),
+            Err(VerifyError::UnsupportedVersion(LEGACY_CERTIFICATE_VERSION))
+        ));
+
+        cert.version = 3;
+        assert!(matches!(
+            cert.verify(),
+            Err(VerifyError::UnsupportedVersion(3))
+        ));
+    }
+
     #[test]
     fn test_merkle_proof_to_prepared_certificate() {
         let cert = create_test_person_certificate();
@@ -1689,7 +2013,7 @@ mod tests {
         let valid_partial = PartialCertificate {
             version: cert.version,
             subject: cert.subject,
-            metadata: cert.metadata.clone(),
+            metadata: cert.metadata.signed(),
             signature: cert.signature.clone(),
             merkle_proof: proof,
         };
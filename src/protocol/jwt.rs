@@ -37,15 +37,16 @@ pub enum JwtError {
 }
 
 /// Custom claims encoded in the token.
-#[derive(uniffi::Object, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Object))]
 pub struct CustomClaims {
     pub target_key: nostr::key::PublicKey,
     // other fields...
 }
 
-#[uniffi::export]
+#[cfg_attr(feature = "bindings", uniffi::export)]
 impl CustomClaims {
-    #[uniffi::constructor]
+    #[cfg_attr(feature = "bindings", uniffi::constructor)]
     pub fn new(target_key: bindings::PublicKey) -> Self {
         Self {
             target_key: target_key.into(),
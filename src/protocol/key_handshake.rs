@@ -3,7 +3,7 @@ use std::{fmt, str::FromStr};
 use nostr::nips::nip19::{FromBech32, ToBech32};
 use thiserror::Error;
 
-use super::model::bindings::PublicKey;
+use super::model::{Timestamp, bindings::PublicKey};
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[cfg_attr(feature = "bindings", derive(uniffi::Record))]
@@ -12,6 +12,21 @@ pub struct KeyHandshakeUrl {
     pub relays: Vec<String>,
     pub token: String,
     pub subkey: Option<PublicKey>,
+    /// The url should be refused after this time
+    pub expires_at: Option<Timestamp>,
+    /// Hex-encoded schnorr signature by the key the handshake is sent to
+    ///
+    /// See [`KeyHandshakeUrl::sign`].
+    pub signature: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct SignedKeyHandshakeData<'a> {
+    main_key: &'a PublicKey,
+    relays: &'a [String],
+    token: &'a str,
+    subkey: Option<&'a PublicKey>,
+    expires_at: Option<&'a Timestamp>,
 }
 
 impl fmt::Display for KeyHandshakeUrl {
@@ -22,7 +37,7 @@ impl fmt::Display for KeyHandshakeUrl {
             .map(|r| urlencoding::encode(r).into_owned())
             .collect::<Vec<_>>();
 
-        let subkey_part = if let Some(key) = self.subkey.as_ref() {
+        let mut subkey_part = if let Some(key) = self.subkey.as_ref() {
             match key.to_bech32() {
                 Ok(bech32) => format!("&subkey={}", bech32),
                 Err(_) => String::new(),
@@ -30,6 +45,12 @@ impl fmt::Display for KeyHandshakeUrl {
         } else {
             String::new()
         };
+        if let Some(expires_at) = self.expires_at.as_ref() {
+            subkey_part.push_str(&format!("&expires_at={}", expires_at.as_u64()));
+        }
+        if let Some(signature) = self.signature.as_ref() {
+            subkey_part.push_str(&format!("&sig={}", signature));
+        }
 
         match self.main_key.to_bech32() {
             Ok(bech32) => write!(
@@ -63,6 +84,80 @@ impl KeyHandshakeUrl {
 
         keys
     }
+
+    pub fn is_signed(&self) -> bool {
+        self.signature.is_some()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at.as_u64() < Timestamp::now().as_u64(),
+            None => false,
+        }
+    }
+
+    fn signed_message(&self) -> Result<nostr::secp256k1::Message, serde_json::Error> {
+        use sha2::{Digest, Sha256};
+
+        let data = serde_json::to_string(&SignedKeyHandshakeData {
+            main_key: &self.main_key,
+            relays: &self.relays,
+            token: &self.token,
+            subkey: self.subkey.as_ref(),
+            expires_at: self.expires_at.as_ref(),
+        })?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(data.as_bytes());
+        Ok(nostr::secp256k1::Message::from_digest(
+            hasher.finalize().into(),
+        ))
+    }
+
+    /// Sign the main key, relays, token, subkey and expiration
+    ///
+    /// The url must be signed by the key the handshake is sent to: the subkey if present,
+    /// otherwise the main key.
+    pub fn sign(&mut self, keys: &nostr::Keys) -> Result<(), SignError> {
+        if keys.public_key() != self.send_to() {
+            return Err(SignError::InvalidKey);
+        }
+
+        let message = self.signed_message()?;
+        let signature = keys
+            .key_pair(&nostr::secp256k1::Secp256k1::new())
+            .sign_schnorr(message);
+        self.signature = Some(hex::encode(signature.serialize()));
+
+        Ok(())
+    }
+
+    /// Verify the signature, if present, and the expiration
+    pub fn verify(&self) -> Result<(), ParseError> {
+        if self.is_expired() {
+            return Err(ParseError::Expired);
+        }
+
+        let Some(signature) = self.signature.as_ref() else {
+            return Ok(());
+        };
+
+        let signature = hex::decode(signature)
+            .ok()
+            .and_then(|s| nostr::secp256k1::schnorr::Signature::from_slice(&s).ok())
+            .ok_or(ParseError::InvalidSignature)?;
+        let message = self
+            .signed_message()
+            .map_err(|_| ParseError::InvalidSignature)?;
+        let signer = self
+            .send_to()
+            .xonly()
+            .map_err(|_| ParseError::InvalidSignature)?;
+
+        nostr::secp256k1::Secp256k1::verification_only()
+            .verify_schnorr(&signature, &message, &signer)
+            .map_err(|_| ParseError::InvalidSignature)
+    }
 }
 
 impl FromStr for KeyHandshakeUrl {
@@ -85,6 +180,8 @@ impl FromStr for KeyHandshakeUrl {
         let mut relays = Vec::new();
         let mut token = None;
         let mut subkey = None;
+        let mut expires_at = None;
+        let mut signature = None;
 
         for param in query.split('&') {
             let (key, value) = param
@@ -101,6 +198,12 @@ impl FromStr for KeyHandshakeUrl {
                 }
                 "token" => token = Some(value.to_string()),
                 "subkey" => subkey = Some(nostr::PublicKey::from_bech32(value)?),
+                "expires_at" => {
+                    expires_at = Some(Timestamp::new(value.parse().map_err(|_| {
+                        ParseError::InvalidQueryParam(format!("invalid expires_at: {}", value))
+                    })?))
+                }
+                "sig" => signature = Some(value.to_string()),
                 _ => {
                     return Err(ParseError::InvalidQueryParam(format!(
                         "unknown parameter: {}",
@@ -115,12 +218,17 @@ impl FromStr for KeyHandshakeUrl {
             return Err(ParseError::NoRelays);
         }
 
-        Ok(Self {
+        let url = Self {
             main_key: PublicKey::from(main_key),
             relays,
             token,
             subkey: subkey.map(|k| PublicKey::from(k)),
-        })
+            expires_at,
+            signature,
+        };
+        url.verify()?;
+
+        Ok(url)
     }
 }
 
@@ -146,4 +254,93 @@ pub enum ParseError {
 
     #[error("Invalid bech32: {0}")]
     Bech32(#[from] nostr::nips::nip19::Error),
+
+    #[error("Invalid signature")]
+    InvalidSignature,
+
+    #[error("Url has expired")]
+    Expired,
+}
+
+#[derive(Debug, Error)]
+pub enum SignError {
+    #[error("Key doesn't match the key the handshake is sent to")]
+    InvalidKey,
+
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(keys: &nostr::Keys) -> KeyHandshakeUrl {
+        KeyHandshakeUrl {
+            main_key: keys.public_key().into(),
+            relays: vec!["wss://relay.nostr.net".to_string()],
+            token: "token".to_string(),
+            subkey: None,
+            expires_at: Some(Timestamp::now_plus_seconds(60)),
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn test_signed_roundtrip() {
+        let keys = nostr::Keys::generate();
+        let mut url = url(&keys);
+        url.sign(&keys).unwrap();
+
+        let parsed = KeyHandshakeUrl::from_str(&url.to_string()).unwrap();
+        assert!(parsed.is_signed());
+        assert_eq!(parsed.expires_at, url.expires_at);
+    }
+
+    #[test]
+    fn test_tampered_relays() {
+        let keys = nostr::Keys::generate();
+        let mut url = url(&keys);
+        url.sign(&keys).unwrap();
+        url.relays = vec!["wss://attacker.example.com".to_string()];
+
+        assert!(matches!(
+            KeyHandshakeUrl::from_str(&url.to_string()),
+            Err(ParseError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_wrong_signer() {
+        let keys = nostr::Keys::generate();
+        let mut url = url(&keys);
+        assert!(matches!(
+            url.sign(&nostr::Keys::generate()),
+            Err(SignError::InvalidKey)
+        ));
+    }
+
+    #[test]
+    fn test_expired() {
+        let keys = nostr::Keys::generate();
+        let mut url = url(&keys);
+        url.expires_at = Some(Timestamp::new(Timestamp::now().as_u64() - 10));
+        url.sign(&keys).unwrap();
+
+        assert!(matches!(
+            KeyHandshakeUrl::from_str(&url.to_string()),
+            Err(ParseError::Expired)
+        ));
+    }
+
+    #[test]
+    fn test_unsigned_url() {
+        let keys = nostr::Keys::generate();
+        let mut url = url(&keys);
+        url.expires_at = None;
+
+        let parsed = KeyHandshakeUrl::from_str(&url.to_string()).unwrap();
+        assert!(!parsed.is_signed());
+        assert!(!parsed.is_expired());
+    }
 }
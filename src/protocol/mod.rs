@@ -7,7 +7,12 @@ pub mod identity;
 pub mod jwt;
 pub mod key_handshake;
 pub mod model;
+pub mod predicate;
+pub mod proof;
 pub mod subkey;
+pub mod ticket;
+pub mod trust;
+pub mod vc;
 
 #[cfg_attr(feature = "bindings", derive(uniffi::Object))]
 #[derive(Clone)]
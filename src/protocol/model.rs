@@ -3,10 +3,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use hex;
 use serde::{Deserialize, Serialize};
 
-#[cfg(feature = "bindings")]
 use bindings::PublicKey;
-#[cfg(not(feature = "bindings"))]
-use nostr::PublicKey;
 
 // Event kind ranges:
 // Authentication: 27000-27999
@@ -40,6 +37,9 @@ pub mod event_kinds {
     pub const CERTIFICATE_REVOCATION: u16 = 29003;
     pub const CERTIFICATE_VERIFY_REQUEST: u16 = 29004;
     pub const CERTIFICATE_VERIFY_RESPONSE: u16 = 29005;
+    pub const CERTIFICATE_ISSUE: u16 = 29006;
+    pub const TICKET_SCAN_REQUEST: u16 = 29007;
+    pub const TICKET_PRESENTATION: u16 = 29008;
 
     // Cashu events (29500-29999)
     pub const CASHU_REQUEST: u16 = 29500;
@@ -99,7 +99,13 @@ impl<'de> Deserialize<'de> for Nonce {
     }
 }
 
+/// Seconds since the unix epoch, serialized as a string
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "openapi",
+    derive(utoipa::ToSchema),
+    schema(value_type = String, example = "1700000000")
+)]
 pub struct Timestamp(u64);
 
 impl Timestamp {
@@ -172,10 +178,82 @@ pub mod auth {
         pub preferred_relays: Vec<String>,
     }
 
+    /// The version of the protocol implemented by this library
+    ///
+    /// Version 2 signs certificates without their salt sequence, see
+    /// [`CERTIFICATE_VERSION`](crate::protocol::identity::CERTIFICATE_VERSION), and extends the
+    /// calendars of recurring payments with days counted from the end of the month (`~`) and
+    /// occurrences of weekdays (`#`).
+    pub const PROTOCOL_VERSION: u32 = 2;
+
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct ClientInfo {
         pub name: String,
         pub version: String,
+        /// Missing for clients that predate capability negotiation
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub protocol_version: Option<u32>,
+        /// The requests the client is able to handle. `None` means the client did not advertise
+        /// them, and every request should be attempted.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub capabilities: Option<Vec<Capability>>,
+    }
+
+    impl ClientInfo {
+        /// Whether the client can handle `capability`. Clients that don't advertise their
+        /// capabilities are assumed to support everything.
+        pub fn supports(&self, capability: Capability) -> bool {
+            self.capabilities
+                .as_ref()
+                .is_none_or(|capabilities| capabilities.contains(&capability))
+        }
+    }
+
+    /// A kind of request a client can handle
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    #[cfg_attr(feature = "bindings", derive(uniffi::Enum))]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+    #[serde(rename_all = "snake_case")]
+    pub enum Capability {
+        Auth,
+        SinglePayment,
+        RecurringPayment,
+        Invoice,
+        Cashu,
+        /// Keeps identity certificates and discloses them on request
+        Certificates,
+        /// A capability introduced by a newer version of the protocol
+        #[serde(other)]
+        Unknown,
+    }
+
+    impl Capability {
+        /// Every capability implemented by this library
+        pub fn all() -> Vec<Capability> {
+            vec![
+                Capability::Auth,
+                Capability::SinglePayment,
+                Capability::RecurringPayment,
+                Capability::Invoice,
+                Capability::Cashu,
+                Capability::Certificates,
+            ]
+        }
+    }
+
+    impl std::fmt::Display for Capability {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let name = match self {
+                Capability::Auth => "auth",
+                Capability::SinglePayment => "single_payment",
+                Capability::RecurringPayment => "recurring_payment",
+                Capability::Invoice => "invoice",
+                Capability::Cashu => "cashu",
+                Capability::Certificates => "certificates",
+                Capability::Unknown => "unknown",
+            };
+            write!(f, "{}", name)
+        }
     }
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -206,7 +284,9 @@ pub mod auth {
         pub status: AuthResponseStatus,
     }
 
-    #[derive(Debug, Clone, Serialize, Deserialize, uniffi::Enum)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[cfg_attr(feature = "bindings", derive(uniffi::Enum))]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
     #[serde(rename_all = "snake_case", tag = "status")]
     pub enum AuthResponseStatus {
         Approved {
@@ -223,27 +303,103 @@ pub mod identity {
     use super::*;
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[cfg_attr(feature = "bindings", derive(uniffi::Record))]
     pub struct CertificateRequestContent {
+        pub request_id: String,
+        /// The types of certificate accepted, `personal`, `business` or `custom`. Any type if
+        /// empty
         pub requested_types: Vec<String>,
+        /// The fields to reveal, as in [`PreparedCertificate::fields`](crate::protocol::identity::PreparedCertificate::fields)
         pub requested_fields: Vec<String>,
+        /// The claims to prove, as named by [`Predicate`](crate::protocol::predicate::Predicate)
+        #[serde(default)]
+        pub requested_predicates: Vec<String>,
         pub purpose: String,
         pub require_status_proofs: Option<bool>,
+        pub expires_at: Timestamp,
+    }
+
+    impl CertificateRequestContent {
+        /// The fields that can be revealed to answer the request, including the claims of the
+        /// requested predicates
+        pub fn disclosable_fields(&self) -> Vec<String> {
+            let predicates = self.requested_predicates.iter().filter_map(|name| {
+                name.parse::<crate::protocol::predicate::Predicate>()
+                    .ok()
+                    .map(|predicate| predicate.field())
+            });
+
+            self.requested_fields
+                .iter()
+                .cloned()
+                .chain(predicates)
+                .collect()
+        }
     }
 
+    /// The certificates disclosed by the user, empty if the request was declined
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct CertificateResponseContent {
+        pub request_id: String,
+        /// [`PartialCertificate`](crate::protocol::identity::PartialCertificate)s by certificate id
         pub certificates: std::collections::HashMap<String, serde_json::Value>,
         pub status_proofs: Option<std::collections::HashMap<String, serde_json::Value>>,
     }
+
+    /// A certificate handed by its issuer to the subject
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct CertificateIssueContent {
+        pub certificate: crate::protocol::identity::Certificate,
+        /// The id of the certificate this one renews, if any
+        pub replaces: Option<String>,
+    }
+
+    /// Notifies the subject that the issuer revoked one of its certificates
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct CertificateRevocationContent {
+        pub certificate_id: String,
+        pub reason: Option<String>,
+        pub revoked_at: Timestamp,
+    }
+}
+
+pub mod ticket {
+    use super::*;
+
+    /// Sent by the door to the holder of a ticket, see [`crate::protocol::ticket`]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+    pub struct TicketScanRequestContent {
+        pub request_id: String,
+        pub event_id: String,
+        /// Signed by the holder together with the ticket, so that the proof can't be replayed
+        pub challenge: String,
+        pub expires_at: Timestamp,
+    }
+
+    /// A ticket, and the proof that the sender owns its subject key
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct TicketPresentationContent {
+        pub request_id: String,
+        pub certificate: crate::protocol::identity::Certificate,
+        /// Schnorr signature of the subject or of its subkey, see [`TicketOwnershipProof`](crate::protocol::ticket::TicketOwnershipProof)
+        pub proof: String,
+        /// Set when `proof` was signed by a subkey of the subject
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub subkey_proof: Option<super::auth::SubkeyProof>,
+    }
 }
 
 pub mod payment {
-    use crate::protocol::calendar::CalendarWrapper;
+    use std::str::FromStr;
+
+    use crate::protocol::calendar::{CalendarError, CalendarWrapper, RRule};
 
     use super::*;
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
     #[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
     pub struct SinglePaymentRequestContent {
         pub amount: u64,
         pub currency: Currency,
@@ -291,8 +447,26 @@ pub mod payment {
         Fiat(String),
     }
 
+    #[cfg(feature = "openapi")]
+    impl<'s> utoipa::ToSchema<'s> for Currency {
+        fn schema() -> (
+            &'s str,
+            utoipa::openapi::RefOr<utoipa::openapi::schema::Schema>,
+        ) {
+            (
+                "Currency",
+                utoipa::openapi::ObjectBuilder::new()
+                    .schema_type(utoipa::openapi::SchemaType::String)
+                    .description(Some("`Millisats`, or the code of a fiat currency"))
+                    .example(Some(serde_json::json!("Millisats")))
+                    .into(),
+            )
+        }
+    }
+
     #[derive(Debug, Clone, Serialize, Deserialize)]
     #[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
     pub struct RecurringPaymentRequestContent {
         pub amount: u64,
         pub currency: Currency,
@@ -306,6 +480,7 @@ pub mod payment {
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
     #[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
     #[serde(rename_all = "snake_case")]
     pub struct RecurringPaymentResponseContent {
         pub request_id: String,
@@ -314,6 +489,7 @@ pub mod payment {
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
     #[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
     pub struct ExchangeRate {
         pub rate: f64,
         pub source: String,
@@ -322,15 +498,49 @@ pub mod payment {
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
     #[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
     pub struct RecurrenceInfo {
         pub until: Option<Timestamp>,
+        /// A calendar expression, such as `monthly`
+        #[cfg_attr(feature = "openapi", schema(value_type = String, example = "monthly"))]
         pub calendar: CalendarWrapper,
         pub max_payments: Option<u32>,
         pub first_payment_due: Timestamp,
     }
 
+    impl RecurrenceInfo {
+        /// Build the recurrence from an iCalendar `DTSTART` and `RRULE`
+        ///
+        /// The `DTSTART` is the first payment, `COUNT` and `UNTIL` bound the payments.
+        pub fn from_rrule(s: &str) -> Result<Self, CalendarError> {
+            let rule = RRule::from_str(s)?;
+            let first_payment_due = rule
+                .start
+                .ok_or_else(|| CalendarError::InvalidRRule("missing DTSTART".to_string()))?;
+
+            Ok(RecurrenceInfo {
+                until: rule.until,
+                calendar: CalendarWrapper::new(rule.calendar),
+                max_payments: rule.count,
+                first_payment_due,
+            })
+        }
+
+        /// Convert the recurrence to an iCalendar `DTSTART` and `RRULE`
+        pub fn to_rrule(&self) -> Result<String, CalendarError> {
+            RRule {
+                calendar: self.calendar.get_calendar().clone(),
+                start: Some(self.first_payment_due),
+                until: self.until,
+                count: self.max_payments,
+            }
+            .to_rrule_string()
+        }
+    }
+
     #[derive(Debug, Clone, Serialize, Deserialize)]
     #[cfg_attr(feature = "bindings", derive(uniffi::Enum))]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
     #[serde(rename_all = "snake_case", tag = "status")]
     pub enum RecurringPaymentStatus {
         Confirmed {
@@ -368,6 +578,7 @@ pub mod payment {
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
     #[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
     pub struct InvoiceRequestContent {
         pub request_id: String,
         pub amount: u64,
@@ -421,6 +632,7 @@ pub mod payment {
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
     #[cfg_attr(feature = "bindings", derive(uniffi::Enum))]
+    #[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
     #[serde(rename_all = "snake_case", tag = "status")]
     pub enum CashuResponseStatus {
         Success { token: String },
@@ -443,8 +655,11 @@ pub mod payment {
     }
 }
 
-#[cfg(feature = "bindings")]
+/// Wrappers of the foreign types exposed to the bindings
+///
+/// They are also used without the `bindings` feature, so that the types don't change with it.
 pub mod bindings {
+    #[cfg(feature = "bindings")]
     use nostr::nips::nip19::ToBech32;
     use serde::{Deserialize, Serialize};
     use std::ops::Deref;
@@ -454,6 +669,7 @@ pub mod bindings {
     #[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
     pub struct PublicKey(pub nostr::PublicKey);
 
+    #[cfg(feature = "bindings")]
     uniffi::custom_type!(PublicKey, String, {
         try_lift: |val| Ok(PublicKey(nostr::PublicKey::parse(&val)?)),
         lower: |obj| obj.0.to_bech32().unwrap(),
@@ -478,10 +694,12 @@ pub mod bindings {
         }
     }
 
+    #[cfg(feature = "bindings")]
     uniffi::custom_type!(Nonce, String, {
         try_lift: |val| Ok(Nonce(hex::decode(&val)?.try_into().map_err(|_| anyhow::anyhow!("Invalid nonce length"))?)),
         lower: |obj| hex::encode(obj.0),
     });
+    #[cfg(feature = "bindings")]
     uniffi::custom_type!(Timestamp, u64, {
         try_lift: |val| Ok(Timestamp(val)),
         lower: |obj| obj.0,
@@ -0,0 +1,287 @@
+//! Claims derived from the data of a certificate
+//!
+//! Issuers can add predicates such as `age_over_18` to a certificate as extra salted leaves, so
+//! that a subject can prove them without revealing the data they were derived from. They are
+//! revealed as the `claims.<name>` fields of the certificate.
+
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{
+    identity::{Address, CertificateData},
+    model::Timestamp,
+};
+
+/// The prefix of the fields that hold the claims of a certificate
+pub const CLAIMS_FIELD: &str = "claims";
+
+#[derive(Debug, thiserror::Error)]
+pub enum PredicateError {
+    #[error("Unknown predicate: {0}")]
+    Unknown(String),
+
+    #[error("Predicate {0} is not supported by this certificate type")]
+    Unsupported(String),
+
+    #[error("Missing field: {0}")]
+    MissingField(&'static str),
+
+    #[error("Invalid date of birth: {0}")]
+    InvalidDate(String),
+
+    #[error("Invalid country: {0}")]
+    InvalidCountry(String),
+}
+
+/// A claim that an issuer can derive from the data of a certificate
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Predicate {
+    /// `age_over_<years>`, the subject was at least this old when the certificate was issued
+    AgeOver(u32),
+    /// `resident_of_<country>`, the address of the subject is in this country
+    ///
+    /// The country is compared case-insensitively with the one of the address.
+    ResidentOf(Country),
+}
+
+/// The country of a [`Predicate::ResidentOf`], ASCII letters stored in lowercase
+///
+/// Only names that can be parsed back from the name of the claim are accepted.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Country(String);
+
+impl Country {
+    pub fn new(country: &str) -> Result<Self, PredicateError> {
+        if country.is_empty() || !country.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(PredicateError::InvalidCountry(country.to_string()));
+        }
+
+        Ok(Self(country.to_ascii_lowercase()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Predicate {
+    /// `resident_of_<country>`, if `country` is made of ASCII letters
+    pub fn resident_of(country: &str) -> Result<Self, PredicateError> {
+        Country::new(country).map(Predicate::ResidentOf)
+    }
+
+    /// The name of the claim in the certificate
+    pub fn name(&self) -> String {
+        self.to_string()
+    }
+
+    /// The field to reveal to prove the predicate
+    pub fn field(&self) -> String {
+        format!("{}.{}", CLAIMS_FIELD, self)
+    }
+
+    /// Compute the value of the claim from `data`, as of `issued_at`
+    pub fn derive(
+        &self,
+        data: &CertificateData,
+        issued_at: Timestamp,
+    ) -> Result<bool, PredicateError> {
+        match (self, data) {
+            (Predicate::AgeOver(years), CertificateData::Person(person)) => {
+                let date_of_birth = NaiveDate::from_str(&person.date_of_birth)
+                    .map_err(|_| PredicateError::InvalidDate(person.date_of_birth.clone()))?;
+                let issued_at = DateTime::from_timestamp(issued_at.as_u64() as i64, 0)
+                    .ok_or_else(|| PredicateError::InvalidDate(issued_at.as_u64().to_string()))?
+                    .date_naive();
+
+                Ok(issued_at
+                    .years_since(date_of_birth)
+                    .is_some_and(|age| age >= *years))
+            }
+            (Predicate::ResidentOf(country), CertificateData::Person(person)) => {
+                let address = person
+                    .address
+                    .as_ref()
+                    .ok_or(PredicateError::MissingField("address"))?;
+                Ok(is_in_country(address, country.as_str()))
+            }
+            (Predicate::ResidentOf(country), CertificateData::Business(business)) => {
+                Ok(is_in_country(&business.address, country.as_str()))
+            }
+            _ => Err(PredicateError::Unsupported(self.name())),
+        }
+    }
+
+    /// Whether the predicate holds for the subject of a verified certificate
+    ///
+    /// `revealed` is the data returned by
+    /// [`PartialCertificate::verify`](crate::protocol::identity::PartialCertificate::verify).
+    /// Returns `None` if the claim was not revealed, or if it can't be decided anymore: a subject
+    /// that was too young when the certificate was issued may have come of age since.
+    pub fn evaluate(&self, revealed: &serde_json::Value) -> Option<bool> {
+        let value = revealed.get(CLAIMS_FIELD)?.get(self.name())?.as_bool()?;
+
+        match self {
+            Predicate::AgeOver(_) => value.then_some(true),
+            Predicate::ResidentOf(_) => Some(value),
+        }
+    }
+}
+
+fn is_in_country(address: &Address, country: &str) -> bool {
+    address.country.eq_ignore_ascii_case(country)
+}
+
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Predicate::AgeOver(years) => write!(f, "age_over_{}", years),
+            Predicate::ResidentOf(country) => write!(f, "resident_of_{}", country.as_str()),
+        }
+    }
+}
+
+impl FromStr for Predicate {
+    type Err = PredicateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(years) = s.strip_prefix("age_over_") {
+            return years
+                .parse()
+                .map(Predicate::AgeOver)
+                .map_err(|_| PredicateError::Unknown(s.to_string()));
+        }
+        s.strip_prefix("resident_of_")
+            .and_then(|country| Predicate::resident_of(country).ok())
+            .ok_or_else(|| PredicateError::Unknown(s.to_string()))
+    }
+}
+
+impl Serialize for Predicate {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for Predicate {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Predicate::from_str(&name).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::identity::PersonData;
+
+    fn person(date_of_birth: &str, country: &str) -> CertificateData {
+        CertificateData::Person(PersonData {
+            full_name: "John Doe".to_string(),
+            date_of_birth: date_of_birth.to_string(),
+            nationality: "US".to_string(),
+            document_type: "passport".to_string(),
+            document_number: "123456789".to_string(),
+            place_of_birth: None,
+            gender: None,
+            issue_date: None,
+            expiry_date: None,
+            address: Some(Address {
+                street: "123 Main St".to_string(),
+                city: "Anytown".to_string(),
+                state: None,
+                postal_code: "12345".to_string(),
+                country: country.to_string(),
+            }),
+        })
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        assert_eq!(
+            Predicate::from_str("age_over_18").unwrap(),
+            Predicate::AgeOver(18)
+        );
+        assert_eq!(
+            Predicate::from_str("resident_of_IT").unwrap(),
+            Predicate::resident_of("it").unwrap()
+        );
+        assert_eq!(
+            Predicate::resident_of("IT").unwrap().field(),
+            "claims.resident_of_it"
+        );
+        assert!(Predicate::from_str("age_over_x").is_err());
+        assert!(Predicate::from_str("height_over_180").is_err());
+
+        // Countries that couldn't be parsed back from the name of the claim are refused
+        for country in ["", "new_zealand", "u.s.", "it 1"] {
+            assert!(matches!(
+                Predicate::resident_of(country),
+                Err(PredicateError::InvalidCountry(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_derive_age() {
+        // 2024-06-15T00:00:00Z
+        let issued_at = Timestamp::new(1718409600);
+        let data = person("2006-06-15", "US");
+
+        assert!(Predicate::AgeOver(18).derive(&data, issued_at).unwrap());
+        assert!(!Predicate::AgeOver(21).derive(&data, issued_at).unwrap());
+
+        // One day short of 18
+        let data = person("2006-06-16", "US");
+        assert!(!Predicate::AgeOver(18).derive(&data, issued_at).unwrap());
+    }
+
+    #[test]
+    fn test_derive_residence() {
+        let data = person("1990-01-01", "IT");
+        let issued_at = Timestamp::now();
+
+        assert!(
+            Predicate::resident_of("it")
+                .unwrap()
+                .derive(&data, issued_at)
+                .unwrap()
+        );
+        assert!(
+            !Predicate::resident_of("fr")
+                .unwrap()
+                .derive(&data, issued_at)
+                .unwrap()
+        );
+
+        let custom = CertificateData::Custom {
+            data: serde_json::json!({ "country": "IT" }),
+        };
+        assert!(matches!(
+            Predicate::resident_of("it")
+                .unwrap()
+                .derive(&custom, issued_at),
+            Err(PredicateError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let revealed = serde_json::json!({
+            "claims": { "age_over_18": true, "age_over_21": false, "resident_of_it": false }
+        });
+
+        assert_eq!(Predicate::AgeOver(18).evaluate(&revealed), Some(true));
+        // The subject may have turned 21 since
+        assert_eq!(Predicate::AgeOver(21).evaluate(&revealed), None);
+        assert_eq!(
+            Predicate::resident_of("it").unwrap().evaluate(&revealed),
+            Some(false)
+        );
+        // Not revealed
+        assert_eq!(Predicate::AgeOver(65).evaluate(&revealed), None);
+    }
+}
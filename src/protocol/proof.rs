@@ -0,0 +1,530 @@
+//! Compact encoding of merkle proofs
+//!
+//! The JSON form of a [`MerkleProofNode`] repeats field names, type tags and hex hashes for every
+//! node of the tree. The compact form is a binary pre-order walk of the same tree:
+//!
+//! ```text
+//! proof   = version:u8 node
+//! node    = 0x00 hash[32]                            blinded subtree
+//!         | 0x01 hash[32]                            hidden leaf
+//!         | 0x02 name:bytes value:bytes salt:bytes   revealed leaf, value as JSON
+//!         | 0x03 node node                           parent
+//!         | 0x04 node                                parent whose right child repeats the left one
+//! bytes   = len:varint data[len]
+//! ```
+//!
+//! Duplicated parents let a few bytes expand into a large tree, so the decoder stops once the
+//! decoded proof would exceed [`MAX_DECODED_SIZE`].
+//!
+//! Together with [`MerkleProofNode::multiproof`], which folds every subtree without revealed
+//! fields into a single hash, the siblings shared by several revealed fields are sent only once.
+//! Partial certificates carry the compact form as a base64 string in place of the proof tree, and
+//! [`PartialCertificate`](crate::protocol::identity::PartialCertificate) accepts both.
+
+use base64::Engine;
+
+use crate::protocol::identity::{MerkleProofLeaf, MerkleProofNode, RevealableField};
+
+/// Version of the compact encoding
+const COMPACT_PROOF_VERSION: u8 = 1;
+
+const HASH_SIZE: usize = 32;
+
+const TAG_BLINDED: u8 = 0x00;
+const TAG_HIDDEN: u8 = 0x01;
+const TAG_CLEARTEXT: u8 = 0x02;
+const TAG_PARENT: u8 = 0x03;
+const TAG_DUPLICATED_PARENT: u8 = 0x04;
+
+/// Nesting limit when decoding, far above the depth of any certificate
+const MAX_DEPTH: usize = 64;
+
+/// Limit on the memory taken by a decoded proof, duplicated subtrees included
+///
+/// A real proof repeats at most one subtree per level of the tree, so it stays within a few times
+/// the size of the certificate.
+pub const MAX_DECODED_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProofEncodingError {
+    #[error("Unsupported proof version: {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("Unexpected end of proof")]
+    UnexpectedEnd,
+
+    #[error("Unknown node tag: {0}")]
+    UnknownTag(u8),
+
+    #[error("Hashes must be {HASH_SIZE} bytes")]
+    InvalidHash,
+
+    #[error("Proof is nested too deeply")]
+    TooDeep,
+
+    #[error("Proof is larger than {MAX_DECODED_SIZE} bytes once decoded")]
+    TooLarge,
+
+    #[error("Trailing bytes after the proof")]
+    TrailingBytes,
+
+    #[error("Invalid field name: {0}")]
+    InvalidName(#[from] std::string::FromUtf8Error),
+
+    #[error("Invalid field value: {0}")]
+    InvalidValue(#[from] serde_json::Error),
+
+    #[error("Invalid base64: {0}")]
+    Base64(#[from] base64::DecodeError),
+}
+
+/// The size of a proof in each encoding, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProofSize {
+    pub json: usize,
+    pub compact: usize,
+    /// The compact form as carried in JSON, base64 encoded
+    pub compact_base64: usize,
+}
+
+impl MerkleProofNode {
+    /// The same proof, with every subtree that reveals nothing folded into a single hash
+    pub fn multiproof(&self) -> MerkleProofNode {
+        match self {
+            MerkleProofNode::Parent { left, right } => {
+                if !self.reveals_any() {
+                    return MerkleProofNode::Blinded {
+                        hash: self.compute_hash(),
+                    };
+                }
+
+                MerkleProofNode::Parent {
+                    left: Box::new(left.multiproof()),
+                    right: Box::new(right.multiproof()),
+                }
+            }
+            other => other.clone(),
+        }
+    }
+
+    fn reveals_any(&self) -> bool {
+        match self {
+            MerkleProofNode::Leaf(MerkleProofLeaf::Cleartext { .. }) => true,
+            MerkleProofNode::Parent { left, right } => left.reveals_any() || right.reveals_any(),
+            _ => false,
+        }
+    }
+
+    /// Encode the proof in the compact binary form
+    pub fn to_compact(&self) -> Result<Vec<u8>, ProofEncodingError> {
+        let mut out = vec![COMPACT_PROOF_VERSION];
+        encode_node(self, &mut out)?;
+        Ok(out)
+    }
+
+    pub fn from_compact(bytes: &[u8]) -> Result<Self, ProofEncodingError> {
+        let mut reader = Reader {
+            bytes,
+            pos: 0,
+            decoded: 0,
+        };
+
+        let version = reader.byte()?;
+        if version != COMPACT_PROOF_VERSION {
+            return Err(ProofEncodingError::UnsupportedVersion(version));
+        }
+
+        let node = decode_node(&mut reader, 0)?;
+        if reader.pos != bytes.len() {
+            return Err(ProofEncodingError::TrailingBytes);
+        }
+
+        Ok(node)
+    }
+
+    /// The compact form, base64 encoded to be carried in JSON
+    pub fn to_compact_base64(&self) -> Result<String, ProofEncodingError> {
+        Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(self.to_compact()?))
+    }
+
+    pub fn from_compact_base64(encoded: &str) -> Result<Self, ProofEncodingError> {
+        let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(encoded)?;
+        Self::from_compact(&bytes)
+    }
+
+    /// How large this proof is in each encoding
+    pub fn size(&self) -> Result<ProofSize, ProofEncodingError> {
+        let compact = self.to_compact()?.len();
+
+        Ok(ProofSize {
+            json: serde_json::to_vec(self)?.len(),
+            compact,
+            compact_base64: compact.div_ceil(3) * 4,
+        })
+    }
+}
+
+fn encode_node(node: &MerkleProofNode, out: &mut Vec<u8>) -> Result<(), ProofEncodingError> {
+    match node {
+        MerkleProofNode::Blinded { hash } => {
+            out.push(TAG_BLINDED);
+            write_hash(out, hash)?;
+        }
+        MerkleProofNode::Leaf(MerkleProofLeaf::Hidden { hash }) => {
+            out.push(TAG_HIDDEN);
+            write_hash(out, hash)?;
+        }
+        MerkleProofNode::Leaf(MerkleProofLeaf::Cleartext { name, field, salt }) => {
+            out.push(TAG_CLEARTEXT);
+            write_bytes(out, name.as_bytes());
+            write_bytes(out, field.value.to_string().as_bytes());
+            write_bytes(out, salt);
+        }
+        MerkleProofNode::Parent { left, right } => {
+            // Odd levels of the tree repeat their last node
+            if left.compute_hash() == right.compute_hash() {
+                out.push(TAG_DUPLICATED_PARENT);
+                encode_node(left, out)?;
+            } else {
+                out.push(TAG_PARENT);
+                encode_node(left, out)?;
+                encode_node(right, out)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_hash(out: &mut Vec<u8>, hash: &[u8]) -> Result<(), ProofEncodingError> {
+    if hash.len() != HASH_SIZE {
+        return Err(ProofEncodingError::InvalidHash);
+    }
+
+    out.extend_from_slice(hash);
+    Ok(())
+}
+
+fn decode_node(reader: &mut Reader, depth: usize) -> Result<MerkleProofNode, ProofEncodingError> {
+    if depth > MAX_DEPTH {
+        return Err(ProofEncodingError::TooDeep);
+    }
+
+    let start = reader.decoded;
+    reader.spend(std::mem::size_of::<MerkleProofNode>())?;
+    let node = match reader.byte()? {
+        TAG_BLINDED => MerkleProofNode::Blinded {
+            hash: reader.hash()?,
+        },
+        TAG_HIDDEN => MerkleProofNode::Leaf(MerkleProofLeaf::Hidden {
+            hash: reader.hash()?,
+        }),
+        TAG_CLEARTEXT => {
+            let name = String::from_utf8(reader.bytes()?.to_vec())?;
+            let value = reader.bytes()?;
+            let salt = reader.bytes()?.to_vec();
+            reader.spend(name.len() + value.len() + salt.len())?;
+            let value = serde_json::from_slice(value)?;
+
+            MerkleProofNode::Leaf(MerkleProofLeaf::Cleartext {
+                name,
+                field: RevealableField { value },
+                salt,
+            })
+        }
+        TAG_PARENT => MerkleProofNode::Parent {
+            left: Box::new(decode_node(reader, depth + 1)?),
+            right: Box::new(decode_node(reader, depth + 1)?),
+        },
+        TAG_DUPLICATED_PARENT => {
+            let child = decode_node(reader, depth + 1)?;
+            // The copy takes as much memory as the child, check it before making it
+            reader.spend(reader.decoded - start)?;
+            MerkleProofNode::Parent {
+                left: Box::new(child.clone()),
+                right: Box::new(child),
+            }
+        }
+        tag => return Err(ProofEncodingError::UnknownTag(tag)),
+    };
+
+    Ok(node)
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    let mut len = bytes.len();
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            out.push(byte);
+            break;
+        }
+        out.push(byte | 0x80);
+    }
+
+    out.extend_from_slice(bytes);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// Approximate memory taken by the nodes decoded so far
+    decoded: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn spend(&mut self, size: usize) -> Result<(), ProofEncodingError> {
+        self.decoded = self
+            .decoded
+            .checked_add(size)
+            .filter(|decoded| *decoded <= MAX_DECODED_SIZE)
+            .ok_or(ProofEncodingError::TooLarge)?;
+        Ok(())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ProofEncodingError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(ProofEncodingError::UnexpectedEnd)?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn byte(&mut self) -> Result<u8, ProofEncodingError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn hash(&mut self) -> Result<Vec<u8>, ProofEncodingError> {
+        self.take(HASH_SIZE).map(<[u8]>::to_vec)
+    }
+
+    fn varint(&mut self) -> Result<usize, ProofEncodingError> {
+        let mut value = 0usize;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(ProofEncodingError::UnexpectedEnd)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], ProofEncodingError> {
+        let len = self.varint()?;
+        self.take(len)
+    }
+}
+
+/// Accepts a merkle proof either as a JSON tree or as the base64 compact form
+pub(crate) mod either_serde {
+    use serde::{Deserialize, Deserializer};
+
+    use super::MerkleProofNode;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum EitherProof {
+        Compact(String),
+        Tree(MerkleProofNode),
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<MerkleProofNode, D::Error> {
+        match EitherProof::deserialize(d)? {
+            EitherProof::Compact(encoded) => {
+                MerkleProofNode::from_compact_base64(&encoded).map_err(serde::de::Error::custom)
+            }
+            EitherProof::Tree(tree) => Ok(tree),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        issuer::testing,
+        protocol::identity::{Certificate, CertificateData, PartialCertificate},
+    };
+
+    fn certificate() -> Certificate {
+        testing::certificate(CertificateData::Custom {
+            data: serde_json::json!({
+                "name": "Alice",
+                "age": 30,
+                "score": 1.5,
+                "address": { "city": "Rome", "country": "IT" },
+                "tags": ["a", "b", "c"],
+            }),
+        })
+    }
+
+    fn fields(fields: &[&str]) -> Vec<String> {
+        fields.iter().map(|f| f.to_string()).collect()
+    }
+
+    fn proof(certificate: &Certificate, reveal: &[&str]) -> MerkleProofNode {
+        certificate
+            .prepare_for_revealing()
+            .unwrap()
+            .create_proof(&certificate.metadata.salt_sequence, &fields(reveal))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_compact_round_trip() {
+        let certificate = certificate();
+
+        for reveal in [
+            &[][..],
+            &["name"][..],
+            &["name", "address.city", "score", "tags.1"][..],
+        ] {
+            let tree = proof(&certificate, reveal);
+            let decoded = MerkleProofNode::from_compact(&tree.to_compact().unwrap()).unwrap();
+
+            // Same JSON form, same root
+            assert_eq!(
+                serde_json::to_value(&decoded).unwrap(),
+                serde_json::to_value(&tree).unwrap()
+            );
+            assert_eq!(
+                decoded.compute_hash(),
+                certificate.metadata.merkle_root.as_bytes()
+            );
+
+            let multiproof = tree.multiproof();
+            let decoded =
+                MerkleProofNode::from_compact_base64(&multiproof.to_compact_base64().unwrap())
+                    .unwrap();
+            assert_eq!(
+                serde_json::to_value(&decoded).unwrap(),
+                serde_json::to_value(&multiproof).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_multiproof_is_smaller() {
+        let certificate = certificate();
+        let tree = proof(&certificate, &["name", "age"]);
+        let multiproof = tree.multiproof();
+
+        assert_eq!(multiproof.compute_hash(), tree.compute_hash());
+        assert_eq!(
+            multiproof.to_prepared_certificate().to_json().unwrap(),
+            serde_json::json!({ "name": "Alice", "age": 30 })
+        );
+
+        let (tree, multiproof) = (tree.size().unwrap(), multiproof.size().unwrap());
+        assert!(multiproof.json < tree.json);
+        assert!(multiproof.compact < tree.compact);
+        assert!(multiproof.compact < multiproof.json / 2);
+    }
+
+    #[test]
+    fn test_estimate_proof_size() {
+        let certificate = certificate();
+        let reveal = fields(&["name", "address"]);
+
+        let estimate = certificate
+            .prepare_for_revealing()
+            .unwrap()
+            .estimate_proof_size(32, &reveal)
+            .unwrap();
+        let actual = certificate
+            .reveal(&reveal)
+            .unwrap()
+            .merkle_proof
+            .size()
+            .unwrap();
+        assert_eq!(estimate.compact, actual.compact);
+        assert_eq!(estimate.compact_base64, actual.compact_base64);
+    }
+
+    #[test]
+    fn test_verify_either_format() {
+        let certificate = certificate();
+        let partial = certificate.reveal(&fields(&["name"])).unwrap();
+
+        let tree = serde_json::to_value(&partial).unwrap();
+        let compact = partial.to_compact_value().unwrap();
+        assert!(compact["merkle_proof"].is_string());
+
+        for value in [tree, compact] {
+            let partial: PartialCertificate = serde_json::from_value(value).unwrap();
+            assert_eq!(
+                partial.verify().unwrap(),
+                serde_json::json!({ "name": "Alice" })
+            );
+        }
+    }
+
+    #[test]
+    fn test_invalid_compact_proofs() {
+        let certificate = certificate();
+        let bytes = proof(&certificate, &["name"])
+            .multiproof()
+            .to_compact()
+            .unwrap();
+
+        assert!(matches!(
+            MerkleProofNode::from_compact(&bytes[..bytes.len() - 1]),
+            Err(ProofEncodingError::UnexpectedEnd)
+        ));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            MerkleProofNode::from_compact(&trailing),
+            Err(ProofEncodingError::TrailingBytes)
+        ));
+
+        let mut version = bytes.clone();
+        version[0] = 42;
+        assert!(matches!(
+            MerkleProofNode::from_compact(&version),
+            Err(ProofEncodingError::UnsupportedVersion(42))
+        ));
+
+        assert!(matches!(
+            MerkleProofNode::from_compact(&[COMPACT_PROOF_VERSION, 0x42]),
+            Err(ProofEncodingError::UnknownTag(0x42))
+        ));
+
+        // A chain of parents deeper than any real certificate
+        let mut deep = vec![COMPACT_PROOF_VERSION];
+        deep.extend(std::iter::repeat_n(TAG_DUPLICATED_PARENT, MAX_DEPTH + 2));
+        assert!(matches!(
+            MerkleProofNode::from_compact(&deep),
+            Err(ProofEncodingError::TooDeep)
+        ));
+    }
+
+    #[test]
+    fn test_duplicated_subtrees_are_bounded() {
+        // Each level doubles the tree, 2^60 nodes once decoded
+        let mut bomb = vec![COMPACT_PROOF_VERSION];
+        bomb.extend(std::iter::repeat_n(TAG_DUPLICATED_PARENT, 60));
+        bomb.push(TAG_BLINDED);
+        bomb.extend([0u8; HASH_SIZE]);
+
+        let started = std::time::Instant::now();
+        assert!(matches!(
+            MerkleProofNode::from_compact(&bomb),
+            Err(ProofEncodingError::TooLarge)
+        ));
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+
+        // A real certificate decodes within the limit
+        let certificate = certificate();
+        let tree = proof(&certificate, &["name", "tags.2"]);
+        assert!(MerkleProofNode::from_compact(&tree.to_compact().unwrap()).is_ok());
+    }
+}
@@ -0,0 +1,586 @@
+//! Event tickets built on identity certificates
+//!
+//! A ticket is a [`Certificate`] with [`CertificateData::Custom`] data describing the event, the
+//! seat, the validity and a single-use nonce. It is issued to the holder's Portal key by an
+//! [`Issuer`](crate::issuer::Issuer), usually once a payment succeeded, and kept in the vault of
+//! the app like any other certificate.
+//!
+//! At the door, the holder signs a challenge with its key, or a subkey of it,
+//! ([`TicketOwnershipProof`]) and a
+//! [`TicketVerifier`] checks everything offline: the signature of the issuer, the validity, the
+//! ownership proof and a [`RedemptionList`] of the nonces already used.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
+
+use nostr::secp256k1::{Secp256k1, XOnlyPublicKey};
+use rand::{RngCore, thread_rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::protocol::{
+    identity::{Certificate, CertificateData, VerificationLevel, VerificationMethod, VerifyError},
+    model::{Timestamp, auth::SubkeyProof, ticket::TicketPresentationContent},
+};
+
+/// The `kind` of the custom data of a ticket
+const TICKET_KIND: &str = "ticket";
+
+/// Size in bytes of the nonce of a ticket
+const NONCE_SIZE: usize = 16;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TicketError {
+    #[error("Not a ticket")]
+    NotATicket,
+
+    #[error("Invalid ticket data: {0}")]
+    InvalidData(#[from] serde_json::Error),
+
+    #[error("Invalid certificate: {0}")]
+    InvalidCertificate(#[from] VerifyError),
+
+    #[error("Ticket issued by an untrusted issuer")]
+    UntrustedIssuer,
+
+    #[error("Ticket for another event: {0}")]
+    WrongEvent(String),
+
+    #[error("Ticket not valid yet")]
+    NotYetValid,
+
+    #[error("Ticket expired")]
+    Expired,
+
+    #[error("Ticket revoked")]
+    Revoked,
+
+    #[error("Ticket already redeemed at {0:?}")]
+    AlreadyRedeemed(Timestamp),
+
+    #[error("Invalid ownership proof")]
+    InvalidProof,
+
+    #[error("Ticket is not transferable")]
+    NotTransferable,
+
+    #[error("Redemption list error: {0}")]
+    RedemptionList(#[from] RedemptionListError),
+}
+
+/// What a ticket grants access to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TicketData {
+    pub event_id: String,
+    pub seat: Option<String>,
+    pub valid_from: Timestamp,
+    pub valid_until: Timestamp,
+    /// Random, hex encoded. Recorded in the [`RedemptionList`] when the ticket is used.
+    pub nonce: String,
+    /// Whether the issuer accepts to re-issue the ticket to another key
+    pub transferable: bool,
+}
+
+impl TicketData {
+    /// A non-transferable ticket without a seat, with a new nonce
+    pub fn new(event_id: String, valid_from: Timestamp, valid_until: Timestamp) -> Self {
+        let mut nonce = [0u8; NONCE_SIZE];
+        thread_rng().fill_bytes(&mut nonce);
+
+        Self {
+            event_id,
+            seat: None,
+            valid_from,
+            valid_until,
+            nonce: hex::encode(nonce),
+            transferable: false,
+        }
+    }
+
+    pub fn with_seat(mut self, seat: String) -> Self {
+        self.seat = Some(seat);
+        self
+    }
+
+    pub fn with_transferable(mut self, transferable: bool) -> Self {
+        self.transferable = transferable;
+        self
+    }
+
+    /// The data of the certificate that carries the ticket
+    pub fn to_certificate_data(&self) -> Result<CertificateData, TicketError> {
+        let mut data = serde_json::to_value(self)?;
+        data["kind"] = TICKET_KIND.into();
+        Ok(CertificateData::Custom { data })
+    }
+
+    /// The ticket carried by `certificate`, the certificate itself is not verified
+    pub fn from_certificate(certificate: &Certificate) -> Result<Self, TicketError> {
+        match &certificate.data {
+            CertificateData::Custom { data }
+                if data.get("kind").and_then(|k| k.as_str()) == Some(TICKET_KIND) =>
+            {
+                Ok(serde_json::from_value(data.clone())?)
+            }
+            _ => Err(TicketError::NotATicket),
+        }
+    }
+
+    /// The verification level and method of ticket certificates
+    ///
+    /// Tickets certify a purchase, not an identity.
+    pub fn verification() -> (VerificationLevel, VerificationMethod) {
+        (
+            VerificationLevel::Low,
+            VerificationMethod::Custom(TICKET_KIND.to_string()),
+        )
+    }
+}
+
+/// Proves that the holder of a ticket owns its subject key
+///
+/// The subject signs the door's challenge together with the id of the ticket, so a proof can't
+/// be reused for another scan or another ticket. Apps running on a subkey sign with it, and
+/// present the [`SubkeyProof`] that chains it to the subject.
+pub struct TicketOwnershipProof;
+
+impl TicketOwnershipProof {
+    pub fn sign(
+        keys: &nostr::Keys,
+        certificate: &Certificate,
+        challenge: &str,
+    ) -> Result<String, TicketError> {
+        let message = Self::message(&certificate.id(), challenge)?;
+        let signature = keys.key_pair(&Secp256k1::new()).sign_schnorr(message);
+
+        Ok(hex::encode(signature.serialize()))
+    }
+
+    /// Check `proof`, signed by the subject of `certificate` or by the subkey of `subkey_proof`
+    pub fn verify(
+        certificate: &Certificate,
+        challenge: &str,
+        proof: &str,
+        subkey_proof: Option<&SubkeyProof>,
+    ) -> Result<(), TicketError> {
+        let message = Self::message(&certificate.id(), challenge)?;
+        let signature = hex::decode(proof)
+            .ok()
+            .and_then(|bytes| nostr::secp256k1::schnorr::Signature::from_slice(&bytes).ok())
+            .ok_or(TicketError::InvalidProof)?;
+        let signer = Self::signer(certificate, subkey_proof)?;
+
+        Secp256k1::verification_only()
+            .verify_schnorr(&signature, &message, &signer)
+            .map_err(|_| TicketError::InvalidProof)
+    }
+
+    /// The subject key, or its subkey derived from `subkey_proof`
+    fn signer(
+        certificate: &Certificate,
+        subkey_proof: Option<&SubkeyProof>,
+    ) -> Result<XOnlyPublicKey, TicketError> {
+        let subject = certificate
+            .subject
+            .xonly()
+            .map_err(|_| TicketError::InvalidProof)?;
+        let Some(subkey_proof) = subkey_proof else {
+            return Ok(subject);
+        };
+
+        let metadata = &subkey_proof.metadata;
+        let now = Timestamp::now();
+        if *subkey_proof.main_key != certificate.subject
+            || now < metadata.valid_from
+            || now >= metadata.expires_at
+        {
+            return Err(TicketError::InvalidProof);
+        }
+
+        let tweak = metadata
+            .get_tweak()
+            .map_err(|_| TicketError::InvalidProof)?;
+        let (subkey, _) = subject
+            .add_tweak(&Secp256k1::verification_only(), &tweak)
+            .map_err(|_| TicketError::InvalidProof)?;
+        Ok(subkey)
+    }
+
+    fn message(ticket_id: &str, challenge: &str) -> Result<nostr::secp256k1::Message, TicketError> {
+        let mut hasher = Sha256::new();
+        hasher.update(format!("portal-ticket:{}:{}", ticket_id, challenge).as_bytes());
+        nostr::secp256k1::Message::from_digest_slice(&hasher.finalize())
+            .map_err(|_| TicketError::InvalidProof)
+    }
+}
+
+/// The nonces of the tickets already used at the door
+pub trait RedemptionList: Send + Sync {
+    /// Record `nonce` as used at `at`, returns when it was first used if it already was
+    fn redeem(&self, nonce: &str, at: Timestamp) -> Result<Option<Timestamp>, RedemptionListError>;
+
+    fn redeemed_at(&self, nonce: &str) -> Result<Option<Timestamp>, RedemptionListError>;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RedemptionListError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Storage error: {0}")]
+    Storage(String),
+}
+
+/// Keeps the redemptions in memory, everything is lost when the door stops
+#[derive(Debug, Default)]
+pub struct MemoryRedemptionList {
+    redeemed: RwLock<HashMap<String, Timestamp>>,
+}
+
+impl MemoryRedemptionList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl RedemptionList for MemoryRedemptionList {
+    fn redeem(&self, nonce: &str, at: Timestamp) -> Result<Option<Timestamp>, RedemptionListError> {
+        let mut redeemed = self.redeemed.write().unwrap();
+        if let Some(first) = redeemed.get(nonce) {
+            return Ok(Some(*first));
+        }
+
+        redeemed.insert(nonce.to_string(), at);
+        Ok(None)
+    }
+
+    fn redeemed_at(&self, nonce: &str) -> Result<Option<Timestamp>, RedemptionListError> {
+        Ok(self.redeemed.read().unwrap().get(nonce).copied())
+    }
+}
+
+/// Checks the tickets of an event at the door, without contacting the issuer
+pub struct TicketVerifier<R = MemoryRedemptionList> {
+    event_id: String,
+    issuers: HashSet<nostr::PublicKey>,
+    revoked: HashSet<String>,
+    redemptions: R,
+}
+
+impl<R: RedemptionList> TicketVerifier<R> {
+    /// A verifier for `event_id` that accepts no issuer yet
+    pub fn new(event_id: String, redemptions: R) -> Self {
+        Self {
+            event_id,
+            issuers: HashSet::new(),
+            revoked: HashSet::new(),
+            redemptions,
+        }
+    }
+
+    /// Accept the tickets signed by `issuer`
+    pub fn with_issuer(mut self, issuer: nostr::PublicKey) -> Self {
+        self.issuers.insert(issuer);
+        self
+    }
+
+    /// Refuse the ticket with this id, for instance because it was transferred
+    pub fn mark_revoked(&mut self, ticket_id: String) {
+        self.revoked.insert(ticket_id);
+    }
+
+    pub fn redemptions(&self) -> &R {
+        &self.redemptions
+    }
+
+    /// Check a ticket without redeeming it
+    pub fn check(&self, certificate: &Certificate) -> Result<TicketData, TicketError> {
+        let ticket = TicketData::from_certificate(certificate)?;
+        certificate.verify()?;
+
+        if !self.issuers.contains(&certificate.metadata.issuer_pubkey) {
+            return Err(TicketError::UntrustedIssuer);
+        }
+        if ticket.event_id != self.event_id {
+            return Err(TicketError::WrongEvent(ticket.event_id));
+        }
+        if self.revoked.contains(&certificate.id()) {
+            return Err(TicketError::Revoked);
+        }
+
+        let now = Timestamp::now();
+        if now < ticket.valid_from {
+            return Err(TicketError::NotYetValid);
+        }
+        if now >= ticket.valid_until || now >= certificate.metadata.expires_at {
+            return Err(TicketError::Expired);
+        }
+
+        if let Some(at) = self.redemptions.redeemed_at(&ticket.nonce)? {
+            return Err(TicketError::AlreadyRedeemed(at));
+        }
+
+        Ok(ticket)
+    }
+
+    /// Check a presented ticket and the ownership proof for `challenge`, then redeem it
+    pub fn redeem(
+        &self,
+        presentation: &TicketPresentationContent,
+        challenge: &str,
+    ) -> Result<TicketData, TicketError> {
+        let certificate = &presentation.certificate;
+        let ticket = self.check(certificate)?;
+        TicketOwnershipProof::verify(
+            certificate,
+            challenge,
+            &presentation.proof,
+            presentation.subkey_proof.as_ref(),
+        )?;
+
+        // Another scan may have redeemed it since the check
+        if let Some(at) = self.redemptions.redeem(&ticket.nonce, Timestamp::now())? {
+            return Err(TicketError::AlreadyRedeemed(at));
+        }
+
+        Ok(ticket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nostr::Keys;
+
+    use crate::{
+        issuer::{Issuer, testing},
+        protocol::{
+            model::Nonce,
+            subkey::{PrivateSubkeyManager, SubkeyMetadata},
+        },
+    };
+
+    const EVENT_ID: &str = "rustconf-2025";
+
+    fn issue(issuer: &Issuer, holder: &Keys, ticket: TicketData) -> Certificate {
+        issuer.issue_ticket(holder.public_key(), ticket).unwrap()
+    }
+
+    fn ticket() -> TicketData {
+        TicketData::new(
+            EVENT_ID.to_string(),
+            Timestamp::new(Timestamp::now().as_u64() - 60),
+            Timestamp::now_plus_seconds(60 * 60),
+        )
+        .with_seat("A12".to_string())
+    }
+
+    fn present(
+        holder: &Keys,
+        certificate: &Certificate,
+        challenge: &str,
+    ) -> TicketPresentationContent {
+        TicketPresentationContent {
+            request_id: "scan".to_string(),
+            certificate: certificate.clone(),
+            proof: TicketOwnershipProof::sign(holder, certificate, challenge).unwrap(),
+            subkey_proof: None,
+        }
+    }
+
+    #[test]
+    fn test_ticket_data_round_trip() {
+        let issuer = testing::issuer();
+        let ticket = ticket();
+        let certificate = issue(&issuer, &Keys::generate(), ticket.clone());
+
+        assert_eq!(TicketData::from_certificate(&certificate).unwrap(), ticket);
+        assert_eq!(certificate.metadata.expires_at, ticket.valid_until);
+        assert_eq!(ticket.nonce.len(), NONCE_SIZE * 2);
+
+        let custom = CertificateData::Custom {
+            data: serde_json::json!({ "event_id": EVENT_ID }),
+        };
+        assert!(matches!(
+            TicketData::from_certificate(&Certificate {
+                data: custom,
+                ..certificate
+            }),
+            Err(TicketError::NotATicket)
+        ));
+    }
+
+    #[test]
+    fn test_redeem_once() {
+        let issuer = testing::issuer();
+        let holder = Keys::generate();
+        let certificate = issue(&issuer, &holder, ticket());
+        let verifier = TicketVerifier::new(EVENT_ID.to_string(), MemoryRedemptionList::new())
+            .with_issuer(issuer.public_key());
+
+        let ticket = verifier
+            .redeem(&present(&holder, &certificate, "first"), "first")
+            .unwrap();
+        assert_eq!(ticket.seat.as_deref(), Some("A12"));
+
+        assert!(matches!(
+            verifier.redeem(&present(&holder, &certificate, "second"), "second"),
+            Err(TicketError::AlreadyRedeemed(_))
+        ));
+    }
+
+    #[test]
+    fn test_ownership_proof() {
+        let issuer = testing::issuer();
+        let holder = Keys::generate();
+        let certificate = issue(&issuer, &holder, ticket());
+        let verifier = TicketVerifier::new(EVENT_ID.to_string(), MemoryRedemptionList::new())
+            .with_issuer(issuer.public_key());
+
+        // Someone else shows the ticket
+        assert!(matches!(
+            verifier.redeem(&present(&Keys::generate(), &certificate, "c"), "c"),
+            Err(TicketError::InvalidProof)
+        ));
+        // A proof for an older scan
+        assert!(matches!(
+            verifier.redeem(&present(&holder, &certificate, "old"), "new"),
+            Err(TicketError::InvalidProof)
+        ));
+        // Nothing was redeemed
+        assert!(verifier.check(&certificate).is_ok());
+    }
+
+    #[test]
+    fn test_ownership_proof_with_subkey() {
+        let issuer = testing::issuer();
+        let holder = Keys::generate();
+        let certificate = issue(&issuer, &holder, ticket());
+        let verifier = TicketVerifier::new(EVENT_ID.to_string(), MemoryRedemptionList::new())
+            .with_issuer(issuer.public_key());
+
+        let metadata = SubkeyMetadata {
+            name: "phone".to_string(),
+            nonce: Nonce::new([0u8; 32]),
+            valid_from: Timestamp::new(0),
+            expires_at: Timestamp::now_plus_seconds(60 * 60),
+            permissions: vec![],
+            version: 1,
+        };
+        let (subkey, subkey_proof) = holder.create_subkey(&metadata).unwrap().split();
+        let present_with =
+            |keys: &Keys, subkey_proof: Option<SubkeyProof>| TicketPresentationContent {
+                subkey_proof,
+                ..present(keys, &certificate, "c")
+            };
+
+        // The subkey is not the subject
+        assert!(matches!(
+            verifier.redeem(&present_with(&subkey, None), "c"),
+            Err(TicketError::InvalidProof)
+        ));
+        // A subkey of somebody else
+        let (other, other_proof) = Keys::generate().create_subkey(&metadata).unwrap().split();
+        assert!(matches!(
+            verifier.redeem(&present_with(&other, Some(other_proof)), "c"),
+            Err(TicketError::InvalidProof)
+        ));
+
+        verifier
+            .redeem(&present_with(&subkey, Some(subkey_proof)), "c")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_refused_tickets() {
+        let issuer = testing::issuer();
+        let holder = Keys::generate();
+        let mut verifier = TicketVerifier::new(EVENT_ID.to_string(), MemoryRedemptionList::new())
+            .with_issuer(issuer.public_key());
+
+        let other = testing::issuer();
+        assert!(matches!(
+            verifier.check(&issue(&other, &holder, ticket())),
+            Err(TicketError::UntrustedIssuer)
+        ));
+
+        let mut wrong_event = ticket();
+        wrong_event.event_id = "another-event".to_string();
+        assert!(matches!(
+            verifier.check(&issue(&issuer, &holder, wrong_event)),
+            Err(TicketError::WrongEvent(_))
+        ));
+
+        let mut early = ticket();
+        early.valid_from = Timestamp::now_plus_seconds(60);
+        assert!(matches!(
+            verifier.check(&issue(&issuer, &holder, early)),
+            Err(TicketError::NotYetValid)
+        ));
+
+        let revoked = issue(&issuer, &holder, ticket());
+        verifier.mark_revoked(revoked.id());
+        assert!(matches!(
+            verifier.check(&revoked),
+            Err(TicketError::Revoked)
+        ));
+
+        let mut tampered = issue(&issuer, &holder, ticket());
+        tampered.data = ticket()
+            .with_seat("VIP".to_string())
+            .to_certificate_data()
+            .unwrap();
+        assert!(matches!(
+            verifier.check(&tampered),
+            Err(TicketError::InvalidCertificate(_))
+        ));
+    }
+
+    #[test]
+    fn test_transfer() {
+        let issuer = testing::issuer();
+        let holder = Keys::generate();
+        let buyer = Keys::generate();
+        let mut verifier = TicketVerifier::new(EVENT_ID.to_string(), MemoryRedemptionList::new())
+            .with_issuer(issuer.public_key());
+
+        let personal = issue(&issuer, &holder, ticket());
+        assert!(matches!(
+            issuer.transfer_ticket(&personal.id(), buyer.public_key()),
+            Err(crate::issuer::IssuerError::Ticket(
+                TicketError::NotTransferable
+            ))
+        ));
+
+        let original = issue(&issuer, &holder, ticket().with_transferable(true));
+        let (transferred, revocation) = issuer
+            .transfer_ticket(&original.id(), buyer.public_key())
+            .unwrap();
+        assert_eq!(revocation.certificate_id, original.id());
+        assert_eq!(transferred.subject, buyer.public_key());
+        assert_eq!(
+            TicketData::from_certificate(&transferred).unwrap().nonce,
+            TicketData::from_certificate(&original).unwrap().nonce
+        );
+
+        // The door learns about the transfer from the issuer
+        for record in issuer.records().unwrap() {
+            if matches!(
+                record.status,
+                crate::issuer::CertificateStatus::Revoked { .. }
+            ) {
+                verifier.mark_revoked(record.certificate.id());
+            }
+        }
+        assert!(matches!(
+            verifier.redeem(&present(&holder, &original, "c"), "c"),
+            Err(TicketError::Revoked)
+        ));
+        verifier
+            .redeem(&present(&buyer, &transferred, "c"), "c")
+            .unwrap();
+    }
+}
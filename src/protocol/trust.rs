@@ -0,0 +1,404 @@
+//! Which certificate issuers we trust, and for what
+//!
+//! [`PartialCertificate::verify`] only proves that the revealed data was signed by the issuer in
+//! the certificate. A [`TrustStore`] decides whether that issuer is trusted for this kind of
+//! certificate, and whether the certificate is still good.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{
+    identity::{PartialCertificate, VerificationLevel, VerificationMethod},
+    model::Timestamp,
+    predicate::Predicate,
+};
+
+/// An issuer we trust, and the certificates we accept from it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrustedIssuer {
+    pub pubkey: nostr::PublicKey,
+    /// `personal`, `business` or `custom`. Any type is accepted if empty.
+    pub allowed_types: Vec<String>,
+    pub min_level: VerificationLevel,
+    /// Any method is accepted if empty
+    pub allowed_methods: Vec<VerificationMethod>,
+}
+
+impl TrustedIssuer {
+    /// Trust every certificate of `pubkey` with at least `min_level`
+    pub fn new(pubkey: nostr::PublicKey, min_level: VerificationLevel) -> Self {
+        Self {
+            pubkey,
+            allowed_types: Vec::new(),
+            min_level,
+            allowed_methods: Vec::new(),
+        }
+    }
+
+    /// Only accept certificates of these types
+    pub fn with_allowed_types(mut self, types: Vec<String>) -> Self {
+        self.allowed_types = types;
+        self
+    }
+
+    /// Only accept certificates verified with these methods
+    pub fn with_allowed_methods(mut self, methods: Vec<VerificationMethod>) -> Self {
+        self.allowed_methods = methods;
+        self
+    }
+}
+
+/// The outcome of checking a certificate against a [`TrustStore`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TrustDecision {
+    /// The certificate is valid and trusted, `data` are the revealed fields
+    Trusted {
+        data: serde_json::Value,
+    },
+    /// The proof or the signature don't verify
+    Invalid {
+        reason: String,
+    },
+    UntrustedIssuer {
+        issuer: nostr::PublicKey,
+    },
+    /// The certificate was issued to someone else than the key that presented it
+    SubjectMismatch {
+        subject: nostr::PublicKey,
+    },
+    Revoked,
+    Expired,
+    /// The type is not accepted from this issuer, or it was not revealed
+    TypeNotAllowed {
+        certificate_type: Option<String>,
+    },
+    LevelTooLow {
+        level: VerificationLevel,
+        required: VerificationLevel,
+    },
+    MethodNotAllowed {
+        method: VerificationMethod,
+    },
+}
+
+impl TrustDecision {
+    pub fn is_trusted(&self) -> bool {
+        matches!(self, TrustDecision::Trusted { .. })
+    }
+
+    /// Whether `predicate` is proven by a trusted certificate, see [`Predicate::evaluate`]
+    pub fn holds(&self, predicate: &Predicate) -> Option<bool> {
+        match self {
+            TrustDecision::Trusted { data } => predicate.evaluate(data),
+            _ => None,
+        }
+    }
+}
+
+/// The issuers we trust, and the certificates we know are revoked
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrustStore {
+    issuers: HashMap<nostr::PublicKey, TrustedIssuer>,
+    revoked: HashSet<String>,
+}
+
+impl TrustStore {
+    /// A store that trusts nobody
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust `issuer`, replacing its previous policy
+    pub fn add_issuer(&mut self, issuer: TrustedIssuer) {
+        self.issuers.insert(issuer.pubkey, issuer);
+    }
+
+    pub fn remove_issuer(&mut self, pubkey: &nostr::PublicKey) -> Option<TrustedIssuer> {
+        self.issuers.remove(pubkey)
+    }
+
+    pub fn issuer(&self, pubkey: &nostr::PublicKey) -> Option<&TrustedIssuer> {
+        self.issuers.get(pubkey)
+    }
+
+    pub fn issuers(&self) -> impl Iterator<Item = &TrustedIssuer> {
+        self.issuers.values()
+    }
+
+    /// Refuse the certificate with this id from now on
+    pub fn mark_revoked(&mut self, certificate_id: String) {
+        self.revoked.insert(certificate_id);
+    }
+
+    pub fn is_revoked(&self, certificate_id: &str) -> bool {
+        self.revoked.contains(certificate_id)
+    }
+
+    /// Verify `certificate`, presented by `holder`, and check it against the policy of its issuer
+    ///
+    /// `holder` is the main key of whoever presented the certificate: a certificate issued to
+    /// somebody else is refused, so disclosed certificates can't be replayed. When the issuer
+    /// restricts the certificate types, the `type` field must be revealed.
+    pub fn evaluate(
+        &self,
+        certificate: &PartialCertificate,
+        holder: &nostr::PublicKey,
+    ) -> TrustDecision {
+        let data = match certificate.verify() {
+            Ok(data) => data,
+            Err(e) => {
+                return TrustDecision::Invalid {
+                    reason: e.to_string(),
+                };
+            }
+        };
+
+        if certificate.subject != *holder {
+            return TrustDecision::SubjectMismatch {
+                subject: certificate.subject,
+            };
+        }
+
+        let metadata = &certificate.metadata;
+        let Some(issuer) = self.issuers.get(&metadata.issuer_pubkey) else {
+            return TrustDecision::UntrustedIssuer {
+                issuer: metadata.issuer_pubkey,
+            };
+        };
+
+        if self.is_revoked(&certificate.id()) {
+            return TrustDecision::Revoked;
+        }
+        if metadata.expires_at <= Timestamp::now() {
+            return TrustDecision::Expired;
+        }
+
+        if !issuer.allowed_types.is_empty() {
+            let certificate_type = data.get("type").and_then(|t| t.as_str());
+            if !certificate_type.is_some_and(|t| issuer.allowed_types.iter().any(|a| a == t)) {
+                return TrustDecision::TypeNotAllowed {
+                    certificate_type: certificate_type.map(str::to_string),
+                };
+            }
+        }
+
+        if metadata.verification_level < issuer.min_level {
+            return TrustDecision::LevelTooLow {
+                level: metadata.verification_level,
+                required: issuer.min_level,
+            };
+        }
+        if !issuer.allowed_methods.is_empty()
+            && !issuer
+                .allowed_methods
+                .contains(&metadata.verification_method)
+        {
+            return TrustDecision::MethodNotAllowed {
+                method: metadata.verification_method.clone(),
+            };
+        }
+
+        TrustDecision::Trusted { data }
+    }
+}
+
+/// A [`TrustStore`] shared between the SDK and its users
+pub type SharedTrustStore = Arc<RwLock<TrustStore>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nostr::Keys;
+
+    use crate::{
+        issuer::{IssueRequest, Issuer, MemoryIssuanceLog, testing},
+        protocol::identity::{Certificate, CertificateData},
+    };
+
+    fn certificate(issuer: &Issuer, level: VerificationLevel) -> Certificate {
+        let data = CertificateData::Custom {
+            data: serde_json::json!({ "name": "Alice" }),
+        };
+        issuer
+            .issue(IssueRequest {
+                verification_level: level,
+                ..testing::request(data)
+            })
+            .unwrap()
+    }
+
+    fn reveal(certificate: &Certificate, fields: &[&str]) -> PartialCertificate {
+        let fields = fields.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+        certificate.reveal(&fields).unwrap()
+    }
+
+    fn issue(issuer: &Issuer, level: VerificationLevel, fields: &[&str]) -> PartialCertificate {
+        reveal(&certificate(issuer, level), fields)
+    }
+
+    #[test]
+    fn test_verification_level_order() {
+        assert!(VerificationLevel::Low < VerificationLevel::Medium);
+        assert!(VerificationLevel::Medium < VerificationLevel::High);
+    }
+
+    #[test]
+    fn test_trusted() {
+        let issuer = testing::issuer();
+        let mut store = TrustStore::new();
+        store.add_issuer(TrustedIssuer::new(
+            issuer.public_key(),
+            VerificationLevel::Medium,
+        ));
+
+        let certificate = issue(&issuer, VerificationLevel::High, &["name"]);
+        assert_eq!(
+            store.evaluate(&certificate, &certificate.subject),
+            TrustDecision::Trusted {
+                data: serde_json::json!({ "name": "Alice" })
+            }
+        );
+    }
+
+    #[test]
+    fn test_untrusted_issuer() {
+        let issuer = testing::issuer();
+        let store = TrustStore::new();
+
+        let certificate = issue(&issuer, VerificationLevel::High, &["name"]);
+        assert_eq!(
+            store.evaluate(&certificate, &certificate.subject),
+            TrustDecision::UntrustedIssuer {
+                issuer: issuer.public_key()
+            }
+        );
+    }
+
+    #[test]
+    fn test_level_too_low() {
+        let issuer = testing::issuer();
+        let mut store = TrustStore::new();
+        store.add_issuer(TrustedIssuer::new(
+            issuer.public_key(),
+            VerificationLevel::High,
+        ));
+
+        let certificate = issue(&issuer, VerificationLevel::Low, &["name"]);
+        assert_eq!(
+            store.evaluate(&certificate, &certificate.subject),
+            TrustDecision::LevelTooLow {
+                level: VerificationLevel::Low,
+                required: VerificationLevel::High,
+            }
+        );
+    }
+
+    #[test]
+    fn test_expired_and_revoked() {
+        let keys = Keys::generate();
+        let issuer = Issuer::new(keys.clone(), MemoryIssuanceLog::default());
+        let mut store = TrustStore::new();
+        store.add_issuer(TrustedIssuer::new(
+            issuer.public_key(),
+            VerificationLevel::Low,
+        ));
+
+        // The issuer refuses to issue expired certificates, sign one by hand
+        let mut expired = certificate(&issuer, VerificationLevel::High);
+        expired.metadata.expires_at = Timestamp::new(Timestamp::now().as_u64() - 1);
+        expired.sign(&keys).unwrap();
+        let expired = reveal(&expired, &["name"]);
+        assert_eq!(
+            store.evaluate(&expired, &expired.subject),
+            TrustDecision::Expired
+        );
+
+        let revoked = issue(&issuer, VerificationLevel::High, &["name"]);
+        store.mark_revoked(revoked.id());
+        assert_eq!(
+            store.evaluate(&revoked, &revoked.subject),
+            TrustDecision::Revoked
+        );
+    }
+
+    #[test]
+    fn test_allowed_types_and_methods() {
+        let issuer = testing::issuer();
+        let mut store = TrustStore::new();
+        store.add_issuer(
+            TrustedIssuer::new(issuer.public_key(), VerificationLevel::Low)
+                .with_allowed_types(vec!["personal".to_string()]),
+        );
+
+        let custom = issue(&issuer, VerificationLevel::High, &["name", "type"]);
+        assert_eq!(
+            store.evaluate(&custom, &custom.subject),
+            TrustDecision::TypeNotAllowed {
+                certificate_type: Some("custom".to_string())
+            }
+        );
+
+        // The type can't be checked if it's not revealed
+        let hidden = issue(&issuer, VerificationLevel::High, &["name"]);
+        assert_eq!(
+            store.evaluate(&hidden, &hidden.subject),
+            TrustDecision::TypeNotAllowed {
+                certificate_type: None
+            }
+        );
+
+        store.add_issuer(
+            TrustedIssuer::new(issuer.public_key(), VerificationLevel::Low)
+                .with_allowed_methods(vec![VerificationMethod::InPerson]),
+        );
+        assert_eq!(
+            store.evaluate(&hidden, &hidden.subject),
+            TrustDecision::MethodNotAllowed {
+                method: VerificationMethod::DocumentUpload
+            }
+        );
+    }
+
+    #[test]
+    fn test_tampered_certificate() {
+        let issuer = testing::issuer();
+        let mut store = TrustStore::new();
+        store.add_issuer(TrustedIssuer::new(
+            issuer.public_key(),
+            VerificationLevel::Low,
+        ));
+
+        let mut certificate = issue(&issuer, VerificationLevel::Low, &["name"]);
+        certificate.metadata.verification_level = VerificationLevel::High;
+        assert!(matches!(
+            store.evaluate(&certificate, &certificate.subject),
+            TrustDecision::Invalid { .. }
+        ));
+    }
+
+    #[test]
+    fn test_replayed_certificate() {
+        let issuer = testing::issuer();
+        let mut store = TrustStore::new();
+        store.add_issuer(TrustedIssuer::new(
+            issuer.public_key(),
+            VerificationLevel::Low,
+        ));
+
+        // A service that received the certificate presents it as its own
+        let certificate = issue(&issuer, VerificationLevel::High, &["name"]);
+        let replayer = Keys::generate().public_key();
+        assert_eq!(
+            store.evaluate(&certificate, &replayer),
+            TrustDecision::SubjectMismatch {
+                subject: certificate.subject
+            }
+        );
+    }
+}
@@ -0,0 +1,442 @@
+//! Conversion between Portal certificates and W3C Verifiable Credentials
+//!
+//! Certificates are represented with the [VC data model](https://www.w3.org/TR/vc-data-model-2.0/):
+//! the subject and the issuer are `did:nostr` identifiers, the certified data and the claims go in
+//! the `credentialSubject`, and everything needed to check the credential with
+//! [`protocol::identity`](crate::protocol::identity) — the signature, the merkle root, and the
+//! salts of full certificates or the merkle proof of partial ones — is carried in a
+//! [`PortalProof`].
+//!
+//! The conversion is lossless in both directions, so a credential received from a partner can be
+//! turned back into a [`Certificate`] or a [`PartialCertificate`] and verified as usual.
+
+use chrono::{DateTime, SecondsFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{
+    identity::{
+        Certificate, CertificateData, MerkleProofNode, MerkleRoot, PartialCertificate, RevealError,
+        SaltSequence, SignedMetadata, VerificationLevel, VerificationMethod,
+    },
+    model::Timestamp,
+    predicate::CLAIMS_FIELD,
+    proof::ProofEncodingError,
+};
+
+pub const VC_CONTEXT: &str = "https://www.w3.org/ns/credentials/v2";
+pub const PORTAL_CONTEXT: &str = "https://getportal.cc/credentials/v1";
+
+const VC_TYPE: &str = "VerifiableCredential";
+const CERTIFICATE_TYPE: &str = "PortalCertificate";
+const PARTIAL_CERTIFICATE_TYPE: &str = "PortalPartialCertificate";
+
+/// The `type` of a [`PortalProof`]
+pub const PORTAL_PROOF_TYPE: &str = "PortalMerkleSchnorr";
+
+const DID_PREFIX: &str = "did:nostr:";
+
+#[derive(Debug, thiserror::Error)]
+pub enum VcError {
+    #[error("Not a Portal credential, type {0:?}")]
+    UnsupportedType(Vec<String>),
+
+    #[error("Unsupported proof type: {0}")]
+    UnsupportedProof(String),
+
+    #[error("Invalid identifier: {0}")]
+    InvalidDid(String),
+
+    #[error("Invalid date: {0}")]
+    InvalidDate(String),
+
+    #[error("Missing field: {0}")]
+    MissingField(&'static str),
+
+    #[error("The credential subject doesn't match the revealed fields")]
+    SubjectMismatch,
+
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+
+    #[error("Proof encoding error: {0}")]
+    ProofEncoding(#[from] ProofEncodingError),
+
+    #[error("Reveal error: {0}")]
+    Reveal(#[from] RevealError),
+}
+
+/// A certificate in the W3C Verifiable Credentials data model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifiableCredential {
+    #[serde(rename = "@context")]
+    pub context: Vec<String>,
+    #[serde(rename = "type")]
+    pub types: Vec<String>,
+    pub issuer: String,
+    pub valid_from: String,
+    pub valid_until: String,
+    /// `id`, the `data` of the certificate and its `claims`
+    pub credential_subject: serde_json::Value,
+    pub proof: PortalProof,
+}
+
+/// The proof of a Portal credential
+///
+/// Full certificates carry their salts, partial certificates only the compact merkle proof of the
+/// revealed fields, which holds their salts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PortalProof {
+    #[serde(rename = "type")]
+    pub proof_type: String,
+    pub created: String,
+    pub verification_method: String,
+    pub proof_purpose: String,
+    /// The hex encoded schnorr signature of the issuer
+    pub proof_value: String,
+    pub version: u32,
+    pub merkle_root: MerkleRoot,
+    pub identity_verification: IdentityVerification,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt_sequence: Option<SaltSequence>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merkle_proof: Option<String>,
+}
+
+/// How the issuer verified the identity of the subject
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IdentityVerification {
+    pub level: VerificationLevel,
+    pub method: VerificationMethod,
+}
+
+impl Certificate {
+    pub fn to_vc(&self) -> Result<VerifiableCredential, VcError> {
+        let mut subject = serde_json::Map::new();
+        subject.insert("id".to_string(), to_did(&self.subject).into());
+        subject.insert("data".to_string(), serde_json::to_value(&self.data)?);
+        if !self.claims.is_empty() {
+            subject.insert(
+                CLAIMS_FIELD.to_string(),
+                serde_json::to_value(&self.claims)?,
+            );
+        }
+
+        let metadata = self.metadata.signed();
+        let mut proof = proof(&metadata, &self.signature, self.version)?;
+        proof.salt_sequence = Some(self.metadata.salt_sequence.clone());
+
+        credential(CERTIFICATE_TYPE, &metadata, subject.into(), proof)
+    }
+
+    /// The certificate carried by `vc`, call [`Certificate::verify`] to check it
+    pub fn from_vc(vc: &VerifiableCredential) -> Result<Self, VcError> {
+        check_types(vc, CERTIFICATE_TYPE)?;
+
+        let subject = &vc.credential_subject;
+        let data: CertificateData = serde_json::from_value(
+            subject
+                .get("data")
+                .cloned()
+                .ok_or(VcError::MissingField("credentialSubject.data"))?,
+        )?;
+        let claims = match subject.get(CLAIMS_FIELD) {
+            Some(claims) => serde_json::from_value(claims.clone())?,
+            None => Default::default(),
+        };
+
+        Ok(Certificate {
+            version: vc.proof.version,
+            subject: subject_key(subject)?,
+            data,
+            claims,
+            metadata: metadata(vc)?.with_salts(
+                vc.proof
+                    .salt_sequence
+                    .clone()
+                    .ok_or(VcError::MissingField("proof.saltSequence"))?,
+            ),
+            signature: vc.proof.proof_value.clone(),
+        })
+    }
+}
+
+impl PartialCertificate {
+    /// The revealed fields go in the `credentialSubject`, the proof is carried in compact form
+    pub fn to_vc(&self) -> Result<VerifiableCredential, VcError> {
+        let mut proof = proof(&self.metadata, &self.signature, self.version)?;
+        proof.merkle_proof = Some(self.merkle_proof.to_compact_base64()?);
+
+        credential(
+            PARTIAL_CERTIFICATE_TYPE,
+            &self.metadata,
+            partial_subject(&self.subject, &self.merkle_proof)?,
+            proof,
+        )
+    }
+
+    /// The partial certificate carried by `vc`, call [`PartialCertificate::verify`] to check it
+    ///
+    /// The `credentialSubject` must be exactly the fields revealed by the proof.
+    pub fn from_vc(vc: &VerifiableCredential) -> Result<Self, VcError> {
+        check_types(vc, PARTIAL_CERTIFICATE_TYPE)?;
+        let merkle_proof = MerkleProofNode::from_compact_base64(
+            vc.proof
+                .merkle_proof
+                .as_deref()
+                .ok_or(VcError::MissingField("proof.merkleProof"))?,
+        )?;
+
+        let subject = subject_key(&vc.credential_subject)?;
+        if partial_subject(&subject, &merkle_proof)? != vc.credential_subject {
+            return Err(VcError::SubjectMismatch);
+        }
+
+        Ok(PartialCertificate {
+            version: vc.proof.version,
+            subject,
+            metadata: metadata(vc)?,
+            signature: vc.proof.proof_value.clone(),
+            merkle_proof,
+        })
+    }
+}
+
+fn credential(
+    certificate_type: &str,
+    metadata: &SignedMetadata,
+    credential_subject: serde_json::Value,
+    proof: PortalProof,
+) -> Result<VerifiableCredential, VcError> {
+    Ok(VerifiableCredential {
+        context: vec![VC_CONTEXT.to_string(), PORTAL_CONTEXT.to_string()],
+        types: vec![VC_TYPE.to_string(), certificate_type.to_string()],
+        issuer: to_did(&metadata.issuer_pubkey),
+        valid_from: proof.created.clone(),
+        valid_until: format_date(metadata.expires_at)?,
+        credential_subject,
+        proof,
+    })
+}
+
+fn proof(metadata: &SignedMetadata, signature: &str, version: u32) -> Result<PortalProof, VcError> {
+    Ok(PortalProof {
+        proof_type: PORTAL_PROOF_TYPE.to_string(),
+        created: format_date(metadata.issued_at)?,
+        verification_method: to_did(&metadata.issuer_pubkey),
+        proof_purpose: "assertionMethod".to_string(),
+        proof_value: signature.to_string(),
+        version,
+        merkle_root: metadata.merkle_root.clone(),
+        identity_verification: IdentityVerification {
+            level: metadata.verification_level,
+            method: metadata.verification_method.clone(),
+        },
+        salt_sequence: None,
+        merkle_proof: None,
+    })
+}
+
+/// The `credentialSubject` of a partial certificate, the revealed data and claims
+fn partial_subject(
+    subject: &nostr::PublicKey,
+    merkle_proof: &MerkleProofNode,
+) -> Result<serde_json::Value, VcError> {
+    let revealed = merkle_proof.to_prepared_certificate().to_json()?;
+    let serde_json::Value::Object(mut data) = revealed else {
+        return Err(VcError::SubjectMismatch);
+    };
+
+    let mut credential_subject = serde_json::Map::new();
+    credential_subject.insert("id".to_string(), to_did(subject).into());
+    if let Some(claims) = data.remove(CLAIMS_FIELD) {
+        credential_subject.insert(CLAIMS_FIELD.to_string(), claims);
+    }
+    credential_subject.insert("data".to_string(), data.into());
+
+    Ok(credential_subject.into())
+}
+
+fn check_types(vc: &VerifiableCredential, certificate_type: &str) -> Result<(), VcError> {
+    if !vc.types.iter().any(|t| t == VC_TYPE) || !vc.types.iter().any(|t| t == certificate_type) {
+        return Err(VcError::UnsupportedType(vc.types.clone()));
+    }
+    if vc.proof.proof_type != PORTAL_PROOF_TYPE {
+        return Err(VcError::UnsupportedProof(vc.proof.proof_type.clone()));
+    }
+
+    Ok(())
+}
+
+fn metadata(vc: &VerifiableCredential) -> Result<SignedMetadata, VcError> {
+    Ok(SignedMetadata {
+        issuer_pubkey: from_did(&vc.issuer)?,
+        issued_at: parse_date(&vc.proof.created)?,
+        expires_at: parse_date(&vc.valid_until)?,
+        verification_level: vc.proof.identity_verification.level,
+        verification_method: vc.proof.identity_verification.method.clone(),
+        merkle_root: vc.proof.merkle_root.clone(),
+    })
+}
+
+fn subject_key(credential_subject: &serde_json::Value) -> Result<nostr::PublicKey, VcError> {
+    let id = credential_subject
+        .get("id")
+        .and_then(|id| id.as_str())
+        .ok_or(VcError::MissingField("credentialSubject.id"))?;
+    from_did(id)
+}
+
+fn to_did(key: &nostr::PublicKey) -> String {
+    format!("{}{}", DID_PREFIX, key.to_hex())
+}
+
+fn from_did(did: &str) -> Result<nostr::PublicKey, VcError> {
+    did.strip_prefix(DID_PREFIX)
+        .and_then(|hex| nostr::PublicKey::from_hex(hex).ok())
+        .ok_or_else(|| VcError::InvalidDid(did.to_string()))
+}
+
+fn format_date(timestamp: Timestamp) -> Result<String, VcError> {
+    let seconds = i64::try_from(timestamp.as_u64())
+        .map_err(|_| VcError::InvalidDate(timestamp.as_u64().to_string()))?;
+    DateTime::from_timestamp(seconds, 0)
+        .map(|date| date.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .ok_or_else(|| VcError::InvalidDate(timestamp.as_u64().to_string()))
+}
+
+fn parse_date(date: &str) -> Result<Timestamp, VcError> {
+    let seconds = DateTime::parse_from_rfc3339(date)
+        .map_err(|_| VcError::InvalidDate(date.to_string()))?
+        .timestamp();
+    u64::try_from(seconds)
+        .map(Timestamp::new)
+        .map_err(|_| VcError::InvalidDate(date.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        issuer::{IssueRequest, testing},
+        protocol::predicate::Predicate,
+    };
+
+    fn certificate() -> Certificate {
+        let data = serde_json::from_value(serde_json::json!({
+            "legal_name": "Acme Corp",
+            "trading_name": null,
+            "registration_number": "REG123456",
+            "tax_id": null,
+            "jurisdiction": "Delaware",
+            "incorporation_date": "2000-01-01",
+            "business_type": "Corporation",
+            "address": {
+                "street": "456 Business Ave",
+                "city": "Dover",
+                "state": "DE",
+                "postal_code": "19901",
+                "country": "US",
+            },
+            "contact": { "email": "contact@acme.com", "phone": null },
+            "website": null,
+        }))
+        .unwrap();
+        let request = IssueRequest {
+            verification_method: VerificationMethod::RegistryCheck,
+            ..testing::request(CertificateData::Business(data))
+        };
+
+        testing::issuer()
+            .issue(request.with_claims(vec![Predicate::resident_of("us").unwrap()]))
+            .unwrap()
+    }
+
+    /// Through JSON, as a partner would send it
+    fn round_trip(vc: &VerifiableCredential) -> VerifiableCredential {
+        serde_json::from_str(&serde_json::to_string(vc).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_certificate_round_trip() {
+        let certificate = certificate();
+        let vc = certificate.to_vc().unwrap();
+
+        assert_eq!(vc.types, vec![VC_TYPE, CERTIFICATE_TYPE]);
+        assert_eq!(vc.issuer, to_did(&certificate.metadata.issuer_pubkey));
+        assert_eq!(vc.credential_subject["data"]["legal_name"], "Acme Corp");
+        assert_eq!(vc.credential_subject["claims"]["resident_of_us"], true);
+
+        let imported = Certificate::from_vc(&round_trip(&vc)).unwrap();
+        assert_eq!(
+            serde_json::to_value(&imported).unwrap(),
+            serde_json::to_value(&certificate).unwrap()
+        );
+        imported.verify().unwrap();
+    }
+
+    #[test]
+    fn test_partial_certificate_round_trip() {
+        let certificate = certificate();
+        let partial = certificate
+            .reveal(&[
+                "legal_name".to_string(),
+                Predicate::resident_of("us").unwrap().field(),
+            ])
+            .unwrap();
+        let vc = partial.to_vc().unwrap();
+
+        assert_eq!(
+            vc.credential_subject,
+            serde_json::json!({
+                "id": to_did(&certificate.subject),
+                "data": { "legal_name": "Acme Corp" },
+                "claims": { "resident_of_us": true },
+            })
+        );
+        assert!(vc.proof.merkle_proof.is_some());
+        assert!(vc.proof.salt_sequence.is_none());
+
+        let imported = PartialCertificate::from_vc(&round_trip(&vc)).unwrap();
+        assert_eq!(imported.id(), certificate.id());
+        assert_eq!(
+            imported.verify().unwrap(),
+            serde_json::json!({ "legal_name": "Acme Corp", "claims": { "resident_of_us": true } })
+        );
+    }
+
+    #[test]
+    fn test_tampered_credentials() {
+        let certificate = certificate();
+        let partial = certificate.reveal(&["legal_name".to_string()]).unwrap();
+
+        // The subject must match the proof
+        let mut vc = partial.to_vc().unwrap();
+        vc.credential_subject["data"]["legal_name"] = "Evil Corp".into();
+        assert!(matches!(
+            PartialCertificate::from_vc(&vc),
+            Err(VcError::SubjectMismatch)
+        ));
+
+        // Changing the expiration breaks the signature
+        let mut vc = certificate.to_vc().unwrap();
+        vc.valid_until = "2999-01-01T00:00:00Z".to_string();
+        assert!(Certificate::from_vc(&vc).unwrap().verify().is_err());
+
+        let mut vc = certificate.to_vc().unwrap();
+        vc.proof.proof_type = "Ed25519Signature2020".to_string();
+        assert!(matches!(
+            Certificate::from_vc(&vc),
+            Err(VcError::UnsupportedProof(_))
+        ));
+
+        // A full certificate is not a partial one
+        let vc = certificate.to_vc().unwrap();
+        assert!(matches!(
+            PartialCertificate::from_vc(&vc),
+            Err(VcError::UnsupportedType(_))
+        ));
+    }
+}
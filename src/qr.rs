@@ -0,0 +1,100 @@
+//! QR code options and images, shared by the SDK that renders them and the clients of the rest
+//! daemon
+//!
+//! The rendering itself lives in the `sdk` crate, so that the clients don't depend on an image
+//! encoder.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum QrFormat {
+    Svg,
+    Png,
+    /// Unicode half blocks, meant to be printed on a dark terminal
+    Terminal,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum QrErrorCorrection {
+    /// Recovers 7% of the data
+    Low,
+    /// Recovers 15% of the data
+    #[default]
+    Medium,
+    /// Recovers 25% of the data
+    Quartile,
+    /// Recovers 30% of the data
+    High,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct QrOptions {
+    #[serde(default)]
+    pub error_correction: QrErrorCorrection,
+    /// Minimum width and height in pixels, up to [`MAX_SIZE`]. Ignored by the terminal format
+    #[serde(default = "default_size")]
+    pub size: u32,
+    /// Add the blank border required by most scanners
+    #[serde(default = "default_quiet_zone")]
+    pub quiet_zone: bool,
+}
+
+fn default_size() -> u32 {
+    256
+}
+
+fn default_quiet_zone() -> bool {
+    true
+}
+
+impl Default for QrOptions {
+    fn default() -> Self {
+        Self {
+            error_correction: QrErrorCorrection::default(),
+            size: default_size(),
+            quiet_zone: default_quiet_zone(),
+        }
+    }
+}
+
+impl QrOptions {
+    pub fn error_correction(mut self, error_correction: QrErrorCorrection) -> Self {
+        self.error_correction = error_correction;
+        self
+    }
+
+    pub fn size(mut self, size: u32) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn quiet_zone(mut self, quiet_zone: bool) -> Self {
+        self.quiet_zone = quiet_zone;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum QrImage {
+    Svg(String),
+    Png(Vec<u8>),
+    Terminal(String),
+}
+
+impl QrImage {
+    pub fn format(&self) -> QrFormat {
+        match self {
+            QrImage::Svg(_) => QrFormat::Svg,
+            QrImage::Png(_) => QrFormat::Png,
+            QrImage::Terminal(_) => QrFormat::Terminal,
+        }
+    }
+}
+
+/// The largest width and height that can be requested, the renderer clamps larger sizes
+pub const MAX_SIZE: u32 = 4096;
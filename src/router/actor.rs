@@ -1,11 +1,13 @@
 use std::{
     collections::{HashMap, HashSet},
     sync::Arc,
+    time::Instant,
 };
 
 use nostr::{
     event::{Event, EventBuilder, Kind},
     filter::{Filter, MatchEventOptions},
+    key::PublicKey,
     message::{RelayMessage, SubscriptionId},
     nips::nip44,
 };
@@ -18,7 +20,11 @@ use crate::{
     protocol::{LocalKeypair, model::event_kinds::SUBKEY_PROOF},
     router::{
         CleartextEvent, Conversation, ConversationError, ConversationMessage, NotificationStream,
-        PortalId, RelayNode, Response, channel::Channel,
+        PortalId, RelayNode, Response,
+        channel::Channel,
+        metrics::{RejectReason, RouterMetrics},
+        pow::PowPolicy,
+        rate_limit::{RateLimitPolicy, RateLimiter},
     },
 };
 
@@ -45,13 +51,18 @@ pub enum MessageRouterActorMessage {
     AddRelay(String, bool, oneshot::Sender<Result<(), ConversationError>>),
     RemoveRelay(String, oneshot::Sender<Result<(), ConversationError>>),
     Shutdown(oneshot::Sender<Result<(), ConversationError>>),
+    AddIdentity(LocalKeypair, oneshot::Sender<Result<(), ConversationError>>),
+    RemoveIdentity(PublicKey, oneshot::Sender<Result<(), ConversationError>>),
+    /// Add a conversation, optionally running it as one of the additional identities
     AddConversation(
         ConversationBox,
+        Option<PublicKey>,
         oneshot::Sender<Result<PortalId, ConversationError>>,
     ),
     AddConversationWithRelays(
         ConversationBox,
         Vec<String>,
+        Option<PublicKey>,
         oneshot::Sender<Result<PortalId, ConversationError>>,
     ),
     SubscribeToServiceRequest(
@@ -60,12 +71,31 @@ pub enum MessageRouterActorMessage {
     ),
     AddAndSubscribe(
         ConversationBox,
+        Option<PublicKey>,
         oneshot::Sender<Result<NotificationStream<serde_json::Value>, ConversationError>>,
     ),
     Ping(oneshot::Sender<()>),
+    SetRateLimitPolicy(RateLimitPolicy, oneshot::Sender<()>),
 
     /// This is used to handle relay pool notifications.
     HandleRelayPoolNotification(RelayPoolNotification),
+
+    /// Sent back to the actor once the proof of work for a conversation's events has been mined.
+    ///
+    /// Contains the conversation id, the signed events, the relays selected for the conversation
+    /// (if any) and whether we should subscribe to subkey proofs for these events.
+    PublishMinedEvents(PortalId, Vec<Event>, Option<HashSet<String>>, bool),
+}
+
+/// Configuration for the [`MessageRouterActor`].
+#[derive(Debug, Clone, Default)]
+pub struct MessageRouterActorConfig {
+    /// Proof of work requirements for outbound and inbound events.
+    pub pow: PowPolicy,
+    /// Where the router records its metrics, a no-op by default.
+    pub metrics: RouterMetrics,
+    /// Limits for inbound events, nothing is limited by default.
+    pub rate_limit: RateLimitPolicy,
 }
 
 pub struct MessageRouterActor<C>
@@ -84,14 +114,24 @@ where
     C::Error: From<nostr::types::url::Error>,
 {
     pub fn new(channel: C, keypair: LocalKeypair) -> Self {
+        Self::new_with_config(channel, keypair, MessageRouterActorConfig::default())
+    }
+
+    pub fn new_with_config(
+        channel: C,
+        keypair: LocalKeypair,
+        config: MessageRouterActorConfig,
+    ) -> Self {
         let keypair_clone = keypair.clone();
         let channel = Arc::new(channel);
 
         let (tx, mut rx) = mpsc::channel(4096);
 
         let channel_clone = Arc::clone(&channel);
+        // Only keep a weak reference, otherwise the actor would never stop after the router is dropped
+        let self_sender = tx.downgrade();
         tokio::spawn(async move {
-            let mut state = MessageRouterActorState::new(keypair_clone);
+            let mut state = MessageRouterActorState::new(keypair_clone, config, self_sender);
             while let Some(message) = rx.recv().await {
                 match message {
                     MessageRouterActorMessage::AddRelay(
@@ -123,8 +163,26 @@ where
                         }
                         break;
                     }
-                    MessageRouterActorMessage::AddConversation(conversation, response_tx) => {
-                        let result = state.add_conversation(&channel_clone, conversation).await;
+                    MessageRouterActorMessage::AddIdentity(keypair, response_tx) => {
+                        let result = state.add_identity(keypair);
+                        if let Err(e) = response_tx.send(result) {
+                            log::error!("Failed to send AddIdentity response: {:?}", e);
+                        }
+                    }
+                    MessageRouterActorMessage::RemoveIdentity(pubkey, response_tx) => {
+                        let result = state.remove_identity(&channel_clone, &pubkey).await;
+                        if let Err(e) = response_tx.send(result) {
+                            log::error!("Failed to send RemoveIdentity response: {:?}", e);
+                        }
+                    }
+                    MessageRouterActorMessage::AddConversation(
+                        conversation,
+                        identity,
+                        response_tx,
+                    ) => {
+                        let result = state
+                            .add_conversation_as(&channel_clone, conversation, identity)
+                            .await;
                         if let Err(e) = response_tx.send(result) {
                             log::error!("Failed to send AddConversation response: {:?}", e);
                         }
@@ -132,10 +190,16 @@ where
                     MessageRouterActorMessage::AddConversationWithRelays(
                         conversation,
                         relays,
+                        identity,
                         response_tx,
                     ) => {
                         let result = state
-                            .add_conversation_with_relays(&channel_clone, conversation, relays)
+                            .add_conversation_with_relays(
+                                &channel_clone,
+                                conversation,
+                                relays,
+                                identity,
+                            )
                             .await;
                         if let Err(e) = response_tx.send(result) {
                             log::error!(
@@ -153,9 +217,17 @@ where
                             );
                         }
                     }
-                    MessageRouterActorMessage::AddAndSubscribe(conversation, response_tx) => {
+                    MessageRouterActorMessage::AddAndSubscribe(
+                        conversation,
+                        identity,
+                        response_tx,
+                    ) => {
                         let result = state
-                            .add_and_subscribe::<_, serde_json::Value>(&channel_clone, conversation)
+                            .add_and_subscribe_as::<_, serde_json::Value>(
+                                &channel_clone,
+                                conversation,
+                                identity,
+                            )
                             .await;
                         if let Err(e) = response_tx.send(result) {
                             log::error!("Failed to send AddAndSubscribe response: {:?}", e);
@@ -164,6 +236,10 @@ where
                     MessageRouterActorMessage::Ping(response_tx) => {
                         let _ = response_tx.send(());
                     }
+                    MessageRouterActorMessage::SetRateLimitPolicy(policy, response_tx) => {
+                        state.rate_limiter.set_policy(policy);
+                        let _ = response_tx.send(());
+                    }
 
                     MessageRouterActorMessage::HandleRelayPoolNotification(notification) => {
                         // Handle notification directly without response channel
@@ -174,6 +250,25 @@ where
                             log::error!("Failed to handle relay pool notification: {:?}", e);
                         }
                     }
+                    MessageRouterActorMessage::PublishMinedEvents(
+                        id,
+                        events,
+                        selected_relays,
+                        subscribe_to_subkey_proofs,
+                    ) => {
+                        if let Err(e) = state
+                            .publish_events(
+                                &channel_clone,
+                                &id,
+                                events,
+                                selected_relays,
+                                subscribe_to_subkey_proofs,
+                            )
+                            .await
+                        {
+                            log::error!("Failed to publish mined events for {}: {:?}", id, e);
+                        }
+                    }
                 }
             }
         });
@@ -256,6 +351,28 @@ where
         result.map_err(MessageRouterActorError::Conversation)
     }
 
+    /// Serve an additional identity from this router
+    ///
+    /// Inbound events tagging the identity's key are decrypted with it, and conversations added
+    /// with [`Self::add_conversation_as`] or [`Self::add_and_subscribe_as`] sign and encrypt
+    /// their replies with it.
+    pub async fn add_identity(&self, keypair: LocalKeypair) -> Result<(), MessageRouterActorError> {
+        let (tx, rx) = oneshot::channel();
+        self.send_message(MessageRouterActorMessage::AddIdentity(keypair, tx))
+            .await?;
+        let result = rx.await.map_err(|e| MessageRouterActorError::Receiver(e))?;
+        result.map_err(MessageRouterActorError::Conversation)
+    }
+
+    /// Stop serving an identity, removing all of its conversations
+    pub async fn remove_identity(&self, pubkey: PublicKey) -> Result<(), MessageRouterActorError> {
+        let (tx, rx) = oneshot::channel();
+        self.send_message(MessageRouterActorMessage::RemoveIdentity(pubkey, tx))
+            .await?;
+        let result = rx.await.map_err(|e| MessageRouterActorError::Receiver(e))?;
+        result.map_err(MessageRouterActorError::Conversation)
+    }
+
     pub async fn ping(&self) -> Result<(), MessageRouterActorError> {
         let (tx, rx) = oneshot::channel();
         self.send_message(MessageRouterActorMessage::Ping(tx))
@@ -264,16 +381,50 @@ where
         Ok(result)
     }
 
+    /// Replace the limits for inbound events set in the [`MessageRouterActorConfig`].
+    pub async fn set_rate_limit_policy(
+        &self,
+        policy: RateLimitPolicy,
+    ) -> Result<(), MessageRouterActorError> {
+        let (tx, rx) = oneshot::channel();
+        self.send_message(MessageRouterActorMessage::SetRateLimitPolicy(policy, tx))
+            .await?;
+        rx.await.map_err(MessageRouterActorError::Receiver)
+    }
+
     pub async fn add_conversation(
         &self,
         conversation: ConversationBox,
+    ) -> Result<PortalId, MessageRouterActorError> {
+        self.add_conversation_internal(conversation, None).await
+    }
+
+    /// Add a conversation that runs as `identity`, which must have been added with
+    /// [`Self::add_identity`] or be the main keypair of the router.
+    pub async fn add_conversation_as(
+        &self,
+        identity: PublicKey,
+        conversation: ConversationBox,
+    ) -> Result<PortalId, MessageRouterActorError> {
+        self.add_conversation_internal(conversation, Some(identity))
+            .await
+    }
+
+    async fn add_conversation_internal(
+        &self,
+        conversation: ConversationBox,
+        identity: Option<PublicKey>,
     ) -> Result<PortalId, MessageRouterActorError> {
         self.ping().await?;
         self.ping().await?;
 
         let (tx, rx) = oneshot::channel();
-        self.send_message(MessageRouterActorMessage::AddConversation(conversation, tx))
-            .await?;
+        self.send_message(MessageRouterActorMessage::AddConversation(
+            conversation,
+            identity,
+            tx,
+        ))
+        .await?;
         let result = rx.await.map_err(|e| MessageRouterActorError::Receiver(e))?;
         result.map_err(MessageRouterActorError::Conversation)
     }
@@ -282,11 +433,35 @@ where
         &self,
         conversation: ConversationBox,
         relays: Vec<String>,
+    ) -> Result<PortalId, MessageRouterActorError> {
+        self.add_conversation_with_relays_internal(conversation, relays, None)
+            .await
+    }
+
+    /// Add a conversation that runs as `identity` and only talks on `relays`.
+    ///
+    /// See [`Self::add_conversation_as`].
+    pub async fn add_conversation_with_relays_as(
+        &self,
+        identity: PublicKey,
+        conversation: ConversationBox,
+        relays: Vec<String>,
+    ) -> Result<PortalId, MessageRouterActorError> {
+        self.add_conversation_with_relays_internal(conversation, relays, Some(identity))
+            .await
+    }
+
+    async fn add_conversation_with_relays_internal(
+        &self,
+        conversation: ConversationBox,
+        relays: Vec<String>,
+        identity: Option<PublicKey>,
     ) -> Result<PortalId, MessageRouterActorError> {
         let (tx, rx) = oneshot::channel();
         self.send_message(MessageRouterActorMessage::AddConversationWithRelays(
             conversation,
             relays,
+            identity,
             tx,
         ))
         .await?;
@@ -346,7 +521,22 @@ where
         &self,
         conversation: ConversationBox,
     ) -> Result<NotificationStream<T>, MessageRouterActorError> {
-        let raw_stream = self.add_and_subscribe_raw(conversation).await?;
+        let raw_stream = self.add_and_subscribe_raw(conversation, None).await?;
+        let NotificationStream { stream } = raw_stream;
+        let typed_stream =
+            stream.map(|result| result.and_then(|value| serde_json::from_value(value)));
+        Ok(NotificationStream::new(typed_stream))
+    }
+
+    /// Same as [`Self::add_and_subscribe`], with the conversation running as `identity`
+    pub async fn add_and_subscribe_as<T: DeserializeOwned + Serialize>(
+        &self,
+        identity: PublicKey,
+        conversation: ConversationBox,
+    ) -> Result<NotificationStream<T>, MessageRouterActorError> {
+        let raw_stream = self
+            .add_and_subscribe_raw(conversation, Some(identity))
+            .await?;
         let NotificationStream { stream } = raw_stream;
         let typed_stream =
             stream.map(|result| result.and_then(|value| serde_json::from_value(value)));
@@ -357,10 +547,15 @@ where
     async fn add_and_subscribe_raw(
         &self,
         conversation: ConversationBox,
+        identity: Option<PublicKey>,
     ) -> Result<NotificationStream<serde_json::Value>, MessageRouterActorError> {
         let (tx, rx) = oneshot::channel();
-        self.send_message(MessageRouterActorMessage::AddAndSubscribe(conversation, tx))
-            .await?;
+        self.send_message(MessageRouterActorMessage::AddAndSubscribe(
+            conversation,
+            identity,
+            tx,
+        ))
+        .await?;
         let result = rx.await.map_err(|e| MessageRouterActorError::Receiver(e))?;
         result.map_err(MessageRouterActorError::Conversation)
     }
@@ -368,6 +563,13 @@ where
 
 pub struct MessageRouterActorState {
     keypair: LocalKeypair,
+    /// Additional identities served by the router, besides `keypair`
+    identities: HashMap<PublicKey, LocalKeypair>,
+    conversation_identities: HashMap<PortalId, PublicKey>,
+    pow: PowPolicy,
+    metrics: RouterMetrics,
+    rate_limiter: RateLimiter,
+    sender: mpsc::WeakSender<MessageRouterActorMessage>,
     conversations: HashMap<PortalId, ConversationBox>,
     aliases: HashMap<PortalId, Vec<u64>>,
     filters: HashMap<PortalId, Filter>,
@@ -379,9 +581,19 @@ pub struct MessageRouterActorState {
 }
 
 impl MessageRouterActorState {
-    pub fn new(keypair: LocalKeypair) -> Self {
+    pub fn new(
+        keypair: LocalKeypair,
+        config: MessageRouterActorConfig,
+        sender: mpsc::WeakSender<MessageRouterActorMessage>,
+    ) -> Self {
         Self {
             keypair,
+            identities: HashMap::new(),
+            conversation_identities: HashMap::new(),
+            pow: config.pow,
+            metrics: config.metrics,
+            rate_limiter: RateLimiter::new(config.rate_limit),
+            sender,
             conversations: HashMap::new(),
             aliases: HashMap::new(),
             filters: HashMap::new(),
@@ -392,6 +604,59 @@ impl MessageRouterActorState {
         }
     }
 
+    pub fn add_identity(&mut self, keypair: LocalKeypair) -> Result<(), ConversationError> {
+        if keypair.public_key() != self.keypair.public_key() {
+            self.identities.insert(keypair.public_key(), keypair);
+        }
+        Ok(())
+    }
+
+    pub async fn remove_identity<C: Channel>(
+        &mut self,
+        channel: &Arc<C>,
+        pubkey: &PublicKey,
+    ) -> Result<(), ConversationError>
+    where
+        C::Error: From<nostr::types::url::Error>,
+    {
+        if self.identities.remove(pubkey).is_none() {
+            return Err(ConversationError::UnknownIdentity(*pubkey));
+        }
+
+        let conversations = self
+            .conversation_identities
+            .iter()
+            .filter(|(_, identity)| *identity == pubkey)
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+        for id in conversations {
+            self.cleanup_conversation(channel, &id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// The keypair a conversation runs as
+    fn keypair_for(&self, id: &PortalId) -> &LocalKeypair {
+        self.conversation_identities
+            .get(id)
+            .and_then(|pubkey| self.identities.get(pubkey))
+            .unwrap_or(&self.keypair)
+    }
+
+    /// The local keypair an inbound event is addressed to, based on its `p` tags
+    fn keypair_for_event(&self, event: &Event) -> &LocalKeypair {
+        event
+            .tags
+            .public_keys()
+            .find_map(|pubkey| self.identities.get(pubkey))
+            .unwrap_or(&self.keypair)
+    }
+
+    fn is_local_key(&self, pubkey: &PublicKey) -> bool {
+        *pubkey == self.keypair.public_key() || self.identities.contains_key(pubkey)
+    }
+
     pub async fn add_relay<C: Channel>(
         &mut self,
         channel: &Arc<C>,
@@ -502,10 +767,13 @@ impl MessageRouterActorState {
         C::Error: From<nostr::types::url::Error>,
     {
         // Remove conversation state
-        self.conversations.remove(conversation);
+        if let Some(removed) = self.conversations.remove(conversation) {
+            self.metrics.conversation_removed(removed.name());
+        }
         self.subscribers.remove(conversation);
         self.filters.remove(conversation);
         self.end_of_stored_events.remove(conversation);
+        self.conversation_identities.remove(conversation);
         let aliases = self.aliases.remove(conversation);
 
         // Remove from global relay node
@@ -549,10 +817,12 @@ impl MessageRouterActorState {
             .map_err(|e| ConversationError::Inner(Box::new(e)))?;
 
         self.conversations.clear();
+        self.metrics.conversations_cleared();
         self.subscribers.clear();
         self.aliases.clear();
         self.filters.clear();
         self.end_of_stored_events.clear();
+        self.conversation_identities.clear();
         self.global_relay_node.conversations.clear();
         Ok(())
     }
@@ -630,18 +900,43 @@ impl MessageRouterActorState {
         let message = match &event {
             LocalEvent::Message(event) => {
                 log::debug!("Processing event: {:?}", event.id);
-                if event.pubkey == self.keypair.public_key() && event.kind != Kind::Metadata {
+                if self.is_local_key(&event.pubkey) && event.kind != Kind::Metadata {
                     log::trace!("Ignoring event from self");
                     return Ok(());
                 }
+                self.metrics.event_received();
 
                 if !event.verify_signature() {
                     log::warn!("Invalid signature for event id: {:?}", event.id);
+                    self.metrics.event_rejected(RejectReason::InvalidSignature);
+                    return Ok(());
+                }
+
+                if !self.pow.accepts(event) {
+                    log::debug!(
+                        "Ignoring event with insufficient proof of work: {:?}",
+                        event.id
+                    );
+                    self.metrics.event_rejected(RejectReason::InsufficientPow);
                     return Ok(());
                 }
 
+                if !self
+                    .rate_limiter
+                    .accepts(&event.pubkey, event.kind, Instant::now())
+                {
+                    log::debug!(
+                        "Ignoring event over the rate limit from {}: {:?}",
+                        event.pubkey,
+                        event.id
+                    );
+                    self.metrics.event_rejected(RejectReason::RateLimited);
+                    return Ok(());
+                }
+
+                let keypair = self.keypair_for_event(event);
                 if let Ok(content) =
-                    nip44::decrypt(&self.keypair.secret_key(), &event.pubkey, &event.content)
+                    nip44::decrypt(&keypair.secret_key(), &event.pubkey, &event.content)
                 {
                     let cleartext = match CleartextEvent::new(&event, &content) {
                         Ok(cleartext) => cleartext,
@@ -657,6 +952,8 @@ impl MessageRouterActorState {
                 {
                     ConversationMessage::Cleartext(CleartextEvent::new_json(&event, cleartext))
                 } else {
+                    // Neither encrypted for us nor plain JSON
+                    self.metrics.decrypt_failed();
                     ConversationMessage::Encrypted(event.clone())
                 }
             }
@@ -723,16 +1020,18 @@ impl MessageRouterActorState {
         };
 
         log::debug!("Looking for conversation: {}", conversation_id);
-        let response = match self.conversations.get_mut(&conversation_id) {
+        let started = Instant::now();
+        let (name, response) = match self.conversations.get_mut(&conversation_id) {
             Some(conv) => {
                 log::debug!("Found conversation, processing message");
-                match conv.on_message(message) {
+                let response = match conv.on_message(message) {
                     Ok(response) => response,
                     Err(e) => {
                         log::warn!("Error in conversation id {}: {:?}", conversation_id, e);
                         Response::new().finish()
                     }
-                }
+                };
+                (conv.name(), response)
             }
             None => {
                 log::warn!("No conversation found for id: {}", conversation_id);
@@ -748,6 +1047,7 @@ impl MessageRouterActorState {
         log::debug!("Processing response for conversation: {}", conversation_id);
         self.process_response(channel, &conversation_id, response)
             .await?;
+        self.metrics.observe_dispatch(name, started.elapsed());
 
         Ok(())
     }
@@ -793,25 +1093,36 @@ impl MessageRouterActorState {
             self.end_of_stored_events.insert(id.clone(), num_relays);
         }
 
-        let mut events_to_broadcast = vec![];
+        let target_relays = match &selected_relays_optional {
+            Some(selected_relays) => selected_relays.iter().cloned().collect::<Vec<_>>(),
+            None => self.relay_nodes.keys().cloned().collect::<Vec<_>>(),
+        };
+
+        let keypair = self.keypair_for(id).clone();
+        let mut builders = vec![];
         for response_entry in response.responses.iter() {
+            let difficulty = self
+                .pow
+                .difficulty_for(response_entry.kind, target_relays.iter());
             let build_event = |content: &str| {
-                EventBuilder::new(response_entry.kind, content)
-                    .tags(response_entry.tags.clone())
-                    .sign_with_keys(&self.keypair)
-                    .map_err(|e| ConversationError::Inner(Box::new(e)))
+                let builder = EventBuilder::new(response_entry.kind, content)
+                    .tags(response_entry.tags.clone());
+                if difficulty > 0 {
+                    builder.pow(difficulty)
+                } else {
+                    builder
+                }
             };
 
             if !response_entry.encrypted {
                 let content = serde_json::to_string(&response_entry.content)
                     .map_err(|e| ConversationError::Inner(Box::new(e)))?;
 
-                let event = build_event(&content)?;
-                events_to_broadcast.push(event);
+                builders.push((difficulty, build_event(&content)));
             } else {
                 for pubkey in response_entry.recepient_keys.iter() {
                     let content = nip44::encrypt(
-                        &self.keypair.secret_key(),
+                        &keypair.secret_key(),
                         &pubkey,
                         serde_json::to_string(&response_entry.content)
                             .map_err(|e| ConversationError::Inner(Box::new(e)))?,
@@ -819,12 +1130,77 @@ impl MessageRouterActorState {
                     )
                     .map_err(|e| ConversationError::Inner(Box::new(e)))?;
 
-                    let event = build_event(&content)?;
-                    events_to_broadcast.push(event);
+                    builders.push((difficulty, build_event(&content)));
                 }
             }
         }
 
+        // Sign the events, notify the subscribers and then publish. Mined events are published
+        // later, once they are ready.
+        let events = if builders.iter().any(|(difficulty, _)| *difficulty > 0) {
+            // Mining can take a while: do it on a blocking thread and send the events back to the actor
+            // once they are ready, so that we can keep processing messages in the meantime.
+            let keys = keypair.get_keys().clone();
+            let sender = self.sender.clone();
+            let id = id.clone();
+            let subscribe_to_subkey_proofs = response.subscribe_to_subkey_proofs;
+            let selected_relays = selected_relays_optional.clone();
+
+            log::debug!(
+                "Mining proof of work for {} events of {}",
+                builders.len(),
+                id
+            );
+            tokio::spawn(async move {
+                let events = tokio::task::spawn_blocking(move || {
+                    builders
+                        .into_iter()
+                        .map(|(_, builder)| builder.sign_with_keys(&keys))
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .await;
+
+                let events = match events {
+                    Ok(Ok(events)) => events,
+                    Ok(Err(e)) => {
+                        log::error!("Failed to sign mined events for {}: {:?}", id, e);
+                        return;
+                    }
+                    Err(e) => {
+                        log::error!("Mining task for {} failed: {:?}", id, e);
+                        return;
+                    }
+                };
+
+                if let Some(sender) = sender.upgrade() {
+                    if let Err(e) = sender
+                        .send(MessageRouterActorMessage::PublishMinedEvents(
+                            id,
+                            events,
+                            selected_relays,
+                            subscribe_to_subkey_proofs,
+                        ))
+                        .await
+                    {
+                        log::error!("Failed to send PublishMinedEvents: {:?}", e);
+                    }
+                }
+            });
+
+            None
+        } else {
+            Some(
+                builders
+                    .into_iter()
+                    .map(|(_, builder)| {
+                        builder
+                            .sign_with_keys(&keypair)
+                            .map_err(|e| ConversationError::Inner(Box::new(e)))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            )
+        };
+
         for notification in response.notifications.iter() {
             log::debug!("Sending notification: {:?}", notification);
             if let Some(senders) = self.subscribers.get_mut(id) {
@@ -834,7 +1210,40 @@ impl MessageRouterActorState {
             }
         }
 
-        if response.subscribe_to_subkey_proofs {
+        if let Some(events) = events {
+            self.publish_events(
+                channel,
+                id,
+                events,
+                selected_relays_optional,
+                response.subscribe_to_subkey_proofs,
+            )
+            .await?;
+        }
+
+        if response.finished {
+            log::info!("Conversation {} finished, cleaning up", id);
+            self.cleanup_conversation(channel, id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Broadcasts signed events for a conversation, optionally subscribing to the subkey proofs
+    /// that will reference them.
+    async fn publish_events<C: Channel>(
+        &mut self,
+        channel: &Arc<C>,
+        id: &PortalId,
+        events_to_broadcast: Vec<Event>,
+        selected_relays_optional: Option<HashSet<String>>,
+        subscribe_to_subkey_proofs: bool,
+    ) -> Result<(), ConversationError>
+    where
+        C::Error: From<nostr::types::url::Error>,
+    {
+        // The conversation might have been removed while we were mining the events
+        if subscribe_to_subkey_proofs && self.conversations.contains_key(id) {
             let alias_num = rand::random::<u64>();
 
             self.aliases.entry(id.clone()).or_default().push(alias_num);
@@ -882,11 +1291,6 @@ impl MessageRouterActorState {
             // TODO: wait for confirmation from relays
         }
 
-        if response.finished {
-            log::info!("Conversation {} finished, cleaning up", id);
-            self.cleanup_conversation(channel, id).await?;
-        }
-
         Ok(())
     }
 
@@ -895,7 +1299,15 @@ impl MessageRouterActorState {
         id: &PortalId,
         mut conversation: ConversationBox,
         relays: Option<Vec<String>>,
+        identity: Option<PublicKey>,
     ) -> Result<Response, ConversationError> {
+        let identity = identity.filter(|identity| *identity != self.keypair.public_key());
+        if let Some(identity) = identity {
+            if !self.identities.contains_key(&identity) {
+                return Err(ConversationError::UnknownIdentity(identity));
+            }
+        }
+
         let response = conversation.init()?;
 
         if let Some(relays) = relays {
@@ -918,7 +1330,13 @@ impl MessageRouterActorState {
             self.global_relay_node.conversations.insert(id.clone());
         }
 
-        self.conversations.insert(id.clone(), conversation);
+        self.metrics.conversation_added(conversation.name());
+        if let Some(replaced) = self.conversations.insert(id.clone(), conversation) {
+            self.metrics.conversation_removed(replaced.name());
+        }
+        if let Some(identity) = identity {
+            self.conversation_identities.insert(id.clone(), identity);
+        }
 
         Ok(response)
     }
@@ -938,30 +1356,49 @@ impl MessageRouterActorState {
         channel: &Arc<C>,
         conversation: ConversationBox,
     ) -> Result<PortalId, ConversationError>
+    where
+        C::Error: From<nostr::types::url::Error>,
+    {
+        self.add_conversation_as(channel, conversation, None).await
+    }
+
+    /// Adds a new conversation that signs and encrypts its replies with `identity`.
+    ///
+    /// If `identity` is `None` the main keypair of the router is used.
+    pub async fn add_conversation_as<C: Channel>(
+        &mut self,
+        channel: &Arc<C>,
+        conversation: ConversationBox,
+        identity: Option<PublicKey>,
+    ) -> Result<PortalId, ConversationError>
     where
         C::Error: From<nostr::types::url::Error>,
     {
         let conversation_id = PortalId::new_conversation();
 
-        let response = self.internal_add_with_id(&conversation_id, conversation, None)?;
+        let response = self.internal_add_with_id(&conversation_id, conversation, None, identity)?;
         self.process_response(channel, &conversation_id, response)
             .await?;
 
         Ok(conversation_id)
     }
 
+    /// Adds a new conversation restricted to `relays`, running as `identity` like
+    /// [`Self::add_conversation_as`].
     pub async fn add_conversation_with_relays<C: Channel>(
         &mut self,
         channel: &Arc<C>,
         conversation: ConversationBox,
         relays: Vec<String>,
+        identity: Option<PublicKey>,
     ) -> Result<PortalId, ConversationError>
     where
         C::Error: From<nostr::types::url::Error>,
     {
         let conversation_id = PortalId::new_conversation();
 
-        let response = self.internal_add_with_id(&conversation_id, conversation, Some(relays))?;
+        let response =
+            self.internal_add_with_id(&conversation_id, conversation, Some(relays), identity)?;
         self.process_response(channel, &conversation_id, response)
             .await?;
 
@@ -1015,6 +1452,19 @@ impl MessageRouterActorState {
         channel: &Arc<C>,
         conversation: ConversationBox,
     ) -> Result<NotificationStream<T>, ConversationError>
+    where
+        C::Error: From<nostr::types::url::Error>,
+    {
+        self.add_and_subscribe_as(channel, conversation, None).await
+    }
+
+    /// Same as [`Self::add_and_subscribe`], with the conversation running as `identity`
+    pub async fn add_and_subscribe_as<C: Channel, T: DeserializeOwned + Serialize>(
+        &mut self,
+        channel: &Arc<C>,
+        conversation: ConversationBox,
+        identity: Option<PublicKey>,
+    ) -> Result<NotificationStream<T>, ConversationError>
     where
         C::Error: From<nostr::types::url::Error>,
     {
@@ -1032,7 +1482,7 @@ impl MessageRouterActorState {
         let rx = NotificationStream::new(rx);
 
         // Now add the conversation
-        let response = self.internal_add_with_id(&conversation_id, conversation, None)?;
+        let response = self.internal_add_with_id(&conversation_id, conversation, None, identity)?;
         self.process_response(channel, &conversation_id, response)
             .await?;
 
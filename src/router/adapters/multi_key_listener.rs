@@ -14,7 +14,7 @@ use nostr::{
 use crate::protocol::model::{auth::SubkeyProof, event_kinds::SUBKEY_PROOF};
 
 use crate::router::{
-    CleartextEvent, Conversation, ConversationError, ConversationMessage, Response,
+    CleartextEvent, Conversation, ConversationError, ConversationMessage, Response, short_type_name,
 };
 
 pub trait MultiKeyListener: Sized + Send + 'static {
@@ -103,6 +103,10 @@ where
             None => false,
         }
     }
+
+    fn name(&self) -> &'static str {
+        short_type_name::<T>()
+    }
 }
 
 impl<Inner: MultiKeyListener> MultiKeyListenerAdapter<Inner> {
@@ -11,7 +11,7 @@ use nostr::{event::Kind, filter::Filter, key::PublicKey};
 use crate::protocol::model::{auth::SubkeyProof, event_kinds::SUBKEY_PROOF};
 
 use crate::router::{
-    CleartextEvent, Conversation, ConversationError, ConversationMessage, Response,
+    CleartextEvent, Conversation, ConversationError, ConversationMessage, Response, short_type_name,
 };
 
 const MAX_CLIENTS: usize = 8;
@@ -150,6 +150,10 @@ impl<T: MultiKeySender> Conversation for MultiKeySenderAdapter<T> {
             None => false,
         }
     }
+
+    fn name(&self) -> &'static str {
+        short_type_name::<T>()
+    }
 }
 
 impl<Inner: MultiKeySender> MultiKeySenderAdapter<Inner> {
@@ -2,7 +2,9 @@ use std::{collections::HashSet, ops::Deref};
 
 use nostr::key::PublicKey;
 
-use crate::router::{Conversation, ConversationError, ConversationMessage, Response};
+use crate::router::{
+    Conversation, ConversationError, ConversationMessage, Response, short_type_name,
+};
 
 pub trait OneShotSender: Sized + Send + 'static {
     type Error: std::error::Error + Send + Sync + 'static;
@@ -38,6 +40,10 @@ impl<T: OneShotSender> Conversation for OneShotSenderAdapter<T> {
     fn is_expired(&self) -> bool {
         false
     }
+
+    fn name(&self) -> &'static str {
+        short_type_name::<T>()
+    }
 }
 
 impl<Inner: OneShotSender> OneShotSenderAdapter<Inner> {
@@ -0,0 +1,285 @@
+//! Prometheus metrics of the router
+//!
+//! [`RouterMetrics`] is always part of the [`MessageRouterActorConfig`](super::MessageRouterActorConfig),
+//! but it only records anything when the `metrics` feature is enabled and it was created with
+//! [`RouterMetrics::new`]. The default instance is a no-op.
+
+use std::time::Duration;
+
+use nostr_relay_pool::monitor::MonitorNotification;
+use tokio::{sync::broadcast, task::JoinHandle};
+
+#[cfg(feature = "metrics")]
+use std::sync::Arc;
+
+#[cfg(feature = "metrics")]
+use prometheus::{HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts};
+
+/// Why an event received from a relay was dropped before reaching a conversation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    InvalidSignature,
+    InsufficientPow,
+    RateLimited,
+}
+
+impl RejectReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::InvalidSignature => "invalid_signature",
+            RejectReason::InsufficientPow => "insufficient_pow",
+            RejectReason::RateLimited => "rate_limited",
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+struct Collectors {
+    conversations: IntGaugeVec,
+    dispatch_duration: HistogramVec,
+    events_received: IntCounter,
+    events_rejected: IntCounterVec,
+    decrypt_failures: IntCounter,
+    relay_status_changes: IntCounterVec,
+}
+
+/// Collects the metrics of a router
+///
+/// Cloning is cheap, all the clones record to the same collectors.
+#[derive(Clone, Default)]
+pub struct RouterMetrics {
+    #[cfg(feature = "metrics")]
+    collectors: Option<Arc<Collectors>>,
+}
+
+impl std::fmt::Debug for RouterMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RouterMetrics")
+            .field("enabled", &self.is_enabled())
+            .finish()
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl RouterMetrics {
+    /// Create the collectors and register them to `registry`
+    pub fn new(registry: &prometheus::Registry) -> Result<Self, prometheus::Error> {
+        let collectors = Collectors {
+            conversations: IntGaugeVec::new(
+                Opts::new(
+                    "portal_router_conversations",
+                    "Number of active conversations, by type",
+                ),
+                &["conversation"],
+            )?,
+            dispatch_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "portal_router_dispatch_duration_seconds",
+                    "Time spent handling an event in a conversation, by type",
+                ),
+                &["conversation"],
+            )?,
+            events_received: IntCounter::new(
+                "portal_router_events_received_total",
+                "Number of events received from the relays",
+            )?,
+            events_rejected: IntCounterVec::new(
+                Opts::new(
+                    "portal_router_events_rejected_total",
+                    "Number of events dropped before reaching a conversation, by reason",
+                ),
+                &["reason"],
+            )?,
+            decrypt_failures: IntCounter::new(
+                "portal_router_decrypt_failures_total",
+                "Number of events that could not be decrypted",
+            )?,
+            relay_status_changes: IntCounterVec::new(
+                Opts::new(
+                    "portal_router_relay_status_changes_total",
+                    "Number of relay status changes, by relay and new status",
+                ),
+                &["relay", "status"],
+            )?,
+        };
+
+        registry.register(Box::new(collectors.conversations.clone()))?;
+        registry.register(Box::new(collectors.dispatch_duration.clone()))?;
+        registry.register(Box::new(collectors.events_received.clone()))?;
+        registry.register(Box::new(collectors.events_rejected.clone()))?;
+        registry.register(Box::new(collectors.decrypt_failures.clone()))?;
+        registry.register(Box::new(collectors.relay_status_changes.clone()))?;
+
+        Ok(Self {
+            collectors: Some(Arc::new(collectors)),
+        })
+    }
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+impl RouterMetrics {
+    /// Whether this instance records anything
+    pub fn is_enabled(&self) -> bool {
+        #[cfg(feature = "metrics")]
+        {
+            self.collectors.is_some()
+        }
+        #[cfg(not(feature = "metrics"))]
+        {
+            false
+        }
+    }
+
+    pub(crate) fn conversation_added(&self, conversation: &str) {
+        #[cfg(feature = "metrics")]
+        if let Some(collectors) = &self.collectors {
+            collectors
+                .conversations
+                .with_label_values(&[conversation])
+                .inc();
+        }
+    }
+
+    pub(crate) fn conversation_removed(&self, conversation: &str) {
+        #[cfg(feature = "metrics")]
+        if let Some(collectors) = &self.collectors {
+            collectors
+                .conversations
+                .with_label_values(&[conversation])
+                .dec();
+        }
+    }
+
+    pub(crate) fn conversations_cleared(&self) {
+        #[cfg(feature = "metrics")]
+        if let Some(collectors) = &self.collectors {
+            collectors.conversations.reset();
+        }
+    }
+
+    pub(crate) fn event_received(&self) {
+        #[cfg(feature = "metrics")]
+        if let Some(collectors) = &self.collectors {
+            collectors.events_received.inc();
+        }
+    }
+
+    pub(crate) fn event_rejected(&self, reason: RejectReason) {
+        #[cfg(feature = "metrics")]
+        if let Some(collectors) = &self.collectors {
+            collectors
+                .events_rejected
+                .with_label_values(&[reason.as_str()])
+                .inc();
+        }
+    }
+
+    pub(crate) fn decrypt_failed(&self) {
+        #[cfg(feature = "metrics")]
+        if let Some(collectors) = &self.collectors {
+            collectors.decrypt_failures.inc();
+        }
+    }
+
+    pub(crate) fn observe_dispatch(&self, conversation: &str, duration: Duration) {
+        #[cfg(feature = "metrics")]
+        if let Some(collectors) = &self.collectors {
+            collectors
+                .dispatch_duration
+                .with_label_values(&[conversation])
+                .observe(duration.as_secs_f64());
+        }
+    }
+
+    /// Record that `relay` switched to `status`
+    pub fn relay_status_changed(&self, relay: &str, status: &str) {
+        #[cfg(feature = "metrics")]
+        if let Some(collectors) = &self.collectors {
+            collectors
+                .relay_status_changes
+                .with_label_values(&[relay, status])
+                .inc();
+        }
+    }
+
+    /// Record the status changes reported by the monitor of a relay pool
+    ///
+    /// The task stops when the monitor is dropped.
+    pub fn watch_relays(
+        &self,
+        mut notifications: broadcast::Receiver<MonitorNotification>,
+    ) -> JoinHandle<()> {
+        let metrics = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match notifications.recv().await {
+                    Ok(MonitorNotification::StatusChanged { relay_url, status }) => {
+                        metrics.relay_status_changed(&relay_url.to_string(), &status.to_string());
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        log::warn!("Missed {} relay status changes", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_metrics() {
+        let registry = prometheus::Registry::new();
+        let metrics = RouterMetrics::new(&registry).unwrap();
+        assert!(metrics.is_enabled());
+
+        metrics.conversation_added("KeyHandshakeReceiverConversation");
+        metrics.conversation_added("KeyHandshakeReceiverConversation");
+        metrics.conversation_removed("KeyHandshakeReceiverConversation");
+        metrics.event_rejected(RejectReason::InsufficientPow);
+        metrics.decrypt_failed();
+
+        let families = registry.gather();
+        let value = |name: &str| {
+            families
+                .iter()
+                .find(|family| family.get_name() == name)
+                .map(|family| family.get_metric()[0].clone())
+                .unwrap()
+        };
+        assert_eq!(
+            value("portal_router_conversations").get_gauge().get_value(),
+            1.0
+        );
+        assert_eq!(
+            value("portal_router_events_rejected_total")
+                .get_counter()
+                .get_value(),
+            1.0
+        );
+        assert_eq!(
+            value("portal_router_decrypt_failures_total")
+                .get_counter()
+                .get_value(),
+            1.0
+        );
+
+        metrics.conversations_cleared();
+        assert!(
+            registry
+                .gather()
+                .iter()
+                .all(|family| family.get_name() != "portal_router_conversations")
+        );
+    }
+
+    #[test]
+    fn test_default_is_noop() {
+        let metrics = RouterMetrics::default();
+        assert!(!metrics.is_enabled());
+        metrics.conversation_added("KeyHandshakeReceiverConversation");
+    }
+}
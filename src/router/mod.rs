@@ -16,13 +16,23 @@ pub mod actor;
 pub mod adapters;
 pub mod channel;
 pub mod ids;
+pub mod metrics;
+pub mod pow;
+pub mod rate_limit;
+pub mod record;
 
 pub use adapters::multi_key_listener::{MultiKeyListener, MultiKeyListenerAdapter};
 pub use adapters::multi_key_sender::{MultiKeySender, MultiKeySenderAdapter};
 pub use ids::PortalId;
+pub use metrics::RouterMetrics;
+pub use pow::PowPolicy;
+pub use rate_limit::{RateLimit, RateLimitPolicy};
+pub use record::{RecordingChannel, ReplayChannel, ReplayClock};
 
 // Re-export MessageRouterActor as MessageRouter for backward compatibility
-pub use actor::{MessageRouterActor as MessageRouter, MessageRouterActorError};
+pub use actor::{
+    MessageRouterActor as MessageRouter, MessageRouterActorConfig, MessageRouterActorError,
+};
 
 pub struct RelayNode {
     conversations: HashSet<PortalId>,
@@ -205,6 +215,9 @@ pub enum ConversationError {
 
     #[error("Relay '{0}' is not connected")]
     RelayNotConnected(String),
+
+    #[error("Identity {0} is not served by this router")]
+    UnknownIdentity(PublicKey),
 }
 
 pub trait Conversation {
@@ -213,6 +226,18 @@ pub trait Conversation {
     fn init(&mut self) -> Result<Response, ConversationError> {
         Ok(Response::default())
     }
+
+    /// The type of the conversation, used to label metrics
+    fn name(&self) -> &'static str {
+        short_type_name::<Self>()
+    }
+}
+
+/// The name of `T` without its path and generic parameters
+pub(crate) fn short_type_name<T: ?Sized>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name)
 }
 
 #[derive(Debug, Clone)]
@@ -299,3 +324,29 @@ impl<T: Serialize> std::fmt::Debug for NotificationStream<T> {
         f.debug_struct("NotificationStream").finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nostr::Keys;
+
+    use super::*;
+    use crate::sdk::auth::{AuthChallengeSenderConversation, KeyHandshakeReceiverConversation};
+
+    #[test]
+    fn test_adapters_are_named_after_their_conversation() {
+        let key = Keys::generate().public_key();
+
+        let listener = MultiKeyListenerAdapter::new(
+            KeyHandshakeReceiverConversation::new(key, "token".to_string()),
+            None,
+        );
+        assert_eq!(listener.name(), "KeyHandshakeReceiverConversation");
+
+        let sender = MultiKeySenderAdapter::new_with_user(
+            key,
+            vec![],
+            AuthChallengeSenderConversation::new(key, None),
+        );
+        assert_eq!(sender.name(), "AuthChallengeSenderConversation");
+    }
+}
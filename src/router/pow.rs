@@ -0,0 +1,153 @@
+//! NIP-13 proof of work policy
+//!
+//! Some public relays drop events that do not carry a minimum amount of proof of work, especially
+//! when they come from pubkeys they have never seen before. This module defines how much work the
+//! router should put into outbound events and how much it should require from inbound ones.
+
+use std::collections::HashMap;
+
+use nostr::event::{Event, Kind};
+
+/// Proof of work requirements for the message router.
+///
+/// The difficulty for an outbound event is the highest of:
+/// - The difficulty configured for its kind, or the default difficulty if none is set
+/// - The difficulty configured for any of the relays the event will be sent to
+///
+/// # Example
+/// ```rust,no_run
+/// use portal::router::PowPolicy;
+/// use nostr::Kind;
+///
+/// let policy = PowPolicy::new()
+///     .default_difficulty(8)
+///     .kind(Kind::from(28000), 16)
+///     .relay("wss://relay.damus.io", 20)
+///     .min_inbound_difficulty(4);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PowPolicy {
+    default_difficulty: u8,
+    kinds: HashMap<Kind, u8>,
+    relays: HashMap<String, u8>,
+    min_inbound_difficulty: Option<u8>,
+}
+
+impl PowPolicy {
+    /// Creates a policy that doesn't require any proof of work.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the difficulty used for kinds that don't have a specific difficulty.
+    pub fn default_difficulty(mut self, difficulty: u8) -> Self {
+        self.default_difficulty = difficulty;
+        self
+    }
+
+    /// Sets the difficulty for a specific kind.
+    pub fn kind(mut self, kind: Kind, difficulty: u8) -> Self {
+        self.kinds.insert(kind, difficulty);
+        self
+    }
+
+    /// Sets the minimum difficulty for every event sent to a relay.
+    pub fn relay<S: Into<String>>(mut self, url: S, difficulty: u8) -> Self {
+        self.relays.insert(url.into(), difficulty);
+        self
+    }
+
+    /// Drop inbound events whose id doesn't have at least `difficulty` leading zero bits.
+    pub fn min_inbound_difficulty(mut self, difficulty: u8) -> Self {
+        self.min_inbound_difficulty = Some(difficulty);
+        self
+    }
+
+    /// Returns the difficulty to use for an event of `kind` sent to `relays`.
+    pub fn difficulty_for<'a, I>(&self, kind: Kind, relays: I) -> u8
+    where
+        I: IntoIterator<Item = &'a String>,
+    {
+        let kind_difficulty = self
+            .kinds
+            .get(&kind)
+            .copied()
+            .unwrap_or(self.default_difficulty);
+
+        relays
+            .into_iter()
+            .filter_map(|url| self.relays.get(url).copied())
+            .fold(kind_difficulty, u8::max)
+    }
+
+    /// Returns whether an inbound event satisfies the minimum difficulty.
+    pub fn accepts(&self, event: &Event) -> bool {
+        match self.min_inbound_difficulty {
+            Some(difficulty) => event.id.check_pow(difficulty),
+            None => true,
+        }
+    }
+
+    /// Returns true if this policy never requires any work on outbound events.
+    pub fn is_disabled(&self) -> bool {
+        self.default_difficulty == 0
+            && self.kinds.values().all(|d| *d == 0)
+            && self.relays.values().all(|d| *d == 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nostr::{EventBuilder, Keys};
+
+    #[test]
+    fn test_default_policy_is_disabled() {
+        let policy = PowPolicy::new();
+        assert!(policy.is_disabled());
+        assert_eq!(policy.difficulty_for(Kind::from(28000), &[]), 0);
+    }
+
+    #[test]
+    fn test_kind_overrides_default() {
+        let policy = PowPolicy::new()
+            .default_difficulty(4)
+            .kind(Kind::from(28000), 12);
+
+        assert_eq!(policy.difficulty_for(Kind::from(28000), &[]), 12);
+        assert_eq!(policy.difficulty_for(Kind::from(27000), &[]), 4);
+    }
+
+    #[test]
+    fn test_relay_raises_difficulty() {
+        let policy = PowPolicy::new()
+            .kind(Kind::from(28000), 8)
+            .relay("wss://strict.relay", 16)
+            .relay("wss://lenient.relay", 2);
+
+        let relays = vec![
+            "wss://strict.relay".to_string(),
+            "wss://lenient.relay".to_string(),
+        ];
+        assert_eq!(policy.difficulty_for(Kind::from(28000), &relays), 16);
+
+        let relays = vec!["wss://lenient.relay".to_string()];
+        assert_eq!(policy.difficulty_for(Kind::from(28000), &relays), 8);
+    }
+
+    #[test]
+    fn test_inbound_difficulty() {
+        let keys = Keys::generate();
+        let mined = EventBuilder::text_note("hello")
+            .pow(8)
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        let policy = PowPolicy::new().min_inbound_difficulty(8);
+        assert!(policy.accepts(&mined));
+
+        let policy = PowPolicy::new();
+        assert!(policy.accepts(&mined));
+    }
+}
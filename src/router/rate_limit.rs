@@ -0,0 +1,341 @@
+//! Rate limits for inbound events
+//!
+//! Anyone can send events to the keys served by the router, including from throwaway keys, and
+//! every request that reaches a listener may wake up the application. The [`RateLimitPolicy`]
+//! drops the events of senders, or of kinds, that exceed their limit before they are dispatched to
+//! any conversation.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use nostr::{event::Kind, key::PublicKey};
+
+/// The most senders tracked at once. When full, the idle ones are forgotten at most once per
+/// period of the per-sender limit, and in the meantime each new sender replaces the tracked one
+/// with the most tokens left.
+const MAX_TRACKED_SENDERS: usize = 10_000;
+
+/// At most `max_events` events, recovered over `period`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+pub struct RateLimit {
+    /// How many events can be received in a burst
+    pub max_events: u32,
+    /// How long it takes to recover the whole burst
+    pub period: Duration,
+}
+
+impl RateLimit {
+    pub fn new(max_events: u32, period: Duration) -> Self {
+        Self { max_events, period }
+    }
+}
+
+/// A token bucket enforcing a [`RateLimit`]
+///
+/// The bucket starts full and is refilled continuously.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        Self::new_at(limit, Instant::now())
+    }
+
+    pub fn new_at(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.max_events as f64,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let max = self.limit.max_events as f64;
+        if self.limit.period.is_zero() {
+            self.tokens = max;
+        } else {
+            let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+            let refilled = elapsed * max / self.limit.period.as_secs_f64();
+            self.tokens = (self.tokens + refilled).min(max);
+        }
+        self.updated_at = now;
+    }
+
+    /// Whether an event would be accepted at `now`, without taking a token
+    pub fn has_token(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= 1.0
+    }
+
+    /// Take a token, returns `false` if the limit is exceeded
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        if !self.has_token(now) {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+        true
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.limit.max_events as f64
+    }
+}
+
+/// Rate limits for the events received by the message router.
+///
+/// An event is accepted only if both its sender and its kind are within their limits. Events from
+/// the keys served by the router are never limited.
+///
+/// # Example
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// use portal::router::{RateLimit, RateLimitPolicy};
+/// use nostr::Kind;
+///
+/// let policy = RateLimitPolicy::new()
+///     .per_sender(RateLimit::new(10, Duration::from_secs(60)))
+///     .kind(Kind::from(27000), RateLimit::new(100, Duration::from_secs(60)));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitPolicy {
+    per_sender: Option<RateLimit>,
+    kinds: HashMap<Kind, RateLimit>,
+}
+
+impl RateLimitPolicy {
+    /// Creates a policy that doesn't limit anything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the events of each sender pubkey, across all kinds.
+    pub fn per_sender(mut self, limit: RateLimit) -> Self {
+        self.per_sender = Some(limit);
+        self
+    }
+
+    /// Limits the events of `kind`, across all senders.
+    pub fn kind(mut self, kind: Kind, limit: RateLimit) -> Self {
+        self.kinds.insert(kind, limit);
+        self
+    }
+
+    /// Returns true if this policy never drops any event.
+    pub fn is_disabled(&self) -> bool {
+        self.per_sender.is_none() && self.kinds.is_empty()
+    }
+}
+
+/// Tracks the events received by the router against a [`RateLimitPolicy`]
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter {
+    policy: RateLimitPolicy,
+    senders: HashMap<PublicKey, TokenBucket>,
+    kinds: HashMap<Kind, TokenBucket>,
+    last_sweep: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(policy: RateLimitPolicy) -> Self {
+        Self {
+            policy,
+            senders: HashMap::new(),
+            kinds: HashMap::new(),
+            last_sweep: None,
+        }
+    }
+
+    /// Replace the policy, forgetting the events received so far
+    pub(crate) fn set_policy(&mut self, policy: RateLimitPolicy) {
+        *self = Self::new(policy);
+    }
+
+    /// Returns whether an event of `kind` from `sender` is within the limits, and counts it if so
+    pub(crate) fn accepts(&mut self, sender: &PublicKey, kind: Kind, now: Instant) -> bool {
+        if self.policy.is_disabled() {
+            return true;
+        }
+
+        if let Some(limit) = self.policy.per_sender {
+            if self.senders.len() >= MAX_TRACKED_SENDERS && !self.senders.contains_key(sender) {
+                // Buckets idle for a whole period are full again, no point in sweeping more often
+                if self
+                    .last_sweep
+                    .is_none_or(|at| now.saturating_duration_since(at) >= limit.period)
+                {
+                    self.senders.retain(|_, bucket| !bucket.is_full(now));
+                    self.last_sweep = Some(now);
+                }
+
+                // Every tracked sender is active. Forget the one closest to its full burst, it has
+                // the least to gain from starting over.
+                if self.senders.len() >= MAX_TRACKED_SENDERS {
+                    let fullest = self
+                        .senders
+                        .iter_mut()
+                        .map(|(key, bucket)| {
+                            bucket.refill(now);
+                            (*key, bucket.tokens)
+                        })
+                        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                        .map(|(key, _)| key);
+                    if let Some(key) = fullest {
+                        self.senders.remove(&key);
+                    }
+                }
+            }
+
+            let bucket = self
+                .senders
+                .entry(*sender)
+                .or_insert_with(|| TokenBucket::new_at(limit, now));
+            if !bucket.has_token(now) {
+                return false;
+            }
+        }
+
+        if let Some(limit) = self.policy.kinds.get(&kind) {
+            let bucket = self
+                .kinds
+                .entry(kind)
+                .or_insert_with(|| TokenBucket::new_at(*limit, now));
+            if !bucket.try_acquire(now) {
+                return false;
+            }
+        }
+
+        if let Some(bucket) = self.senders.get_mut(sender) {
+            bucket.try_acquire(now);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nostr::Keys;
+
+    #[test]
+    fn test_token_bucket_refills() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new_at(RateLimit::new(2, Duration::from_secs(10)), start);
+
+        assert!(bucket.try_acquire(start));
+        assert!(bucket.try_acquire(start));
+        assert!(!bucket.try_acquire(start));
+
+        // One token every 5 seconds
+        assert!(!bucket.try_acquire(start + Duration::from_secs(4)));
+        assert!(bucket.try_acquire(start + Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_disabled_policy_accepts_everything() {
+        let mut limiter = RateLimiter::new(RateLimitPolicy::new());
+        let sender = Keys::generate().public_key();
+        let now = Instant::now();
+
+        for _ in 0..1000 {
+            assert!(limiter.accepts(&sender, Kind::from(27000), now));
+        }
+    }
+
+    #[test]
+    fn test_per_sender_limit() {
+        let mut limiter = RateLimiter::new(
+            RateLimitPolicy::new().per_sender(RateLimit::new(2, Duration::from_secs(60))),
+        );
+        let flooder = Keys::generate().public_key();
+        let other = Keys::generate().public_key();
+        let now = Instant::now();
+
+        assert!(limiter.accepts(&flooder, Kind::from(27000), now));
+        assert!(limiter.accepts(&flooder, Kind::from(27001), now));
+        assert!(!limiter.accepts(&flooder, Kind::from(27000), now));
+        assert!(limiter.accepts(&other, Kind::from(27000), now));
+    }
+
+    #[test]
+    fn test_kind_limit_stops_throwaway_keys() {
+        let mut limiter = RateLimiter::new(
+            RateLimitPolicy::new()
+                .per_sender(RateLimit::new(5, Duration::from_secs(60)))
+                .kind(
+                    Kind::from(27000),
+                    RateLimit::new(3, Duration::from_secs(60)),
+                ),
+        );
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.accepts(&Keys::generate().public_key(), Kind::from(27000), now));
+        }
+        assert!(!limiter.accepts(&Keys::generate().public_key(), Kind::from(27000), now));
+
+        // Other kinds are not affected
+        assert!(limiter.accepts(&Keys::generate().public_key(), Kind::from(27001), now));
+    }
+
+    #[test]
+    fn test_rejected_kind_does_not_consume_sender_budget() {
+        let mut limiter = RateLimiter::new(
+            RateLimitPolicy::new()
+                .per_sender(RateLimit::new(1, Duration::from_secs(60)))
+                .kind(
+                    Kind::from(27000),
+                    RateLimit::new(0, Duration::from_secs(60)),
+                ),
+        );
+        let sender = Keys::generate().public_key();
+        let now = Instant::now();
+
+        assert!(!limiter.accepts(&sender, Kind::from(27000), now));
+        assert!(limiter.accepts(&sender, Kind::from(27001), now));
+    }
+
+    #[test]
+    fn test_new_sender_gets_through_when_full() {
+        let limit = RateLimit::new(2, Duration::from_secs(60));
+        let mut limiter = RateLimiter::new(RateLimitPolicy::new().per_sender(limit));
+        let start = Instant::now();
+
+        let senders = (0..MAX_TRACKED_SENDERS)
+            .map(|_| Keys::generate().public_key())
+            .collect::<Vec<_>>();
+        for sender in &senders {
+            assert!(limiter.accepts(sender, Kind::from(27000), start));
+        }
+        // A flooder that used its whole burst
+        assert!(limiter.accepts(&senders[0], Kind::from(27000), start));
+        assert!(!limiter.accepts(&senders[0], Kind::from(27000), start));
+
+        // Every sender is active, an honest newcomer still gets through
+        let newcomer = Keys::generate().public_key();
+        assert!(limiter.accepts(&newcomer, Kind::from(27000), start));
+        assert!(limiter.senders.contains_key(&newcomer));
+        assert_eq!(limiter.senders.len(), MAX_TRACKED_SENDERS);
+
+        // The emptiest bucket is never the one replaced, so the flooder stays limited
+        let another = Keys::generate().public_key();
+        assert!(limiter.accepts(&another, Kind::from(27000), start));
+        assert!(!limiter.accepts(&senders[0], Kind::from(27000), start));
+
+        // Once a period passed, the idle senders are forgotten
+        let later = start + limit.period;
+        let last = Keys::generate().public_key();
+        assert!(limiter.accepts(&last, Kind::from(27000), later));
+        assert_eq!(limiter.senders.len(), 1);
+    }
+}
@@ -0,0 +1,935 @@
+//! Record and replay the traffic of a [`Channel`]
+//!
+//! [`RecordingChannel`] wraps any channel and appends every subscription, outbound event and
+//! inbound notification to a JSONL file. [`ReplayChannel`] reads such a file back and feeds the
+//! inbound notifications to a router, releasing them according to a [`ReplayClock`] controlled by
+//! the caller.
+//!
+//! Conversations pick random subscription ids, so each subscription created during the replay is
+//! mapped to the first unmatched recorded subscription with the same filter, ignoring `since` and
+//! `until`. Notifications for a recorded subscription that the router never re-creates are
+//! reported as [`ReplayError::UnmatchedSubscription`] after a timeout. To decrypt the replayed
+//! events the router must be created with the same keypair used while recording.
+//!
+//! Only the relay traffic is replayed: conversations still read the wall clock, for example to
+//! check expirations or to timestamp the events they build. A replay is therefore only
+//! deterministic for conversations that don't depend on the current time, and filters that
+//! contain the ids of freshly signed events (such as the subkey proof subscriptions) won't match
+//! the recorded ones.
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use nostr::{
+    event::Event,
+    filter::Filter,
+    message::{RelayMessage, SubscriptionId},
+    types::{RelayUrl, TryIntoUrl},
+};
+use nostr_relay_pool::RelayPoolNotification;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, Notify, watch};
+
+use crate::router::{PortalId, channel::Channel};
+
+/// A single line of a recording
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedEntry {
+    /// Milliseconds since the UNIX epoch
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub record: Record,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record {
+    Subscribe {
+        id: String,
+        filter: Filter,
+        relays: Option<Vec<String>>,
+    },
+    Unsubscribe {
+        id: String,
+    },
+    Broadcast {
+        event: Event,
+        relays: Option<Vec<String>>,
+    },
+    /// An event notification from the relay pool
+    Event {
+        relay: String,
+        subscription_id: String,
+        event: Event,
+    },
+    /// An event received as a raw relay message
+    RelayEvent {
+        relay: String,
+        subscription_id: String,
+        event: Event,
+    },
+    EndOfStoredEvents {
+        relay: String,
+        subscription_id: String,
+    },
+}
+
+impl Record {
+    fn from_notification(notification: &RelayPoolNotification) -> Option<Self> {
+        match notification {
+            RelayPoolNotification::Event {
+                relay_url,
+                subscription_id,
+                event,
+            } => Some(Record::Event {
+                relay: relay_url.to_string(),
+                subscription_id: subscription_id.to_string(),
+                event: (**event).clone(),
+            }),
+            RelayPoolNotification::Message {
+                relay_url,
+                message:
+                    RelayMessage::Event {
+                        subscription_id,
+                        event,
+                    },
+            } => Some(Record::RelayEvent {
+                relay: relay_url.to_string(),
+                subscription_id: subscription_id.to_string(),
+                event: (**event).clone(),
+            }),
+            RelayPoolNotification::Message {
+                relay_url,
+                message: RelayMessage::EndOfStoredEvents(subscription_id),
+            } => Some(Record::EndOfStoredEvents {
+                relay: relay_url.to_string(),
+                subscription_id: subscription_id.to_string(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn subscription_id(&self) -> Option<&str> {
+        match self {
+            Record::Event {
+                subscription_id, ..
+            }
+            | Record::RelayEvent {
+                subscription_id, ..
+            }
+            | Record::EndOfStoredEvents {
+                subscription_id, ..
+            } => Some(subscription_id),
+            _ => None,
+        }
+    }
+
+    fn into_notification(
+        self,
+        subscription_id: String,
+    ) -> Result<RelayPoolNotification, ReplayError> {
+        let subscription_id = SubscriptionId::new(subscription_id);
+        match self {
+            Record::Event { relay, event, .. } => Ok(RelayPoolNotification::Event {
+                relay_url: RelayUrl::parse(&relay)?,
+                subscription_id,
+                event: Box::new(event),
+            }),
+            Record::RelayEvent { relay, event, .. } => Ok(RelayPoolNotification::Message {
+                relay_url: RelayUrl::parse(&relay)?,
+                message: RelayMessage::Event {
+                    subscription_id: Cow::Owned(subscription_id),
+                    event: Cow::Owned(event),
+                },
+            }),
+            Record::EndOfStoredEvents { relay, .. } => Ok(RelayPoolNotification::Message {
+                relay_url: RelayUrl::parse(&relay)?,
+                message: RelayMessage::EndOfStoredEvents(Cow::Owned(subscription_id)),
+            }),
+            _ => Err(ReplayError::NotANotification),
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn to_strings<I, U>(urls: I) -> Result<Vec<String>, U::Err>
+where
+    I: IntoIterator<Item = U>,
+    U: TryIntoUrl,
+{
+    urls.into_iter()
+        .map(|url| url.try_into_url().map(|url| url.to_string()))
+        .collect()
+}
+
+/// A channel that records all the traffic of the inner channel to a JSONL file
+///
+/// Failing to write the recording never affects the inner channel, errors are only logged.
+pub struct RecordingChannel<C> {
+    inner: C,
+    file: StdMutex<File>,
+}
+
+impl<C: Channel> RecordingChannel<C> {
+    /// Wrap `inner`, appending the recording to the file at `path`
+    pub fn new<P: AsRef<Path>>(inner: C, path: P) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            inner,
+            file: StdMutex::new(file),
+        })
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    fn record(&self, record: Record) {
+        let entry = RecordedEntry {
+            timestamp: now_millis(),
+            record,
+        };
+
+        let line = match serde_json::to_string(&entry) {
+            Ok(line) => line,
+            Err(e) => {
+                log::error!("Failed to serialize recorded entry: {}", e);
+                return;
+            }
+        };
+
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+            log::error!("Failed to write recording: {}", e);
+        }
+    }
+}
+
+impl<C> Channel for RecordingChannel<C>
+where
+    C: Channel + Sync,
+    C::Error: From<nostr::types::url::Error>,
+{
+    type Error = C::Error;
+
+    async fn subscribe(&self, id: PortalId, filter: Filter) -> Result<usize, Self::Error> {
+        self.record(Record::Subscribe {
+            id: id.to_string(),
+            filter: filter.clone(),
+            relays: None,
+        });
+        self.inner.subscribe(id, filter).await
+    }
+
+    async fn subscribe_to<I, U>(
+        &self,
+        urls: I,
+        id: PortalId,
+        filter: Filter,
+    ) -> Result<(), Self::Error>
+    where
+        <I as IntoIterator>::IntoIter: Send,
+        I: IntoIterator<Item = U> + Send,
+        U: TryIntoUrl,
+        Self::Error: From<<U as TryIntoUrl>::Err>,
+    {
+        let urls = to_strings(urls)?;
+        self.record(Record::Subscribe {
+            id: id.to_string(),
+            filter: filter.clone(),
+            relays: Some(urls.clone()),
+        });
+        self.inner.subscribe_to(urls, id, filter).await
+    }
+
+    async fn unsubscribe(&self, id: PortalId) -> Result<(), Self::Error> {
+        self.record(Record::Unsubscribe { id: id.to_string() });
+        self.inner.unsubscribe(id).await
+    }
+
+    async fn broadcast(&self, event: Event) -> Result<(), Self::Error> {
+        self.record(Record::Broadcast {
+            event: event.clone(),
+            relays: None,
+        });
+        self.inner.broadcast(event).await
+    }
+
+    async fn broadcast_to<I, U>(&self, urls: I, event: Event) -> Result<(), Self::Error>
+    where
+        <I as IntoIterator>::IntoIter: Send,
+        I: IntoIterator<Item = U> + Send,
+        U: TryIntoUrl,
+        Self::Error: From<<U as TryIntoUrl>::Err>,
+    {
+        let urls = to_strings(urls)?;
+        self.record(Record::Broadcast {
+            event: event.clone(),
+            relays: Some(urls.clone()),
+        });
+        self.inner.broadcast_to(urls, event).await
+    }
+
+    async fn receive(&self) -> Result<RelayPoolNotification, Self::Error> {
+        let notification = self.inner.receive().await?;
+        if let Some(record) = Record::from_notification(&notification) {
+            self.record(record);
+        }
+        Ok(notification)
+    }
+
+    async fn shutdown(&self) -> Result<(), Self::Error> {
+        self.inner.shutdown().await
+    }
+}
+
+/// The clock used to release the notifications of a [`ReplayChannel`]
+///
+/// The time is measured from the first entry of the recording. Notifications are only returned
+/// once the clock has reached their offset, so the caller can step through a recording.
+#[derive(Debug, Clone)]
+pub struct ReplayClock {
+    now: Arc<watch::Sender<Duration>>,
+}
+
+impl ReplayClock {
+    pub fn new() -> Self {
+        Self {
+            now: Arc::new(watch::channel(Duration::ZERO).0),
+        }
+    }
+
+    pub fn now(&self) -> Duration {
+        *self.now.borrow()
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.send_modify(|now| *now = now.saturating_add(by));
+    }
+
+    pub fn set(&self, now: Duration) {
+        self.now.send_replace(now);
+    }
+
+    /// Release every remaining notification immediately
+    pub fn run_to_end(&self) {
+        self.set(Duration::MAX);
+    }
+
+    async fn wait_until(&self, time: Duration) {
+        let mut receiver = self.now.subscribe();
+        let _ = receiver.wait_for(|now| *now >= time).await;
+    }
+}
+
+impl Default for ReplayClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid recording: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid url: {0}")]
+    Url(#[from] nostr::types::url::Error),
+    #[error("Entry is not a notification")]
+    NotANotification,
+    #[error("End of the recording")]
+    Finished,
+    #[error("Recorded subscription {0} was never created during the replay")]
+    UnmatchedSubscription(String),
+}
+
+/// How long [`ReplayChannel::receive`] waits for the router to create a recorded subscription
+const DEFAULT_SUBSCRIPTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Compare two filters ignoring their time bounds, which usually depend on the wall clock
+fn same_filter(a: &Filter, b: &Filter) -> bool {
+    let strip = |filter: &Filter| {
+        let mut filter = filter.clone();
+        filter.since = None;
+        filter.until = None;
+        filter
+    };
+    strip(a) == strip(b)
+}
+
+struct ReplayState {
+    /// Inbound notifications with their offset from the start of the recording
+    inbound: VecDeque<(Duration, Record)>,
+    /// Subscriptions that haven't been matched yet, in the order they were created while recording
+    recorded_subscriptions: Vec<(String, Filter)>,
+    /// Every subscription id that appears in the recording
+    known_subscriptions: HashSet<String>,
+    /// Recorded subscription id to the id used in the replay
+    mapping: HashMap<String, String>,
+    broadcasts: Vec<Event>,
+}
+
+/// A channel that replays a recording made by [`RecordingChannel`]
+///
+/// Outbound events are not sent anywhere, they are collected and can be compared with the ones in
+/// the recording using [`ReplayChannel::broadcasts`] and [`ReplayChannel::recorded_broadcasts`].
+pub struct ReplayChannel {
+    state: Mutex<ReplayState>,
+    subscribed: Notify,
+    recorded_broadcasts: Vec<Event>,
+    clock: ReplayClock,
+    subscription_timeout: Duration,
+}
+
+impl ReplayChannel {
+    pub fn new(entries: Vec<RecordedEntry>, clock: ReplayClock) -> Self {
+        let start = entries.first().map(|e| e.timestamp).unwrap_or_default();
+
+        let mut inbound = VecDeque::new();
+        let mut recorded_subscriptions = Vec::new();
+        let mut known_subscriptions = HashSet::new();
+        let mut recorded_broadcasts = Vec::new();
+        for entry in entries {
+            let offset = Duration::from_millis(entry.timestamp.saturating_sub(start));
+            match entry.record {
+                // The same id is subscribed again when a relay is added
+                Record::Subscribe { id, filter, .. } => {
+                    if known_subscriptions.insert(id.clone()) {
+                        recorded_subscriptions.push((id, filter));
+                    }
+                }
+                Record::Broadcast { event, .. } => recorded_broadcasts.push(event),
+                Record::Unsubscribe { .. } => {}
+                record => inbound.push_back((offset, record)),
+            }
+        }
+
+        Self {
+            state: Mutex::new(ReplayState {
+                inbound,
+                recorded_subscriptions,
+                known_subscriptions,
+                mapping: HashMap::new(),
+                broadcasts: Vec::new(),
+            }),
+            subscribed: Notify::new(),
+            recorded_broadcasts,
+            clock,
+            subscription_timeout: DEFAULT_SUBSCRIPTION_TIMEOUT,
+        }
+    }
+
+    /// Set how long to wait for the router to create a recorded subscription before giving up
+    pub fn with_subscription_timeout(mut self, timeout: Duration) -> Self {
+        self.subscription_timeout = timeout;
+        self
+    }
+
+    /// Load a recording from a JSONL file
+    pub fn from_file<P: AsRef<Path>>(path: P, clock: ReplayClock) -> Result<Self, ReplayError> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut entries = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            entries.push(serde_json::from_str(&line)?);
+        }
+
+        Ok(Self::new(entries, clock))
+    }
+
+    pub fn clock(&self) -> &ReplayClock {
+        &self.clock
+    }
+
+    /// The events broadcast during the replay
+    pub async fn broadcasts(&self) -> Vec<Event> {
+        self.state.lock().await.broadcasts.clone()
+    }
+
+    /// The events that were broadcast in the recording
+    pub fn recorded_broadcasts(&self) -> &[Event] {
+        &self.recorded_broadcasts
+    }
+
+    /// The number of notifications that haven't been replayed yet
+    pub async fn remaining(&self) -> usize {
+        self.state.lock().await.inbound.len()
+    }
+
+    async fn map_subscription(&self, id: &PortalId, filter: &Filter) {
+        let mut state = self.state.lock().await;
+        let id = id.to_string();
+        // Subscribing again to a new relay
+        if state.mapping.values().any(|replayed| *replayed == id) {
+            return;
+        }
+
+        let position = state
+            .recorded_subscriptions
+            .iter()
+            .position(|(_, recorded)| same_filter(recorded, filter));
+        match position {
+            Some(position) => {
+                let (recorded, _) = state.recorded_subscriptions.remove(position);
+                log::trace!("Mapping recorded subscription {} to {}", recorded, id);
+                state.mapping.insert(recorded, id);
+                self.subscribed.notify_waiters();
+            }
+            None => log::debug!("Subscription {} doesn't match any recorded filter", id),
+        }
+    }
+}
+
+impl Channel for ReplayChannel {
+    type Error = ReplayError;
+
+    async fn subscribe(&self, id: PortalId, filter: Filter) -> Result<usize, Self::Error> {
+        self.map_subscription(&id, &filter).await;
+        Ok(1)
+    }
+
+    async fn subscribe_to<I, U>(
+        &self,
+        _urls: I,
+        id: PortalId,
+        filter: Filter,
+    ) -> Result<(), Self::Error>
+    where
+        <I as IntoIterator>::IntoIter: Send,
+        I: IntoIterator<Item = U> + Send,
+        U: TryIntoUrl,
+        Self::Error: From<<U as TryIntoUrl>::Err>,
+    {
+        self.map_subscription(&id, &filter).await;
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, _id: PortalId) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn broadcast(&self, event: Event) -> Result<(), Self::Error> {
+        self.state.lock().await.broadcasts.push(event);
+        Ok(())
+    }
+
+    async fn broadcast_to<I, U>(&self, _urls: I, event: Event) -> Result<(), Self::Error>
+    where
+        <I as IntoIterator>::IntoIter: Send,
+        I: IntoIterator<Item = U> + Send,
+        U: TryIntoUrl,
+        Self::Error: From<<U as TryIntoUrl>::Err>,
+    {
+        self.broadcast(event).await
+    }
+
+    async fn receive(&self) -> Result<RelayPoolNotification, Self::Error> {
+        loop {
+            let offset = match self.state.lock().await.inbound.front() {
+                Some((offset, _)) => *offset,
+                None => return Err(ReplayError::Finished),
+            };
+            self.clock.wait_until(offset).await;
+
+            let mut state = self.state.lock().await;
+            let subscription_id = match state.inbound.front() {
+                Some((_, record)) => record.subscription_id().unwrap_or_default().to_string(),
+                None => return Err(ReplayError::Finished),
+            };
+
+            // Wait until the router creates the matching subscription
+            let replayed_id = match state.mapping.get(&subscription_id) {
+                Some(id) => id.clone(),
+                None if state.known_subscriptions.contains(&subscription_id) => {
+                    let subscribed = self.subscribed.notified();
+                    drop(state);
+                    if tokio::time::timeout(self.subscription_timeout, subscribed)
+                        .await
+                        .is_err()
+                    {
+                        let mut state = self.state.lock().await;
+                        if !state.mapping.contains_key(&subscription_id) {
+                            state.inbound.pop_front();
+                            return Err(ReplayError::UnmatchedSubscription(subscription_id));
+                        }
+                    }
+                    continue;
+                }
+                // Subscribed before the recording started, keep the original id
+                None => subscription_id,
+            };
+
+            let (_, record) = state.inbound.pop_front().expect("Checked above");
+            return record.into_notification(replayed_id);
+        }
+    }
+
+    async fn shutdown(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nostr::{EventBuilder, Keys, Kind, Tag, nips::nip44};
+
+    use crate::{
+        protocol::{
+            LocalKeypair,
+            model::{
+                Timestamp,
+                event_kinds::{PAYMENT_CONFIRMATION, PAYMENT_RESPONSE},
+                payment::{
+                    Currency, PaymentResponseContent, PaymentStatus, SinglePaymentRequestContent,
+                },
+            },
+        },
+        router::{MessageRouter, MultiKeySenderAdapter},
+        sdk::payments::SinglePaymentRequestSenderConversation,
+        test_framework::SimulatedChannel,
+    };
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.jsonl", name, Keys::generate().public_key()))
+    }
+
+    #[tokio::test]
+    async fn test_record() {
+        let path = temp_path("portal-record");
+        let network = SimulatedChannel::new();
+        let channel = RecordingChannel::new(network.connect().await, &path).unwrap();
+
+        let id = PortalId::new_conversation();
+        channel
+            .subscribe(id.clone(), Filter::new().kind(Kind::TextNote))
+            .await
+            .unwrap();
+
+        let event = EventBuilder::text_note("hello")
+            .sign_with_keys(&Keys::generate())
+            .unwrap();
+        network.broadcast(event.clone()).await.unwrap();
+        channel.receive().await.unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let entries = contents
+            .lines()
+            .map(|line| serde_json::from_str::<RecordedEntry>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 2);
+        assert!(
+            matches!(&entries[0].record, Record::Subscribe { id: recorded, .. } if *recorded == id.to_string())
+        );
+        assert!(
+            matches!(&entries[1].record, Record::Event { event: recorded, .. } if recorded.id == event.id)
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_follows_clock() {
+        let event = EventBuilder::text_note("hello")
+            .sign_with_keys(&Keys::generate())
+            .unwrap();
+        let entries = vec![
+            RecordedEntry {
+                timestamp: 1_000,
+                record: Record::Subscribe {
+                    id: "p1recorded".to_string(),
+                    filter: Filter::new(),
+                    relays: None,
+                },
+            },
+            RecordedEntry {
+                timestamp: 3_000,
+                record: Record::Event {
+                    relay: "wss://relay.example.com".to_string(),
+                    subscription_id: "p1recorded".to_string(),
+                    event: event.clone(),
+                },
+            },
+        ];
+
+        let clock = ReplayClock::new();
+        let channel = ReplayChannel::new(entries, clock.clone());
+
+        let id = PortalId::new_conversation();
+        channel.subscribe(id.clone(), Filter::new()).await.unwrap();
+
+        // Not released until the clock reaches the offset of the event
+        clock.advance(Duration::from_secs(1));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), channel.receive())
+                .await
+                .is_err()
+        );
+
+        clock.advance(Duration::from_secs(1));
+        match channel.receive().await.unwrap() {
+            RelayPoolNotification::Event {
+                subscription_id,
+                event: received,
+                ..
+            } => {
+                assert_eq!(subscription_id.to_string(), id.to_string());
+                assert_eq!(received.id, event.id);
+            }
+            other => panic!("Unexpected notification: {:?}", other),
+        }
+
+        assert!(matches!(
+            channel.receive().await,
+            Err(ReplayError::Finished)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_replay_matches_subscriptions_by_filter() {
+        let notes = Filter::new().kind(Kind::TextNote);
+        let metadata = Filter::new().kind(Kind::Metadata);
+        let event = EventBuilder::text_note("hello")
+            .sign_with_keys(&Keys::generate())
+            .unwrap();
+        let subscribe = |id: &str, filter: &Filter| RecordedEntry {
+            timestamp: 0,
+            record: Record::Subscribe {
+                id: id.to_string(),
+                filter: filter.clone(),
+                relays: None,
+            },
+        };
+        let entries = vec![
+            subscribe("p1notes", &notes),
+            subscribe("p1metadata", &metadata),
+            RecordedEntry {
+                timestamp: 0,
+                record: Record::Event {
+                    relay: "wss://relay.example.com".to_string(),
+                    subscription_id: "p1notes".to_string(),
+                    event: event.clone(),
+                },
+            },
+        ];
+
+        let clock = ReplayClock::new();
+        clock.run_to_end();
+        let channel = ReplayChannel::new(entries, clock);
+
+        // Subscribed in a different order, and with a `since` taken from the wall clock
+        let metadata_id = PortalId::new_conversation();
+        channel.subscribe(metadata_id, metadata).await.unwrap();
+        let notes_id = PortalId::new_conversation();
+        channel
+            .subscribe(
+                notes_id.clone(),
+                notes.since(nostr::types::Timestamp::now()),
+            )
+            .await
+            .unwrap();
+
+        match channel.receive().await.unwrap() {
+            RelayPoolNotification::Event {
+                subscription_id,
+                event: received,
+                ..
+            } => {
+                assert_eq!(subscription_id.to_string(), notes_id.to_string());
+                assert_eq!(received.id, event.id);
+            }
+            other => panic!("Unexpected notification: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_unmatched_subscription() {
+        let event = EventBuilder::text_note("hello")
+            .sign_with_keys(&Keys::generate())
+            .unwrap();
+        let entries = vec![
+            RecordedEntry {
+                timestamp: 0,
+                record: Record::Subscribe {
+                    id: "p1recorded".to_string(),
+                    filter: Filter::new().kind(Kind::TextNote),
+                    relays: None,
+                },
+            },
+            RecordedEntry {
+                timestamp: 0,
+                record: Record::Event {
+                    relay: "wss://relay.example.com".to_string(),
+                    subscription_id: "p1recorded".to_string(),
+                    event,
+                },
+            },
+        ];
+
+        let clock = ReplayClock::new();
+        clock.run_to_end();
+        let channel =
+            ReplayChannel::new(entries, clock).with_subscription_timeout(Duration::from_millis(50));
+
+        // A subscription with a different filter doesn't take the recorded one
+        channel
+            .subscribe(
+                PortalId::new_conversation(),
+                Filter::new().kind(Kind::Metadata),
+            )
+            .await
+            .unwrap();
+
+        let result = tokio::time::timeout(Duration::from_secs(5), channel.receive())
+            .await
+            .unwrap();
+        assert!(
+            matches!(result, Err(ReplayError::UnmatchedSubscription(id)) if id == "p1recorded")
+        );
+        assert_eq!(channel.remaining().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_replay_payment_through_router() {
+        let service_keys = Keys::generate();
+        let user_keys = Keys::generate();
+
+        let response = |request_id: &str, status: PaymentStatus| {
+            let content = serde_json::to_string(&PaymentResponseContent {
+                request_id: request_id.to_string(),
+                status,
+            })
+            .unwrap();
+            let content = nip44::encrypt(
+                user_keys.secret_key(),
+                &service_keys.public_key(),
+                content,
+                nip44::Version::V2,
+            )
+            .unwrap();
+            EventBuilder::new(Kind::Custom(PAYMENT_RESPONSE), content)
+                .tags([Tag::public_key(service_keys.public_key())])
+                .sign_with_keys(&user_keys)
+                .unwrap()
+        };
+        let received = |timestamp: u64, event: &Event| RecordedEntry {
+            timestamp,
+            record: Record::Event {
+                relay: "wss://relay.example.com".to_string(),
+                subscription_id: "p1recorded".to_string(),
+                event: event.clone(),
+            },
+        };
+
+        let approved = response("request", PaymentStatus::Approved);
+        let success = response("request", PaymentStatus::Success { preimage: None });
+        let entries = vec![
+            RecordedEntry {
+                timestamp: 0,
+                record: Record::Subscribe {
+                    id: "p1recorded".to_string(),
+                    // The filter created by the payment request conversation
+                    filter: Filter::new()
+                        .kinds(vec![
+                            Kind::Custom(PAYMENT_RESPONSE),
+                            Kind::Custom(PAYMENT_CONFIRMATION),
+                        ])
+                        .author(user_keys.public_key())
+                        .pubkey(service_keys.public_key()),
+                    relays: None,
+                },
+            },
+            received(1_000, &approved),
+            // The same event delivered again by another relay
+            received(1_100, &approved),
+            // A stale response to an older request
+            received(
+                2_000,
+                &response("old-request", PaymentStatus::Success { preimage: None }),
+            ),
+            received(3_000, &success),
+            // Replayed after the conversation is finished
+            received(4_000, &success),
+        ];
+
+        let clock = ReplayClock::new();
+        let router = Arc::new(MessageRouter::new(
+            ReplayChannel::new(entries, clock.clone()),
+            LocalKeypair::new(service_keys.clone(), None),
+        ));
+        let listener = Arc::clone(&router);
+        tokio::spawn(async move { listener.listen().await });
+
+        let mut notifications = router
+            .add_and_subscribe(Box::new(MultiKeySenderAdapter::new_with_user(
+                user_keys.public_key(),
+                vec![],
+                SinglePaymentRequestSenderConversation::new(
+                    service_keys.public_key(),
+                    None,
+                    SinglePaymentRequestContent {
+                        amount: 1000,
+                        currency: Currency::Millisats,
+                        current_exchange_rate: None,
+                        invoice: "lnbc10n1".to_string(),
+                        auth_token: None,
+                        // The conversation checks expirations against the wall clock, not the
+                        // replay clock
+                        expires_at: Timestamp::now_plus_seconds(300),
+                        subscription_id: None,
+                        description: None,
+                        request_id: "request".to_string(),
+                    },
+                ),
+            )))
+            .await
+            .unwrap();
+        clock.run_to_end();
+
+        let statuses = tokio::time::timeout(Duration::from_secs(5), async {
+            let mut statuses = Vec::new();
+            while let Some(notification) = notifications.next().await {
+                let notification: PaymentResponseContent = notification.unwrap();
+                assert_eq!(notification.request_id, "request");
+                statuses.push(notification.status);
+            }
+            statuses
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            statuses,
+            vec![
+                PaymentStatus::Approved,
+                PaymentStatus::Success { preimage: None }
+            ]
+        );
+
+        // Every recorded event has been fed to the router
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while router.channel().remaining().await > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -1,3 +1,8 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+};
+
 use nostr::{
     Filter,
     event::{Kind, Tag},
@@ -9,8 +14,8 @@ use crate::{
     protocol::model::{
         Timestamp,
         auth::{
-            AuthChallengeContent, AuthResponseContent, AuthResponseStatus, KeyHandshakeContent,
-            SubkeyProof,
+            AuthChallengeContent, AuthResponseContent, AuthResponseStatus, Capability, ClientInfo,
+            KeyHandshakeContent, SubkeyProof,
         },
         event_kinds::*,
     },
@@ -25,12 +30,69 @@ use crate::{
 pub struct KeyHandshakeReceiverConversation {
     local_key: PublicKey,
     token: String,
+    #[new(default)]
+    expires_at: Option<Timestamp>,
+    #[new(default)]
+    peers: Option<PeerCapabilities>,
+}
+
+impl KeyHandshakeReceiverConversation {
+    /// Ignore handshakes received after `expires_at`
+    pub fn with_expiration(mut self, expires_at: Option<Timestamp>) -> Self {
+        self.expires_at = expires_at;
+        self
+    }
+
+    /// Record the capabilities advertised by the users that complete the handshake
+    pub fn with_peer_capabilities(mut self, peers: PeerCapabilities) -> Self {
+        self.peers = Some(peers);
+        self
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KeyHandshakeEvent {
     pub main_key: PublicKey,
     pub relays: Vec<String>,
+    pub client_info: ClientInfo,
+}
+
+/// The capabilities advertised by each user during the key handshake
+///
+/// Cloning is cheap, all the clones share the same data.
+#[derive(Debug, Clone, Default)]
+pub struct PeerCapabilities(Arc<RwLock<HashMap<PublicKey, HashSet<Capability>>>>);
+
+impl PeerCapabilities {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&self, key: PublicKey, capabilities: impl IntoIterator<Item = Capability>) {
+        self.0
+            .write()
+            .unwrap()
+            .insert(key, capabilities.into_iter().collect());
+    }
+
+    pub fn remove(&self, key: &PublicKey) {
+        self.0.write().unwrap().remove(key);
+    }
+
+    /// The capabilities of `key`, or `None` if they are not known
+    pub fn get(&self, key: &PublicKey) -> Option<HashSet<Capability>> {
+        self.0.read().unwrap().get(key).cloned()
+    }
+
+    /// Whether `key` can handle `capability`. Users with unknown capabilities are assumed to
+    /// support everything.
+    pub fn supports(&self, key: &PublicKey, capability: Capability) -> bool {
+        self.0
+            .read()
+            .unwrap()
+            .get(key)
+            .is_none_or(|capabilities| capabilities.contains(&capability))
+    }
 }
 
 impl MultiKeyListener for KeyHandshakeReceiverConversation {
@@ -57,11 +119,26 @@ impl MultiKeyListener for KeyHandshakeReceiverConversation {
         event: &crate::router::CleartextEvent,
         message: &Self::Message,
     ) -> Result<Response, Self::Error> {
+        if let Some(expires_at) = state.expires_at {
+            if Timestamp::now() > expires_at {
+                log::debug!("Ignoring key handshake for expired token");
+                return Ok(Response::new().finish());
+            }
+        }
+
         if message.token == state.token {
+            if let Some(peers) = &state.peers {
+                match &message.client_info.capabilities {
+                    Some(capabilities) => peers.insert(event.pubkey, capabilities.iter().copied()),
+                    None => peers.remove(&event.pubkey),
+                }
+            }
+
             Ok(Response::new()
                 .notify(KeyHandshakeEvent {
                     main_key: event.pubkey,
                     relays: message.preferred_relays.clone(),
+                    client_info: message.client_info.clone(),
                 })
                 .finish())
         } else {
@@ -196,3 +273,43 @@ impl MultiKeySender for AuthChallengeSenderConversation {
 impl ConversationWithNotification for MultiKeySenderAdapter<AuthChallengeSenderConversation> {
     type Notification = AuthResponseEvent;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_info_without_capabilities() {
+        let client_info: ClientInfo =
+            serde_json::from_str(r#"{"name":"Portal","version":"0.1.0"}"#).unwrap();
+        assert!(client_info.capabilities.is_none());
+        assert!(client_info.supports(Capability::Cashu));
+    }
+
+    #[test]
+    fn test_client_info_unknown_capability() {
+        let client_info: ClientInfo = serde_json::from_str(
+            r#"{"name":"Portal","version":"0.2.0","protocol_version":2,"capabilities":["auth","teleport"]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            client_info.capabilities,
+            Some(vec![Capability::Auth, Capability::Unknown])
+        );
+        assert!(!client_info.supports(Capability::SinglePayment));
+    }
+
+    #[test]
+    fn test_peer_capabilities() {
+        let peers = PeerCapabilities::new();
+        let key = nostr::Keys::generate().public_key();
+        assert!(peers.supports(&key, Capability::Invoice));
+
+        peers.insert(key, [Capability::Auth]);
+        assert!(peers.supports(&key, Capability::Auth));
+        assert!(!peers.supports(&key, Capability::Invoice));
+
+        peers.remove(&key);
+        assert!(peers.get(&key).is_none());
+    }
+}
@@ -0,0 +1,91 @@
+use nostr::{
+    event::{Kind, Tag},
+    filter::Filter,
+    key::PublicKey,
+};
+
+use crate::{
+    protocol::model::{
+        auth::SubkeyProof,
+        event_kinds::{CERTIFICATE_REQUEST, CERTIFICATE_RESPONSE},
+        identity::{CertificateRequestContent, CertificateResponseContent},
+    },
+    router::{
+        CleartextEvent, ConversationError, MultiKeySender, MultiKeySenderAdapter, Response,
+        adapters::ConversationWithNotification,
+    },
+};
+
+/// Sender conversation to ask a user to disclose some certificates.
+///
+/// Notifies the sender with a [`CertificateResponseContent`] event.
+#[derive(derive_new::new)]
+pub struct CertificateRequestSenderConversation {
+    local_key: PublicKey,
+    subkey_proof: Option<SubkeyProof>,
+
+    content: CertificateRequestContent,
+}
+
+impl MultiKeySender for CertificateRequestSenderConversation {
+    const VALIDITY_SECONDS: Option<u64> = Some(60 * 5);
+
+    type Error = ConversationError;
+    type Message = CertificateResponseContent;
+
+    fn get_filter(state: &MultiKeySenderAdapter<Self>) -> Result<Filter, Self::Error> {
+        let mut filter = Filter::new()
+            .kinds(vec![Kind::Custom(CERTIFICATE_RESPONSE)])
+            .authors(state.subkeys.iter().chain([&state.user]).cloned())
+            .pubkey(state.local_key);
+
+        if let Some(subkey_proof) = &state.subkey_proof {
+            filter = filter.pubkey(subkey_proof.main_key.into());
+        }
+
+        Ok(filter)
+    }
+
+    fn build_initial_message(
+        state: &mut MultiKeySenderAdapter<Self>,
+        new_key: Option<PublicKey>,
+    ) -> Result<Response, Self::Error> {
+        let tags = state
+            .subkeys
+            .iter()
+            .chain([&state.user])
+            .map(|k| Tag::public_key(*k))
+            .collect();
+
+        if let Some(new_key) = new_key {
+            Ok(Response::new().subscribe_to_subkey_proofs().reply_to(
+                new_key,
+                Kind::Custom(CERTIFICATE_REQUEST),
+                tags,
+                state.content.clone(),
+            ))
+        } else {
+            Ok(Response::new().subscribe_to_subkey_proofs().reply_all(
+                Kind::Custom(CERTIFICATE_REQUEST),
+                tags,
+                state.content.clone(),
+            ))
+        }
+    }
+
+    fn on_message(
+        state: &mut MultiKeySenderAdapter<Self>,
+        _event: &CleartextEvent,
+        message: &Self::Message,
+    ) -> Result<Response, Self::Error> {
+        if message.request_id == state.content.request_id {
+            Ok(Response::new().notify(message.clone()).finish())
+        } else {
+            Ok(Response::default())
+        }
+    }
+}
+
+impl ConversationWithNotification for MultiKeySenderAdapter<CertificateRequestSenderConversation> {
+    type Notification = CertificateResponseContent;
+}
@@ -1,2 +1,4 @@
 pub mod auth;
+pub mod certificates;
 pub mod payments;
+pub mod tickets;
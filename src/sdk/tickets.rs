@@ -0,0 +1,92 @@
+use nostr::{
+    event::{Kind, Tag},
+    filter::Filter,
+    key::PublicKey,
+};
+
+use crate::{
+    protocol::model::{
+        auth::SubkeyProof,
+        event_kinds::{TICKET_PRESENTATION, TICKET_SCAN_REQUEST},
+        ticket::{TicketPresentationContent, TicketScanRequestContent},
+    },
+    router::{
+        CleartextEvent, ConversationError, MultiKeySender, MultiKeySenderAdapter, Response,
+        adapters::ConversationWithNotification,
+    },
+};
+
+/// Sender conversation to ask the holder of a ticket to present it at the door.
+///
+/// Notifies the sender with a [`TicketPresentationContent`] event, to be checked with a
+/// [`TicketVerifier`](crate::protocol::ticket::TicketVerifier).
+#[derive(derive_new::new)]
+pub struct TicketScanSenderConversation {
+    local_key: PublicKey,
+    subkey_proof: Option<SubkeyProof>,
+
+    content: TicketScanRequestContent,
+}
+
+impl MultiKeySender for TicketScanSenderConversation {
+    const VALIDITY_SECONDS: Option<u64> = Some(60 * 2);
+
+    type Error = ConversationError;
+    type Message = TicketPresentationContent;
+
+    fn get_filter(state: &MultiKeySenderAdapter<Self>) -> Result<Filter, Self::Error> {
+        let mut filter = Filter::new()
+            .kinds(vec![Kind::Custom(TICKET_PRESENTATION)])
+            .authors(state.subkeys.iter().chain([&state.user]).cloned())
+            .pubkey(state.local_key);
+
+        if let Some(subkey_proof) = &state.subkey_proof {
+            filter = filter.pubkey(subkey_proof.main_key.into());
+        }
+
+        Ok(filter)
+    }
+
+    fn build_initial_message(
+        state: &mut MultiKeySenderAdapter<Self>,
+        new_key: Option<PublicKey>,
+    ) -> Result<Response, Self::Error> {
+        let tags = state
+            .subkeys
+            .iter()
+            .chain([&state.user])
+            .map(|k| Tag::public_key(*k))
+            .collect();
+
+        if let Some(new_key) = new_key {
+            Ok(Response::new().subscribe_to_subkey_proofs().reply_to(
+                new_key,
+                Kind::Custom(TICKET_SCAN_REQUEST),
+                tags,
+                state.content.clone(),
+            ))
+        } else {
+            Ok(Response::new().subscribe_to_subkey_proofs().reply_all(
+                Kind::Custom(TICKET_SCAN_REQUEST),
+                tags,
+                state.content.clone(),
+            ))
+        }
+    }
+
+    fn on_message(
+        state: &mut MultiKeySenderAdapter<Self>,
+        _event: &CleartextEvent,
+        message: &Self::Message,
+    ) -> Result<Response, Self::Error> {
+        if message.request_id == state.content.request_id {
+            Ok(Response::new().notify(message.clone()).finish())
+        } else {
+            Ok(Response::default())
+        }
+    }
+}
+
+impl ConversationWithNotification for MultiKeySenderAdapter<TicketScanSenderConversation> {
+    type Notification = TicketPresentationContent;
+}
@@ -1,4 +1,17 @@
-use std::{collections::HashMap, sync::Arc};
+//! In-process test harness
+//!
+//! This module provides a [`SimulatedChannel`] that connects multiple [`MessageRouter`]s without any
+//! real relay, so that full protocol flows can be tested end-to-end. Faults such as dropped, delayed,
+//! duplicated or reordered events can be injected with [`FaultConfig`].
+//!
+//! It's always available in this crate's tests, and it's exported for downstream crates with the
+//! `testing` feature.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
 
 use nostr::{
     RelayUrl,
@@ -7,6 +20,7 @@ use nostr::{
     message::SubscriptionId,
 };
 use nostr_relay_pool::RelayPoolNotification;
+use rand::{Rng, SeedableRng, rngs::StdRng};
 use tokio::sync::{Mutex, RwLock, mpsc};
 
 use crate::{
@@ -19,30 +33,164 @@ use crate::{
 
 pub mod logger;
 
+/// The relay url reported for every event delivered by the [`SimulatedChannel`]
+pub const SIMULATED_RELAY_URL: &str = "wss://simulated";
+
+/// Faults to inject when delivering events through a [`SimulatedChannel`]
+///
+/// Probabilities are in the `0.0..=1.0` range and are evaluated independently for every subscriber
+/// that matches an event.
+///
+/// # Example
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use portal::test_framework::FaultConfig;
+///
+/// let faults = FaultConfig::new()
+///     .drop(0.1)
+///     .duplicate(0.05)
+///     .delay(Duration::from_millis(10), Duration::from_millis(50))
+///     .reorder(0.2, Duration::from_millis(100))
+///     .seed(42);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FaultConfig {
+    drop_probability: f64,
+    duplicate_probability: f64,
+    delay: Option<(Duration, Duration)>,
+    reorder_probability: f64,
+    reorder_window: Duration,
+    seed: Option<u64>,
+}
+
+impl FaultConfig {
+    /// Creates a configuration that delivers every event exactly once, immediately.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drop events with the given probability.
+    pub fn drop(mut self, probability: f64) -> Self {
+        self.drop_probability = probability;
+        self
+    }
+
+    /// Deliver events twice with the given probability.
+    pub fn duplicate(mut self, probability: f64) -> Self {
+        self.duplicate_probability = probability;
+        self
+    }
+
+    /// Delay every event by a random duration between `min` and `max`.
+    pub fn delay(mut self, min: Duration, max: Duration) -> Self {
+        self.delay = Some((min, max.max(min)));
+        self
+    }
+
+    /// Hold back events with the given probability for up to `window`, letting later events overtake them.
+    pub fn reorder(mut self, probability: f64, window: Duration) -> Self {
+        self.reorder_probability = probability;
+        self.reorder_window = window;
+        self
+    }
+
+    /// Use a fixed seed for the random decisions, to make a scenario reproducible.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+}
+
+/// The faults currently applied to a network, shared by all its channels
+struct FaultState {
+    config: FaultConfig,
+    rng: StdRng,
+}
+
+impl FaultState {
+    fn new(config: FaultConfig) -> Self {
+        Self {
+            rng: config.rng(),
+            config,
+        }
+    }
+
+    /// Returns the delays after which each copy of an event should be delivered. An empty vec means the event is dropped.
+    fn plan_delivery(&mut self) -> Vec<Duration> {
+        let config = &self.config;
+
+        if self.rng.gen_bool(config.drop_probability.clamp(0.0, 1.0)) {
+            return vec![];
+        }
+
+        let copies = if self
+            .rng
+            .gen_bool(config.duplicate_probability.clamp(0.0, 1.0))
+        {
+            2
+        } else {
+            1
+        };
+
+        (0..copies)
+            .map(|_| {
+                let mut delay = match config.delay {
+                    Some((min, max)) if max > min => self.rng.gen_range(min..=max),
+                    Some((min, _)) => min,
+                    None => Duration::ZERO,
+                };
+                if !config.reorder_window.is_zero()
+                    && self
+                        .rng
+                        .gen_bool(config.reorder_probability.clamp(0.0, 1.0))
+                {
+                    delay += self.rng.gen_range(Duration::ZERO..=config.reorder_window);
+                }
+                delay
+            })
+            .collect()
+    }
+}
+
 /// A simulated channel that broadcasts messages to all connected nodes
+///
+/// Every node gets its own channel by calling [`SimulatedChannel::connect`] on an existing one. All
+/// the channels created this way share the same subscriptions, event log and [`FaultConfig`].
 pub struct SimulatedChannel {
     subscribers: Arc<RwLock<HashMap<PortalId, (Filter, mpsc::Sender<RelayPoolNotification>)>>>,
     messages: Arc<Mutex<Vec<Event>>>,
     senders: Arc<Mutex<Vec<mpsc::Sender<RelayPoolNotification>>>>,
+    faults: Arc<StdMutex<FaultState>>,
     receiver: Mutex<mpsc::Receiver<RelayPoolNotification>>,
     my_sender: mpsc::Sender<RelayPoolNotification>,
 }
 
 impl SimulatedChannel {
     pub fn new() -> Self {
+        Self::new_with_faults(FaultConfig::default())
+    }
+
+    pub fn new_with_faults(faults: FaultConfig) -> Self {
         let (tx, rx) = mpsc::channel(32);
         Self {
             subscribers: Arc::new(RwLock::new(HashMap::new())),
             messages: Arc::new(Mutex::new(Vec::new())),
             senders: Arc::new(Mutex::new(vec![tx.clone()])),
+            faults: Arc::new(StdMutex::new(FaultState::new(faults))),
             receiver: Mutex::new(rx),
             my_sender: tx,
         }
     }
-}
 
-impl SimulatedChannel {
-    async fn clone(&self) -> Self {
+    /// Creates a new channel for another node connected to the same network
+    pub async fn connect(&self) -> Self {
         let (tx, rx) = mpsc::channel(32);
 
         // Add the new sender and receiver to their respective lists
@@ -52,11 +200,69 @@ impl SimulatedChannel {
         Self {
             subscribers: self.subscribers.clone(),
             messages: self.messages.clone(),
+            faults: self.faults.clone(),
             receiver: Mutex::new(rx),
             my_sender: tx,
             senders: self.senders.clone(),
         }
     }
+
+    /// Replaces the faults applied to the whole network
+    pub fn set_faults(&self, faults: FaultConfig) {
+        *self.faults.lock().unwrap() = FaultState::new(faults);
+    }
+
+    /// Returns every event broadcast on the network so far
+    pub async fn messages(&self) -> Vec<Event> {
+        self.messages.lock().await.clone()
+    }
+
+    async fn deliver(&self, event: Event) {
+        // Store the event
+        self.messages.lock().await.push(event.clone());
+
+        // Broadcast to all subscribers
+        let subscribers = self.subscribers.read().await;
+        for (subscription_id, (filter, sender)) in subscribers.iter() {
+            if !filter.match_event(&event, MatchEventOptions::default()) {
+                continue;
+            }
+
+            let delays = self.faults.lock().unwrap().plan_delivery();
+            if delays.is_empty() {
+                log::debug!(
+                    "Simulating dropped event {} for {}",
+                    event.id,
+                    subscription_id
+                );
+                continue;
+            }
+
+            for delay in delays {
+                let notification = RelayPoolNotification::Event {
+                    event: Box::new(event.clone()),
+                    subscription_id: SubscriptionId::new(subscription_id.to_string()),
+                    relay_url: RelayUrl::parse(SIMULATED_RELAY_URL).unwrap(),
+                };
+
+                if delay.is_zero() {
+                    let _ = sender.send(notification).await;
+                } else {
+                    let sender = sender.clone();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        let _ = sender.send(notification).await;
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl Default for SimulatedChannel {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -117,23 +323,7 @@ impl Channel for SimulatedChannel {
     }
 
     async fn broadcast(&self, event: Event) -> Result<(), Self::Error> {
-        // Store the event
-        self.messages.lock().await.push(event.clone());
-
-        // Broadcast to all subscribers
-        let subscribers = self.subscribers.write().await;
-        for (subscription_id, (filter, sender)) in subscribers.iter() {
-            if filter.match_event(&event, MatchEventOptions::default()) {
-                let relay_url = RelayUrl::parse("wss://simulated").unwrap();
-                let notification = RelayPoolNotification::Event {
-                    event: Box::new(event.clone()),
-                    subscription_id: SubscriptionId::new(subscription_id.to_string()),
-                    relay_url,
-                };
-                let _ = sender.send(notification).await;
-            }
-        }
-
+        self.deliver(event).await;
         Ok(())
     }
 
@@ -144,23 +334,8 @@ impl Channel for SimulatedChannel {
         U: nostr::types::TryIntoUrl,
         Self::Error: From<<U as nostr::types::TryIntoUrl>::Err>,
     {
-        // Store the event
-        self.messages.lock().await.push(event.clone());
-
-        // Broadcast to all subscribers
-        let subscribers = self.subscribers.write().await;
-        for (subscription_id, (filter, sender)) in subscribers.iter() {
-            if filter.match_event(&event, MatchEventOptions::default()) {
-                let relay_url = RelayUrl::parse("wss://simulated").unwrap();
-                let notification = RelayPoolNotification::Event {
-                    event: Box::new(event.clone()),
-                    subscription_id: SubscriptionId::new(subscription_id.to_string()),
-                    relay_url,
-                };
-                let _ = sender.send(notification).await;
-            }
-        }
-
+        // TODO: use the urls to select the recipients
+        self.deliver(event).await;
         Ok(())
     }
 
@@ -189,19 +364,40 @@ pub struct SimulatedNetwork {
 
 impl SimulatedNetwork {
     pub fn new() -> Self {
+        Self::new_with_faults(FaultConfig::default())
+    }
+
+    pub fn new_with_faults(faults: FaultConfig) -> Self {
         Self {
-            channel: SimulatedChannel::new(),
+            channel: SimulatedChannel::new_with_faults(faults),
             nodes: HashMap::new(),
         }
     }
 
+    /// Creates a channel connected to this network, without adding a node for it
+    ///
+    /// This is useful to build higher level clients (like the SDK or the app) on top of the network.
+    pub async fn connect(&self) -> SimulatedChannel {
+        self.channel.connect().await
+    }
+
+    /// Replaces the faults applied to the network
+    pub fn set_faults(&self, faults: FaultConfig) {
+        self.channel.set_faults(faults);
+    }
+
+    /// Returns every event broadcast on the network so far
+    pub async fn messages(&self) -> Vec<Event> {
+        self.channel.messages().await
+    }
+
     /// Add a new node to the network
     pub async fn add_node(
         &mut self,
         id: String,
         keypair: LocalKeypair,
     ) -> Arc<MessageRouter<SimulatedChannel>> {
-        let router = Arc::new(MessageRouter::new(self.channel.clone().await, keypair));
+        let router = Arc::new(MessageRouter::new(self.channel.connect().await, keypair));
         self.nodes.insert(id, Arc::clone(&router));
         router
     }
@@ -222,6 +418,12 @@ impl SimulatedNetwork {
     }
 }
 
+impl Default for SimulatedNetwork {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Helper to create test scenarios
 pub struct ScenarioBuilder {
     network: SimulatedNetwork,
@@ -234,6 +436,11 @@ impl ScenarioBuilder {
         }
     }
 
+    pub fn with_faults(self, faults: FaultConfig) -> Self {
+        self.network.set_faults(faults);
+        self
+    }
+
     pub async fn with_node(mut self, id: String, keypair: LocalKeypair) -> Self {
         self.network.add_node(id, keypair).await;
         self
@@ -268,6 +475,12 @@ impl ScenarioBuilder {
     }
 }
 
+impl Default for ScenarioBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -291,5 +504,46 @@ mod tests {
         assert!(network.get_node("node2").is_some());
     }
 
+    #[tokio::test]
+    async fn test_fault_injection() {
+        use nostr::{EventBuilder, Kind};
+
+        let channel = SimulatedChannel::new_with_faults(FaultConfig::new().duplicate(1.0));
+        let node = channel.connect().await;
+        node.subscribe(
+            PortalId::new_conversation(),
+            Filter::new().kind(Kind::TextNote),
+        )
+        .await
+        .unwrap();
+
+        let event = EventBuilder::text_note("hello")
+            .sign_with_keys(&Keys::generate())
+            .unwrap();
+
+        // Every event is delivered twice
+        channel.broadcast(event.clone()).await.unwrap();
+        for _ in 0..2 {
+            match node.receive().await.unwrap() {
+                RelayPoolNotification::Event {
+                    event: received, ..
+                } => {
+                    assert_eq!(received.id, event.id)
+                }
+                other => panic!("Unexpected notification: {:?}", other),
+            }
+        }
+
+        // Every event is dropped
+        channel.set_faults(FaultConfig::new().drop(1.0));
+        channel.broadcast(event).await.unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), node.receive())
+                .await
+                .is_err()
+        );
+        assert_eq!(channel.messages().await.len(), 2);
+    }
+
     pub mod auth_scenario;
 }
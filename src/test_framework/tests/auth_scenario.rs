@@ -33,6 +33,8 @@ async fn test_auth_flow() {
         relays: vec!["simulated".to_string()],
         token: token.clone(),
         subkey: None,
+        expires_at: None,
+        signature: None,
     };
 
     // Create the network with both nodes